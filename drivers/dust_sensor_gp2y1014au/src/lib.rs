@@ -42,7 +42,8 @@ where
         PeripheralADCI: Peripheral<P = ADCI> + 'd,
         PinLed: Peripheral<P = PinLed> + esp_hal::gpio::OutputPin,
     {
-        let pin_led = Output::new(hardware.pin_led, Level::Low, OutputConfig::default());
+        // the LED is active low, keep it off until the next reading
+        let pin_led = Output::new(hardware.pin_led, Level::High, OutputConfig::default());
         let mut adc1_config = AdcConfig::new();
         let pin_data = adc1_config.enable_pin(hardware.pin_data, Attenuation::_0dB);
         let adc_reader = Adc::new(hardware.adci, adc1_config);
@@ -0,0 +1,197 @@
+#![cfg_attr(not(test), no_std)]
+
+use defmt::Format;
+use embassy_time::{Duration, Instant, Timer};
+use embedded_hal_async::i2c::I2c;
+
+/// I2C address of every SCD40 and SCD41
+pub const SCD4X_I2C_ADDRESS: u8 = 0x62;
+/// Interval between two measurements in periodic mode
+pub const SCD4X_MEASUREMENT_INTERVAL: Duration = Duration::from_secs(5);
+
+const CMD_START_PERIODIC_MEASUREMENT: u16 = 0x21b1;
+const CMD_STOP_PERIODIC_MEASUREMENT: u16 = 0x3f86;
+const CMD_READ_MEASUREMENT: u16 = 0xec05;
+const CMD_GET_DATA_READY_STATUS: u16 = 0xe4b8;
+
+/// Time for the sensor to process a command before its response can be read
+const COMMAND_EXECUTION_TIME: Duration = Duration::from_millis(1);
+/// Time for the sensor to accept commands again after stopping periodic measurements
+const STOP_EXECUTION_TIME: Duration = Duration::from_millis(500);
+/// Interval between two data-ready checks once a measurement is due
+const DATA_READY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Time after which [`Scd4x::measure`] gives up waiting for data
+const MEASUREMENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Scd4xHardware<I2C> {
+    pub i2c: I2C,
+}
+
+pub struct Scd4x<I2C> {
+    i2c: I2C,
+    /// When periodic measurements were started, `None` if they are stopped
+    started_at: Option<Instant>,
+}
+
+/// A reading of the sensor.
+#[derive(Clone, Copy, Format)]
+pub struct Measurement {
+    /// CO2 concentration in ppm
+    pub co2: u16,
+    /// Temperature in °C
+    pub temperature: f32,
+    /// Relative humidity in percent
+    pub humidity: f32,
+}
+
+impl Measurement {
+    /// Converts the words of a `read_measurement` response.
+    fn from_words([co2, temperature, humidity]: [u16; 3]) -> Self {
+        Self {
+            co2,
+            temperature: -45.0 + 175.0 * (temperature as f32) / 65535.0,
+            humidity: 100.0 * (humidity as f32) / 65535.0,
+        }
+    }
+}
+
+#[derive(Format)]
+pub enum Error<I2cError> {
+    /// The I2C transaction failed
+    I2c(I2cError),
+    /// A word of the response doesn't match its checksum
+    Crc,
+    /// No measurement was ready in time
+    Timeout,
+}
+
+impl<I2C: I2c> Scd4x<I2C> {
+    /// Creates a new instance of the SCD4x CO2 sensor, periodic measurements are started by the first [`measure`](Self::measure).
+    pub fn new(hardware: Scd4xHardware<I2C>) -> Self {
+        Self {
+            i2c: hardware.i2c,
+            started_at: None,
+        }
+    }
+
+    /// Starts measuring every [`SCD4X_MEASUREMENT_INTERVAL`].
+    pub async fn start_periodic_measurement(&mut self) -> Result<(), Error<I2C::Error>> {
+        self.write_command(CMD_START_PERIODIC_MEASUREMENT).await?;
+        self.started_at = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Stops periodic measurements, lowering the power consumption of the sensor.
+    pub async fn stop_periodic_measurement(&mut self) -> Result<(), Error<I2C::Error>> {
+        self.write_command(CMD_STOP_PERIODIC_MEASUREMENT).await?;
+        self.started_at = None;
+        Timer::after(STOP_EXECUTION_TIME).await;
+        Ok(())
+    }
+
+    /// Whether a measurement can be read.
+    pub async fn data_ready(&mut self) -> Result<bool, Error<I2C::Error>> {
+        let [status] = self.read_words(CMD_GET_DATA_READY_STATUS).await?;
+        // the 11 least significant bits are 0 while no data is ready
+        Ok(status & 0x07ff != 0)
+    }
+
+    /// Waits for the next measurement and reads it.
+    ///
+    /// Periodic measurements are started if needed, the first one is then available after [`SCD4X_MEASUREMENT_INTERVAL`].
+    pub async fn measure(&mut self) -> Result<Measurement, Error<I2C::Error>> {
+        let started_at = match self.started_at {
+            Some(started_at) => started_at,
+            None => {
+                self.start_periodic_measurement().await?;
+                Instant::now()
+            }
+        };
+        Timer::at(started_at + SCD4X_MEASUREMENT_INTERVAL).await;
+
+        let deadline = Instant::now() + MEASUREMENT_TIMEOUT;
+        while !self.data_ready().await? {
+            if Instant::now() >= deadline {
+                return Err(Error::Timeout);
+            }
+            Timer::after(DATA_READY_POLL_INTERVAL).await;
+        }
+
+        let words = self.read_words(CMD_READ_MEASUREMENT).await?;
+        Ok(Measurement::from_words(words))
+    }
+
+    async fn write_command(&mut self, command: u16) -> Result<(), Error<I2C::Error>> {
+        self.i2c
+            .write(SCD4X_I2C_ADDRESS, &command.to_be_bytes())
+            .await
+            .map_err(Error::I2c)
+    }
+
+    /// Sends `command` and reads its response, made of `N` words each followed by their CRC.
+    async fn read_words<const N: usize>(
+        &mut self,
+        command: u16,
+    ) -> Result<[u16; N], Error<I2C::Error>> {
+        self.write_command(command).await?;
+        Timer::after(COMMAND_EXECUTION_TIME).await;
+
+        // the largest response is the one of read_measurement
+        let mut buf = [0u8; 9];
+        let buf = &mut buf[..N * 3];
+        self.i2c
+            .read(SCD4X_I2C_ADDRESS, buf)
+            .await
+            .map_err(Error::I2c)?;
+
+        let mut words = [0u16; N];
+        for (word, chunk) in words.iter_mut().zip(buf.chunks_exact(3)) {
+            if crc8(&chunk[..2]) != chunk[2] {
+                return Err(Error::Crc);
+            }
+            *word = u16::from_be_bytes([chunk[0], chunk[1]]);
+        }
+        Ok(words)
+    }
+}
+
+/// CRC-8 of the Sensirion datasheets: polynomial 0x31, initialized to 0xff.
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0xff, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x31
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_crc8() {
+        // example of the datasheet
+        assert_eq!(crc8(&[0xbe, 0xef]), 0x92);
+        assert_eq!(crc8(&[]), 0xff);
+    }
+
+    #[test]
+    fn test_measurement_from_words() {
+        // read_measurement example of the datasheet
+        let measurement = Measurement::from_words([0x01f4, 0x6667, 0x5eb9]);
+        assert_eq!(measurement.co2, 500);
+        assert!((measurement.temperature - 25.0).abs() < 0.01);
+        assert!((measurement.humidity - 37.0).abs() < 0.01);
+
+        let lowest = Measurement::from_words([0, 0, 0]);
+        assert_eq!(lowest.temperature, -45.0);
+        assert_eq!(lowest.humidity, 0.0);
+        let highest = Measurement::from_words([u16::MAX; 3]);
+        assert_eq!(highest.temperature, 130.0);
+        assert_eq!(highest.humidity, 100.0);
+    }
+}
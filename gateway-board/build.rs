@@ -1,3 +1,97 @@
+use flate2::{write::DeflateEncoder, Compression};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Write;
+use std::path::Path;
+use std::{env, fs};
+use util::auth::hash_password;
+use util::template::{segments, Segment};
+
+/// Dashboard pages, as constant names and files in `PAGES_DIR`
+const PAGES: &[(&str, &str)] = &[
+    ("DASHBOARD", "dashboard.html"),
+    ("PROCESSING", "processing.html"),
+    ("REBOOT", "reboot.html"),
+    ("TEST_EXPORT", "test_export.html"),
+];
+const PAGES_DIR: &str = "src/net/http/pages";
+
 fn main() {
     println!("cargo:rustc-link-arg=-Tlinkall.x");
+    compress_pages();
+    hash_dashboard_password();
+}
+
+/// Splits each page at its `{{value}}` placeholders and deflates the parts in between,
+/// so that they can be sent as is in a gzip stream. See `src/net/http/pages.rs`.
+fn compress_pages() {
+    let mut out = String::new();
+
+    for (name, file) in PAGES {
+        let path = Path::new(PAGES_DIR).join(file);
+        println!("cargo:rerun-if-changed={}", path.display());
+        let html = fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("failed to read {}: {e}", path.display()));
+
+        // consecutive text segments are merged, so that there is a part before, between and after each value
+        let mut parts = vec![String::new()];
+        let mut values = Vec::new();
+        for segment in segments(&html) {
+            match segment {
+                Segment::Text(text) => parts.last_mut().unwrap().push_str(text),
+                Segment::Placeholder(name) => {
+                    values.push(name);
+                    parts.push(String::new());
+                }
+            }
+        }
+
+        out +=
+            &format!("pub const {name}: Page = Page {{\n    values: &{values:?},\n    parts: &[\n");
+        for part in parts {
+            out += &format!(
+                "        PagePart {{ text: {part:?}, deflated: &{:?} }},\n",
+                deflate(&part)
+            );
+        }
+        out += "    ],\n};\n";
+    }
+
+    let out_path = Path::new(&env::var("OUT_DIR").unwrap()).join("pages.rs");
+    fs::write(out_path, out).expect("failed to write pages.rs");
+}
+
+/// Hashes `DASHBOARD_PASSWORD` with a random salt, so that only the salt and the hash end up in the firmware.
+fn hash_dashboard_password() {
+    println!("cargo:rerun-if-env-changed=DASHBOARD_PASSWORD");
+    let hash = match env::var("DASHBOARD_PASSWORD") {
+        Ok(password) => {
+            // each `RandomState` is seeded from the OS, good enough for a salt
+            let mut salt = [0u8; 16];
+            for chunk in salt.chunks_exact_mut(8) {
+                let mut hasher = RandomState::new().build_hasher();
+                hasher.write(password.as_bytes());
+                chunk.copy_from_slice(&hasher.finish().to_le_bytes());
+            }
+            format!(
+                "Some(({salt:?}, {:?}))",
+                hash_password(&salt, password.as_bytes())
+            )
+        }
+        Err(_) => "None".to_string(),
+    };
+
+    let out =
+        format!("pub const DASHBOARD_PASSWORD_HASH: Option<([u8; 16], [u8; 32])> = {hash};\n");
+    let out_path = Path::new(&env::var("OUT_DIR").unwrap()).join("dashboard_auth.rs");
+    fs::write(out_path, out).expect("failed to write dashboard_auth.rs");
+}
+
+/// Deflates `text` into non-final blocks ending on a byte boundary.
+fn deflate(text: &str) -> Vec<u8> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(text.as_bytes()).unwrap();
+    // a sync flush aligns the output without ending the deflate stream, unlike `finish()`
+    encoder.flush().unwrap();
+    encoder.get_ref().clone()
 }
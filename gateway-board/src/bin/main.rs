@@ -1,17 +1,21 @@
 #![no_std]
 #![no_main]
 
-use defmt::{info, warn};
+use defmt::{info, warn, Debug2Format};
 use embassy_executor::Spawner;
 use esp_backtrace as _;
 use esp_hal::{
     clock::CpuClock,
     peripherals::{RADIO_CLK, TIMG0, WIFI},
     rng::Rng,
+    rtc_cntl::Rtc,
     timer::timg::TimerGroup,
+    uart::{self, UartRx},
+    Async,
 };
-use gateway_board::{config::CONFIG, ValueChannel, ValueReceiver, ValueSender};
-use protocol::app::v1::{SensorValue, SensorValuePoint};
+use gateway_board::watchdog;
+use gateway_board::{config::CONFIG, ValueReceiver, ValueSender, DEFAULT_VALUE_CHANNEL_SIZE};
+use protocol::app::v1::SensorValuePoint;
 use static_cell::StaticCell;
 
 #[embassy_executor::task]
@@ -20,11 +24,27 @@ async fn display_things(hardware: gateway_board::display::GatewayDisplayHardware
     gateway_board::display::run_display(hardware).await
 }
 
+#[embassy_executor::task]
+async fn run_diagnostics() -> ! {
+    gateway_board::diagnostics::run().await
+}
+
 #[cfg(feature = "wifi")]
 #[embassy_executor::task]
 async fn run_wifi_controller(mut controller: gateway_board::net::WifiController<'static>) {
     info!("start wifi task");
     controller.run().await.expect("error while running wifi");
+    // the task is not respawned, stop reporting heartbeats so that the watchdog reboots the board
+    warn!("wifi task stopped");
+}
+
+#[cfg(feature = "wifi")]
+#[embassy_executor::task]
+async fn run_ap_button(button: gateway_board::board::Button) -> ! {
+    use esp_hal::gpio::{Input, InputConfig, Pull};
+
+    let button = Input::new(button, InputConfig::default().with_pull(Pull::Up));
+    gateway_board::net::wifi::run_ap_button(button).await
 }
 
 #[cfg(feature = "wifi")]
@@ -48,7 +68,14 @@ async fn run_http(
     ap_stack: embassy_net::Stack<'static>,
     sta_stack: embassy_net::Stack<'static>,
 ) -> ! {
-    let mut server = gateway_board::net::http::HttpServer::new(ap_stack, sta_stack, 80).await;
+    let (port, interfaces) = {
+        let config = CONFIG.lock().await;
+        (config.http_port, config.http_interfaces)
+    };
+    // large enough for the dashboard form with all of its fields filled
+    let mut server =
+        gateway_board::net::http::HttpServer::<2048>::new(ap_stack, sta_stack, port, interfaces)
+            .await;
     server
         .run(gateway_board::net::http::api::dispatch_http_request)
         .await
@@ -60,29 +87,85 @@ async fn export_values(
     sta_stack: embassy_net::Stack<'static>,
     mut value_receiver: ValueReceiver,
 ) -> ! {
+    use embassy_futures::select::{select, Either};
     use gateway_board::export;
 
-    let mut value_buf: heapless::Vec<SensorValuePoint, { VALUE_CHANNEL_SIZE * 2 }> =
+    let mut value_buf: heapless::Vec<SensorValuePoint, { DEFAULT_VALUE_CHANNEL_SIZE * 2 }> =
         heapless::Vec::new();
+    let mut client = export::new_client(sta_stack);
 
     loop {
-        let values = export::collect_values(&mut value_buf, &mut value_receiver).await;
-        export::export_to_all(sta_stack, values).await;
+        watchdog::park(watchdog::WatchedTask::Export);
+        // nothing is received until the first value is, so collecting values can be cancelled
+        let res = select(
+            export::collect_values(&mut value_buf, &mut value_receiver),
+            export::wait_test_export_request(),
+        )
+        .await;
+        watchdog::heartbeat(watchdog::WatchedTask::Export);
+        match res {
+            Either::First(values) => export::export_to_all(&mut client, values).await,
+            Either::Second(()) => export::test_export(&mut client).await,
+        }
     }
 }
 
 #[cfg(feature = "wifi")]
 static ESP_WIFI_CTRL: StaticCell<esp_wifi::EspWifiController<'static>> = StaticCell::new();
 
-#[cfg(feature = "lora")]
 #[embassy_executor::task]
-async fn run_lora(hardware: gateway_board::lora::LoraHardware, sender: ValueSender) {
-    use gateway_board::lora::LoraController;
+async fn run_watchdog(rtc: Rtc<'static>) -> ! {
+    let threshold =
+        embassy_time::Duration::from_secs(CONFIG.lock().await.watchdog_timeout_secs.into());
+    watchdog::run(rtc, threshold).await
+}
 
-    let lora = LoraController::new(hardware)
+#[embassy_executor::task]
+async fn run_console(rx: UartRx<'static, Async>) -> ! {
+    gateway_board::console::run(rx).await
+}
+
+#[cfg(all(feature = "lora", not(feature = "uart-phy")))]
+#[embassy_executor::task]
+async fn run_lora(hardware: gateway_board::lora::LoraHardware, sender: ValueSender, rng: Rng) {
+    use gateway_board::lora::{LoraConfig, LoraController};
+
+    let config = LoraConfig {
+        sync_word: CONFIG.lock().await.lora_sync_word,
+        ..LoraConfig::default()
+    };
+    let mut lora = LoraController::new(hardware, config)
         .await
         .expect("failed to initialize LoRa");
-    gateway_board::comm::app::run(lora, sender).await
+    if let Some(tx_power) = CONFIG.lock().await.lora_tx_power {
+        if lora.set_tx_power(tx_power.into()).is_err() {
+            warn!(
+                "lora: keeping default TX power of {=i32} dBm",
+                lora.tx_power()
+            );
+        }
+    }
+    #[cfg(feature = "lora-debug")]
+    {
+        let _ = (sender, rng);
+        lora.sniff(b"SECRET").await
+    }
+    #[cfg(not(feature = "lora-debug"))]
+    {
+        use protocol::app::v1::session::AppLayerTimings;
+        gateway_board::comm::app::run(lora, rng, sender, AppLayerTimings::default()).await
+    }
+}
+
+/// Runs the app and link layers over a serial link instead of the radio.
+#[cfg(feature = "uart-phy")]
+#[embassy_executor::task]
+async fn run_uart_phy(uart: uart::Uart<'static, Async>, sender: ValueSender, rng: Rng) {
+    use protocol::app::v1::session::AppLayerTimings;
+    use util::uart_phy::UartPhy;
+
+    info!("uart-phy: using the serial link instead of the radio");
+    gateway_board::comm::app::run(UartPhy::new(uart), rng, sender, AppLayerTimings::default()).await
 }
 
 #[esp_hal_embassy::main]
@@ -91,6 +174,9 @@ async fn main(spawner: Spawner) {
     let peripherals = esp_hal::init(config);
     let rng_context: Rng = Rng::new(peripherals.RNG);
 
+    // dependencies logging with the `log` crate share the defmt output
+    gateway_board::logging::init();
+
     esp_alloc::heap_allocator!(size: 72 * 1024);
 
     // Initialize config struct
@@ -112,7 +198,19 @@ async fn main(spawner: Spawner) {
 
     info!("HAL intialized!");
 
-    let (value_sender, value_receiver) = make_value_channel();
+    spawner.must_spawn(run_watchdog(Rtc::new(peripherals.LPWR)));
+    spawner.must_spawn(run_diagnostics());
+
+    let pins = gateway_board::board_pins!(peripherals);
+
+    // the console shares the UART of the logs, which only transmit
+    match UartRx::new(peripherals.UART0, uart::Config::default()) {
+        Ok(rx) => spawner.must_spawn(run_console(rx.with_rx(pins.console_rx).into_async())),
+        Err(e) => warn!("console: failed to set up UART: {}", Debug2Format(&e)),
+    }
+
+    let (value_sender, value_receiver) =
+        gateway_board::try_take_value_channel().expect("value channel already taken");
 
     #[cfg(feature = "wifi")]
     setup_wifi(
@@ -121,6 +219,7 @@ async fn main(spawner: Spawner) {
         rng_context,
         peripherals.RADIO_CLK,
         peripherals.WIFI,
+        pins.button,
         value_receiver,
     )
     .await;
@@ -129,26 +228,30 @@ async fn main(spawner: Spawner) {
     spawner.must_spawn(display_things(
         gateway_board::display::GatewayDisplayHardware {
             i2c: peripherals.I2C0,
-            vext: peripherals.GPIO36,
-            sda: peripherals.GPIO17,
-            scl: peripherals.GPIO18,
-            rst: peripherals.GPIO21,
+            pins: pins.display,
         },
     ));
 
-    #[cfg(feature = "lora")]
+    #[cfg(feature = "uart-phy")]
+    match uart::Uart::new(peripherals.UART1, uart::Config::default()) {
+        Ok(uart) => spawner.must_spawn(run_uart_phy(
+            uart.with_tx(pins.uart_phy.tx)
+                .with_rx(pins.uart_phy.rx)
+                .into_async(),
+            value_sender,
+            rng_context,
+        )),
+        Err(e) => warn!("uart-phy: failed to set up UART: {}", Debug2Format(&e)),
+    }
+
+    #[cfg(all(feature = "lora", not(feature = "uart-phy")))]
     spawner.must_spawn(run_lora(
         gateway_board::lora::LoraHardware {
             spi: peripherals.SPI2,
-            spi_nss: peripherals.GPIO8,
-            spi_scl: peripherals.GPIO9,
-            spi_mosi: peripherals.GPIO10,
-            spi_miso: peripherals.GPIO11,
-            reset: peripherals.GPIO12,
-            busy: peripherals.GPIO13,
-            dio1: peripherals.GPIO14,
+            pins: pins.lora,
         },
         value_sender,
+        rng_context,
     ));
 }
 
@@ -159,6 +262,7 @@ async fn setup_wifi(
     rng: Rng,
     radio_clk: RADIO_CLK,
     wifi: WIFI,
+    button: gateway_board::board::Button,
     value_receiver: ValueReceiver,
 ) {
     let timg0 = TimerGroup::new(timg0);
@@ -170,8 +274,12 @@ async fn setup_wifi(
         .await
         .expect("failed to initialize wifi stack");
 
+    let (wifi_ap_ssid, wifi_ap_pass) = {
+        let config = CONFIG.lock().await;
+        (config.wifi_ap_ssid.clone(), config.wifi_ap_pass.clone())
+    };
     wifi_ctrl
-        .enable_ap(CONFIG.lock().await.wifi_ap_ssid.clone())
+        .enable_ap(wifi_ap_ssid, wifi_ap_pass)
         .expect("AP configuration failed");
 
     let wifi_sta_ssid = CONFIG.lock().await.wifi_sta_ssid.clone();
@@ -194,29 +302,7 @@ async fn setup_wifi(
     spawner.must_spawn(run_net_stack(wifi_runners.sta_runner));
     spawner.must_spawn(run_dhcp(ap_stack));
     spawner.must_spawn(run_wifi_controller(wifi_ctrl));
+    spawner.must_spawn(run_ap_button(button));
     spawner.must_spawn(run_http(ap_stack, sta_stack));
     spawner.must_spawn(export_values(sta_stack, value_receiver));
 }
-
-const VALUE_CHANNEL_SIZE: usize = 4;
-
-/// Create a pair and sender/receiver for sensor values.
-/// The channel itself is a singleton allocated in static memory, calling this function twice will result in a panic.
-fn make_value_channel() -> (ValueSender, ValueReceiver) {
-    static VALUE_CHANNEL_BUF: StaticCell<[SensorValuePoint; VALUE_CHANNEL_SIZE]> =
-        StaticCell::new();
-    static VALUE_CHANNEL: StaticCell<ValueChannel> = StaticCell::new();
-
-    const DUMMY_VALUE: SensorValuePoint = SensorValuePoint {
-        value: SensorValue::Unknown {
-            id: 255,
-            value_len: 0,
-        },
-        time_offset: -99,
-    };
-
-    let value_channel: &'static mut ValueChannel = VALUE_CHANNEL.init_with(|| {
-        ValueChannel::new(VALUE_CHANNEL_BUF.init_with(|| [DUMMY_VALUE; VALUE_CHANNEL_SIZE]))
-    });
-    value_channel.split()
-}
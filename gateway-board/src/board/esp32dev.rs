@@ -0,0 +1,22 @@
+//! Generic ESP32 development board, without a radio or a display.
+
+use esp_hal::gpio::GpioPin;
+
+#[cfg(any(feature = "display-ssd1306", feature = "lora"))]
+compile_error!("the esp32dev board has no display or LoRa radio, disable the `display-ssd1306` and `lora` features");
+
+pub type ConsoleRx = GpioPin<3>;
+/// The `BOOT` button
+pub type Button = GpioPin<0>;
+
+/// Moves the pins of the board out of `peripherals`, into a [`BoardPins`](crate::board::BoardPins).
+#[macro_export]
+macro_rules! board_pins {
+    ($peripherals:ident) => {
+        $crate::board::BoardPins {
+            console_rx: $peripherals.GPIO3,
+            #[cfg(feature = "wifi")]
+            button: $peripherals.GPIO0,
+        }
+    };
+}
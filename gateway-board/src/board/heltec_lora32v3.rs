@@ -0,0 +1,57 @@
+//! Heltec WiFi LoRa 32 (V3): ESP32-S3 with an SX1262 radio and an SSD1306 display.
+
+use esp_hal::gpio::GpioPin;
+
+pub type ConsoleRx = GpioPin<44>;
+/// The `PRG` button
+pub type Button = GpioPin<0>;
+
+pub type DisplayVext = GpioPin<36>;
+pub type DisplaySda = GpioPin<17>;
+pub type DisplayScl = GpioPin<18>;
+pub type DisplayRst = GpioPin<21>;
+
+pub type LoraSpiNss = GpioPin<8>;
+pub type LoraSpiScl = GpioPin<9>;
+pub type LoraSpiMosi = GpioPin<10>;
+pub type LoraSpiMiso = GpioPin<11>;
+pub type LoraReset = GpioPin<12>;
+pub type LoraBusy = GpioPin<13>;
+pub type LoraDio1 = GpioPin<14>;
+
+pub type UartPhyTx = GpioPin<47>;
+pub type UartPhyRx = GpioPin<48>;
+
+/// Moves the pins of the board out of `peripherals`, into a [`BoardPins`](crate::board::BoardPins).
+#[macro_export]
+macro_rules! board_pins {
+    ($peripherals:ident) => {
+        $crate::board::BoardPins {
+            console_rx: $peripherals.GPIO44,
+            #[cfg(feature = "wifi")]
+            button: $peripherals.GPIO0,
+            #[cfg(feature = "display-ssd1306")]
+            display: $crate::board::DisplayPins {
+                vext: $peripherals.GPIO36,
+                sda: $peripherals.GPIO17,
+                scl: $peripherals.GPIO18,
+                rst: $peripherals.GPIO21,
+            },
+            #[cfg(feature = "lora")]
+            lora: $crate::board::LoraPins {
+                spi_nss: $peripherals.GPIO8,
+                spi_scl: $peripherals.GPIO9,
+                spi_mosi: $peripherals.GPIO10,
+                spi_miso: $peripherals.GPIO11,
+                reset: $peripherals.GPIO12,
+                busy: $peripherals.GPIO13,
+                dio1: $peripherals.GPIO14,
+            },
+            #[cfg(feature = "uart-phy")]
+            uart_phy: $crate::board::UartPhyPins {
+                tx: $peripherals.GPIO47,
+                rx: $peripherals.GPIO48,
+            },
+        }
+    };
+}
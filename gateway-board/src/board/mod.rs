@@ -0,0 +1,58 @@
+//! Pin maps of the supported boards, selected by the `board-*` features.
+//!
+//! Each board gets its own module, defining the types of its pins and a `board_pins!` macro that moves them out of
+//! the peripherals. Supporting another board only takes a new module and feature.
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "board-esp32dev")] {
+        mod esp32dev;
+        pub use esp32dev::*;
+    } else if #[cfg(feature = "board-heltec-lora32v3")] {
+        mod heltec_lora32v3;
+        pub use heltec_lora32v3::*;
+    } else {
+        compile_error!("no board selected, enable one of the `board-*` features");
+    }
+}
+
+/// Pins used by the gateway, taken from the peripherals with [`board_pins!`](crate::board_pins).
+pub struct BoardPins {
+    /// Receive pin of the UART shared by the logs and the console
+    pub console_rx: ConsoleRx,
+    /// Boot button, low while pressed, held to start the access point again
+    #[cfg(feature = "wifi")]
+    pub button: Button,
+    #[cfg(feature = "display-ssd1306")]
+    pub display: DisplayPins,
+    #[cfg(feature = "lora")]
+    pub lora: LoraPins,
+    #[cfg(feature = "uart-phy")]
+    pub uart_phy: UartPhyPins,
+}
+
+#[cfg(feature = "display-ssd1306")]
+pub struct DisplayPins {
+    /// Powers the display when low
+    pub vext: DisplayVext,
+    pub sda: DisplaySda,
+    pub scl: DisplayScl,
+    pub rst: DisplayRst,
+}
+
+#[cfg(feature = "lora")]
+pub struct LoraPins {
+    pub spi_nss: LoraSpiNss,
+    pub spi_scl: LoraSpiScl,
+    pub spi_mosi: LoraSpiMosi,
+    pub spi_miso: LoraSpiMiso,
+    pub reset: LoraReset,
+    pub busy: LoraBusy,
+    pub dio1: LoraDio1,
+}
+
+/// Serial link replacing the radio, see [`util::uart_phy`]
+#[cfg(feature = "uart-phy")]
+pub struct UartPhyPins {
+    pub tx: UartPhyTx,
+    pub rx: UartPhyRx,
+}
@@ -1,36 +1,25 @@
-use core::fmt::{Display, Formatter};
-
-use defmt::{error, info, warn, Debug2Format};
+use defmt::{error, info, warn, Debug2Format, Display2Format};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
 use embassy_time::{Duration, Instant, Timer};
+use esp_hal::rng::Rng;
 use protocol::{
-    app::v1::{HandshakeEnd, HandshakeStart, Packet, SensorData, SensorValuePoint},
-    codec::{AsyncDecoder, AsyncEncoder},
-    link::v1::LinkLayer,
-    phy::PhysicalLayer,
+    app::v1::{
+        session::{self, AppLayer, AppLayerError, AppLayerTimings, ValuesReader},
+        Capabilities, Command, HandshakeStart, Packet, SensorValuePoint,
+    },
+    codec::AsyncDecoder,
+    link::v1::{LinkLayer, SensorBoardId},
 };
-use thiserror::Error;
-
-#[cfg(feature = "display-ssd1306")]
-use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
+use util::history::History;
 
 use crate::{
-    comm::link::GatewayLinkLayer, ValueSender, PROTOCOL_VERSION_MAJOR, PROTOCOL_VERSION_MINOR,
+    comm::{link::GatewayLinkLayer, Radio},
+    config::{ValueOverflowPolicy, CONFIG, SENSOR_ID_COUNT},
+    lora::LORA_RX_TIMEOUT,
+    FutureTimeoutExt, ValueSender, PROTOCOL_CAPABILITIES, PROTOCOL_VERSION_MAJOR,
+    PROTOCOL_VERSION_MINOR,
 };
 
-pub struct GatewayAppLayer<LINK> {
-    link: LINK,
-    offset: usize,
-}
-
-#[derive(Debug, Error)]
-pub enum GatewayAppLayerError<LINK: core::error::Error> {
-    Decoding,
-    UnexpectedPacket(u8),
-    IncompatibleProtocol(u8, u8),
-    Timeout,
-    Link(LINK),
-}
-
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum AppLayerPhase {
     Initial,
@@ -41,44 +30,218 @@ pub enum AppLayerPhase {
 #[cfg(feature = "display-ssd1306")]
 pub struct DisplayStatus {
     pub phase: AppLayerPhase,
+    /// Last time a packet was received from any sensor board, `None` if none was since boot
+    pub last_packet: Option<Instant>,
 }
 
 #[cfg(feature = "display-ssd1306")]
 pub static CURRENT_STATUS: Mutex<CriticalSectionRawMutex, DisplayStatus> =
     Mutex::new(DisplayStatus {
         phase: AppLayerPhase::Initial,
+        last_packet: None,
     });
 
-/// Listens for LoRa packets in an infinite loop.
-pub async fn run<PHY: PhysicalLayer>(phy: PHY, mut value_sender: ValueSender) -> ! {
+/// Connection statistics, kept across reconnects.
+pub struct Stats {
+    pub packets_received: u32,
+    pub acks_sent: u32,
+    pub handshakes_completed: u32,
+    pub decode_errors: u32,
+    pub unexpected_packets: u32,
+    /// Last time a packet was received from each sensor board, indexed by ID
+    pub last_seen: [Option<Instant>; SENSOR_ID_COUNT],
+}
+
+impl Stats {
+    pub const fn new() -> Self {
+        Self {
+            packets_received: 0,
+            acks_sent: 0,
+            handshakes_completed: 0,
+            decode_errors: 0,
+            unexpected_packets: 0,
+            last_seen: [None; SENSOR_ID_COUNT],
+        }
+    }
+
+    /// Zeroes every counter, from the `reset-stats` console command.
+    ///
+    /// The last seen times are kept, they pick the sensor board ID to reassign once all of them are taken.
+    pub fn reset(&mut self) {
+        *self = Self {
+            last_seen: self.last_seen,
+            ..Self::new()
+        };
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Stats::new()
+    }
+}
+
+pub static STATS: Mutex<CriticalSectionRawMutex, Stats> = Mutex::new(Stats::new());
+
+/// Number of values kept for each sensor value type in [`HISTORY`].
+pub const HISTORY_LEN: usize = 16;
+
+/// Most recent values received from sensor boards, kept across reconnects.
+pub static HISTORY: Mutex<CriticalSectionRawMutex, History<HISTORY_LEN>> =
+    Mutex::new(History::new());
+
+/// Spreading factor recommendations for each sensor board, indexed by ID.
+#[cfg(feature = "adr")]
+static ADR: Mutex<CriticalSectionRawMutex, [util::adr::Adr; crate::config::SENSOR_ID_COUNT]> =
+    Mutex::new(
+        [util::adr::Adr::new(crate::lora::LORA_SPREADING_FACTOR); crate::config::SENSOR_ID_COUNT],
+    );
+
+/// Time without hearing from a connected sensor board before it gets pinged.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+/// Time given to a sensor board to answer a ping, before considering it disconnected.
+const KEEPALIVE_PONG_TIMEOUT: Duration = Duration::from_secs(5);
+/// Time added to the configured measurement interval before dropping the connection of a quiet sensor board.
+const UPLINK_TIMEOUT_MARGIN: Duration = Duration::from_secs(30);
+/// Longest wait for each value of a sensor data packet, in case it announces more values than it contains.
+const SENSOR_VALUE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Whether each sensor board, indexed by ID, was sent the configured measurement interval since its last handshake.
+static MEASURE_INTERVAL_SENT: Mutex<CriticalSectionRawMutex, [bool; SENSOR_ID_COUNT]> =
+    Mutex::new([false; SENSOR_ID_COUNT]);
+
+type GatewayAppLayer<PHY> = AppLayer<GatewayLinkLayer<PHY>>;
+
+/// Listens for packets of sensor boards in an infinite loop, over LoRa or any other [`Radio`].
+///
+/// `rng` picks the nonce of each link handshake.
+pub async fn run<PHY: Radio>(
+    phy: PHY,
+    rng: Rng,
+    mut value_sender: ValueSender,
+    timings: AppLayerTimings,
+) -> ! {
     // let mut value_sender = self.value_sender.take().expect("broken: no sender");
-    let link = GatewayLinkLayer::new(phy);
+    let link = GatewayLinkLayer::new(phy, rng);
     let mut phase = AppLayerPhase::Initial;
-    let mut app = GatewayAppLayer::new(link);
+    let mut ping_pending = false;
+    // values of types added after this firmware are forwarded as-is to the webhook
+    let mut app = AppLayer::with_timings(link, timings).with_unknown_values_retained(true);
 
     loop {
+        reinit_radio_if_stuck(&mut app).await;
         #[cfg(feature = "display-ssd1306")]
         {
             // update the display status
             CURRENT_STATUS.lock().await.phase = phase;
         }
-        if let Err(err) = comm_cycle(&mut app, &mut phase, &mut value_sender).await {
-            error!("comm error: {:?}", Debug2Format(&err));
+        match comm_cycle(&mut app, &mut phase, &mut ping_pending, &mut value_sender).await {
+            Ok(()) => (),
+            // the rest of a sensor data packet with a bad count cannot be decoded, the connection is dropped
+            Err(err @ (AppLayerError::Timeout | AppLayerError::TooManyValues(_))) => {
+                warn!(
+                    "app: {}, waiting for a new handshake...",
+                    Display2Format(&err)
+                );
+                app.reset();
+                reset_channel(&mut app);
+                phase = AppLayerPhase::Initial;
+                ping_pending = false;
+            }
+            Err(err) => {
+                error!("comm error: {:?}", Debug2Format(&err));
+            }
         }
     }
 }
 
-async fn comm_cycle<LINK: LinkLayer>(
-    app: &mut GatewayAppLayer<LINK>,
+async fn comm_cycle<PHY: Radio>(
+    app: &mut GatewayAppLayer<PHY>,
     phase: &mut AppLayerPhase,
+    ping_pending: &mut bool,
     value_sender: &mut ValueSender,
-) -> Result<(), GatewayAppLayerError<LINK::Error>> {
+) -> Result<(), AppLayerError<PHY::Error>> {
     info!("app: Waiting for sensor board request...");
 
-    match app.read::<Packet>().await? {
+    // nothing is expected until a handshake, pongs are expected quickly
+    let rx_timeout = match *phase {
+        AppLayerPhase::Uplink if *ping_pending => Some(KEEPALIVE_PONG_TIMEOUT),
+        AppLayerPhase::Uplink => Some(LORA_RX_TIMEOUT),
+        _ => None,
+    };
+    app.link_mut().phy_mut().set_rx_timeout(rx_timeout);
+
+    let keepalive = app.capabilities().contains(Capabilities::KEEPALIVE);
+    let read = match *phase {
+        AppLayerPhase::Uplink if keepalive => {
+            let timeout = if *ping_pending {
+                KEEPALIVE_PONG_TIMEOUT
+            } else {
+                KEEPALIVE_INTERVAL
+            };
+            app.read::<Packet>().with_timeout(timeout).await
+        }
+        AppLayerPhase::Uplink => {
+            let timeout = uplink_timeout(app).await;
+            match app.read::<Packet>().with_timeout(timeout).await {
+                Ok(read) => Ok(read),
+                Err(_) => return Err(AppLayerError::Timeout),
+            }
+        }
+        _ => Ok(app.read::<Packet>().await),
+    };
+
+    let packet = match read {
+        Ok(Ok(packet)) => packet,
+        Err(_) if *ping_pending => {
+            warn!("app: sensor board did not answer ping");
+            return Err(AppLayerError::Timeout);
+        }
+        Err(_) => {
+            info!("app: no news from sensor board, sending ping");
+            session::send_ping(app).await?;
+            *ping_pending = true;
+            return Ok(());
+        }
+        Ok(Err(e)) => {
+            if matches!(e, AppLayerError::Decoding) {
+                STATS.lock().await.decode_errors += 1;
+            }
+            return Err(e);
+        }
+    };
+
+    // any packet shows that the sensor board is still there
+    *ping_pending = false;
+
+    {
+        let mut stats = STATS.lock().await;
+        stats.packets_received += 1;
+        if let Some(peer) = app.last_peer() {
+            stats.last_seen[(peer.0 & 0xf) as usize] = Some(Instant::now());
+        }
+    }
+    #[cfg(feature = "display-ssd1306")]
+    {
+        CURRENT_STATUS.lock().await.last_packet = Some(Instant::now());
+    }
+
+    if let (Packet::HandshakeStart(_), Some(peer)) = (&packet, app.last_peer()) {
+        MEASURE_INTERVAL_SENT.lock().await[(peer.0 & 0xf) as usize] = false;
+        // sensor boards go back to the default spreading factor before each handshake
+        #[cfg(feature = "adr")]
+        ADR.lock().await[(peer.0 & 0xf) as usize].reset(crate::lora::LORA_SPREADING_FACTOR);
+        // and to the beginning of the hopping schedule
+        reset_channel(app);
+    }
+
+    let res = match packet {
         Packet::HandshakeStart(pkt) => match app_on_handshake_start(app, pkt).await {
             Ok(()) => {
                 *phase = AppLayerPhase::Uplink;
+                let data_acks = app.capabilities().contains(Capabilities::LINK_ACKS);
+                app.link_mut().set_data_acks(data_acks);
+                STATS.lock().await.handshakes_completed += 1;
                 Ok(())
             }
             Err(e) => {
@@ -87,135 +250,223 @@ async fn comm_cycle<LINK: LinkLayer>(
             }
         },
         Packet::SensorData(pkt) if *phase == AppLayerPhase::Uplink => {
-            app_on_sensor_data(app, value_sender, pkt).await
+            info!("app: got sensor data");
+            app_on_sensor_data(app, value_sender, ValuesReader::new(pkt)).await
+        }
+        Packet::SensorDataCompressed(pkt) if *phase == AppLayerPhase::Uplink => {
+            info!("app: got compressed sensor data");
+            app_on_sensor_data(app, value_sender, ValuesReader::new_compressed(pkt)).await
+        }
+        Packet::Pong if *phase == AppLayerPhase::Uplink => {
+            info!("app: got pong");
+            Ok(())
+        }
+        pkt => Err(AppLayerError::UnexpectedPacket(pkt.id())),
+    };
+
+    match &res {
+        Err(AppLayerError::Decoding | AppLayerError::TooManyValues(_)) => {
+            STATS.lock().await.decode_errors += 1
         }
-        pkt => Err(GatewayAppLayerError::UnexpectedPacket(pkt.id())),
+        Err(AppLayerError::UnexpectedPacket(_)) => STATS.lock().await.unexpected_packets += 1,
+        _ => (),
     }
+    res
 }
 
 async fn app_on_handshake_start<LINK: LinkLayer>(
-    app: &mut GatewayAppLayer<LINK>,
+    app: &mut AppLayer<LINK>,
     pkt: HandshakeStart,
-) -> Result<(), GatewayAppLayerError<LINK::Error>> {
+) -> Result<(), AppLayerError<LINK::Error>> {
     if pkt.major != PROTOCOL_VERSION_MAJOR {
-        return Err(GatewayAppLayerError::IncompatibleProtocol(
-            pkt.major, pkt.minor,
-        ));
-    }
-    info!("app: got handshake start");
+        warn!(
+            "app: rejecting handshake with incompatible protocol {=u8}.{=u8}",
+            pkt.major, pkt.minor
+        );
+    } else {
+        info!("app: got handshake start");
 
-    // FIXME: artificial delay, remove if LBT is implemented
-    Timer::after(Duration::from_millis(100)).await;
+        // FIXME: artificial delay, remove if LBT is implemented
+        Timer::after(app.timings().handshake_reply_delay).await;
+    }
 
     let epoch = Instant::now();
-    app.emit(&Packet::HandshakeEnd(HandshakeEnd {
-        major: PROTOCOL_VERSION_MAJOR,
-        minor: PROTOCOL_VERSION_MINOR,
-        epoch: epoch.as_millis(),
-    }))
+    session::accept_handshake(
+        app,
+        pkt,
+        PROTOCOL_VERSION_MAJOR,
+        PROTOCOL_VERSION_MINOR,
+        epoch.as_millis(),
+        PROTOCOL_CAPABILITIES,
+    )
     .await?;
-    app.flush().await?;
 
-    info!("Client handshake complete, waiting for sensor data...");
+    info!(
+        "Client handshake complete (capabilities: {}), waiting for sensor data...",
+        app.capabilities()
+    );
 
     Ok(())
 }
 
-async fn app_on_sensor_data<LINK: LinkLayer>(
-    app: &mut GatewayAppLayer<LINK>,
+async fn app_on_sensor_data<PHY: Radio>(
+    app: &mut GatewayAppLayer<PHY>,
     value_sender: &mut ValueSender,
-    pkt: SensorData,
-) -> Result<(), GatewayAppLayerError<LINK::Error>> {
-    info!("app: got sensor data");
-
-    for _ in 0..pkt.count {
-        // Send values to other thread for exporting
-        if let Some(value_point) = value_sender.try_send() {
-            *value_point = app.read::<SensorValuePoint>().await?;
-            value_sender.send_done();
-        } else {
-            let value_point = app.read::<SensorValuePoint>().await?;
-            warn!(
-                "lora: dropping value #{=u32} (at T+{=i64}): queue is full",
-                value_point.value.id(),
-                value_point.time_offset
-            );
-        }
-    }
-    embassy_time::Timer::after(embassy_time::Duration::from_secs(2)).await;
-    info!("Done receiving sensor data, sending ack");
+    mut values: ValuesReader,
+) -> Result<(), AppLayerError<PHY::Error>> {
+    let policy = CONFIG.lock().await.value_overflow_policy;
 
-    app.emit(&Packet::Ack).await?;
-    app.flush().await?;
+    loop {
+        let value_point = match values.next(app).with_timeout(SENSOR_VALUE_TIMEOUT).await {
+            Ok(Ok(Some(value_point))) => value_point,
+            Ok(Ok(None)) => break,
+            Ok(Err(e)) => return Err(e),
+            Err(_) => {
+                warn!("app: sensor data packet ended before all of its values");
+                return Err(AppLayerError::Timeout);
+            }
+        };
+        HISTORY.lock().await.push(value_point);
+        forward_value(value_sender, value_point, policy).await;
+    }
+    app_ack_sensor_data(app).await?;
+    hop_channel(app);
     Ok(())
 }
 
-impl<LINK: LinkLayer> GatewayAppLayer<LINK> {
-    pub fn new(link: LINK) -> Self {
-        Self { link, offset: 0 }
+/// Moves to the next channel once an uplink is answered, if frequency hopping was negotiated.
+///
+/// The radio only listens on one channel at a time, so this is only suitable for a single sensor board.
+fn hop_channel<PHY: Radio>(app: &mut GatewayAppLayer<PHY>) {
+    if !app.capabilities().contains(Capabilities::FREQUENCY_HOPPING) {
+        return;
+    }
+    let phy = app.link_mut().phy_mut();
+    match phy.hop() {
+        Ok(()) => info!("app: hopped to {=u32} Hz", phy.frequency()),
+        Err(e) => error!("app: failed to change channel: {}", Display2Format(&e)),
     }
+}
 
-    pub async fn flush(&mut self) -> Result<(), GatewayAppLayerError<LINK::Error>> {
-        self.link
-            .flush(None)
-            .await
-            .map_err(GatewayAppLayerError::Link)
+/// Resets the radio after repeated errors, instead of failing every cycle.
+async fn reinit_radio_if_stuck<PHY: Radio>(app: &mut GatewayAppLayer<PHY>) {
+    let phy = app.link_mut().phy_mut();
+    if !phy.needs_reinit() {
+        return;
+    }
+    match phy.reinit().await {
+        Ok(()) => info!("app: radio reset"),
+        Err(e) => error!("app: failed to reset the radio: {}", Display2Format(&e)),
+    }
+}
+
+/// Goes back to the default channel, where handshakes happen.
+fn reset_channel<PHY: Radio>(app: &mut GatewayAppLayer<PHY>) {
+    if let Err(e) = app.link_mut().phy_mut().reset_channel() {
+        error!("app: failed to change channel: {}", Display2Format(&e));
     }
 }
 
-impl<LINK: LinkLayer> AsyncEncoder for GatewayAppLayer<LINK> {
-    type Error = GatewayAppLayerError<LINK::Error>;
+/// Sends values to other thread for exporting.
+async fn forward_value(
+    value_sender: &mut ValueSender,
+    value_point: SensorValuePoint,
+    policy: ValueOverflowPolicy,
+) {
+    let slot = match policy {
+        ValueOverflowPolicy::Drop => value_sender.try_send(),
+        ValueOverflowPolicy::Block { timeout_ms } => value_sender
+            .send()
+            .with_timeout(Duration::from_millis(timeout_ms.into()))
+            .await
+            .ok(),
+    };
 
-    async fn emit_bytes(&mut self, mut buf: &[u8]) -> Result<(), Self::Error> {
-        while !buf.is_empty() {
-            let written = self
-                .link
-                .write(None, buf)
-                .await
-                .map_err(GatewayAppLayerError::Link)?;
-            buf = &buf[written..];
-        }
-        Ok(())
+    if let Some(slot) = slot {
+        *slot = value_point;
+        value_sender.send_done();
+    } else {
+        warn!(
+            "lora: dropping value {} (at T+{=i64}): queue is full",
+            value_point.value, value_point.time_offset
+        );
     }
 }
 
-impl<LINK: LinkLayer> AsyncDecoder for GatewayAppLayer<LINK> {
-    type Error = GatewayAppLayerError<LINK::Error>;
+async fn app_ack_sensor_data<PHY: Radio>(
+    app: &mut GatewayAppLayer<PHY>,
+) -> Result<(), AppLayerError<PHY::Error>> {
+    Timer::after(app.timings().ack_delay).await;
 
-    async fn read_bytes(&mut self, buf: &mut [u8]) -> Result<(), Self::Error> {
-        let mut bytes_read = 0usize;
+    #[cfg(feature = "adr")]
+    if let Some(spreading_factor) = recommend_spreading_factor(app).await {
+        info!(
+            "Done receiving sensor data, recommending SF{=u8}",
+            spreading_factor
+        );
+        session::send_command(app, Command::SetSpreadingFactor(spreading_factor)).await?;
+        // the sensor board switches right after receiving the command
+        app.link_mut()
+            .phy_mut()
+            .set_spreading_factor(spreading_factor)
+            .map_err(AppLayerError::Link)?;
+        STATS.lock().await.acks_sent += 1;
+        return Ok(());
+    }
 
-        while bytes_read < buf.len() {
-            let (read, _from) = self
-                .link
-                .read(&mut buf[bytes_read..])
-                .await
-                .map_err(GatewayAppLayerError::Link)?;
-            self.offset += read;
-            bytes_read += read;
+    if let Some(secs) = pending_measure_interval(app).await {
+        info!(
+            "Done receiving sensor data, setting the measurement interval to {=u32}s",
+            secs
+        );
+        session::send_command(app, Command::SetMeasureInterval(secs)).await?;
+        if let Some(peer) = app.last_peer() {
+            MEASURE_INTERVAL_SENT.lock().await[(peer.0 & 0xf) as usize] = true;
         }
-        Ok(())
+        STATS.lock().await.acks_sent += 1;
+        return Ok(());
     }
 
-    fn current_offset(&self) -> usize {
-        self.offset
+    info!("Done receiving sensor data, sending ack");
+
+    session::send_ack(app).await?;
+    STATS.lock().await.acks_sent += 1;
+    Ok(())
+}
+
+/// Time without hearing from a sensor board before dropping its connection, when the keepalive feature is not used.
+///
+/// Sensor boards with nothing new to send stay quiet for a whole measurement interval, so the timeout of the app
+/// layer is extended to the configured interval plus [`UPLINK_TIMEOUT_MARGIN`].
+async fn uplink_timeout<PHY: Radio>(app: &GatewayAppLayer<PHY>) -> Duration {
+    let timeout = app.timings().uplink_timeout;
+    match CONFIG.lock().await.sensor_measure_interval_secs {
+        Some(secs) => timeout.max(Duration::from_secs(secs.into()) + UPLINK_TIMEOUT_MARGIN),
+        None => timeout,
     }
+}
 
-    fn decoding_error(&self) -> Self::Error {
-        GatewayAppLayerError::Decoding
+/// Measurement interval to send to the last sensor board, if configured and not sent since its handshake.
+async fn pending_measure_interval<PHY: Radio>(app: &GatewayAppLayer<PHY>) -> Option<u32> {
+    if !app.capabilities().contains(Capabilities::DOWNLINK_COMMANDS) {
+        return None;
+    }
+    let peer = app.last_peer()?;
+    if MEASURE_INTERVAL_SENT.lock().await[(peer.0 & 0xf) as usize] {
+        return None;
     }
+    CONFIG.lock().await.sensor_measure_interval_secs
 }
 
-impl<LINK: core::error::Error> Display for GatewayAppLayerError<LINK> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
-        match &self {
-            GatewayAppLayerError::Decoding => f.write_str("decoding error"),
-            GatewayAppLayerError::Link(err) => write!(f, "{}", err),
-            GatewayAppLayerError::UnexpectedPacket(id) => write!(f, "unexpected packet: {}", id),
-            GatewayAppLayerError::IncompatibleProtocol(major, minor) => {
-                write!(f, "incompatible protocol: {}.{}", major, minor)
-            }
-            GatewayAppLayerError::Timeout => f.write_str("timeout exceeded"),
-        }
+/// Feeds the SNR of the last packet to the policy of its sender, returns the spreading factor it should switch to.
+///
+/// The radio only listens with one spreading factor at a time, so this is only suitable for a single sensor board.
+#[cfg(feature = "adr")]
+async fn recommend_spreading_factor<PHY: Radio>(app: &GatewayAppLayer<PHY>) -> Option<u8> {
+    if !app.capabilities().contains(Capabilities::DOWNLINK_COMMANDS) {
+        return None;
     }
+    let peer = app.last_peer()?;
+    let snr = app.link().phy().rx_quality()?.snr;
+    ADR.lock().await[(peer.0 & 0xf) as usize].update(snr)
 }
@@ -1,29 +1,53 @@
-use defmt::{info, trace, warn};
+use defmt::{error, info, trace, warn};
+use embassy_time::Instant;
+use esp_hal::rng::Rng;
 use protocol::{
-    link::v1::{LinkLayer, LinkPacket, LinkPhase, SensorBoardId},
+    link::v1::{
+        handshake_reply_payload, LinkKeys, LinkLayer, LinkPacket, LinkPhase, LinkTxBuffer,
+        SensorBoardId, LINK_ACK_PAYLOAD_LEN, LINK_ACK_TIMEOUT, LINK_FINGERPRINT_LEN,
+        LINK_HEADER_LEN, LINK_NONCE_LEN,
+    },
     phy::PhysicalLayer,
 };
 
-use crate::lora::LORA_RX_BUF_SIZE;
+use crate::{
+    comm::app::STATS,
+    config::{CONFIG, SENSOR_ID_COUNT},
+    lora::LORA_RX_BUF_SIZE,
+};
 
 pub struct GatewayLinkLayer<PHY> {
     phase: LinkPhase,
     curr_sensor_id: SensorBoardId,
     phy: PHY,
-    tx_buf: heapless::Vec<u8, 64>,
+    /// Session keys of `curr_sensor_id`, a new nonce is picked for each handshake
+    keys: LinkKeys<'static>,
+    rng: Rng,
+    tx_buf: LinkTxBuffer<64>,
+    /// Destination of the data in `tx_buf`
+    tx_dest: Option<SensorBoardId>,
     payload_start: usize,
     payload_end: usize,
+    /// Whether data packets are acknowledged, see [`Self::set_data_acks`]
+    data_acks: bool,
+    /// Ack payload of the last data packet received and when, to drop the copies sent again when an ack is lost
+    last_data: Option<([u8; LINK_ACK_PAYLOAD_LEN], Instant)>,
 }
 
 impl<PHY: PhysicalLayer> GatewayLinkLayer<PHY> {
-    pub fn new(phy: PHY) -> Self {
+    pub fn new(phy: PHY, rng: Rng) -> Self {
         Self {
             phase: LinkPhase::Handshake,
             curr_sensor_id: SensorBoardId(15),
             phy,
-            tx_buf: heapless::Vec::new(),
+            keys: LinkKeys::new(b"SECRET"),
+            rng,
+            tx_buf: LinkTxBuffer::new(),
+            tx_dest: None,
             payload_start: 0,
             payload_end: 0,
+            data_acks: false,
+            last_data: None,
         }
     }
 
@@ -32,7 +56,24 @@ impl<PHY: PhysicalLayer> GatewayLinkLayer<PHY> {
         let payload =
             heapless::Vec::<u8, LORA_RX_BUF_SIZE>::from_slice(LinkPacket::get_payload(&self.phy))
                 .unwrap();
-        self.curr_sensor_id = SensorBoardId((self.curr_sensor_id.0.wrapping_add(1)) & 0x0F);
+        let Ok(mac) = <[u8; LINK_FINGERPRINT_LEN]>::try_from(payload.as_slice()) else {
+            error!(
+                "link: ignoring handshake with invalid MAC {=[u8]:02x}",
+                &payload
+            );
+            return Ok(());
+        };
+        self.curr_sensor_id = self.stable_sensor_id(mac).await;
+
+        // frames of the previous session, recorded or not, are no longer accepted
+        let mut nonce = [0u8; LINK_NONCE_LEN];
+        for chunk in nonce.chunks_mut(4) {
+            chunk.copy_from_slice(&self.rng.random().to_le_bytes());
+        }
+        self.keys.start_session(&nonce);
+        // until the app layer negotiates them again
+        self.data_acks = false;
+        self.last_data = None;
 
         // FIXME: artificial delay, remove if LBT is implemented
         embassy_time::Timer::after(embassy_time::Duration::from_millis(100)).await;
@@ -43,16 +84,94 @@ impl<PHY: PhysicalLayer> GatewayLinkLayer<PHY> {
         LinkPacket {
             phase: LinkPhase::Handshake,
             id: self.curr_sensor_id.0,
-            payload: &payload,
+            payload: &handshake_reply_payload(&mac, &nonce),
         }
-        .write(&mut self.phy, b"SECRET")
+        .write(&mut self.phy, &self.keys)
         .await
     }
 
+    /// Acknowledges each data packet and drops the copies sent again, once the link-acks feature is negotiated.
+    ///
+    /// Disabled by default and on each reset, sensor boards that don't ask for acks never wait for them.
+    pub fn set_data_acks(&mut self, enabled: bool) {
+        self.data_acks = enabled;
+        self.last_data = None;
+    }
+
+    /// Gives access to the underlying physical layer, e.g. to get the signal quality of the last packet.
+    pub fn phy(&self) -> &PHY {
+        &self.phy
+    }
+
+    pub fn phy_mut(&mut self) -> &mut PHY {
+        &mut self.phy
+    }
+
+    /// Largest payload of a link packet allowed by the physical layer, `tx_buf` limits it further.
+    fn max_payload_len(&self) -> usize {
+        self.phy.max_payload_len().saturating_sub(LINK_HEADER_LEN)
+    }
+
+    /// Sends `payload` as a data frame to the sensor board `dest`.
+    async fn send_data(
+        phy: &mut PHY,
+        keys: &LinkKeys<'static>,
+        dest: u8,
+        payload: &[u8],
+    ) -> Result<(), PHY::Error> {
+        info!("link: flushing to sensor board {=u8}", dest);
+        LinkPacket {
+            phase: LinkPhase::Data,
+            id: dest,
+            payload,
+        }
+        .write(&mut *phy, keys)
+        .await?;
+        phy.flush().await
+    }
+
+    /// Returns the ID assigned to the sensor board with the MAC address `mac`, or assigns it one.
+    ///
+    /// IDs are kept in flash so that sensor boards get the same one after a reboot of the gateway.
+    /// When every ID is taken, the one of the sensor board heard from the longest ago is reassigned,
+    /// starting with the ones not heard from since the gateway booted.
+    async fn stable_sensor_id(&mut self, mac: [u8; 6]) -> SensorBoardId {
+        let last_seen = STATS.lock().await.last_seen;
+        let mut config = CONFIG.lock().await;
+
+        if let Some(id) = config.sensor_macs.iter().position(|m| *m == Some(mac)) {
+            return SensorBoardId(id as u8);
+        }
+
+        let id = match config.sensor_macs.iter().position(Option::is_none) {
+            Some(id) => id as u8,
+            None => {
+                // `None` sorts first, the first of the equally old ones is kept
+                let id = (0..SENSOR_ID_COUNT)
+                    .min_by_key(|&id| last_seen[id])
+                    .unwrap_or_default() as u8;
+                warn!(
+                    "link: all sensor board IDs are taken, reassigning {=u8}",
+                    id
+                );
+                id
+            }
+        };
+        info!(
+            "link: assigning id {=u8} to new sensor board {=[u8]:02x}",
+            id, mac
+        );
+        config.sensor_macs[id as usize] = Some(mac);
+        config.save_to_flash();
+        // keeps the ID from being reassigned again before the sensor board sends its first packet
+        STATS.lock().await.last_seen[id as usize] = Some(Instant::now());
+        SensorBoardId(id)
+    }
+
     /// Requests the next payload from the PHY, clearing the rx buffer.
     async fn read_payload(&mut self) -> Result<(), PHY::Error> {
         loop {
-            let (res_phase, res_id) = LinkPacket::read(&mut self.phy, b"SECRET").await?;
+            let (res_phase, res_id) = LinkPacket::read(&mut self.phy, &self.keys).await?;
 
             if res_phase == LinkPhase::Handshake {
                 info!("link: GatewayLinkLayer::read_payload(), inbound handshake");
@@ -75,9 +194,46 @@ impl<PHY: PhysicalLayer> GatewayLinkLayer<PHY> {
                 continue;
             }
 
+            if !self.data_acks {
+                break Ok(());
+            }
+
+            let ack = LinkPacket::received_ack_payload(&self.phy);
+            // copies are sent at most `LINK_ACK_TIMEOUT` apart, plus the time to send them
+            let duplicate = self.last_data.is_some_and(|(last_ack, received)| {
+                last_ack == ack && received.elapsed() < LINK_ACK_TIMEOUT * 2
+            });
+            self.last_data = Some((ack, Instant::now()));
+            // copies are acknowledged too, the first ack was probably lost
+            self.send_ack(res_id, ack).await?;
+            if duplicate {
+                info!("link: dropping copy of the last data packet");
+                continue;
+            }
+
             break Ok(());
         }
     }
+
+    /// Acknowledges a data packet, sensor boards may wait for it before sending the next one.
+    ///
+    /// Sending doesn't touch the rx buffer, the payload of the data packet is kept.
+    async fn send_ack(
+        &mut self,
+        id: u8,
+        ack: [u8; LINK_ACK_PAYLOAD_LEN],
+    ) -> Result<(), PHY::Error> {
+        // FIXME: artificial delay, remove if LBT is implemented
+        embassy_time::Timer::after(embassy_time::Duration::from_millis(100)).await;
+        trace!("link: acknowledging data packet of sensor board {=u8}", id);
+        LinkPacket {
+            phase: LinkPhase::Ack,
+            id,
+            payload: &ack,
+        }
+        .write(&mut self.phy, &self.keys)
+        .await
+    }
 }
 
 impl<PHY: PhysicalLayer> LinkLayer for GatewayLinkLayer<PHY> {
@@ -109,39 +265,35 @@ impl<PHY: PhysicalLayer> LinkLayer for GatewayLinkLayer<PHY> {
         dest: Option<Self::PeerId>,
         buf: &[u8],
     ) -> Result<usize, Self::Error> {
-        let mut bytes_sent = 0usize;
-
-        for chunk in buf.chunks(self.tx_buf.capacity()) {
-            if let Err(()) = self.tx_buf.extend_from_slice(chunk) {
-                self.flush(dest).await?;
-                // SAFETY:
-                // - size of `chunk` is lower or equal to tx_buf's capacity
-                // - `buf` and `chunk` cannot overlap, tx_buf is private to this struct
-                unsafe {
-                    self.tx_buf
-                        .as_mut_ptr()
-                        .copy_from_nonoverlapping(chunk.as_ptr(), chunk.len());
-                    self.tx_buf.set_len(chunk.len());
-                }
-            }
-            bytes_sent += chunk.len();
+        if !self.tx_buf.is_empty() && self.tx_dest != dest {
+            // don't mix data addressed to different sensor boards in the same packet
+            self.flush(self.tx_dest).await?;
         }
-        Ok(bytes_sent)
+
+        let max_len = self.max_payload_len();
+        self.tx_dest = dest;
+        // fall back to the last sensor board that completed a handshake
+        let id = dest.unwrap_or(self.curr_sensor_id).0;
+        let (phy, keys) = (&mut self.phy, &self.keys);
+        self.tx_buf
+            .write(buf, max_len, async |frame| {
+                Self::send_data(phy, keys, id, frame).await
+            })
+            .await?;
+        Ok(buf.len())
     }
 
-    async fn flush(&mut self, _dest: Option<Self::PeerId>) -> Result<(), Self::Error> {
-        // FIXME: "hardcoded" destination, should be set by the app layer
-        let dest = self.curr_sensor_id.0;
-        info!("link: flushing");
-        LinkPacket {
-            phase: LinkPhase::Data,
-            id: dest,
-            payload: &self.tx_buf,
+    async fn flush(&mut self, dest: Option<Self::PeerId>) -> Result<(), Self::Error> {
+        if self.tx_buf.is_empty() {
+            // already sent by `write()` once full, empty frames are invalid
+            return Ok(());
         }
-        .write(&mut self.phy, b"SECRET")
-        .await?;
+        // fall back to the last sensor board that completed a handshake
+        let dest = dest.unwrap_or(self.curr_sensor_id).0;
+        Self::send_data(&mut self.phy, &self.keys, dest, self.tx_buf.as_slice()).await?;
         self.tx_buf.clear();
-        self.phy.flush().await
+        self.tx_dest = None;
+        Ok(())
     }
 
     fn reset(&mut self) {
@@ -151,5 +303,9 @@ impl<PHY: PhysicalLayer> LinkLayer for GatewayLinkLayer<PHY> {
         self.payload_start = 0;
         self.payload_end = 0;
         self.tx_buf.clear();
+        self.tx_dest = None;
+        self.data_acks = false;
+        self.last_data = None;
+        self.keys.end_session();
     }
 }
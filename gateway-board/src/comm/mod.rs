@@ -1,2 +1,93 @@
+use embassy_time::Duration;
+use protocol::phy::PhysicalLayer;
+
+#[cfg(feature = "adr")]
+use crate::lora::RxQuality;
+use crate::lora::{LoraController, LoraError};
+
 pub mod app;
 pub mod link;
+
+/// Radio settings changed by the app layer, on top of sending and receiving packets.
+///
+/// Physical layers without a radio, like [`util::uart_phy::UartPhy`], keep the defaults, which do nothing.
+pub trait Radio: PhysicalLayer {
+    /// Changes the receive timeout of `PhysicalLayer::read()`, `None` to wait until a packet is received.
+    fn set_rx_timeout(&mut self, _timeout: Option<Duration>) {}
+
+    /// Current channel, in Hz.
+    fn frequency(&self) -> u32 {
+        0
+    }
+
+    /// Moves to the next channel of the hopping schedule.
+    fn hop(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Goes back to the default channel and to the beginning of the hopping schedule.
+    fn reset_channel(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Whether repeated errors may have left the radio stuck, in which case it should go through [`Self::reinit`].
+    fn needs_reinit(&self) -> bool {
+        false
+    }
+
+    /// Resets the radio and applies the current settings again.
+    async fn reinit(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Changes the spreading factor, applied from the next transmission or reception onwards.
+    #[cfg(feature = "adr")]
+    fn set_spreading_factor(&mut self, _spreading_factor: u8) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Signal quality of the last received packet, `None` if unknown.
+    #[cfg(feature = "adr")]
+    fn rx_quality(&self) -> Option<RxQuality> {
+        None
+    }
+}
+
+impl Radio for LoraController {
+    fn set_rx_timeout(&mut self, timeout: Option<Duration>) {
+        LoraController::set_rx_timeout(self, timeout);
+    }
+
+    fn frequency(&self) -> u32 {
+        LoraController::frequency(self)
+    }
+
+    fn hop(&mut self) -> Result<(), LoraError> {
+        LoraController::hop(self)
+    }
+
+    fn reset_channel(&mut self) -> Result<(), LoraError> {
+        LoraController::reset_channel(self)
+    }
+
+    fn needs_reinit(&self) -> bool {
+        LoraController::needs_reinit(self)
+    }
+
+    async fn reinit(&mut self) -> Result<(), LoraError> {
+        LoraController::reinit(self).await
+    }
+
+    #[cfg(feature = "adr")]
+    fn set_spreading_factor(&mut self, spreading_factor: u8) -> Result<(), LoraError> {
+        LoraController::set_spreading_factor(self, spreading_factor)
+    }
+
+    #[cfg(feature = "adr")]
+    fn rx_quality(&self) -> Option<RxQuality> {
+        LoraController::rx_quality(self)
+    }
+}
+
+#[cfg(feature = "uart-phy")]
+impl<U: embedded_io_async::Read + embedded_io_async::Write> Radio for util::uart_phy::UartPhy<U> {}
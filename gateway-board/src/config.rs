@@ -1,21 +1,29 @@
 use core::fmt::Write;
 use core::mem::MaybeUninit;
 use core::{net::Ipv4Addr, str::FromStr};
-use defmt::{error, info, warn, Debug2Format};
+use defmt::{error, info, warn, Debug2Format, Format};
 use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
 use embedded_storage::{ReadStorage, Storage};
-use esp_hal::rng::Rng;
+use esp_hal::{efuse::Efuse, rng::Rng};
 use esp_storage::FlashStorage;
 use sha2::{Digest, Sha256};
+use util::auth::{constant_time_eq, hash_password};
+use util::export::{ExportRoutes, Exporter};
+use util::form::{parse_bounded_string, parse_dns_server, parse_port, parse_wpa2_passphrase};
 
-const CURRENT_CONFIG_VERSION: u8 = 3;
+const CURRENT_CONFIG_VERSION: u8 = 12;
 /// Start of the non-volatile storage (NVS) partition
 const NVS_PARTITION_OFFSET: u32 = 0x9000;
+/// Lengths of the WPA2 passphrases accepted for the access point
+/// Number of sensor board IDs available on the link layer
+pub const SENSOR_ID_COUNT: usize = 16;
 
 pub struct EnvVariables {
     pub wifi_sta_ssid: Option<&'static str>,
     pub wifi_sta_pass: Option<&'static str>,
     pub wifi_ap_ssid: Option<&'static str>,
+    pub wifi_ap_pass: Option<&'static str>,
+    pub wifi_ap_shutdown_delay: Option<&'static str>,
     pub dns_server_1: Option<&'static str>,
     pub dns_server_2: Option<&'static str>,
     pub influx_db_host: Option<&'static str>,
@@ -23,6 +31,25 @@ pub struct EnvVariables {
     pub influx_db_api_token: Option<&'static str>,
     pub influx_db_org: Option<&'static str>,
     pub influx_db_bucket: Option<&'static str>,
+    pub influx_db_gateway_id: Option<&'static str>,
+    pub influx_db_location: Option<&'static str>,
+    pub webhook_host: Option<&'static str>,
+    pub webhook_port: Option<&'static str>,
+    pub webhook_path: Option<&'static str>,
+    pub webhook_token: Option<&'static str>,
+    pub lora_tx_power: Option<&'static str>,
+    pub lora_sync_word: Option<&'static str>,
+    pub watchdog_timeout: Option<&'static str>,
+    pub sensor_measure_interval: Option<&'static str>,
+    pub value_overflow_policy: Option<&'static str>,
+    pub value_overflow_timeout: Option<&'static str>,
+    pub export_routes: Option<&'static str>,
+    pub sensor_community_id: Option<&'static str>,
+    pub dashboard_user: Option<&'static str>,
+    /// Salt and hash of `DASHBOARD_PASSWORD`, computed by the build script
+    pub dashboard_password_hash: Option<([u8; 16], [u8; 32])>,
+    pub http_port: Option<&'static str>,
+    pub http_interfaces: Option<&'static str>,
 }
 
 #[derive(Clone)]
@@ -37,6 +64,125 @@ pub struct InfluxDBConfig {
     pub bucket: &'static str,
     /// API token for authentication with InfluxDB
     pub api_token: &'static str,
+    /// Value of the `gateway_id` tag of every point, to tell gateways apart in a shared bucket
+    pub gateway_id: Option<&'static str>,
+    /// Value of the `location` tag of every point
+    pub location: Option<&'static str>,
+    /// Whether values are exported to InfluxDB when a host is configured. Defaults to true.
+    pub enabled: bool,
+}
+
+#[derive(Clone)]
+pub struct WebhookConfig {
+    /// Host of the webhook endpoint
+    pub host: Option<heapless::String<64>>,
+    /// Port of the webhook endpoint. Defaults to 80 if not specified.
+    pub port: u16,
+    /// Path of the webhook endpoint. Defaults to `/` if not specified.
+    pub path: &'static str,
+    /// Bearer token sent in the `Authorization` header (optional)
+    pub token: Option<&'static str>,
+    /// Whether values are posted to the webhook when a host is configured. Defaults to true.
+    pub enabled: bool,
+}
+
+/// What to do with a received sensor value when the export channel is full.
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+pub enum ValueOverflowPolicy {
+    /// Drop the value right away
+    Drop,
+    /// Wait up to `timeout_ms` milliseconds for the exporter to make room, then drop the value.
+    /// The ack is delayed meanwhile, so this should stay well below the sensor board's ack timeout.
+    Block { timeout_ms: u32 },
+}
+
+/// LoRa sync word, radios only receive packets sent with the same one as theirs.
+///
+/// The radio driver only lets the gateway pick between the private and the public (LoRaWAN) sync words.
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+pub enum LoraSyncWord {
+    /// `0x12`, used by default
+    Private,
+    /// `0x34`, shared with LoRaWAN networks
+    Public,
+}
+
+impl LoraSyncWord {
+    /// Name of the sync word, as accepted by [`LoraSyncWord::from_name`]
+    pub const fn name(self) -> &'static str {
+        match self {
+            LoraSyncWord::Private => "private",
+            LoraSyncWord::Public => "public",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "private" => Some(LoraSyncWord::Private),
+            "public" => Some(LoraSyncWord::Public),
+            _ => None,
+        }
+    }
+}
+
+/// Wi-Fi interfaces the configuration dashboard is served on.
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+pub enum HttpInterfaces {
+    /// Both the gateway's access point and the external network, used by default
+    Both,
+    /// Only the gateway's access point
+    Ap,
+    /// Only the external network, the access point is still used if the gateway is not connected to it
+    Sta,
+}
+
+impl HttpInterfaces {
+    /// Name of the interfaces, as accepted by [`HttpInterfaces::from_name`]
+    pub const fn name(self) -> &'static str {
+        match self {
+            HttpInterfaces::Both => "both",
+            HttpInterfaces::Ap => "ap",
+            HttpInterfaces::Sta => "sta",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "both" => Some(HttpInterfaces::Both),
+            "ap" => Some(HttpInterfaces::Ap),
+            "sta" => Some(HttpInterfaces::Sta),
+            _ => None,
+        }
+    }
+}
+
+/// Credentials required to access the configuration dashboard.
+///
+/// Only a salted hash of the password is kept, in memory as well as in flash.
+#[derive(Clone)]
+pub struct DashboardAuth {
+    pub username: heapless::String<32>,
+    salt: [u8; 16],
+    password_hash: [u8; 32],
+}
+
+impl DashboardAuth {
+    pub fn new(username: heapless::String<32>, salt: [u8; 16], password_hash: [u8; 32]) -> Self {
+        Self {
+            username,
+            salt,
+            password_hash,
+        }
+    }
+
+    /// Checks the given credentials, in constant time for a given username and password length.
+    pub fn verify(&self, username: &[u8], password: &[u8]) -> bool {
+        let hash = hash_password(&self.salt, password);
+        // evaluate both, so that a wrong username takes as long as a wrong password
+        let username_ok = constant_time_eq(self.username.as_bytes(), username);
+        let password_ok = constant_time_eq(&self.password_hash, &hash);
+        username_ok & password_ok
+    }
 }
 
 pub struct Config {
@@ -46,14 +192,50 @@ pub struct Config {
     pub wifi_sta_pass: Option<heapless::String<64>>,
     /// Name of the Wi-Fi access point (AP) to create for the configuration dashboard
     pub wifi_ap_ssid: heapless::String<32>,
+    /// WPA2 passphrase of the access point, applied on the next boot. The access point is open if not specified.
+    pub wifi_ap_pass: Option<heapless::String<64>>,
+    /// Time in seconds the access point keeps running once the station is connected, with the dashboard then only
+    /// reachable from the external network. The access point always runs if not specified.
+    pub wifi_ap_shutdown_delay_secs: Option<u32>,
     /// Primary DNS server
     pub dns_server_1: Ipv4Addr,
     /// Secondary DNS server
     pub dns_server_2: Ipv4Addr,
     /// InfluxDB configuration
     pub influx_db: InfluxDBConfig,
+    /// Generic webhook configuration
+    pub webhook: WebhookConfig,
+    /// LoRa transmit power in dBm, applied on the next boot. Uses the radio's default if not specified.
+    pub lora_tx_power: Option<i8>,
+    /// LoRa sync word, applied on the next boot. Must be the one of the sensor boards, defaults to the private one.
+    pub lora_sync_word: LoraSyncWord,
+    /// Time in seconds after which a task that stopped reporting progress causes a reboot. Defaults to 120.
+    pub watchdog_timeout_secs: u32,
+    /// Time in seconds between two measurements of the sensor boards, sent to them after each handshake.
+    /// Sensor boards keep their own if not specified.
+    pub sensor_measure_interval_secs: Option<u32>,
+    /// Handling of sensor values received while the export channel is full. Defaults to dropping them.
+    pub value_overflow_policy: ValueOverflowPolicy,
+    /// Exporters receiving each type of sensor value. Defaults to all of them for every type.
+    pub export_routes: ExportRoutes,
+    /// Sensor ID sent to sensor.community in the `X-Sensor` header. Defaults to one derived from the MAC address.
+    pub sensor_community_id: heapless::String<32>,
+    /// Whether values are pushed to sensor.community. Defaults to true.
+    pub sensor_community_enabled: bool,
     /// CSRF token for the configuration dashboard
     pub csrf_token: heapless::String<32>,
+    /// HTTP Basic authentication of the configuration dashboard. Anyone on the network can access it if not specified.
+    pub dashboard_auth: Option<DashboardAuth>,
+    /// TCP port of the configuration dashboard, applied on the next boot. Never 0, defaults to 80.
+    pub http_port: u16,
+    /// Interfaces the configuration dashboard is served on, applied on the next boot. Defaults to both.
+    pub http_interfaces: HttpInterfaces,
+    /// MAC addresses of the known sensor boards, indexed by the ID assigned to them
+    pub sensor_macs: [Option<[u8; 6]>; SENSOR_ID_COUNT],
+    /// Number of times the gateway booted since the configuration was first stored, including this one
+    pub boot_count: u32,
+    /// Reason of the reset that started this boot, e.g. `ChipPowerOn` or a watchdog
+    pub reset_reason: heapless::String<24>,
 }
 
 impl Config {
@@ -62,6 +244,8 @@ impl Config {
             wifi_sta_ssid: None,
             wifi_sta_pass: None,
             wifi_ap_ssid: heapless::String::new(),
+            wifi_ap_pass: None,
+            wifi_ap_shutdown_delay_secs: None,
             dns_server_1: Ipv4Addr::new(0, 0, 0, 0),
             dns_server_2: Ipv4Addr::new(0, 0, 0, 0),
             influx_db: InfluxDBConfig {
@@ -70,8 +254,32 @@ impl Config {
                 org: "",
                 bucket: "",
                 api_token: "",
+                gateway_id: None,
+                location: None,
+                enabled: true,
+            },
+            webhook: WebhookConfig {
+                host: None,
+                port: 80,
+                path: "/",
+                token: None,
+                enabled: true,
             },
+            lora_tx_power: None,
+            lora_sync_word: LoraSyncWord::Private,
+            watchdog_timeout_secs: 120,
+            sensor_measure_interval_secs: None,
+            value_overflow_policy: ValueOverflowPolicy::Drop,
+            export_routes: ExportRoutes::ALL,
+            sensor_community_id: heapless::String::new(),
+            sensor_community_enabled: true,
             csrf_token: heapless::String::new(),
+            dashboard_auth: None,
+            http_port: 80,
+            http_interfaces: HttpInterfaces::Both,
+            sensor_macs: [None; SENSOR_ID_COUNT],
+            boot_count: 0,
+            reset_reason: heapless::String::new(),
         }
     }
 
@@ -80,6 +288,7 @@ impl Config {
         let config: &mut Config = &mut guard;
         config.load_from_env(rng);
         config.load_from_flash();
+        config.record_boot();
         config.save_to_flash();
 
         if let Some(influx_db_host) = &config.influx_db.host {
@@ -93,6 +302,59 @@ impl Config {
         } else {
             warn!("config: InfluxDB is not configured");
         }
+
+        if let Some(webhook_host) = &config.webhook.host {
+            info!(
+                "config: webhook '{}:{}{}'",
+                webhook_host, config.webhook.port, config.webhook.path
+            );
+        } else {
+            info!("config: webhook is not configured");
+        }
+
+        info!(
+            "config: value overflow policy: {}",
+            config.value_overflow_policy
+        );
+        info!(
+            "config: sensor.community sensor ID '{}'",
+            config.sensor_community_id
+        );
+        if config.wifi_ap_pass.is_none() {
+            warn!("config: the access point is open");
+        }
+        if let Some(auth) = &config.dashboard_auth {
+            info!("config: dashboard protected, user '{}'", auth.username);
+        } else {
+            warn!("config: dashboard is not password-protected");
+        }
+    }
+
+    /// Counts this boot and records its reset reason, replacing the one of the previous boot.
+    fn record_boot(&mut self) {
+        let previous: heapless::String<24> = core::mem::take(&mut self.reset_reason);
+        self.boot_count = self.boot_count.wrapping_add(1);
+        if let Some(reason) = esp_hal::system::reset_reason() {
+            write!(self.reset_reason, "{:?}", reason).ok();
+        } else {
+            self.reset_reason.push_str("Unknown").ok();
+        }
+        info!(
+            "config: boot #{=u32}, reset reason: {} (previous: {})",
+            self.boot_count, self.reset_reason, previous
+        );
+    }
+
+    /// Sensor ID used on sensor.community when none is configured, `esp32-<chip ID>` like the airrohr firmware.
+    ///
+    /// The chip ID is made of the last three bytes of the factory MAC address.
+    pub fn default_sensor_community_id() -> heapless::String<32> {
+        let mac = Efuse::read_base_mac_address();
+        let chip_id = u32::from_be_bytes([0, mac[3], mac[4], mac[5]]);
+        let mut id = heapless::String::new();
+        // at most 14 characters
+        write!(id, "esp32-{}", chip_id).unwrap();
+        id
     }
 
     pub fn load_from_env(&mut self, mut rng: Rng) -> &mut Self {
@@ -122,15 +384,28 @@ impl Config {
             warn!("WIFI_AP_SSID is too long, using default 'lora-gateway-wifi'");
             heapless::String::<32>::from_str("lora-gateway-wifi").unwrap()
         });
+        self.wifi_ap_pass = ENVIRONMENT_VARIABLES
+            .wifi_ap_pass
+            .filter(|pass| !pass.is_empty())
+            .and_then(|pass| {
+                parse_wpa2_passphrase(pass).map(Some).unwrap_or_else(|| {
+                    error!("WIFI_AP_PASS must be 8 to 63 printable ASCII characters, the access point is open");
+                    None
+                })
+            });
+
+        self.wifi_ap_shutdown_delay_secs = ENVIRONMENT_VARIABLES
+            .wifi_ap_shutdown_delay
+            .and_then(|t| t.parse().ok());
 
         self.dns_server_1 = ENVIRONMENT_VARIABLES
             .dns_server_1
-            .and_then(|s| s.parse().ok())
+            .and_then(Self::dns_server)
             .unwrap_or_else(|| Ipv4Addr::new(1, 1, 1, 1)); // Cloudflare DNS (main)
 
         self.dns_server_2 = ENVIRONMENT_VARIABLES
             .dns_server_2
-            .and_then(|s| s.parse().ok())
+            .and_then(Self::dns_server)
             .unwrap_or_else(|| Ipv4Addr::new(1, 0, 0, 1)); // Cloudflare DNS (backup)
 
         // Randomize the CSRF token for security purposes
@@ -158,6 +433,135 @@ impl Config {
             api_token: ENVIRONMENT_VARIABLES
                 .influx_db_api_token
                 .unwrap_or("my_token"),
+            // InfluxDB rejects tags without a value
+            gateway_id: ENVIRONMENT_VARIABLES
+                .influx_db_gateway_id
+                .filter(|id| !id.is_empty()),
+            location: ENVIRONMENT_VARIABLES
+                .influx_db_location
+                .filter(|location| !location.is_empty()),
+            enabled: true,
+        };
+
+        self.webhook = WebhookConfig {
+            host: ENVIRONMENT_VARIABLES.webhook_host.and_then(|s| {
+                heapless::String::<64>::from_str(s)
+                    .map(Some)
+                    .unwrap_or_else(|_| {
+                        warn!("WEBHOOK_HOST is too long, using default None");
+                        None
+                    })
+            }),
+            port: ENVIRONMENT_VARIABLES
+                .webhook_port
+                .and_then(|p| p.parse().ok())
+                .unwrap_or(80),
+            path: ENVIRONMENT_VARIABLES.webhook_path.unwrap_or("/"),
+            token: ENVIRONMENT_VARIABLES.webhook_token,
+            enabled: true,
+        };
+
+        self.lora_tx_power = ENVIRONMENT_VARIABLES
+            .lora_tx_power
+            .and_then(|p| p.parse().ok());
+
+        self.lora_sync_word = match ENVIRONMENT_VARIABLES.lora_sync_word {
+            None => LoraSyncWord::Private,
+            Some(name) => LoraSyncWord::from_name(name).unwrap_or_else(|| {
+                warn!(
+                    "LORA_SYNC_WORD '{}' is invalid (expected 'private' or 'public'), using the private one",
+                    name
+                );
+                LoraSyncWord::Private
+            }),
+        };
+
+        self.watchdog_timeout_secs = ENVIRONMENT_VARIABLES
+            .watchdog_timeout
+            .and_then(|t| t.parse().ok())
+            .unwrap_or(120);
+
+        self.sensor_measure_interval_secs = ENVIRONMENT_VARIABLES
+            .sensor_measure_interval
+            .and_then(|t| t.parse().ok())
+            .filter(|&secs| secs > 0);
+
+        let overflow_timeout_ms = ENVIRONMENT_VARIABLES
+            .value_overflow_timeout
+            .and_then(|t| t.parse().ok())
+            .unwrap_or(1000);
+        self.value_overflow_policy = match ENVIRONMENT_VARIABLES.value_overflow_policy {
+            None | Some("drop") => ValueOverflowPolicy::Drop,
+            Some("block") => ValueOverflowPolicy::Block {
+                timeout_ms: overflow_timeout_ms,
+            },
+            Some(other) => {
+                warn!(
+                    "VALUE_OVERFLOW_POLICY '{}' is invalid (expected 'drop' or 'block'), dropping values",
+                    other
+                );
+                ValueOverflowPolicy::Drop
+            }
+        };
+
+        if let Some(routes) = ENVIRONMENT_VARIABLES.export_routes {
+            self.export_routes = ExportRoutes::parse(routes).unwrap_or_else(|e| {
+                warn!(
+                    "EXPORT_ROUTES '{}' is invalid ({}), exporting all values everywhere",
+                    routes,
+                    Debug2Format(&e)
+                );
+                ExportRoutes::ALL
+            });
+        }
+
+        self.sensor_community_id = match ENVIRONMENT_VARIABLES.sensor_community_id {
+            None => Self::default_sensor_community_id(),
+            Some(id) => heapless::String::<32>::from_str(id).unwrap_or_else(|_| {
+                warn!("SENSOR_COMMUNITY_ID is too long, deriving it from the MAC address");
+                Self::default_sensor_community_id()
+            }),
+        };
+
+        self.dashboard_auth = match (
+            ENVIRONMENT_VARIABLES.dashboard_user,
+            ENVIRONMENT_VARIABLES.dashboard_password_hash,
+        ) {
+            (Some(user), Some((salt, hash))) => match heapless::String::<32>::from_str(user) {
+                Ok(username) => Some(DashboardAuth::new(username, salt, hash)),
+                Err(_) => {
+                    error!("DASHBOARD_USER is too long, the dashboard is not password-protected");
+                    None
+                }
+            },
+            (None, None) => None,
+            _ => {
+                error!("DASHBOARD_USER and DASHBOARD_PASSWORD must be set together, the dashboard is not password-protected");
+                None
+            }
+        };
+
+        self.http_port = match ENVIRONMENT_VARIABLES.http_port {
+            None => 80,
+            Some(port) => port
+                .parse()
+                .ok()
+                .filter(|&p: &u16| p != 0)
+                .unwrap_or_else(|| {
+                    warn!("HTTP_PORT '{}' is invalid, using port 80", port);
+                    80
+                }),
+        };
+
+        self.http_interfaces = match ENVIRONMENT_VARIABLES.http_interfaces {
+            None => HttpInterfaces::Both,
+            Some(name) => HttpInterfaces::from_name(name).unwrap_or_else(|| {
+                warn!(
+                    "HTTP_INTERFACES '{}' is invalid (expected 'both', 'ap' or 'sta'), using both",
+                    name
+                );
+                HttpInterfaces::Both
+            }),
         };
 
         info!("config: loaded from environment variables");
@@ -180,6 +584,19 @@ impl Config {
                 dns_server_2: self.dns_server_2.into(),
                 influx_db_host: self.influx_db.host.clone().map(|s| s.into()).into(),
                 influx_db_port: self.influx_db.port,
+                lora_tx_power: self.lora_tx_power.into(),
+                lora_sync_word: self.lora_sync_word as u8,
+                sensor_community_id: self.sensor_community_id.clone().into(),
+                dashboard_auth: self.dashboard_auth.clone().map(|a| a.into()).into(),
+                http_port: self.http_port.to_le_bytes(),
+                http_interfaces: self.http_interfaces as u8,
+                sensor_community_enabled: self.sensor_community_enabled.into(),
+                influx_db_enabled: self.influx_db.enabled.into(),
+                webhook_enabled: self.webhook.enabled.into(),
+                wifi_ap_pass: self.wifi_ap_pass.clone().map(|s| s.into()).into(),
+                sensor_macs: self.sensor_macs.map(|mac| mac.into()),
+                boot_count: self.boot_count.to_le_bytes(),
+                reset_reason: self.reset_reason.clone().into(),
             },
         };
 
@@ -253,20 +670,398 @@ impl Config {
             self.influx_db.host = influx_db_host;
         }
         self.influx_db.port = payload.influx_db_port;
+        self.lora_tx_power = payload.lora_tx_power.into();
+        self.lora_sync_word = match payload.lora_sync_word {
+            1 => LoraSyncWord::Public,
+            _ => LoraSyncWord::Private,
+        };
+        if let Ok(sensor_community_id) = payload.sensor_community_id.try_into() {
+            self.sensor_community_id = sensor_community_id;
+        }
+        // unlike the other settings, credentials set while building are kept if none are stored,
+        // so that protecting an already flashed gateway does not silently leave it open
+        if let Ok(Some(dashboard_auth)) = payload.dashboard_auth.try_decode() {
+            self.dashboard_auth = Some(dashboard_auth);
+        }
+        match u16::from_le_bytes(payload.http_port) {
+            0 => warn!("config: invalid HTTP port in flash, keeping the current one"),
+            port => self.http_port = port,
+        }
+        self.http_interfaces = match payload.http_interfaces {
+            1 => HttpInterfaces::Ap,
+            2 => HttpInterfaces::Sta,
+            _ => HttpInterfaces::Both,
+        };
+        self.sensor_community_enabled = payload.sensor_community_enabled != 0;
+        self.influx_db.enabled = payload.influx_db_enabled != 0;
+        self.webhook.enabled = payload.webhook_enabled != 0;
+        match payload.wifi_ap_pass.try_decode() {
+            Ok(Some(pass)) if parse_wpa2_passphrase(&pass).is_none() => {
+                warn!("config: invalid access point password in flash, keeping the current one")
+            }
+            Ok(wifi_ap_pass) => self.wifi_ap_pass = wifi_ap_pass,
+            Err(()) => {}
+        }
+        self.sensor_macs = payload.sensor_macs.map(|mac| mac.into());
+        self.boot_count = u32::from_le_bytes(payload.boot_count);
+        if let Ok(reset_reason) = payload.reset_reason.try_into() {
+            self.reset_reason = reset_reason;
+        }
+    }
+
+    /// Whether values may be sent to `exporter`, those without a host configured are never used regardless.
+    pub fn exporter_enabled(&self, exporter: Exporter) -> bool {
+        match exporter {
+            Exporter::SensorCommunity => self.sensor_community_enabled,
+            Exporter::InfluxDb => self.influx_db.enabled,
+            Exporter::Webhook => self.webhook.enabled,
+        }
+    }
+
+    /// Validates `value` and assigns it to `var`, keeping the current value if it is invalid.
+    ///
+    /// An empty value clears optional settings, or restores the default sensor.community ID.
+    pub fn set_variable(&mut self, var: ConfigVariable, value: &str) -> Result<(), InvalidValue> {
+        match var {
+            ConfigVariable::WifiStaSsid => match parse_bounded_string::<32>(value) {
+                Some(s) if s.is_empty() => {
+                    info!("Empty WiFi STA SSID received, clearing config.");
+                    self.wifi_sta_ssid = None;
+                }
+                Some(s) => {
+                    info!("Setting WiFi STA SSID: {}", s);
+                    self.wifi_sta_ssid = Some(s);
+                }
+                None => {
+                    warn!("Invalid WiFi STA SSID, keeping current value.");
+                    return Err(InvalidValue);
+                }
+            },
+            ConfigVariable::WifiStaPassword => match parse_bounded_string::<64>(value) {
+                Some(s) if s.is_empty() => {
+                    info!("Empty WiFi STA PASS received, clearing config.");
+                    self.wifi_sta_pass = None;
+                }
+                Some(s) => {
+                    info!("Updating WiFi STA PASS.");
+                    self.wifi_sta_pass = Some(s);
+                }
+                None => {
+                    warn!("Invalid WiFi STA PASS, keeping current value.");
+                    return Err(InvalidValue);
+                }
+            },
+            ConfigVariable::WifiApSsid => match parse_bounded_string::<32>(value) {
+                Some(s) => {
+                    info!("Setting WiFi AP SSID: {}", s);
+                    self.wifi_ap_ssid = s;
+                }
+                None => {
+                    warn!("Invalid WiFi AP SSID, keeping current value.");
+                    return Err(InvalidValue);
+                }
+            },
+            ConfigVariable::WifiApPassword if value.is_empty() => {
+                warn!("Empty WiFi AP password, the access point will be open.");
+                self.wifi_ap_pass = None;
+            }
+            ConfigVariable::WifiApPassword => match parse_wpa2_passphrase(value) {
+                Some(pass) => {
+                    info!("Updating WiFi AP password.");
+                    self.wifi_ap_pass = Some(pass);
+                }
+                None => {
+                    warn!("Invalid WiFi AP password (8 to 63 printable ASCII characters), keeping current value.");
+                    return Err(InvalidValue);
+                }
+            },
+            ConfigVariable::DnsServer1 => match parse_dns_server(value) {
+                Some(ip) => {
+                    info!("Setting DNS server 1: {}", ip);
+                    self.dns_server_1 = ip;
+                }
+                None => {
+                    warn!("Invalid DNS server 1 address.");
+                    return Err(InvalidValue);
+                }
+            },
+            ConfigVariable::DnsServer2 => match parse_dns_server(value) {
+                Some(ip) => {
+                    info!("Setting DNS server 2: {}", ip);
+                    self.dns_server_2 = ip;
+                }
+                None => {
+                    warn!("Invalid DNS server 2 address.");
+                    return Err(InvalidValue);
+                }
+            },
+            ConfigVariable::InfluxDbHost => match parse_bounded_string::<64>(value) {
+                Some(s) if s.is_empty() => {
+                    info!("Empty InfluxDB host, clearing config.");
+                    self.influx_db.host = None;
+                }
+                Some(s) => {
+                    info!("Setting InfluxDB host: {}", s);
+                    self.influx_db.host = Some(s);
+                }
+                None => {
+                    warn!("Invalid InfluxDB host, keeping current value.");
+                    return Err(InvalidValue);
+                }
+            },
+            ConfigVariable::InfluxDbPort => match parse_port(value) {
+                Some(port) => {
+                    info!("Setting InfluxDB port: {}", port);
+                    self.influx_db.port = port;
+                }
+                None => {
+                    warn!("Invalid InfluxDB port, keeping current value.");
+                    return Err(InvalidValue);
+                }
+            },
+            ConfigVariable::LoraTxPower if value.is_empty() => {
+                info!("Empty LoRa TX power, using default.");
+                self.lora_tx_power = None;
+            }
+            ConfigVariable::LoraTxPower => match value.parse::<i8>() {
+                Ok(tx_power) => {
+                    info!("Setting LoRa TX power: {} dBm", tx_power);
+                    self.lora_tx_power = Some(tx_power);
+                }
+                Err(_) => {
+                    warn!("Invalid LoRa TX power, keeping current value.");
+                    return Err(InvalidValue);
+                }
+            },
+            ConfigVariable::LoraSyncWord if value.is_empty() => {
+                info!("Empty LoRa sync word, using the private one.");
+                self.lora_sync_word = LoraSyncWord::Private;
+            }
+            ConfigVariable::LoraSyncWord => match LoraSyncWord::from_name(value) {
+                Some(sync_word) => {
+                    info!("Setting LoRa sync word: {}", sync_word.name());
+                    self.lora_sync_word = sync_word;
+                }
+                None => {
+                    warn!("Invalid LoRa sync word, keeping current value.");
+                    return Err(InvalidValue);
+                }
+            },
+            ConfigVariable::SensorCommunityId => match parse_bounded_string::<32>(value) {
+                Some(s) if s.is_empty() => {
+                    info!("Empty sensor.community sensor ID, deriving it from the MAC address.");
+                    self.sensor_community_id = Config::default_sensor_community_id();
+                }
+                Some(s) => {
+                    info!("Setting sensor.community sensor ID: {}", s);
+                    self.sensor_community_id = s;
+                }
+                None => {
+                    warn!("Invalid sensor.community sensor ID, keeping current value.");
+                    return Err(InvalidValue);
+                }
+            },
+            ConfigVariable::SensorCommunityEnabled => {
+                self.set_exporter_enabled(Exporter::SensorCommunity, value)?
+            }
+            ConfigVariable::InfluxDbEnabled => {
+                self.set_exporter_enabled(Exporter::InfluxDb, value)?
+            }
+            ConfigVariable::WebhookEnabled => {
+                self.set_exporter_enabled(Exporter::Webhook, value)?
+            }
+            ConfigVariable::HttpPort => match parse_port(value) {
+                Some(port) => {
+                    info!("Setting HTTP port: {}", port);
+                    self.http_port = port;
+                }
+                None => {
+                    warn!("Invalid HTTP port, keeping current value.");
+                    return Err(InvalidValue);
+                }
+            },
+            ConfigVariable::HttpInterfaces if value.is_empty() => {
+                info!("Empty HTTP interfaces, using both.");
+                self.http_interfaces = HttpInterfaces::Both;
+            }
+            ConfigVariable::HttpInterfaces => match HttpInterfaces::from_name(value) {
+                Some(interfaces) => {
+                    info!("Setting HTTP interfaces: {}", interfaces.name());
+                    self.http_interfaces = interfaces;
+                }
+                None => {
+                    warn!("Invalid HTTP interfaces, keeping current value.");
+                    return Err(InvalidValue);
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Enables or disables `exporter` from `true` or `false`, an empty value enables it.
+    fn set_exporter_enabled(
+        &mut self,
+        exporter: Exporter,
+        value: &str,
+    ) -> Result<(), InvalidValue> {
+        let enabled = match value {
+            "" | "true" => true,
+            "false" => false,
+            _ => {
+                warn!("Invalid {} toggle, keeping current value.", exporter.name());
+                return Err(InvalidValue);
+            }
+        };
+        info!(
+            "{} the {} exporter.",
+            if enabled { "Enabling" } else { "Disabling" },
+            exporter.name()
+        );
+        *match exporter {
+            Exporter::SensorCommunity => &mut self.sensor_community_enabled,
+            Exporter::InfluxDb => &mut self.influx_db.enabled,
+            Exporter::Webhook => &mut self.webhook.enabled,
+        } = enabled;
+        Ok(())
+    }
+
+    /// Writes the current value of `var`, as accepted by [`Config::set_variable`].
+    ///
+    /// Unset optional settings are written as an empty string, and the Wi-Fi passwords are never shown.
+    pub fn write_variable<W: Write + ?Sized>(
+        &self,
+        var: ConfigVariable,
+        out: &mut W,
+    ) -> core::fmt::Result {
+        match var {
+            ConfigVariable::WifiStaSsid => {
+                out.write_str(self.wifi_sta_ssid.as_deref().unwrap_or(""))
+            }
+            ConfigVariable::WifiStaPassword if self.wifi_sta_pass.is_some() => {
+                out.write_str("(hidden)")
+            }
+            ConfigVariable::WifiStaPassword => Ok(()),
+            ConfigVariable::WifiApSsid => out.write_str(&self.wifi_ap_ssid),
+            ConfigVariable::WifiApPassword if self.wifi_ap_pass.is_some() => {
+                out.write_str("(hidden)")
+            }
+            ConfigVariable::WifiApPassword => Ok(()),
+            ConfigVariable::DnsServer1 => write!(out, "{}", self.dns_server_1),
+            ConfigVariable::DnsServer2 => write!(out, "{}", self.dns_server_2),
+            ConfigVariable::InfluxDbHost => {
+                out.write_str(self.influx_db.host.as_deref().unwrap_or(""))
+            }
+            ConfigVariable::InfluxDbPort => write!(out, "{}", self.influx_db.port),
+            ConfigVariable::LoraTxPower => match self.lora_tx_power {
+                Some(tx_power) => write!(out, "{}", tx_power),
+                None => Ok(()),
+            },
+            ConfigVariable::LoraSyncWord => out.write_str(self.lora_sync_word.name()),
+            ConfigVariable::SensorCommunityId => out.write_str(&self.sensor_community_id),
+            ConfigVariable::SensorCommunityEnabled => {
+                write!(out, "{}", self.exporter_enabled(Exporter::SensorCommunity))
+            }
+            ConfigVariable::InfluxDbEnabled => {
+                write!(out, "{}", self.exporter_enabled(Exporter::InfluxDb))
+            }
+            ConfigVariable::WebhookEnabled => {
+                write!(out, "{}", self.exporter_enabled(Exporter::Webhook))
+            }
+            ConfigVariable::HttpPort => write!(out, "{}", self.http_port),
+            ConfigVariable::HttpInterfaces => out.write_str(self.http_interfaces.name()),
+        }
+    }
+}
+
+/// Settings that can be changed while the gateway runs, from the dashboard or the serial console.
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+pub enum ConfigVariable {
+    WifiStaSsid,
+    WifiStaPassword,
+    WifiApSsid,
+    WifiApPassword,
+    DnsServer1,
+    DnsServer2,
+    InfluxDbHost,
+    InfluxDbPort,
+    LoraTxPower,
+    LoraSyncWord,
+    SensorCommunityId,
+    SensorCommunityEnabled,
+    InfluxDbEnabled,
+    WebhookEnabled,
+    HttpPort,
+    HttpInterfaces,
+}
+
+impl ConfigVariable {
+    pub const ALL: [ConfigVariable; 16] = [
+        ConfigVariable::WifiStaSsid,
+        ConfigVariable::WifiStaPassword,
+        ConfigVariable::WifiApSsid,
+        ConfigVariable::WifiApPassword,
+        ConfigVariable::DnsServer1,
+        ConfigVariable::DnsServer2,
+        ConfigVariable::InfluxDbHost,
+        ConfigVariable::InfluxDbPort,
+        ConfigVariable::LoraTxPower,
+        ConfigVariable::LoraSyncWord,
+        ConfigVariable::SensorCommunityId,
+        ConfigVariable::SensorCommunityEnabled,
+        ConfigVariable::InfluxDbEnabled,
+        ConfigVariable::WebhookEnabled,
+        ConfigVariable::HttpPort,
+        ConfigVariable::HttpInterfaces,
+    ];
+
+    /// Name of the variable, the same in the dashboard form and the serial console
+    pub const fn name(self) -> &'static str {
+        match self {
+            ConfigVariable::WifiStaSsid => "wifi_sta_ssid",
+            ConfigVariable::WifiStaPassword => "wifi_sta_password",
+            ConfigVariable::WifiApSsid => "wifi_ap_ssid",
+            ConfigVariable::WifiApPassword => "wifi_ap_password",
+            ConfigVariable::DnsServer1 => "dns_server_1",
+            ConfigVariable::DnsServer2 => "dns_server_2",
+            ConfigVariable::InfluxDbHost => "influx_db_host",
+            ConfigVariable::InfluxDbPort => "influx_db_port",
+            ConfigVariable::LoraTxPower => "lora_tx_power",
+            ConfigVariable::LoraSyncWord => "lora_sync_word",
+            ConfigVariable::SensorCommunityId => "sensor_community_id",
+            ConfigVariable::SensorCommunityEnabled => "sensor_community_enabled",
+            ConfigVariable::InfluxDbEnabled => "influx_db_enabled",
+            ConfigVariable::WebhookEnabled => "webhook_enabled",
+            ConfigVariable::HttpPort => "http_port",
+            ConfigVariable::HttpInterfaces => "http_interfaces",
+        }
+    }
+
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|var| var.name().as_bytes() == name)
     }
 }
 
+/// Returned by [`Config::set_variable`] when a value is rejected.
+#[derive(Debug)]
+pub struct InvalidValue;
+
 impl Default for Config {
     fn default() -> Self {
         Config::new()
     }
 }
 
+include!(concat!(env!("OUT_DIR"), "/dashboard_auth.rs"));
+
 pub const ENVIRONMENT_VARIABLES: EnvVariables = EnvVariables {
     // These are the environment variables that can be set to configure the gateway
     wifi_sta_ssid: option_env!("WIFI_STA_SSID"),
     wifi_sta_pass: option_env!("WIFI_STA_PASS"),
     wifi_ap_ssid: option_env!("WIFI_AP_SSID"),
+    wifi_ap_pass: option_env!("WIFI_AP_PASS"),
+    wifi_ap_shutdown_delay: option_env!("WIFI_AP_SHUTDOWN_DELAY"),
     dns_server_1: option_env!("DNS_SERVER_1"),
     dns_server_2: option_env!("DNS_SERVER_2"),
     influx_db_host: option_env!("INFLUXDB_HOST"),
@@ -274,6 +1069,24 @@ pub const ENVIRONMENT_VARIABLES: EnvVariables = EnvVariables {
     influx_db_api_token: option_env!("INFLUXDB_API_TOKEN"),
     influx_db_org: option_env!("INFLUXDB_ORG"),
     influx_db_bucket: option_env!("INFLUXDB_BUCKET"),
+    influx_db_gateway_id: option_env!("INFLUXDB_GATEWAY_ID"),
+    influx_db_location: option_env!("INFLUXDB_LOCATION"),
+    webhook_host: option_env!("WEBHOOK_HOST"),
+    webhook_port: option_env!("WEBHOOK_PORT"),
+    webhook_path: option_env!("WEBHOOK_PATH"),
+    webhook_token: option_env!("WEBHOOK_TOKEN"),
+    lora_tx_power: option_env!("LORA_TX_POWER"),
+    lora_sync_word: option_env!("LORA_SYNC_WORD"),
+    watchdog_timeout: option_env!("WATCHDOG_TIMEOUT"),
+    sensor_measure_interval: option_env!("SENSOR_MEASURE_INTERVAL"),
+    value_overflow_policy: option_env!("VALUE_OVERFLOW_POLICY"),
+    value_overflow_timeout: option_env!("VALUE_OVERFLOW_TIMEOUT"),
+    export_routes: option_env!("EXPORT_ROUTES"),
+    sensor_community_id: option_env!("SENSOR_COMMUNITY_ID"),
+    dashboard_user: option_env!("DASHBOARD_USER"),
+    dashboard_password_hash: DASHBOARD_PASSWORD_HASH,
+    http_port: option_env!("HTTP_PORT"),
+    http_interfaces: option_env!("HTTP_INTERFACES"),
 };
 
 pub static CONFIG: Mutex<CriticalSectionRawMutex, Config> = Mutex::new(Config::new());
@@ -299,6 +1112,32 @@ struct SerializedConfigPayload {
     dns_server_2: u32,
     influx_db_host: SerializedOption<SerializedString<64>>,
     influx_db_port: u16,
+    lora_tx_power: SerializedOption<i8>,
+    /// 0 for the private sync word, 1 for the public one
+    lora_sync_word: u8,
+    sensor_community_id: SerializedString<32>,
+    dashboard_auth: SerializedOption<SerializedDashboardAuth>,
+    /// Little endian, as bytes to not add padding after the unaligned fields above
+    http_port: [u8; 2],
+    /// 0 for both interfaces, 1 for the AP only, 2 for the STA only
+    http_interfaces: u8,
+    /// 0 when disabled, 1 when enabled
+    sensor_community_enabled: u8,
+    influx_db_enabled: u8,
+    webhook_enabled: u8,
+    wifi_ap_pass: SerializedOption<SerializedString<64>>,
+    sensor_macs: [SerializedOption<[u8; 6]>; SENSOR_ID_COUNT],
+    /// Little endian, as bytes to not add padding after the unaligned fields above
+    boot_count: [u8; 4],
+    reset_reason: SerializedString<24>,
+}
+
+#[repr(C, align(1))]
+#[derive(Clone, Copy)]
+struct SerializedDashboardAuth {
+    username: SerializedString<32>,
+    salt: [u8; 16],
+    password_hash: [u8; 32],
 }
 
 #[repr(C, align(1))]
@@ -350,6 +1189,36 @@ impl<const N: usize> TryFrom<SerializedString<N>> for heapless::String<N> {
     }
 }
 
+impl From<DashboardAuth> for SerializedDashboardAuth {
+    fn from(val: DashboardAuth) -> Self {
+        SerializedDashboardAuth {
+            username: val.username.into(),
+            salt: val.salt,
+            password_hash: val.password_hash,
+        }
+    }
+}
+
+impl TryFrom<SerializedDashboardAuth> for DashboardAuth {
+    type Error = ();
+
+    fn try_from(value: SerializedDashboardAuth) -> Result<Self, Self::Error> {
+        Ok(DashboardAuth {
+            username: value.username.try_into()?,
+            salt: value.salt,
+            password_hash: value.password_hash,
+        })
+    }
+}
+
+impl SerializedOption<SerializedDashboardAuth> {
+    pub fn try_decode(self) -> Result<Option<DashboardAuth>, ()> {
+        Option::<SerializedDashboardAuth>::from(self)
+            .map(|a| a.try_into())
+            .transpose()
+    }
+}
+
 impl<const N: usize> SerializedOption<SerializedString<N>> {
     pub fn try_decode(self) -> Result<Option<heapless::String<N>>, ()> {
         Option::<SerializedString<N>>::from(self)
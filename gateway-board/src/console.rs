@@ -0,0 +1,116 @@
+//! Configuration console over the serial port, for when the dashboard's access point is out of reach.
+//!
+//! Commands are read line by line and operate on the same [`Config`] as the dashboard.
+//! Changes are only written to flash by the `save` command.
+
+use defmt::{warn, Debug2Format};
+use embedded_io_async::Read;
+use esp_hal::{uart::UartRx, Async};
+use esp_println::println;
+use util::console::{parse_command, CommandError, ConsoleCommand, LineBuffer, LineError};
+
+#[cfg(feature = "lora")]
+use crate::comm::app::STATS;
+use crate::config::{Config, ConfigVariable, CONFIG};
+
+/// Longest line accepted, enough to set the longest values
+const CONSOLE_LINE_LEN: usize = 128;
+
+const HELP: &str = "commands:
+  get [config]         show all variables
+  get <name>           show a single variable
+  set <name> [value]   change a variable, an empty value clears it
+  save                 write the configuration to flash
+  reboot               restart the gateway
+  reset-stats          zero the connection statistics";
+
+/// Reads and executes commands from `rx` in an infinite loop.
+pub async fn run(mut rx: UartRx<'static, Async>) -> ! {
+    let mut lines = LineBuffer::<CONSOLE_LINE_LEN>::new();
+    let mut buf = [0u8; 32];
+
+    println!("console: type 'help' for the list of commands");
+    loop {
+        let len = match rx.read(&mut buf).await {
+            Ok(len) => len,
+            Err(e) => {
+                warn!("console: read failed: {}", Debug2Format(&e));
+                continue;
+            }
+        };
+
+        for &byte in &buf[..len] {
+            match lines.push(byte) {
+                None => (),
+                Some(Ok(line)) => execute(line).await,
+                Some(Err(LineError::TooLong)) => println!("error: line too long"),
+                Some(Err(LineError::InvalidUtf8)) => println!("error: invalid UTF-8"),
+            }
+        }
+    }
+}
+
+async fn execute(line: &str) {
+    let command = match parse_command(line) {
+        Ok(command) => command,
+        Err(CommandError::Unknown(name)) => {
+            println!(
+                "error: unknown command '{}', type 'help' for the list",
+                name
+            );
+            return;
+        }
+        Err(CommandError::MissingArgument) => {
+            println!("error: missing argument");
+            return;
+        }
+        Err(CommandError::UnexpectedArgument) => {
+            println!("error: unexpected argument");
+            return;
+        }
+    };
+
+    match command {
+        ConsoleCommand::Help => println!("{}", HELP),
+        ConsoleCommand::Get(None) => {
+            let config = CONFIG.lock().await;
+            for var in ConfigVariable::ALL {
+                print_variable(&config, var);
+            }
+        }
+        ConsoleCommand::Get(Some(name)) => match ConfigVariable::from_name(name.as_bytes()) {
+            Some(var) => print_variable(&*CONFIG.lock().await, var),
+            None => println!("error: unknown variable '{}'", name),
+        },
+        ConsoleCommand::Set { name, value } => match ConfigVariable::from_name(name.as_bytes()) {
+            Some(var) => match CONFIG.lock().await.set_variable(var, value) {
+                Ok(()) => println!("ok, type 'save' to keep it after a reboot"),
+                Err(_) => println!("error: invalid value for '{}'", name),
+            },
+            None => println!("error: unknown variable '{}'", name),
+        },
+        ConsoleCommand::Save => {
+            CONFIG.lock().await.save_to_flash();
+            println!("ok");
+        }
+        ConsoleCommand::Reboot => {
+            println!("rebooting...");
+            esp_hal::system::software_reset()
+        }
+        #[cfg(feature = "lora")]
+        ConsoleCommand::ResetStats => {
+            STATS.lock().await.reset();
+            println!("ok");
+        }
+        #[cfg(not(feature = "lora"))]
+        ConsoleCommand::ResetStats => {
+            println!("error: built without LoRa, there are no statistics")
+        }
+    }
+}
+
+fn print_variable(config: &Config, var: ConfigVariable) {
+    let mut value: heapless::String<64> = heapless::String::new();
+    config.write_variable(var, &mut value).ok();
+    println!("{} = {}", var.name(), value);
+}
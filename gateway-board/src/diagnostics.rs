@@ -0,0 +1,60 @@
+//! Heap and socket usage, to tell leaks apart from load over long runs.
+//!
+//! The peak heap usage is the highest one seen by [`heap_usage`], which samples the heap after each TCP socket
+//! allocation, on each `/status` request and on each periodic log.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use defmt::{info, Format};
+use embassy_time::{Duration, Timer};
+
+/// Interval between two logs of the heap and socket usage.
+const DIAGNOSTICS_LOG_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+static LIVE_TCP_SOCKETS: AtomicUsize = AtomicUsize::new(0);
+static PEAK_HEAP_USED: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Clone, Copy, Format)]
+pub struct HeapUsage {
+    /// Bytes currently allocated
+    pub used: usize,
+    pub free: usize,
+    /// Most bytes allocated at once since boot, among the samples
+    pub peak_used: usize,
+}
+
+/// Samples the current heap usage, updating the peak one.
+pub fn heap_usage() -> HeapUsage {
+    let used = esp_alloc::HEAP.used();
+    let peak_used = PEAK_HEAP_USED.fetch_max(used, Ordering::Relaxed).max(used);
+    HeapUsage {
+        used,
+        free: esp_alloc::HEAP.free(),
+        peak_used,
+    }
+}
+
+/// Number of TCP sockets whose buffers are currently allocated.
+pub fn live_tcp_sockets() -> usize {
+    LIVE_TCP_SOCKETS.load(Ordering::Relaxed)
+}
+
+pub(crate) fn tcp_socket_allocated() {
+    LIVE_TCP_SOCKETS.fetch_add(1, Ordering::Relaxed);
+    heap_usage();
+}
+
+pub(crate) fn tcp_socket_freed() {
+    LIVE_TCP_SOCKETS.fetch_sub(1, Ordering::Relaxed);
+}
+
+/// Logs the heap and socket usage every few minutes, in an infinite loop.
+pub async fn run() -> ! {
+    loop {
+        info!(
+            "diagnostics: heap {}, {=usize} live TCP sockets",
+            heap_usage(),
+            live_tcp_sockets()
+        );
+        Timer::after(DIAGNOSTICS_LOG_INTERVAL).await;
+    }
+}
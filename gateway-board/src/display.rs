@@ -7,7 +7,7 @@ use defmt::info;
 use display_interface::DisplayError;
 use embassy_time::{Duration, Ticker, Timer};
 use esp_hal::{
-    gpio::{GpioPin, Level, Output, OutputConfig},
+    gpio::{Level, Output, OutputConfig},
     i2c::master::{ConfigError, I2c},
     peripherals::I2C0,
     time::Rate,
@@ -21,6 +21,8 @@ use ssd1306::{
 };
 use thiserror::Error;
 
+use crate::board::DisplayPins;
+
 type HeltecLora32Display =
     Ssd1306<I2CInterface<I2c<'static, Async>>, DisplaySize128x64, TerminalMode>;
 
@@ -29,10 +31,7 @@ pub struct GatewayDisplay(HeltecLora32Display);
 
 pub struct GatewayDisplayHardware {
     pub i2c: I2C0,
-    pub vext: GpioPin<36>,
-    pub sda: GpioPin<17>,
-    pub scl: GpioPin<18>,
-    pub rst: GpioPin<21>,
+    pub pins: DisplayPins,
 }
 
 impl GatewayDisplay {
@@ -42,11 +41,11 @@ impl GatewayDisplay {
         info!("initializing display...");
 
         // init power
-        let mut vext = Output::new(hardware.vext, Level::Low, OutputConfig::default());
+        let mut vext = Output::new(hardware.pins.vext, Level::Low, OutputConfig::default());
         vext.set_low();
 
         // init screen
-        let mut rst = Output::new(hardware.rst, Level::High, OutputConfig::default());
+        let mut rst = Output::new(hardware.pins.rst, Level::High, OutputConfig::default());
         Timer::after_millis(1).await;
         rst.set_low();
         Timer::after_millis(1).await;
@@ -58,8 +57,8 @@ impl GatewayDisplay {
             hardware.i2c,
             esp_hal::i2c::master::Config::default().with_frequency(Rate::from_hz(500000)),
         )?
-        .with_scl(hardware.scl)
-        .with_sda(hardware.sda)
+        .with_scl(hardware.pins.scl)
+        .with_sda(hardware.pins.sda)
         .into_async();
 
         let interface = I2CDisplayInterface::new(i2c);
@@ -162,6 +161,9 @@ async fn do_display(display: &mut GatewayDisplay) -> Result<(), GatewayDisplayEr
                 draw_lora_page(display).await?;
                 ticker.next().await;
             }
+
+            draw_system_page(display).await?;
+            ticker.next().await;
         }
     }
 
@@ -171,6 +173,30 @@ async fn do_display(display: &mut GatewayDisplay) -> Result<(), GatewayDisplayEr
     }
 }
 
+#[cfg(any(feature = "wifi", feature = "lora"))]
+async fn draw_system_page(display: &mut GatewayDisplay) -> Result<(), GatewayDisplayError> {
+    display.set_position(0, 2)?;
+    write!(display, "* System")?;
+
+    let boot = {
+        crate::config::CONFIG
+            .try_lock()
+            .map(|config| (config.boot_count, config.reset_reason.clone()))
+            .ok()
+        // force lock guard to drop after this
+    };
+
+    display.set_position(0, 3)?;
+    match boot {
+        Some((boot_count, reset_reason)) => {
+            write!(display, "boot #{boot_count:<10}\n{reset_reason:<16.16}")?
+        }
+        None => write!(display, "                \n                ")?,
+    }
+
+    Ok(())
+}
+
 #[cfg(feature = "wifi")]
 async fn draw_http_page(display: &mut GatewayDisplay) -> Result<(), GatewayDisplayError> {
     use crate::net::http::DisplayStatus;
@@ -194,6 +220,9 @@ async fn draw_http_page(display: &mut GatewayDisplay) -> Result<(), GatewayDispl
         DisplayStatus::ApOnly(address, port) => {
             write!(display, "{address:<16}\nport (AP): {port:<5}")?
         }
+        DisplayStatus::StaOnly(address, port) => {
+            write!(display, "{address:<16}\nport (STA): {port:<4}")?
+        }
         DisplayStatus::DualStack(address, port) => {
             write!(display, "{address:<16}\nport: {port:<10}")?
         }
@@ -228,6 +257,10 @@ async fn draw_wifi_page(display: &mut GatewayDisplay) -> Result<(), GatewayDispl
     Ok(())
 }
 
+/// Largest age of the last packet shown, it fits in 5 digits.
+#[cfg(feature = "lora")]
+const LAST_PACKET_MAX_AGE_SECS: u64 = 99_999;
+
 #[cfg(feature = "lora")]
 async fn draw_lora_page(display: &mut GatewayDisplay) -> Result<(), GatewayDisplayError> {
     use crate::comm::app::AppLayerPhase;
@@ -235,19 +268,38 @@ async fn draw_lora_page(display: &mut GatewayDisplay) -> Result<(), GatewayDispl
     display.set_position(0, 2)?;
     write!(display, "* LoRa")?;
 
-    let phase = {
+    let (phase, last_packet) = {
         crate::comm::app::CURRENT_STATUS
             .try_lock()
-            .map(|status| status.phase)
-            .unwrap_or(AppLayerPhase::Initial)
+            .map(|status| (status.phase, status.last_packet))
+            .unwrap_or((AppLayerPhase::Initial, None))
+        // force lock guard to drop after this
+    };
+    let counts = {
+        crate::comm::app::STATS
+            .try_lock()
+            .map(|stats| (stats.packets_received, stats.acks_sent))
+            .ok()
         // force lock guard to drop after this
     };
 
     display.set_position(0, 3)?;
-    match phase {
-        AppLayerPhase::Initial => write!(display, "waiting...      \n                ")?,
-        AppLayerPhase::Handshake => write!(display, "handshaking...  \n                ")?,
-        AppLayerPhase::Uplink => write!(display, "connected       \n                ")?,
+    let phase = match phase {
+        AppLayerPhase::Initial => "waiting",
+        AppLayerPhase::Handshake => "handshake",
+        AppLayerPhase::Uplink => "connected",
+    };
+    // time since the last packet, to tell a stuck connection from a live one
+    match last_packet {
+        Some(time) => {
+            let secs = time.elapsed().as_secs().min(LAST_PACKET_MAX_AGE_SECS);
+            write!(display, "{phase:<10}{secs:>5}s\n")?
+        }
+        None => write!(display, "{phase:<10} no rx\n")?,
+    }
+    match counts {
+        Some((received, acks)) => write!(display, "rx:{received:<5}ack:{acks:<4}")?,
+        None => write!(display, "                ")?,
     }
 
     Ok(())
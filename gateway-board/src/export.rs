@@ -1,23 +1,68 @@
 //! Sensor data exporting
 
 use crate::config::CONFIG;
+use crate::net::wifi;
+use crate::watchdog::{self, WatchedTask};
 use crate::{
-    net::http::{HttpBody, HttpClient, HttpClientError, HttpMethod},
-    ValueReceiver,
+    net::http::{HttpClient, HttpClientError, HttpMethod},
+    FutureTimeoutExt, TimeoutError, ValueReceiver,
 };
-use defmt::{error, info, Debug2Format};
+use defmt::{error, info, warn, Debug2Format};
 use embassy_net::Stack;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex, signal::Signal};
+use embassy_time::{Duration, Instant};
 use protocol::app::v1::{SensorValue, SensorValuePoint};
+use util::export::{ExportBackoff, ExportRoutes, Exporter, SensorCommunitySensor};
+
+/// Number of times a request is sent again after a retryable error, see [`HttpClientError::is_retryable`].
+const EXPORT_RETRIES: u32 = 1;
+/// Most values sent to a single exporter at once when not all of them are routed to it,
+/// as many as the export task collects.
+const ROUTED_VALUES_LEN: usize = 2 * crate::DEFAULT_VALUE_CHANNEL_SIZE;
+/// Longest wait for the export task to run a test export, it may be busy with real values.
+const TEST_EXPORT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Longest wait for the uplink to get an IP address once the station is connected, values are dropped after it.
+const UPLINK_TIMEOUT: Duration = Duration::from_secs(30);
+/// Sent by test exports, recognizable as fake by its impossible temperature.
+const TEST_EXPORT_VALUE: SensorValuePoint = SensorValuePoint {
+    value: SensorValue::Temperature(-99.0),
+    time_offset: 0,
+};
+
+static TEST_EXPORT_REQUEST: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+static TEST_EXPORT_RESULTS: Signal<CriticalSectionRawMutex, TestExportResults> = Signal::new();
+/// Exporters skipped after being rate limited, until the delay asked by their server is over
+static EXPORT_BACKOFF: Mutex<CriticalSectionRawMutex, ExportBackoff> =
+    Mutex::new(ExportBackoff::new());
 
 pub trait ValuesExporter {
+    /// Returns the status of the last response, or of the first failed one, `None` if nothing was sent.
     async fn export(
         &self,
         client: &mut HttpClient<'_>,
         values: &[SensorValuePoint],
-    ) -> Result<(), HttpClientError>;
+    ) -> Result<Option<u16>, HttpClientError>;
 }
 
-pub struct SensorCommunityExporter;
+/// Outcome of a single exporter in a test export, see [`request_test_export`].
+pub enum TestExportResult {
+    /// The exporter has no host configured
+    NotConfigured,
+    /// The exporter is disabled in the configuration
+    Disabled,
+    /// The exporter sent no request, see its logs
+    NotSent,
+    Status(u16),
+    Failed(HttpClientError),
+}
+
+/// Outcomes of a test export, in the order of [`Exporter::ALL`].
+pub type TestExportResults = [TestExportResult; Exporter::ALL.len()];
+
+pub struct SensorCommunityExporter {
+    /// Sent in the `X-Sensor` header, and as part of the `User-Agent`
+    sensor_id: heapless::String<32>,
+}
 
 pub struct InfluxDbExporter {
     host: heapless::String<64>,
@@ -25,6 +70,16 @@ pub struct InfluxDbExporter {
     org: &'static str,
     bucket: &'static str,
     api_token: &'static str,
+    gateway_id: Option<&'static str>,
+    location: Option<&'static str>,
+}
+
+/// Posts values as a JSON array of `{"type", "value", "time_offset"}` objects to a custom endpoint.
+pub struct WebhookExporter {
+    host: heapless::String<64>,
+    port: u16,
+    path: &'static str,
+    token: Option<&'static str>,
 }
 
 /// Attempts to fetch as many values as possible from `receiver` until either the buffer is full or the channel is empty.
@@ -53,50 +108,229 @@ pub async fn collect_values<'a, const N: usize>(
     buf.as_slice()
 }
 
-/// Exports the given values using all exporters
-pub async fn export_to_all(stack: Stack<'_>, values: &[SensorValuePoint]) {
-    info!("export: waiting for network");
-    stack.wait_link_up().await;
+/// Creates the client used by [`export_to_all`].
+///
+/// It should be kept across exports, so that it can reuse its cached DNS lookups.
+pub fn new_client(stack: Stack<'_>) -> HttpClient<'_> {
+    // most exports go to the same few hosts, no need to reconnect for each request
+    HttpClient::new(stack)
+        .with_keep_alive(true)
+        .with_stale_dns_fallback(true)
+}
 
-    let mut client = HttpClient::new(stack);
-    let ex = SensorCommunityExporter;
+/// Exports the given values using all exporters.
+///
+/// The values are dropped if the uplink is down, so that the export task keeps draining the value channel.
+pub async fn export_to_all(client: &mut HttpClient<'_>, values: &[SensorValuePoint]) {
+    if !wifi::is_sta_ready() {
+        warn!(
+            "export: uplink down, dropping {=usize} values",
+            values.len()
+        );
+        return;
+    }
+    info!("export: waiting for network");
+    watchdog::park(WatchedTask::Export);
+    let res = client
+        .stack()
+        .wait_config_up()
+        .with_timeout(UPLINK_TIMEOUT)
+        .await;
+    watchdog::heartbeat(WatchedTask::Export);
+    if res.is_err() {
+        warn!(
+            "export: no IP address on the uplink, dropping {=usize} values",
+            values.len()
+        );
+        return;
+    }
 
-    if let Err(e) = ex.export(&mut client, values).await {
+    let (routes, enabled) = {
+        let config = CONFIG.lock().await;
+        (
+            config.export_routes,
+            Exporter::ALL.map(|exporter| config.exporter_enabled(exporter)),
+        )
+    };
+    let mut routed_buf = heapless::Vec::new();
+
+    let ex = SensorCommunityExporter::from_config().await;
+    // retries are done for each request, as a new attempt would send the successful ones again
+    let routed = routed_values(&routes, Exporter::SensorCommunity, values, &mut routed_buf);
+    if !enabled[Exporter::SensorCommunity as usize]
+        || routed.is_empty()
+        || is_backing_off(Exporter::SensorCommunity, routed).await
+    {
+        // disabled, every value type is routed elsewhere, or the server asked to wait
+    } else if let Err(e) = ex.export(client, routed).await {
         error!("export: sensor.community: error: {}", Debug2Format(&e));
+        back_off_if_rate_limited(Exporter::SensorCommunity, &e).await;
     }
-    let influx_db_cfg = CONFIG.lock().await.influx_db.clone();
-    if let Some(host) = influx_db_cfg.host {
-        let ex = InfluxDbExporter {
-            host,
-            port: influx_db_cfg.port,
-            org: influx_db_cfg.org,
-            bucket: influx_db_cfg.bucket,
-            api_token: influx_db_cfg.api_token,
-        };
-        if let Err(e) = ex.export(&mut client, values).await {
-            error!("export: influxdb: error: {}", Debug2Format(&e));
+    watchdog::heartbeat(WatchedTask::Export);
+    if let Some(ex) = InfluxDbExporter::from_config()
+        .await
+        .filter(|_| enabled[Exporter::InfluxDb as usize])
+    {
+        let routed = routed_values(&routes, Exporter::InfluxDb, values, &mut routed_buf);
+        if !routed.is_empty() && !is_backing_off(Exporter::InfluxDb, routed).await {
+            if let Err(e) = export_with_retries("influxdb", &ex, client, routed).await {
+                back_off_if_rate_limited(Exporter::InfluxDb, &e).await;
+            }
+        }
+    }
+    watchdog::heartbeat(WatchedTask::Export);
+    if let Some(ex) = WebhookExporter::from_config()
+        .await
+        .filter(|_| enabled[Exporter::Webhook as usize])
+    {
+        let routed = routed_values(&routes, Exporter::Webhook, values, &mut routed_buf);
+        if !routed.is_empty() && !is_backing_off(Exporter::Webhook, routed).await {
+            if let Err(e) = export_with_retries("webhook", &ex, client, routed).await {
+                back_off_if_rate_limited(Exporter::Webhook, &e).await;
+            }
         }
     }
+
+    // the next export is a while away, don't keep the connection open until then
+    client.close();
+}
+
+/// Waits for the dashboard to ask for a test export, to be answered with [`test_export`].
+pub async fn wait_test_export_request() {
+    TEST_EXPORT_REQUEST.wait().await
+}
+
+/// Sends a synthetic value to every configured and enabled exporter, regardless of the export routes.
+///
+/// The outcomes are returned to the pending [`request_test_export`].
+pub async fn test_export(client: &mut HttpClient<'_>) {
+    info!("export: test export requested");
+    let values = [TEST_EXPORT_VALUE];
+    let outcome = |res| match res {
+        Ok(Some(status)) => TestExportResult::Status(status),
+        Ok(None) => TestExportResult::NotSent,
+        Err(e) => TestExportResult::Failed(e),
+    };
+
+    let enabled = {
+        let config = CONFIG.lock().await;
+        Exporter::ALL.map(|exporter| config.exporter_enabled(exporter))
+    };
+
+    let sensor_community = if enabled[Exporter::SensorCommunity as usize] {
+        let ex = SensorCommunityExporter::from_config().await;
+        outcome(ex.export(client, &values).await)
+    } else {
+        TestExportResult::Disabled
+    };
+    watchdog::heartbeat(WatchedTask::Export);
+    let influx_db = match InfluxDbExporter::from_config().await {
+        Some(_) if !enabled[Exporter::InfluxDb as usize] => TestExportResult::Disabled,
+        Some(ex) => outcome(export_with_retries("influxdb", &ex, client, &values).await),
+        None => TestExportResult::NotConfigured,
+    };
+    watchdog::heartbeat(WatchedTask::Export);
+    let webhook = match WebhookExporter::from_config().await {
+        Some(_) if !enabled[Exporter::Webhook as usize] => TestExportResult::Disabled,
+        Some(ex) => outcome(export_with_retries("webhook", &ex, client, &values).await),
+        None => TestExportResult::NotConfigured,
+    };
+
+    client.close();
+    TEST_EXPORT_RESULTS.signal([sensor_community, influx_db, webhook]);
+}
+
+/// Asks the export task to run [`test_export`] as soon as it is idle, and waits for its outcomes.
+pub(crate) async fn request_test_export() -> Result<TestExportResults, TimeoutError> {
+    // drop the outcomes of a request that timed out
+    TEST_EXPORT_RESULTS.reset();
+    TEST_EXPORT_REQUEST.signal(());
+    let res = TEST_EXPORT_RESULTS
+        .wait()
+        .with_timeout(TEST_EXPORT_TIMEOUT)
+        .await;
+    if res.is_err() {
+        // don't run it later, nobody would see the outcomes
+        TEST_EXPORT_REQUEST.reset();
+    }
+    res
+}
+
+/// Returns the values whose type is routed to `exporter`, copied to `buf` unless all of them are.
+fn routed_values<'a>(
+    routes: &ExportRoutes,
+    exporter: Exporter,
+    values: &'a [SensorValuePoint],
+    buf: &'a mut heapless::Vec<SensorValuePoint, ROUTED_VALUES_LEN>,
+) -> &'a [SensorValuePoint] {
+    if routes.accepts_all(exporter) {
+        return values;
+    }
+
+    buf.clear();
+    let mut routed = values
+        .iter()
+        .filter(|point| routes.accepts(exporter, point.value));
+    buf.extend(routed.by_ref().take(ROUTED_VALUES_LEN).copied());
+    let dropped = routed.count();
+    if dropped > 0 {
+        warn!(
+            "export: {=str}: dropping {=usize} values, too many at once",
+            exporter.name(),
+            dropped
+        );
+    }
+    buf
 }
 
-#[repr(u8)]
-#[derive(Clone, Copy)]
-enum SensorCommunitySensor {
-    // sensor.community expects certain "pin" values for each sensor type
-    ParticulateMatter = 1,
-    TemperaturePressure = 3,
+/// Whether `exporter` is still waiting after being rate limited, in which case its `values` are dropped.
+async fn is_backing_off(exporter: Exporter, values: &[SensorValuePoint]) -> bool {
+    let now_ms = Instant::now().as_millis();
+    let Some(remaining_ms) = EXPORT_BACKOFF.lock().await.remaining_ms(exporter, now_ms) else {
+        return false;
+    };
+    warn!(
+        "export: {=str}: rate limited for {=u64} more s, dropping {=usize} value(s)",
+        exporter.name(),
+        remaining_ms.div_ceil(1000),
+        values.len()
+    );
+    true
 }
 
-impl SensorCommunitySensor {
-    fn supports_value(self, value: SensorValue) -> bool {
-        match self {
-            SensorCommunitySensor::ParticulateMatter => {
-                matches!(value, SensorValue::AirQuality(_))
+/// Skips `exporter` for the delay asked by its server, if `error` is [`HttpClientError::RateLimited`].
+async fn back_off_if_rate_limited(exporter: Exporter, error: &HttpClientError) {
+    if let Some(delay) = error.retry_after() {
+        let now_ms = Instant::now().as_millis();
+        EXPORT_BACKOFF
+            .lock()
+            .await
+            .hold(exporter, now_ms, delay.as_millis());
+    }
+}
+
+/// Exports values with a single request, sending it again up to [`EXPORT_RETRIES`] times on retryable errors.
+///
+/// Returns the outcome of the last attempt.
+async fn export_with_retries(
+    name: &str,
+    ex: &impl ValuesExporter,
+    client: &mut HttpClient<'_>,
+    values: &[SensorValuePoint],
+) -> Result<Option<u16>, HttpClientError> {
+    let mut attempt: u32 = 0;
+    loop {
+        match ex.export(client, values).await {
+            Ok(status) => return Ok(status),
+            Err(e) if e.is_retryable() && attempt < EXPORT_RETRIES => {
+                attempt += 1;
+                warn!("export: {=str}: {}, retrying", name, Debug2Format(&e));
+                watchdog::heartbeat(WatchedTask::Export);
+            }
+            Err(e) => {
+                error!("export: {=str}: error: {}", name, Debug2Format(&e));
+                return Err(e);
             }
-            SensorCommunitySensor::TemperaturePressure => matches!(
-                value,
-                SensorValue::Temperature(_) | SensorValue::Pressure(_)
-            ),
         }
     }
 }
@@ -106,31 +340,96 @@ impl ValuesExporter for SensorCommunityExporter {
         &self,
         client: &mut HttpClient<'_>,
         values: &[SensorValuePoint],
-    ) -> Result<(), HttpClientError> {
-        Self::export_by_sensor(client, SensorCommunitySensor::ParticulateMatter, values).await?;
-        Self::export_by_sensor(client, SensorCommunitySensor::TemperaturePressure, values).await
+    ) -> Result<Option<u16>, HttpClientError> {
+        let mut status = None;
+        for sensor in SensorCommunitySensor::ALL {
+            let sensor_status = self.export_by_sensor(client, sensor, values).await?;
+            // a later success doesn't hide a failure
+            let failed = status.is_some_and(|status| !(200..300).contains(&status));
+            if !failed {
+                status = sensor_status.or(status);
+            }
+        }
+        Ok(status)
     }
 }
 
 impl SensorCommunityExporter {
+    async fn from_config() -> Self {
+        Self {
+            sensor_id: CONFIG.lock().await.sensor_community_id.clone(),
+        }
+    }
+
+    /// Returns the status of the last response, see [`ValuesExporter::export`].
     async fn export_by_sensor(
+        &self,
         client: &mut HttpClient<'_>,
         sensor: SensorCommunitySensor,
         values: &[SensorValuePoint],
-    ) -> Result<(), HttpClientError> {
-        use core::fmt::Write;
+    ) -> Result<Option<u16>, HttpClientError> {
+        let mut remaining = values;
+        let mut exported_count: u32 = 0;
+        let mut attempt: u32 = 0;
+        let mut last_status = None;
+
+        loop {
+            // skip to the next supported value, don't send empty requests
+            match remaining
+                .iter()
+                .position(|v| sensor.supports_value(v.value))
+            {
+                Some(start) => remaining = &remaining[start..],
+                None => break,
+            }
 
-        if values
-            .iter()
-            .filter(|&&v| sensor.supports_value(v.value))
-            .count()
-            == 0
-        {
-            // don't send empty requests
-            return Ok(());
+            let (status, consumed, written) = match self.send_chunk(client, sensor, remaining).await
+            {
+                Ok(res) => res,
+                Err(e) if e.is_retryable() && attempt < EXPORT_RETRIES => {
+                    attempt += 1;
+                    warn!("export: sensor.community: {}, retrying", Debug2Format(&e));
+                    continue;
+                }
+                Err(e) => {
+                    Self::log_dropped(sensor, remaining);
+                    return Err(e);
+                }
+            };
+            attempt = 0;
+            last_status = Some(status);
+
+            if !(200..300).contains(&status) {
+                error!("export: sensor.community: request failed: {=u16}", status);
+                Self::log_dropped(sensor, remaining);
+                break;
+            }
+            exported_count += written;
+            remaining = &remaining[consumed..];
+        }
+
+        if exported_count > 0 {
+            info!(
+                "export: sensor.community: successfully exported {=u32} value(s)",
+                exported_count
+            );
         }
+        Ok(last_status)
+    }
+
+    /// Sends a single request containing at most [`util::export::SENSOR_COMMUNITY_MAX_VALUES_PER_REQUEST`] values.
+    ///
+    /// Returns the response status, the number of values consumed from `values` and the number of values sent.
+    async fn send_chunk(
+        &self,
+        client: &mut HttpClient<'_>,
+        sensor: SensorCommunitySensor,
+        values: &[SensorValuePoint],
+    ) -> Result<(u16, usize, u32), HttpClientError> {
+        use core::fmt::Write;
 
         let mut header_buf: heapless::String<10> = heapless::String::new();
+        let mut user_agent: heapless::String<96> = heapless::String::new();
 
         let mut req = client
             .request(
@@ -142,64 +441,52 @@ impl SensorCommunityExporter {
             .await?;
 
         req.header("Content-Type", "application/json").await?;
-        req.header("User-Agent", "NRZ-2021-134-B4-ESP32/4123/4123")
-            .await?;
-        req.header("X-Sensor", "esp32-32344").await?;
+        // at most 23 + 2 * 32 characters
+        _ = write!(
+            &mut user_agent,
+            "NRZ-2021-134-B4-ESP32/{}/{}",
+            self.sensor_id, self.sensor_id
+        );
+        req.header("User-Agent", &user_agent).await?;
+        req.header("X-Sensor", &self.sensor_id).await?;
 
-        _ = write!(&mut header_buf, "{}", sensor as u8);
+        _ = write!(&mut header_buf, "{}", sensor.pin());
         req.header("X-Pin", &header_buf).await?;
 
-        req.body().extend_from_slice(br#"{"sensordatavalues":["#);
-
-        let mut exported_count: u32 = 0;
-
-        for value in values.iter().copied() {
-            if !sensor.supports_value(value.value) {
-                continue;
-            }
-            Self::write_value_to_body(req.body(), value.value, exported_count == 0);
-            exported_count += 1;
-        }
-        req.body().extend_from_slice(b"]}");
+        // an oversized body makes the request fail with `BufferOverflow`
+        let (consumed, written) =
+            util::export::write_sensor_community_body(&mut req.body(), sensor, values)
+                .unwrap_or_default();
 
         let response = req.finish().await?;
-
-        if response.status() < 200 || response.status() >= 300 {
-            error!(
-                "export: sensor.community: request failed: {=u16}",
-                response.status()
-            );
-        } else {
-            info!(
-                "export: sensor.community: successfully exported {=u32} value(s)",
-                exported_count
-            );
-        }
-        Ok(())
+        Ok((response.status(), consumed, written))
     }
 
-    fn write_value_to_body(body_buf: &mut HttpBody, value: SensorValue, first_value: bool) {
-        use core::fmt::Write;
-
-        if !first_value {
-            body_buf.push(b',');
-        }
+    fn log_dropped(sensor: SensorCommunitySensor, values: &[SensorValuePoint]) {
+        let dropped = values
+            .iter()
+            .filter(|&&v| sensor.supports_value(v.value))
+            .count();
+        warn!(
+            "export: sensor.community: {=usize} value(s) were not exported",
+            dropped
+        );
+    }
+}
 
-        let _ = match value {
-            SensorValue::Temperature(v) => {
-                write!(body_buf, r#"{{"value":{v},"value_type":"temperature"}}"#)
-            }
-            SensorValue::Pressure(v) => {
-                write!(body_buf, r#"{{"value":{v},"value_type":"pressure"}}"#)
-            }
-            SensorValue::Altitude(v) => {
-                write!(body_buf, r#"{{"value":{v},"value_type":"altitude"}}"#)
-            }
-            SensorValue::AirQuality(v) => {
-                write!(body_buf, r#"{{"value":{v},"value_type":"dust_density"}}"#)
-            }
-            SensorValue::Unknown { .. } => Ok(()),
-        };
+impl InfluxDbExporter {
+    /// Returns `None` if no host is configured.
+    async fn from_config() -> Option<Self> {
+        let cfg = CONFIG.lock().await.influx_db.clone();
+        Some(Self {
+            host: cfg.host?,
+            port: cfg.port,
+            org: cfg.org,
+            bucket: cfg.bucket,
+            api_token: cfg.api_token,
+            gateway_id: cfg.gateway_id,
+            location: cfg.location,
+        })
     }
 }
 
@@ -208,12 +495,12 @@ impl ValuesExporter for InfluxDbExporter {
         &self,
         client: &mut HttpClient<'_>,
         values: &[SensorValuePoint],
-    ) -> Result<(), HttpClientError> {
+    ) -> Result<Option<u16>, HttpClientError> {
         use core::fmt::Write;
 
         if values.is_empty() {
             // don't send empty requests
-            return Ok(());
+            return Ok(None);
         }
 
         // 100 bytes should be enough for the path, this allows up to 63 characters for org and bucket names
@@ -235,49 +522,101 @@ impl ValuesExporter for InfluxDbExporter {
         _ = write!(&mut buffer, "Token {}", self.api_token);
         req.header("Authorization", &buffer).await?;
 
-        let mut exported_count: u32 = 0;
-        for value in values.iter().copied() {
-            Self::write_value_to_body(req.body(), value, exported_count == 0);
-            exported_count += 1;
-        }
-
-        let response = req.finish().await?;
+        let tags: heapless::Vec<(&str, &str), 2> = [
+            self.gateway_id.map(|id| ("gateway_id", id)),
+            self.location.map(|location| ("location", location)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        let exported_count =
+            util::export::write_influxdb_body(&mut req.body(), values, &tags).unwrap_or_default();
+
+        // InfluxDB explains errors with a `{"code", "message"}` JSON body
+        let mut response_body = [0u8; 256];
+        let (response, body_len) = req.finish_with_body(&mut response_body).await?;
         if response.status() < 200 || response.status() >= 300 {
-            error!(
-                "export: influxdb: request failed: {=u16}",
-                response.status()
-            );
+            let mut message: heapless::String<256> = heapless::String::new();
+            let found = core::str::from_utf8(&response_body[..body_len])
+                .ok()
+                .and_then(|body| util::json::find_json_str_field(body, "message"))
+                // the unescaped message is never longer than the body itself
+                .is_some_and(|raw| util::json::unescape_json_str(&mut message, raw).is_ok());
+
+            if found {
+                error!(
+                    "export: influxdb: request failed: {=u16}: {=str}",
+                    response.status(),
+                    message.as_str()
+                );
+            } else {
+                error!(
+                    "export: influxdb: request failed: {=u16}",
+                    response.status()
+                );
+            }
         } else {
             info!(
                 "export: influxdb: successfully exported {=u32} value(s)",
                 exported_count
             );
         }
-        Ok(())
+        Ok(Some(response.status()))
     }
 }
 
-impl InfluxDbExporter {
-    fn write_value_to_body(body_buf: &mut HttpBody, value: SensorValuePoint, first_value: bool) {
+impl WebhookExporter {
+    /// Returns `None` if no host is configured.
+    async fn from_config() -> Option<Self> {
+        let cfg = CONFIG.lock().await.webhook.clone();
+        Some(Self {
+            host: cfg.host?,
+            port: cfg.port,
+            path: cfg.path,
+            token: cfg.token,
+        })
+    }
+}
+
+impl ValuesExporter for WebhookExporter {
+    async fn export(
+        &self,
+        client: &mut HttpClient<'_>,
+        values: &[SensorValuePoint],
+    ) -> Result<Option<u16>, HttpClientError> {
         use core::fmt::Write;
 
-        if !first_value {
-            body_buf.push(b'\n');
+        if values.is_empty() {
+            // don't send empty requests
+            return Ok(None);
         }
-        let _ = match value.value {
-            SensorValue::Temperature(v) => {
-                write!(body_buf, r#"temperature value={v}"#)
-            }
-            SensorValue::Pressure(v) => {
-                write!(body_buf, r#"pressure value={v}"#)
-            }
-            SensorValue::Altitude(v) => {
-                write!(body_buf, r#"altitude value={v}"#)
-            }
-            SensorValue::AirQuality(v) => {
-                write!(body_buf, r#"dust_density value={v}"#)
+
+        let mut req = client
+            .request(HttpMethod::Post, &self.host, self.port, self.path)
+            .await?;
+        req.header("Content-Type", "application/json").await?;
+
+        if let Some(token) = self.token {
+            let mut auth_buf: heapless::String<128> = heapless::String::new();
+            if write!(&mut auth_buf, "Bearer {}", token).is_err() {
+                error!("export: webhook: token is too long, skipping export");
+                return Ok(None);
             }
-            SensorValue::Unknown { .. } => Ok(()),
-        };
+            req.header("Authorization", &auth_buf).await?;
+        }
+
+        let exported_count =
+            util::export::write_webhook_body(&mut req.body(), values).unwrap_or_default();
+
+        let response = req.finish().await?;
+        if response.status() < 200 || response.status() >= 300 {
+            error!("export: webhook: request failed: {=u16}", response.status());
+        } else {
+            info!(
+                "export: webhook: successfully exported {=u32} value(s)",
+                exported_count
+            );
+        }
+        Ok(Some(response.status()))
     }
 }
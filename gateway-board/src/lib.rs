@@ -3,23 +3,46 @@
 #![allow(clippy::missing_panics_doc, clippy::missing_errors_doc)]
 
 use embassy_sync::blocking_mutex::raw::NoopRawMutex;
-use protocol::app::v1::SensorValuePoint;
+use protocol::app::v1::{Capabilities, RawValue, SensorValue, SensorValuePoint};
+use static_cell::StaticCell;
 
 extern crate alloc;
 
+pub mod board;
 #[cfg(feature = "lora")]
 pub mod comm;
 pub mod config;
+pub mod console;
+pub mod diagnostics;
 #[cfg(feature = "display-ssd1306")]
 pub mod display;
 pub mod export;
+pub mod logging;
 #[cfg(feature = "lora")]
 pub mod lora;
 #[cfg(feature = "wifi")]
 pub mod net;
+pub mod watchdog;
 
 pub const PROTOCOL_VERSION_MAJOR: u8 = 1;
 pub const PROTOCOL_VERSION_MINOR: u8 = 0;
+/// Optional protocol features supported by the gateway, negotiated with each sensor board during the handshake.
+///
+/// Downlink commands carry the configured measurement interval and, with the `adr` feature,
+/// spreading factor recommendations. Frequency hopping is only offered with the `frequency-hopping` feature.
+/// Link acks are only sent to the sensor boards that ask for them.
+pub const PROTOCOL_CAPABILITIES: Capabilities = Capabilities::COMPRESSED_DATA
+    .union(Capabilities::KEEPALIVE)
+    .union(Capabilities::DOWNLINK_COMMANDS)
+    .union(Capabilities::LINK_ACKS)
+    .union(if cfg!(feature = "frequency-hopping") {
+        Capabilities::FREQUENCY_HOPPING
+    } else {
+        Capabilities::NONE
+    });
+
+/// Default number of sensor values buffered between the LoRa and export tasks.
+pub const DEFAULT_VALUE_CHANNEL_SIZE: usize = 16;
 
 pub type ValueChannel =
     embassy_sync::zerocopy_channel::Channel<'static, NoopRawMutex, SensorValuePoint>;
@@ -28,6 +51,35 @@ pub type ValueSender =
 pub type ValueReceiver =
     embassy_sync::zerocopy_channel::Receiver<'static, NoopRawMutex, SensorValuePoint>;
 
+/// Takes the sender and receiver of the channel of sensor values, holding [`DEFAULT_VALUE_CHANNEL_SIZE`] values.
+///
+/// The channel is a singleton allocated in static memory, returns `None` if it was already taken.
+pub fn try_take_value_channel() -> Option<(ValueSender, ValueReceiver)> {
+    const {
+        assert!(
+            DEFAULT_VALUE_CHANNEL_SIZE > 0,
+            "the value channel cannot be empty"
+        );
+    }
+
+    static VALUE_CHANNEL_BUF: StaticCell<[SensorValuePoint; DEFAULT_VALUE_CHANNEL_SIZE]> =
+        StaticCell::new();
+    static VALUE_CHANNEL: StaticCell<ValueChannel> = StaticCell::new();
+
+    const DUMMY_VALUE: SensorValuePoint = SensorValuePoint {
+        value: SensorValue::Unknown {
+            id: 255,
+            value_len: 0,
+            raw: RawValue::EMPTY,
+        },
+        time_offset: -99,
+    };
+
+    let buf = VALUE_CHANNEL_BUF.try_init_with(|| [DUMMY_VALUE; DEFAULT_VALUE_CHANNEL_SIZE])?;
+    let value_channel = VALUE_CHANNEL.try_init_with(|| ValueChannel::new(buf))?;
+    Some(value_channel.split())
+}
+
 struct TimeoutError;
 
 trait FutureTimeoutExt: core::future::Future {
@@ -0,0 +1,43 @@
+//! Forwards the records of the `log` crate to defmt, so that dependencies logging through it end up in the same
+//! stream as the gateway, instead of being dropped for lack of a logger.
+//!
+//! The gateway itself only logs with defmt.
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// Records more verbose than this are dropped before being formatted.
+const MAX_LOG_LEVEL: LevelFilter = LevelFilter::Info;
+
+struct DefmtLogger;
+
+impl Log for DefmtLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= MAX_LOG_LEVEL
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let target = record.target();
+        let args = defmt::Display2Format(record.args());
+        match record.level() {
+            Level::Error => defmt::error!("{=str}: {}", target, args),
+            Level::Warn => defmt::warn!("{=str}: {}", target, args),
+            Level::Info => defmt::info!("{=str}: {}", target, args),
+            Level::Debug => defmt::debug!("{=str}: {}", target, args),
+            Level::Trace => defmt::trace!("{=str}: {}", target, args),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: DefmtLogger = DefmtLogger;
+
+/// Installs the bridge, to be called once before spawning the tasks that may log.
+pub fn init() {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(MAX_LOG_LEVEL);
+    }
+}
@@ -1,10 +1,12 @@
-use defmt::{error, trace, Format};
+#[cfg(feature = "lora-debug")]
+use defmt::info;
+use defmt::{error, trace, warn, Debug2Format, Format};
 use embassy_embedded_hal::shared_bus::asynch::spi::SpiDevice;
 use embassy_futures::select::Either;
 use embassy_sync::{blocking_mutex::raw::NoopRawMutex, mutex::Mutex};
-use embassy_time::Delay;
+use embassy_time::{Delay, Duration, Instant};
 use esp_hal::{
-    gpio::{GpioPin, Input, InputConfig, Level, Output, OutputConfig},
+    gpio::{Input, InputConfig, Level, Output, OutputConfig},
     peripherals::SPI2,
     spi::Mode,
     time::Rate,
@@ -21,8 +23,17 @@ use lora_phy::{
 use protocol::phy::PhysicalLayer;
 use static_cell::StaticCell;
 use thiserror::Error;
+use util::hopping::{default_hop_channels, parse_hop_channels, HopChannels, HopSchedule};
+
+use crate::{
+    board::LoraPins,
+    config::LoraSyncWord,
+    watchdog::{self, WatchedTask},
+};
 
 /// Channel to use, should be "unique". Use same frequencies as other devices causes spurious packets.
+///
+/// Used for handshakes, frequency hopping may switch to other channels afterwards.
 pub const LORA_FREQUENCY_IN_HZ: u32 = 868_200_000;
 /// Channel width. Lower values increase time on air, but may be able to find clear frequencies.
 pub const LORA_BANDWITH: Bandwidth = Bandwidth::_250KHz;
@@ -30,18 +41,39 @@ pub const LORA_BANDWITH: Bandwidth = Bandwidth::_250KHz;
 /// of actual data in transmissions.
 pub const LORA_CODING_RATE: CodingRate = CodingRate::_4_8;
 /// Controls the chirp rate. Lower values are slower bandwidth (longer time on air), but more robust.
-pub const LORA_SPREADING_FACTOR: SpreadingFactor = SpreadingFactor::_10;
+///
+/// Used for handshakes, adaptive data rate may switch to another one afterwards.
+pub const LORA_SPREADING_FACTOR: u8 = 10;
 pub const LORA_RX_BUF_SIZE: usize = 128;
+/// Default receive timeout of `PhysicalLayer::read()`, after which an empty packet is returned.
+///
+/// Without a timeout, see [`LoraController::set_rx_timeout`], the radio still stops waiting this often
+/// to feed the watchdog and to check the fallbacks.
+pub const LORA_RX_TIMEOUT: Duration = Duration::from_secs(5);
+/// Consecutive radio errors after which [`LoraController::needs_reinit`] asks for the radio to be reset.
+pub const LORA_MAX_CONSECUTIVE_RADIO_ERRORS: u8 = 3;
+/// Transmit power used when none is configured, in dBm.
+///
+/// This is above [`LORA_BAND_MAX_ERP_DBM`], it only stays within the EU868 limit when the antenna
+/// and cable losses make up for the difference.
+pub const LORA_DEFAULT_TX_POWER_DBM: i32 = 20;
+/// Range of transmit power supported by the SX1262, in dBm.
+pub const LORA_TX_POWER_RANGE_DBM: core::ops::RangeInclusive<i32> = -9..=22;
+/// Maximum radiated power allowed in the EU868 sub-band used by [`LORA_FREQUENCY_IN_HZ`] (25 mW ERP), in dBm.
+///
+/// The actual radiated power depends on the antenna, so a power set above it with
+/// [`LoraController::set_tx_power`] only triggers a warning.
+pub const LORA_BAND_MAX_ERP_DBM: i32 = 14;
+/// Time without receiving anything after which the radio goes back to [`LORA_SPREADING_FACTOR`],
+/// in case the sensor board missed the switch to another one.
+pub const LORA_SPREADING_FACTOR_FALLBACK_TIMEOUT: Duration = Duration::from_secs(60);
+/// Time without receiving anything after which the radio goes back to [`LORA_FREQUENCY_IN_HZ`],
+/// in case the sensor board missed a hop to another channel.
+pub const LORA_CHANNEL_FALLBACK_TIMEOUT: Duration = Duration::from_secs(60);
 
 pub struct LoraHardware {
     pub spi: SPI2,
-    pub spi_nss: GpioPin<8>,
-    pub spi_scl: GpioPin<9>,
-    pub spi_mosi: GpioPin<10>,
-    pub spi_miso: GpioPin<11>,
-    pub reset: GpioPin<12>,
-    pub busy: GpioPin<13>,
-    pub dio1: GpioPin<14>,
+    pub pins: LoraPins,
 }
 
 type AsyncSpi = esp_hal::spi::master::Spi<'static, Async>;
@@ -51,13 +83,69 @@ type HeltecLora32Lora = LoRa<
     Delay,
 >;
 
+/// Radio settings that may differ between deployments.
+#[derive(Clone)]
+pub struct LoraConfig {
+    /// Transmit power in dBm
+    pub tx_power: i32,
+    /// Channels hopped through when frequency hopping is negotiated, the same as the sensor boards'
+    pub hop_channels: HopChannels,
+    /// Sync word, the same as the sensor boards'
+    pub sync_word: LoraSyncWord,
+}
+
+impl Default for LoraConfig {
+    fn default() -> Self {
+        Self {
+            tx_power: LORA_DEFAULT_TX_POWER_DBM,
+            hop_channels: hop_channels_from_env(),
+            sync_word: LoraSyncWord::Private,
+        }
+    }
+}
+
+/// Channels set with the `LORA_HOP_CHANNELS` environment variable while building, the EU868 defaults otherwise.
+fn hop_channels_from_env() -> HopChannels {
+    let Some(channels) = option_env!("LORA_HOP_CHANNELS") else {
+        return default_hop_channels();
+    };
+    parse_hop_channels(channels).unwrap_or_else(|e| {
+        warn!(
+            "phy: invalid LORA_HOP_CHANNELS ({}), using the defaults",
+            Debug2Format(&e)
+        );
+        default_hop_channels()
+    })
+}
+
+/// Signal quality of a received packet.
+#[derive(Clone, Copy, Format)]
+pub struct RxQuality {
+    /// Received signal strength in dBm
+    pub rssi: i16,
+    /// Signal-to-noise ratio in dB
+    pub snr: i16,
+}
+
 pub struct LoraController {
     lora: HeltecLora32Lora,
     modulation_params: ModulationParams,
     tx_packet_params: PacketParams,
     rx_packet_params: PacketParams,
+    tx_power: i32,
+    spreading_factor: u8,
+    frequency: u32,
+    hop_schedule: HopSchedule,
+    /// Reception time of the last packet
+    last_rx: Instant,
     rx_buffer: heapless::Vec<u8, LORA_RX_BUF_SIZE>,
+    /// Signal quality of the packet in `rx_buffer`
+    rx_quality: Option<RxQuality>,
+    /// Receive timeout of `PhysicalLayer::read()`, `None` to wait until a packet is received
+    rx_timeout: Option<Duration>,
     tx_buffer: heapless::Vec<u8, LORA_RX_BUF_SIZE>,
+    /// Radio errors since the last successful transmission or reception
+    consecutive_radio_errors: u8,
 }
 
 /// One-stop shop for LoRa-related errors
@@ -69,6 +157,10 @@ pub enum LoraError {
     Radio(RadioError),
     #[error("buffer overflow")]
     BufferOverflow,
+    #[error("unsupported TX power: {0} dBm")]
+    InvalidTxPower(i32),
+    #[error("unsupported spreading factor: SF{0}")]
+    InvalidSpreadingFactor(u8),
 }
 
 impl From<RadioError> for LoraError {
@@ -80,7 +172,9 @@ impl From<RadioError> for LoraError {
 static SPI_BUS: StaticCell<Mutex<NoopRawMutex, AsyncSpi>> = StaticCell::new();
 
 impl LoraController {
-    pub async fn new(hardware: LoraHardware) -> Result<Self, LoraError> {
+    pub async fn new(hardware: LoraHardware, config: LoraConfig) -> Result<Self, LoraError> {
+        check_tx_power(config.tx_power)?;
+
         // The SPI bus used by the lora dio is exclusive to it.
         // No need to use mutexes or other synchonization
         let spi: AsyncSpi = esp_hal::spi::master::Spi::new(
@@ -89,9 +183,9 @@ impl LoraController {
                 .with_frequency(Rate::from_khz(100))
                 .with_mode(Mode::_0),
         )?
-        .with_sck(hardware.spi_scl)
-        .with_mosi(hardware.spi_mosi)
-        .with_miso(hardware.spi_miso)
+        .with_sck(hardware.pins.spi_scl)
+        .with_mosi(hardware.pins.spi_mosi)
+        .with_miso(hardware.pins.spi_miso)
         .into_async();
 
         // Create the SX126x configuration
@@ -103,10 +197,10 @@ impl LoraController {
         };
 
         // Initialize GPIO pins
-        let nss = Output::new(hardware.spi_nss, Level::High, OutputConfig::default());
-        let reset = Output::new(hardware.reset, Level::Low, OutputConfig::default());
-        let busy = Input::new(hardware.busy, InputConfig::default());
-        let dio1 = Input::new(hardware.dio1, InputConfig::default());
+        let nss = Output::new(hardware.pins.spi_nss, Level::High, OutputConfig::default());
+        let reset = Output::new(hardware.pins.reset, Level::Low, OutputConfig::default());
+        let busy = Input::new(hardware.pins.busy, InputConfig::default());
+        let dio1 = Input::new(hardware.pins.dio1, InputConfig::default());
 
         // Initialize the SPI bus
         let spi_bus: &mut Mutex<NoopRawMutex, AsyncSpi> = SPI_BUS.init_with(|| Mutex::new(spi));
@@ -120,16 +214,186 @@ impl LoraController {
         // Create the radio instance
         let iv: GenericSx126xInterfaceVariant<Output<'static>, Input<'static>> =
             GenericSx126xInterfaceVariant::new(reset, dio1, busy, None, None).unwrap();
-        let mut lora: HeltecLora32Lora =
-            LoRa::new(Sx126x::new(spi_device, iv, sx126x_config), false, Delay)
-                .await
-                .unwrap();
+        let enable_public_network = config.sync_word == LoraSyncWord::Public;
+        let mut lora: HeltecLora32Lora = LoRa::new(
+            Sx126x::new(spi_device, iv, sx126x_config),
+            enable_public_network,
+            Delay,
+        )
+        .await
+        .unwrap();
+
+        let (modulation_params, tx_packet_params, rx_packet_params) =
+            Self::create_params(&mut lora, LORA_SPREADING_FACTOR, LORA_FREQUENCY_IN_HZ)?;
+
+        Ok(LoraController {
+            lora,
+            modulation_params,
+            tx_packet_params,
+            rx_packet_params,
+            tx_power: config.tx_power,
+            spreading_factor: LORA_SPREADING_FACTOR,
+            frequency: LORA_FREQUENCY_IN_HZ,
+            hop_schedule: HopSchedule::new(config.hop_channels),
+            last_rx: Instant::now(),
+            rx_buffer: heapless::Vec::new(),
+            rx_quality: None,
+            rx_timeout: Some(LORA_RX_TIMEOUT),
+            tx_buffer: heapless::Vec::new(),
+            consecutive_radio_errors: 0,
+        })
+    }
+
+    /// Current transmit power in dBm.
+    pub fn tx_power(&self) -> i32 {
+        self.tx_power
+    }
+
+    /// Changes the transmit power, applied from the next transmission onwards.
+    ///
+    /// Warns if it may exceed the band's limits.
+    pub fn set_tx_power(&mut self, tx_power: i32) -> Result<(), LoraError> {
+        check_tx_power(tx_power)?;
+        if tx_power > LORA_BAND_MAX_ERP_DBM {
+            warn!(
+                "phy: TX power {=i32} dBm exceeds the band limit of {=i32} dBm ERP, make sure the antenna compensates",
+                tx_power, LORA_BAND_MAX_ERP_DBM
+            );
+        }
+        self.tx_power = tx_power;
+        Ok(())
+    }
+
+    /// Current spreading factor.
+    pub fn spreading_factor(&self) -> u8 {
+        self.spreading_factor
+    }
+
+    /// Changes the spreading factor, applied from the next transmission or reception onwards.
+    pub fn set_spreading_factor(&mut self, spreading_factor: u8) -> Result<(), LoraError> {
+        self.set_params(spreading_factor, self.frequency)
+    }
+
+    /// Receive timeout of `PhysicalLayer::read()`, `None` if it waits until a packet is received.
+    pub fn rx_timeout(&self) -> Option<Duration> {
+        self.rx_timeout
+    }
+
+    /// Changes the receive timeout of `PhysicalLayer::read()`, `None` to wait until a packet is received.
+    ///
+    /// `PhysicalLayer::read_timeout()` keeps using its own timeout.
+    pub fn set_rx_timeout(&mut self, timeout: Option<Duration>) {
+        self.rx_timeout = timeout;
+    }
+
+    /// Current channel, in Hz.
+    pub fn frequency(&self) -> u32 {
+        self.frequency
+    }
+
+    /// Moves to the next channel of the hopping schedule, applied from the next transmission or reception onwards.
+    pub fn hop(&mut self) -> Result<(), LoraError> {
+        let Some(frequency) = self.hop_schedule.advance() else {
+            return Ok(());
+        };
+        trace!("phy: hopping to {=u32} Hz", frequency);
+        self.set_params(self.spreading_factor, frequency)
+    }
+
+    /// Goes back to [`LORA_FREQUENCY_IN_HZ`] and to the beginning of the hopping schedule.
+    pub fn reset_channel(&mut self) -> Result<(), LoraError> {
+        self.hop_schedule.reset();
+        if self.frequency == LORA_FREQUENCY_IN_HZ {
+            return Ok(());
+        }
+        self.set_params(self.spreading_factor, LORA_FREQUENCY_IN_HZ)
+    }
 
+    /// Whether the last [`LORA_MAX_CONSECUTIVE_RADIO_ERRORS`] transmissions or receptions all failed,
+    /// in which case the radio may be stuck and should go through [`Self::reinit`].
+    pub fn needs_reinit(&self) -> bool {
+        self.consecutive_radio_errors >= LORA_MAX_CONSECUTIVE_RADIO_ERRORS
+    }
+
+    /// Resets the radio through its reset pin and applies the current settings again,
+    /// to recover from failed SPI transactions that may have left it in an inconsistent state.
+    pub async fn reinit(&mut self) -> Result<(), LoraError> {
+        warn!(
+            "phy: resetting the radio after {=u8} consecutive errors",
+            self.consecutive_radio_errors
+        );
+        self.consecutive_radio_errors = 0;
+        self.tx_buffer.clear();
+        self.lora.init().await?;
+        self.set_params(self.spreading_factor, self.frequency)
+    }
+
+    /// Keeps track of consecutive radio errors, see [`Self::needs_reinit`].
+    fn count_radio_errors<T>(&mut self, res: Result<T, LoraError>) -> Result<T, LoraError> {
+        match &res {
+            Ok(_) => self.consecutive_radio_errors = 0,
+            Err(LoraError::Radio(_)) => {
+                self.consecutive_radio_errors = self.consecutive_radio_errors.saturating_add(1);
+            }
+            Err(_) => (),
+        }
+        res
+    }
+
+    fn set_params(&mut self, spreading_factor: u8, frequency: u32) -> Result<(), LoraError> {
+        let (modulation_params, tx_packet_params, rx_packet_params) =
+            Self::create_params(&mut self.lora, spreading_factor, frequency)?;
+        self.modulation_params = modulation_params;
+        self.tx_packet_params = tx_packet_params;
+        self.rx_packet_params = rx_packet_params;
+        self.spreading_factor = spreading_factor;
+        self.frequency = frequency;
+        Ok(())
+    }
+
+    /// Goes back to [`LORA_SPREADING_FACTOR`] if nothing was received with another one for a while.
+    fn fall_back_to_default_spreading_factor(&mut self) {
+        if self.spreading_factor == LORA_SPREADING_FACTOR
+            || self.last_rx.elapsed() < LORA_SPREADING_FACTOR_FALLBACK_TIMEOUT
+        {
+            return;
+        }
+        warn!(
+            "phy: nothing received with SF{=u8}, going back to SF{=u8}",
+            self.spreading_factor, LORA_SPREADING_FACTOR
+        );
+        if let Err(err) = self.set_spreading_factor(LORA_SPREADING_FACTOR) {
+            error!("phy: failed to change spreading factor: {}", err);
+        }
+    }
+
+    /// Goes back to [`LORA_FREQUENCY_IN_HZ`] if nothing was received on another channel for a while.
+    fn fall_back_to_default_channel(&mut self) {
+        if self.frequency == LORA_FREQUENCY_IN_HZ
+            || self.last_rx.elapsed() < LORA_CHANNEL_FALLBACK_TIMEOUT
+        {
+            return;
+        }
+        warn!(
+            "phy: nothing received on {=u32} Hz, going back to {=u32} Hz",
+            self.frequency, LORA_FREQUENCY_IN_HZ
+        );
+        if let Err(err) = self.reset_channel() {
+            error!("phy: failed to change channel: {}", err);
+        }
+    }
+
+    /// Creates the modulation and packet parameters for `spreading_factor` and `frequency`, the latter depend on the former.
+    fn create_params(
+        lora: &mut HeltecLora32Lora,
+        spreading_factor: u8,
+        frequency: u32,
+    ) -> Result<(ModulationParams, PacketParams, PacketParams), LoraError> {
         let modulation_params = lora.create_modulation_params(
-            LORA_SPREADING_FACTOR,
+            to_spreading_factor(spreading_factor)?,
             LORA_BANDWITH,
             LORA_CODING_RATE,
-            LORA_FREQUENCY_IN_HZ,
+            frequency,
         )?;
 
         // Don't ask: I don't know what that is either
@@ -145,14 +409,69 @@ impl LoraController {
             &modulation_params,
         )?;
 
-        Ok(LoraController {
-            lora,
-            modulation_params,
-            tx_packet_params,
-            rx_packet_params,
-            rx_buffer: heapless::Vec::new(),
-            tx_buffer: heapless::Vec::new(),
-        })
+        Ok((modulation_params, tx_packet_params, rx_packet_params))
+    }
+
+    /// Signal quality of the last received packet, `None` if the last read timed out.
+    pub fn rx_quality(&self) -> Option<RxQuality> {
+        self.rx_quality
+    }
+
+    /// Logs every received frame in an infinite loop, without answering any of them.
+    ///
+    /// Unlike the link layer, frames with a bad signature or length are reported instead of being dropped.
+    /// Only handshakes are signed with `shared_key`, the other frames are checked with the session key of their
+    /// sensor board, derived from the last handshake reply sniffed for it.
+    #[cfg(feature = "lora-debug")]
+    pub async fn sniff(&mut self, shared_key: &[u8]) -> ! {
+        use protocol::link::v1::{LinkPhase, LinkSniffer};
+
+        let mut sniffer = LinkSniffer::new(shared_key);
+        info!("phy: sniffing LoRa frames");
+        loop {
+            match self.read_timeout(LORA_RX_TIMEOUT).await {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(err) => {
+                    error!("phy: failed to receive while sniffing: {}", err);
+                    continue;
+                }
+            }
+            let Some(quality) = self.rx_quality else {
+                continue;
+            };
+            info!(
+                "phy: sniffed {=usize} bytes (rssi: {=i16} dBm, snr: {=i16} dB): {=[u8]:02x}",
+                self.rx_buffer.len(),
+                quality.rssi,
+                quality.snr,
+                &self.rx_buffer
+            );
+            match sniffer.inspect(&self.rx_buffer) {
+                Some(frame) => info!(
+                    "phy: frame phase {}, id {=u8}, declared length {=u8}{}, signature {}",
+                    match frame.phase {
+                        Some(LinkPhase::Handshake) => "handshake",
+                        Some(LinkPhase::Data) => "data",
+                        Some(LinkPhase::Ack) => "ack",
+                        None => "reserved",
+                    },
+                    frame.id,
+                    frame.declared_len,
+                    if frame.declared_len as usize == self.rx_buffer.len() {
+                        ""
+                    } else {
+                        " (mismatch)"
+                    },
+                    if frame.signature_valid {
+                        "valid"
+                    } else {
+                        "invalid"
+                    }
+                ),
+                None => info!("phy: frame too small for a link header"),
+            }
+        }
     }
 
     pub async fn send(&mut self) -> Result<(), LoraError> {
@@ -160,7 +479,7 @@ impl LoraController {
             .prepare_for_tx(
                 &self.modulation_params,
                 &mut self.tx_packet_params,
-                20,
+                self.tx_power,
                 &self.tx_buffer,
             )
             .await?;
@@ -172,7 +491,8 @@ impl LoraController {
         Ok(())
     }
 
-    async fn recv(&mut self) -> Result<(), LoraError> {
+    /// Waits for a packet for at most `timeout`, returns whether one was received.
+    async fn recv(&mut self, timeout: Duration) -> Result<bool, LoraError> {
         self.lora
             .prepare_for_rx(
                 lora_phy::RxMode::Continuous,
@@ -184,11 +504,14 @@ impl LoraController {
         unsafe {
             self.rx_buffer.set_len(LORA_RX_BUF_SIZE);
         }
-        trace!("phy: waiting for data (timeout in 5 seconds)");
+        trace!(
+            "phy: waiting for data (timeout in {=u64} ms)",
+            timeout.as_millis()
+        );
 
         let res = embassy_futures::select::select(
             self.lora.rx(&self.rx_packet_params, &mut self.rx_buffer),
-            embassy_time::Timer::after(embassy_time::Duration::from_secs(5)),
+            embassy_time::Timer::after(timeout),
         )
         .await;
 
@@ -198,36 +521,91 @@ impl LoraController {
                 unsafe {
                     self.rx_buffer.set_len(received_len as usize);
                 }
+                self.rx_quality = Some(RxQuality {
+                    rssi: rx_pkt_status.rssi,
+                    snr: rx_pkt_status.snr,
+                });
+                self.last_rx = Instant::now();
                 trace!(
                     "phy: received packet of length {=usize} (rssi: {=i16}, snr: {=i16})",
                     self.rx_buffer.len(),
                     rx_pkt_status.rssi,
                     rx_pkt_status.snr
                 );
+                Ok(true)
             }
             Either::Second(()) => {
                 trace!("phy: timeout while waiting for data");
                 self.rx_buffer.clear();
+                self.rx_quality = None;
+                self.fall_back_to_default_spreading_factor();
+                self.fall_back_to_default_channel();
+                Ok(false)
             }
         }
+    }
+}
 
-        Ok(())
+/// Converts a spreading factor number to its radio setting.
+fn to_spreading_factor(spreading_factor: u8) -> Result<SpreadingFactor, LoraError> {
+    Ok(match spreading_factor {
+        5 => SpreadingFactor::_5,
+        6 => SpreadingFactor::_6,
+        7 => SpreadingFactor::_7,
+        8 => SpreadingFactor::_8,
+        9 => SpreadingFactor::_9,
+        10 => SpreadingFactor::_10,
+        11 => SpreadingFactor::_11,
+        12 => SpreadingFactor::_12,
+        _ => return Err(LoraError::InvalidSpreadingFactor(spreading_factor)),
+    })
+}
+
+/// Checks that `tx_power` is supported by the radio.
+fn check_tx_power(tx_power: i32) -> Result<(), LoraError> {
+    if !LORA_TX_POWER_RANGE_DBM.contains(&tx_power) {
+        error!(
+            "phy: TX power {=i32} dBm is outside of the supported range ({=i32} to {=i32} dBm)",
+            tx_power,
+            *LORA_TX_POWER_RANGE_DBM.start(),
+            *LORA_TX_POWER_RANGE_DBM.end()
+        );
+        return Err(LoraError::InvalidTxPower(tx_power));
     }
+    Ok(())
 }
 
 impl PhysicalLayer for LoraController {
     type Error = LoraError;
 
     async fn read(&mut self) -> Result<(), Self::Error> {
+        loop {
+            // called in a loop even when idle, thanks to the receive timeout
+            watchdog::heartbeat(WatchedTask::Lora);
+            self.rx_buffer.clear();
+            let res = self.recv(self.rx_timeout.unwrap_or(LORA_RX_TIMEOUT)).await;
+            // an empty buffer on timeout lets callers poll in a loop
+            if self.count_radio_errors(res)? || self.rx_timeout.is_some() {
+                return Ok(());
+            }
+        }
+    }
+
+    async fn read_timeout(&mut self, timeout: Duration) -> Result<bool, Self::Error> {
+        watchdog::heartbeat(WatchedTask::Lora);
         self.rx_buffer.clear();
-        self.recv().await?;
-        Ok(())
+        let res = self.recv(timeout).await;
+        self.count_radio_errors(res)
     }
 
     fn rx_buffer(&self) -> &[u8] {
         &self.rx_buffer
     }
 
+    fn max_payload_len(&self) -> usize {
+        LORA_RX_BUF_SIZE
+    }
+
     async fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
         self.tx_buffer
             .extend_from_slice(data)
@@ -238,6 +616,7 @@ impl PhysicalLayer for LoraController {
         if self.tx_buffer.is_empty() {
             return Ok(());
         }
-        self.send().await
+        let res = self.send().await;
+        self.count_radio_errors(res)
     }
 }
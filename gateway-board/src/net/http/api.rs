@@ -1,57 +1,66 @@
 use core::fmt::Write;
-use core::str::FromStr;
-use defmt::{info, warn};
+use defmt::{info, warn, Debug2Format};
+use embassy_time::Instant;
+use util::{
+    form::{FieldError, FormAction, FormConfig, FormSubmission},
+    gzip,
+    template::html_escape,
+};
 
 use crate::{
-    config::CONFIG,
-    net::http::{HttpMethod, HttpServerError, HttpServerRequest, HttpServerResponse},
+    config::{Config, ConfigVariable, CONFIG},
+    net::http::pages::{self, Page},
+    net::http::{
+        HttpMethod, HttpServerError, HttpServerRequest, HttpServerResponse,
+        HTTP_MAX_AUTHORIZATION_LEN,
+    },
 };
 
-#[derive(PartialEq)]
-pub enum ConfigurationVariable {
-    CsrfToken,
-    WifiStaSsid,
-    WifiStaPassword,
-    WifiApSsid,
-    DnsServer1,
-    DnsServer2,
-    InfluxDbHost,
-    InfluxDbPort,
-    HtmlFormAction,
-}
+/// Shown by browsers when asking for the dashboard credentials
+const DASHBOARD_REALM: &str = "SensorSensei gateway";
 
-impl TryFrom<&[u8]> for ConfigurationVariable {
-    type Error = ();
-
-    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        match value {
-            b"csrf_token" => Ok(ConfigurationVariable::CsrfToken),
-            b"wifi_sta_ssid" => Ok(ConfigurationVariable::WifiStaSsid),
-            b"wifi_sta_password" => Ok(ConfigurationVariable::WifiStaPassword),
-            b"wifi_ap_ssid" => Ok(ConfigurationVariable::WifiApSsid),
-            b"dns_server_1" => Ok(ConfigurationVariable::DnsServer1),
-            b"dns_server_2" => Ok(ConfigurationVariable::DnsServer2),
-            b"influx_db_host" => Ok(ConfigurationVariable::InfluxDbHost),
-            b"influx_db_port" => Ok(ConfigurationVariable::InfluxDbPort),
-            b"action" => Ok(ConfigurationVariable::HtmlFormAction),
-            _ => Err(()),
-        }
+/// Sensor boards heard from within this delay are reported as connected by `/status`
+#[cfg(feature = "lora")]
+const SENSOR_CONNECTED_TIMEOUT: embassy_time::Duration = embassy_time::Duration::from_secs(60);
+
+impl FormConfig for Config {
+    type Variable = ConfigVariable;
+
+    fn variable(name: &[u8]) -> Option<ConfigVariable> {
+        ConfigVariable::from_name(name)
+    }
+
+    fn is_password(var: ConfigVariable) -> bool {
+        matches!(
+            var,
+            ConfigVariable::WifiStaPassword | ConfigVariable::WifiApPassword
+        )
+    }
+
+    fn csrf_token(&self) -> &str {
+        &self.csrf_token
+    }
+
+    fn set_variable(&mut self, var: ConfigVariable, value: &str) -> Result<(), ()> {
+        // invalid values are already logged
+        Config::set_variable(self, var, value).map_err(drop)
     }
 }
 
-enum HtmlFormAction {
-    Apply,
-    SaveAndReboot,
+/// Key/value pairs of a form submission, from either supported body type.
+enum FormFields<'b> {
+    UrlEncoded(util::encoding::DecodeFormUrlEncoded<'b>),
+    /// A flat JSON object, for scripts
+    Json(util::json::DecodeJsonObject<'b>),
 }
 
-impl TryFrom<&[u8]> for HtmlFormAction {
-    type Error = ();
+impl<'b> Iterator for FormFields<'b> {
+    type Item = (&'b [u8], &'b [u8]);
 
-    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        match value {
-            b"apply" => Ok(Self::Apply),
-            b"save-reboot" => Ok(Self::SaveAndReboot),
-            _ => Err(()),
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::UrlEncoded(fields) => fields.next(),
+            Self::Json(fields) => fields.next(),
         }
     }
 }
@@ -59,78 +68,273 @@ impl TryFrom<&[u8]> for HtmlFormAction {
 pub async fn dispatch_http_request<'a, 'r>(
     request: HttpServerRequest<'a, 'r>,
 ) -> Result<HttpServerResponse<'a, 'r>, HttpServerError> {
-    Ok(match request.method() {
-        HttpMethod::Get => return_dashboard_form(request).await?,
-        HttpMethod::Post => handle_dashboard_post(request).await?,
+    if !is_authorized(&request).await {
+        warn!(
+            "HTTP request without valid credentials from {:?}, requesting authentication",
+            request.peer()
+        );
+        let mut res = request.new_response();
+        res.return_unauthorized(DASHBOARD_REALM).await?;
+        return Ok(res);
+    }
+
+    Ok(match (request.method(), request.path()) {
+        #[cfg(feature = "lora")]
+        (HttpMethod::Get, "/metrics") => return_metrics(request).await?,
+        (HttpMethod::Get, "/status") => return_status(request).await?,
+        (HttpMethod::Get, _) => return_dashboard_form(request).await?,
+        (HttpMethod::Post, "/test-export") => handle_test_export(request).await?,
+        (HttpMethod::Post, _) => handle_dashboard_post(request).await?,
     })
 }
 
-async fn return_dashboard_form<'a, 'r>(
+/// Checks the Basic authentication credentials of `request`, if the dashboard is protected.
+async fn is_authorized(request: &HttpServerRequest<'_, '_>) -> bool {
+    // don't keep the config locked while hashing the password
+    let Some(auth) = CONFIG.lock().await.dashboard_auth.clone() else {
+        return true;
+    };
+    // decoded credentials are shorter than their base64 encoding
+    let mut credentials = [0u8; HTTP_MAX_AUTHORIZATION_LEN];
+    request
+        .authorization()
+        .and_then(|value| util::http::parse_basic_auth(value, &mut credentials))
+        .is_some_and(|(username, password)| auth.verify(username, password))
+}
+
+/// Returns the LoRa connection statistics and the latest value of each type in the Prometheus text format.
+#[cfg(feature = "lora")]
+async fn return_metrics<'a, 'r>(
     request: HttpServerRequest<'a, 'r>,
 ) -> Result<HttpServerResponse<'a, 'r>, HttpServerError> {
-    info!("HTTP GET request, returning form page");
+    use crate::comm::app::{HISTORY, STATS};
+    use protocol::app::v1::SensorValue;
+    use util::export::{value_with_type, VALUE_TYPE_NAMES};
+
+    info!("HTTP GET request, returning metrics");
     let mut res = request.new_response();
-    res.status = 200;
-    let config = CONFIG.lock().await;
+    res.write_status(200).await?;
+    res.write_all(b"Content-Type: text/plain; version=0.0.4\r\nConnection: close\r\n\r\n")
+        .await?;
 
-    let mut ip_str: heapless::String<15> = heapless::String::new();
-    write!(&mut ip_str, "{}", config.dns_server_1).ok();
-    #[rustfmt::skip]
-    res.write_all_vectored(&[concat!("HTTP/1.0 200 OK\r\nConnection: close\r\n\r\n",
-r#"<!DOCTYPE html>
-<html lang="en">
-<head>
-<title>Gateway Board Configuration</title>
-<meta name="viewport" content="width=device-width,initial-scale=1">
-<style>
-body {
-font-family: Arial, Helvetica, sans-serif;
-}
-#gw-config {
-display: flex; flex-direction: column; gap: 0.5em; max-width: 400px;
-}
-#gw-config label {
-font-weight: bold;
+    // format everything while holding the lock, then release it before writing to the socket
+    let mut body: heapless::String<1024> = heapless::String::new();
+    {
+        let stats = STATS.lock().await;
+        let counters = [
+            (
+                "gateway_lora_packets_received_total",
+                stats.packets_received,
+            ),
+            ("gateway_lora_acks_sent_total", stats.acks_sent),
+            (
+                "gateway_lora_handshakes_completed_total",
+                stats.handshakes_completed,
+            ),
+            ("gateway_lora_decode_errors_total", stats.decode_errors),
+            (
+                "gateway_lora_unexpected_packets_total",
+                stats.unexpected_packets,
+            ),
+        ];
+        for (name, value) in counters {
+            writeln!(&mut body, "# TYPE {name} counter\n{name} {value}").ok();
+        }
+
+        body.push_str("# TYPE gateway_lora_sensor_last_seen_seconds gauge\n")
+            .ok();
+        for (id, last_seen) in stats.last_seen.iter().enumerate() {
+            if let Some(last_seen) = last_seen {
+                writeln!(
+                    &mut body,
+                    "gateway_lora_sensor_last_seen_seconds{{sensor=\"{id}\"}} {}",
+                    last_seen.elapsed().as_secs()
+                )
+                .ok();
+            }
+        }
+    }
+    res.write_all(body.as_bytes()).await?;
+
+    body.clear();
+    {
+        let history = HISTORY.lock().await;
+        body.push_str("# TYPE gateway_sensor_value gauge\n").ok();
+        let latest = VALUE_TYPE_NAMES
+            .iter()
+            .filter_map(|&(id, _)| history.latest(id));
+        for point in latest {
+            if let SensorValue::Location { lat, lon } = point.value {
+                writeln!(
+                    &mut body,
+                    "gateway_sensor_value{{type=\"latitude\"}} {lat}\ngateway_sensor_value{{type=\"longitude\"}} {lon}"
+                )
+                .ok();
+            } else if let Some((value, value_type)) = value_with_type(point.value) {
+                writeln!(
+                    &mut body,
+                    "gateway_sensor_value{{type=\"{value_type}\"}} {value}"
+                )
+                .ok();
+            }
+        }
+    }
+    res.write_all(body.as_bytes()).await?;
+    Ok(res)
 }
-</style>
-</head>
-<body>
-<h1>Gateway Board Configuration</h1>
-<form method="post" id="gw-config">
-<input type="hidden" name="csrf_token" value=""#).as_bytes(), config.csrf_token.as_bytes(), br#"">
-<label for="wifi_sta_ssid">WiFi external access point SSID</label>
-<input type="text" name="wifi_sta_ssid" placeholder="WiFi SSID" value=""#, config.wifi_sta_ssid.as_deref().unwrap_or("").as_bytes(), br#"" required>
-<label for="wifi_sta_password">WiFi external access point password</label>
-<input type="password" name="wifi_sta_password" placeholder="WiFi Password" value="(_unchanged_)" required>
-<label for="wifi_sta_ssid">WiFi internal access point SSID</label>
-<input type="text" name="wifi_ap_ssid" placeholder="WiFi AP SSID" value=""#, config.wifi_ap_ssid.as_bytes(), br#"" required>
-<label for="dns_server_1">Primary DNS server</label>
-<input type="text" name="dns_server_1" placeholder="1.1.1.1" value=""#, ip_str.as_bytes(), br#"" required>"#,
-    ]).await?;
-
-    ip_str.clear();
-    write!(&mut ip_str, "{}", config.dns_server_2).ok();
-
-    #[rustfmt::skip]
-    res.write_all_vectored(&[
-br#"<label for="dns_server_2">Secondary DNS server</label>
-<input type="text" name="dns_server_2" placeholder="1.0.0.1" value=""#, ip_str.as_bytes(), br#"" required>
-<label for="influx_db_host">InfluxDB host</label>
-<input type="text" name="influx_db_host" placeholder="host" value=""#, config.influx_db.host.as_deref().unwrap_or("").as_bytes(), br#"">"#,
-    ]).await?;
 
-    ip_str.clear();
-    write!(&mut ip_str, "{}", config.influx_db.port).ok();
+/// Returns a summary of the gateway health as a JSON object, for monitoring scripts.
+async fn return_status<'a, 'r>(
+    request: HttpServerRequest<'a, 'r>,
+) -> Result<HttpServerResponse<'a, 'r>, HttpServerError> {
+    use crate::net::wifi::CURRENT_STATUS;
+    use crate::{PROTOCOL_VERSION_MAJOR, PROTOCOL_VERSION_MINOR};
+    use util::json::write_json_str;
+
+    info!("HTTP GET request, returning status");
+    let mut body: heapless::String<1536> = heapless::String::new();
+    let mut version: heapless::String<7> = heapless::String::new();
+    write!(
+        &mut version,
+        "{PROTOCOL_VERSION_MAJOR}.{PROTOCOL_VERSION_MINOR}"
+    )
+    .ok();
 
-    #[rustfmt::skip]
+    body.push_str("{\"protocol_version\":").ok();
+    write_json_str(&mut body, &version).ok();
+    write!(&mut body, ",\"uptime_secs\":{}", Instant::now().as_secs()).ok();
+
+    {
+        let config = CONFIG.lock().await;
+        write!(
+            &mut body,
+            ",\"boot_count\":{},\"reset_reason\":",
+            config.boot_count
+        )
+        .ok();
+        write_json_str(&mut body, &config.reset_reason).ok();
+    }
+
+    {
+        let wifi = CURRENT_STATUS.lock().await;
+        body.push_str(",\"wifi\":{\"sta\":").ok();
+        write_json_str(&mut body, wifi.sta_status.as_ref()).ok();
+        body.push_str(",\"ap\":").ok();
+        write_json_str(&mut body, wifi.ap_status.as_ref()).ok();
+        body.push('}').ok();
+    }
+
+    #[cfg(feature = "lora")]
+    {
+        let sensor_macs = CONFIG.lock().await.sensor_macs;
+        let stats = crate::comm::app::STATS.lock().await;
+        let ages = stats.last_seen.iter().flatten().map(Instant::elapsed);
+        let connected = ages
+            .clone()
+            .filter(|&age| age < SENSOR_CONNECTED_TIMEOUT)
+            .count();
+        write!(
+            &mut body,
+            ",\"sensors\":{{\"connected\":{connected},\"last_packet_age_secs\":"
+        )
+        .ok();
+        match ages.min() {
+            Some(age) => write!(&mut body, "{}", age.as_secs()).ok(),
+            None => body.push_str("null").ok(),
+        };
+
+        // stable IDs assigned to the sensor boards, by MAC address
+        body.push_str(",\"boards\":[").ok();
+        let boards = sensor_macs
+            .iter()
+            .enumerate()
+            .filter_map(|(id, mac)| Some((id, (*mac)?)));
+        for (i, (id, mac)) in boards.enumerate() {
+            let mut mac_str: heapless::String<17> = heapless::String::new();
+            for (j, byte) in mac.iter().enumerate() {
+                let sep = if j == 0 { "" } else { ":" };
+                write!(&mut mac_str, "{sep}{byte:02x}").ok();
+            }
+            let sep = if i == 0 { "" } else { "," };
+            write!(&mut body, "{sep}{{\"id\":{id},\"mac\":").ok();
+            write_json_str(&mut body, &mac_str).ok();
+            match stats.last_seen[id] {
+                Some(last_seen) => write!(
+                    &mut body,
+                    ",\"last_seen_secs\":{}}}",
+                    last_seen.elapsed().as_secs()
+                )
+                .ok(),
+                None => body.push_str(",\"last_seen_secs\":null}").ok(),
+            };
+        }
+        body.push_str("]}").ok();
+    }
+
+    let heap = crate::diagnostics::heap_usage();
+    write!(
+        &mut body,
+        ",\"heap\":{{\"free\":{},\"used\":{},\"peak_used\":{}}},\"tcp_sockets\":{}}}",
+        heap.free,
+        heap.used,
+        heap.peak_used,
+        crate::diagnostics::live_tcp_sockets()
+    )
+    .ok();
+
+    let mut res = request.new_response();
+    res.write_status(200).await?;
     res.write_all_vectored(&[
-br#"<label for="influx_db_port">InfluxDB port</label>
-<input type="number" name="influx_db_port" placeholder="8086" value=""#, ip_str.as_bytes(), br#"">
-<button type="submit" name="action" value="apply">Apply</button>
-<button type="submit" name="action" value="save-reboot">Save & Reboot</button>
-</form>
-</body>"#,
-    ]).await?;
+        b"Content-Type: application/json\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        body.as_bytes(),
+    ])
+    .await?;
+    Ok(res)
+}
+
+async fn return_dashboard_form<'a, 'r>(
+    request: HttpServerRequest<'a, 'r>,
+) -> Result<HttpServerResponse<'a, 'r>, HttpServerError> {
+    info!("HTTP GET request, returning form page");
+    let gzip = request.accepts_gzip();
+    let mut res = request.new_response();
+    res.write_status(200).await?;
+    let config = CONFIG.lock().await;
+
+    let mut dns_server_1: heapless::String<15> = heapless::String::new();
+    write!(&mut dns_server_1, "{}", config.dns_server_1).ok();
+    let mut dns_server_2: heapless::String<15> = heapless::String::new();
+    write!(&mut dns_server_2, "{}", config.dns_server_2).ok();
+    let mut influx_db_port: heapless::String<5> = heapless::String::new();
+    write!(&mut influx_db_port, "{}", config.influx_db.port).ok();
+    let mut lora_tx_power: heapless::String<4> = heapless::String::new();
+    if let Some(tx_power) = config.lora_tx_power {
+        write!(&mut lora_tx_power, "{}", tx_power).ok();
+    }
+    let mut http_port: heapless::String<5> = heapless::String::new();
+    write!(&mut http_port, "{}", config.http_port).ok();
+
+    // the attribute of checked checkboxes
+    let checked = |enabled: bool| if enabled { "checked" } else { "" };
+    let lookup = |name: &str| match name {
+        "csrf_token" => Some(config.csrf_token.as_str()),
+        "wifi_sta_ssid" => config.wifi_sta_ssid.as_deref(),
+        "wifi_ap_ssid" => Some(config.wifi_ap_ssid.as_str()),
+        "dns_server_1" => Some(dns_server_1.as_str()),
+        "dns_server_2" => Some(dns_server_2.as_str()),
+        "influx_db_host" => config.influx_db.host.as_deref(),
+        "influx_db_port" => Some(influx_db_port.as_str()),
+        "lora_tx_power" => Some(lora_tx_power.as_str()),
+        "lora_sync_word" => Some(config.lora_sync_word.name()),
+        "sensor_community_id" => Some(config.sensor_community_id.as_str()),
+        "sensor_community_enabled" => Some(checked(config.sensor_community_enabled)),
+        "influx_db_enabled" => Some(checked(config.influx_db.enabled)),
+        "webhook_enabled" => Some(checked(config.webhook.enabled)),
+        "http_port" => Some(http_port.as_str()),
+        "http_interfaces" => Some(config.http_interfaces.name()),
+        _ => None,
+    };
+    write_page(&mut res, &pages::DASHBOARD, lookup, gzip).await?;
     Ok(res)
 }
 
@@ -138,44 +342,127 @@ async fn return_processing_form_page<'a, 'r>(
     request: HttpServerRequest<'a, 'r>,
 ) -> Result<HttpServerResponse<'a, 'r>, HttpServerError> {
     info!("HTTP POST request, returning processing page");
+    let gzip = request.accepts_gzip();
     let mut res = request.new_response();
-    res.status = 202;
-
-    #[rustfmt::skip]
-    res.write_all(concat!("HTTP/1.0 202 Accepted\r\nRefresh: 2, /\r\nConnection: close\r\n\r\n",
-r#"<!DOCTYPE html>
-<html lang="en">
-<head>
-<title>Processing request...</title>
-<meta name="viewport" content="width=device-width,initial-scale=1">
-</head>
-<body>
-<h1>Processing request...</h1>
-<p>Please wait a few seconds.</p>
-</body>"#
-    ).as_bytes()) .await?;
+    res.write_status(202).await?;
+    res.write_all(b"Refresh: 2, /\r\n").await?;
+    write_page(&mut res, &pages::PROCESSING, |_| None, gzip).await?;
     Ok(res)
 }
 
 async fn return_reboot_page<'a, 'r>(
     request: HttpServerRequest<'a, 'r>,
 ) -> Result<HttpServerResponse<'a, 'r>, HttpServerError> {
+    let gzip = request.accepts_gzip();
     let mut res = request.new_response();
-    res.status = 200;
-
-    #[rustfmt::skip]
-    res.write_all(concat!("HTTP/1.0 202 Accepted\r\nRefresh: 10, /\r\nConnection: close\r\n\r\n",
-r#"<!DOCTYPE html>
-<html lang="en">
-<head>
-<title>Gateway rebooting...</title>
-<meta name="viewport" content="width=device-width,initial-scale=1">
-</head>
-<body>
-<h1>Gateway Board is rebooting...</h1>
-<p>Please wait a few seconds for the system to come back online.</p>
-</body>"#
-    ).as_bytes()).await?;
+    res.write_status(202).await?;
+    res.write_all(b"Refresh: 10, /\r\n").await?;
+    write_page(&mut res, &pages::REBOOT, |_| None, gzip).await?;
+    Ok(res)
+}
+
+/// Writes the end of the headers and `page`, gzip-compressed if `compress` is set.
+///
+/// Placeholders are replaced by the HTML-escaped values returned by `lookup`, or left empty.
+/// The static parts of the page are compressed while building, values are sent uncompressed.
+async fn write_page<'v>(
+    res: &mut HttpServerResponse<'_, '_>,
+    page: &Page,
+    lookup: impl Fn(&str) -> Option<&'v str>,
+    compress: bool,
+) -> Result<(), HttpServerError> {
+    let values = page
+        .values
+        .iter()
+        .map(|&name| html_escape(lookup(name).unwrap_or_default()));
+
+    if !compress {
+        res.write_all(b"Vary: Accept-Encoding\r\nConnection: close\r\n\r\n")
+            .await?;
+        for (part, value) in page.parts.iter().zip(values.map(Some).chain([None])) {
+            res.write_all(part.text.as_bytes()).await?;
+            for piece in value.into_iter().flatten() {
+                res.write_all(piece.as_bytes()).await?;
+            }
+        }
+        return Ok(());
+    }
+
+    res.write_all_vectored(&[
+        b"Content-Encoding: gzip\r\nVary: Accept-Encoding\r\nConnection: close\r\n\r\n",
+        &gzip::GZIP_HEADER,
+    ])
+    .await?;
+    let mut trailer = gzip::GzipTrailer::new();
+    for (part, value) in page.parts.iter().zip(values.map(Some).chain([None])) {
+        res.write_all(part.deflated).await?;
+        trailer.update(part.text.as_bytes());
+
+        // values are short, a stored block per escaped piece is simpler than buffering them
+        for piece in value.into_iter().flatten() {
+            for block in piece.as_bytes().chunks(gzip::MAX_STORED_BLOCK_LEN) {
+                let header = gzip::stored_block_header(block.len() as u16);
+                res.write_all_vectored(&[&header, block]).await?;
+                trailer.update(block);
+            }
+        }
+    }
+    res.write_all_vectored(&[&gzip::GZIP_LAST_BLOCK, &trailer.to_bytes()])
+        .await
+}
+
+/// Runs a test export and returns the outcome of each exporter.
+async fn handle_test_export<'a, 'r>(
+    mut request: HttpServerRequest<'a, 'r>,
+) -> Result<HttpServerResponse<'a, 'r>, HttpServerError> {
+    use crate::export::{request_test_export, TestExportResult};
+
+    info!("HTTP POST request, running a test export");
+    // the token is the only field of the form
+    let token = util::encoding::decode_form_url_encoded(request.body())
+        .next()
+        .and_then(|(key, value)| (key == b"csrf_token").then_some(value));
+    let valid_csrf_token = match token {
+        Some(token) => CONFIG.lock().await.csrf_token.as_bytes() == token,
+        None => false,
+    };
+    if !valid_csrf_token {
+        warn!("CSRF token is missing or invalid in test export");
+        let mut res = request.new_response();
+        res.return_bad_request().await?;
+        return Ok(res);
+    }
+
+    let Ok(results) = request_test_export().await else {
+        warn!("test export timed out");
+        let mut res = request.new_response();
+        res.return_service_unavailable().await?;
+        return Ok(res);
+    };
+
+    let outcomes = results.map(|result| {
+        let mut outcome: heapless::String<96> = heapless::String::new();
+        match result {
+            TestExportResult::NotConfigured => outcome.push_str("not configured").ok(),
+            TestExportResult::Disabled => outcome.push_str("disabled").ok(),
+            TestExportResult::NotSent => outcome.push_str("nothing sent, see the logs").ok(),
+            TestExportResult::Status(status) => write!(&mut outcome, "HTTP {status}").ok(),
+            TestExportResult::Failed(e) => write!(&mut outcome, "error: {e}").ok(),
+        };
+        outcome
+    });
+
+    let gzip = request.accepts_gzip();
+    let mut res = request.new_response();
+    res.write_status(200).await?;
+    let [sensor_community, influx_db, webhook] = &outcomes;
+    let lookup = |name: &str| match name {
+        "sensor_community" => Some(sensor_community.as_str()),
+        "influxdb" => Some(influx_db.as_str()),
+        "webhook" => Some(webhook.as_str()),
+        _ => None,
+    };
+    write_page(&mut res, &pages::TEST_EXPORT, lookup, gzip).await?;
     Ok(res)
 }
 
@@ -183,134 +470,62 @@ async fn handle_dashboard_post<'a, 'r>(
     mut request: HttpServerRequest<'a, 'r>,
 ) -> Result<HttpServerResponse<'a, 'r>, HttpServerError> {
     info!("HTTP POST request, processing form submission");
-    let mut valid_csrf_token: bool = false;
-    let mut action = HtmlFormAction::Apply; // Default action
+    let mut submission = FormSubmission::new();
+
+    let is_json = request
+        .content_type()
+        .is_some_and(|t| util::http::media_type(t).eq_ignore_ascii_case(b"application/json"));
+    let fields = if is_json {
+        match util::json::decode_json_object(request.body()) {
+            Some(members) => FormFields::Json(members),
+            None => {
+                warn!("Malformed JSON in form submission");
+                let mut res = request.new_response();
+                res.return_bad_request().await?;
+                return Ok(res);
+            }
+        }
+    } else {
+        FormFields::UrlEncoded(util::encoding::decode_form_url_encoded(request.body()))
+    };
 
     // Scope the config lock to this block to ensure it is released before returning
     {
         let mut config = CONFIG.lock().await;
 
-        for (key, value) in util::encoding::decode_form_url_encoded(request.body()) {
-            let Ok(config_var) = ConfigurationVariable::try_from(key) else {
-                warn!("Invalid configuration variable name: {=[u8]:a}", key);
-                continue;
-            };
-
-            let Ok(value_str) = core::str::from_utf8(value) else {
-                warn!("Invalid UTF-8 in value for {=[u8]:a}", key);
-                continue;
-            };
-
-            // Expect CSRF token to be the first field in the form
-            if !valid_csrf_token && config_var != ConfigurationVariable::CsrfToken {
-                warn!("Missing or invalid CSRF token (or not as first variable). Aborting form processing.");
-                break;
-            }
-
-            match config_var {
-                ConfigurationVariable::CsrfToken => {
-                    if value_str.is_empty() {
-                        warn!("Empty CSRF token received, ignoring");
-                        continue;
-                    }
-                    info!("Validating CSRF token: {}", value_str);
-                    valid_csrf_token = config.csrf_token == value_str;
-                }
-                ConfigurationVariable::WifiStaSsid => {
-                    match heapless::String::<32>::from_str(value_str) {
-                        Ok(s) if s.is_empty() => {
-                            info!("Empty WiFi STA SSID received, clearing config.");
-                            config.wifi_sta_ssid = None;
-                        }
-                        Ok(s) => {
-                            info!("Setting WiFi STA SSID: {}", s);
-                            config.wifi_sta_ssid = Some(s);
-                        }
-                        Err(_) => warn!("Invalid WiFi STA SSID, keeping current value."),
-                    }
-                }
-                ConfigurationVariable::WifiStaPassword => {
-                    match heapless::String::<64>::from_str(value_str) {
-                        Ok(s) if s.is_empty() => {
-                            info!("Empty WiFi STA PASS received, clearing config.");
-                            config.wifi_sta_pass = None;
-                        }
-                        Ok(s) if s != "(_unchanged_)" => {
-                            info!("Updating WiFi STA PASS.");
-                            config.wifi_sta_pass = Some(s);
-                        }
-                        Ok(_) => { /* unchanged, skip */ }
-                        Err(_) => warn!("Invalid WiFi STA PASS, keeping current value."),
-                    }
+        for (key, value) in fields {
+            match submission.apply_config_field(&mut *config, key, value) {
+                Ok(()) => (),
+                Err(FieldError::MissingCsrfToken) => {
+                    warn!("Missing or invalid CSRF token (or not as first variable). Aborting form processing.");
+                    break;
                 }
-                ConfigurationVariable::WifiApSsid => {
-                    match heapless::String::<32>::from_str(value_str) {
-                        Ok(s) => {
-                            info!("Setting WiFi AP SSID: {}", s);
-                            config.wifi_ap_ssid = s;
-                        }
-                        Err(_) => warn!("Invalid WiFi AP SSID, keeping current value."),
-                    }
-                }
-                ConfigurationVariable::DnsServer1 => match value_str.parse() {
-                    Ok(ip) => {
-                        info!("Setting DNS server 1: {}", ip);
-                        config.dns_server_1 = ip;
-                    }
-                    Err(_) => warn!("Invalid DNS server 1 address."),
-                },
-                ConfigurationVariable::DnsServer2 => match value_str.parse() {
-                    Ok(ip) => {
-                        info!("Setting DNS server 2: {}", ip);
-                        config.dns_server_2 = ip;
-                    }
-                    Err(_) => warn!("Invalid DNS server 2 address."),
-                },
-                ConfigurationVariable::InfluxDbHost => {
-                    match heapless::String::<64>::from_str(value_str) {
-                        Ok(s) if s.is_empty() => {
-                            info!("Empty InfluxDB host, clearing config.");
-                            config.influx_db.host = None;
-                        }
-                        Ok(s) => {
-                            info!("Setting InfluxDB host: {}", s);
-                            config.influx_db.host = Some(s);
-                        }
-                        Err(_) => warn!("Invalid InfluxDB host, keeping current value."),
-                    }
-                }
-                ConfigurationVariable::InfluxDbPort => match value_str.parse::<u16>() {
-                    Ok(port) => {
-                        info!("Setting InfluxDB port: {}", port);
-                        config.influx_db.port = port;
-                    }
-                    Err(_) => warn!("Invalid InfluxDB port, keeping current value."),
-                },
-                ConfigurationVariable::HtmlFormAction => match HtmlFormAction::try_from(value) {
-                    // browser typically sends this as the last field
-                    Ok(a) => action = a,
-                    Err(_) => warn!("Invalid HTML form action: {=[u8]:a}", value),
-                },
+                // the config already logged why the value is invalid
+                Err(FieldError::InvalidValue) => (),
+                Err(e) => warn!("Skipping form field {=[u8]:a}: {}", key, Debug2Format(&e)),
             }
         }
 
         config.save_to_flash();
     }
 
-    if !valid_csrf_token {
+    if !submission.has_valid_csrf_token() {
         let mut res = request.new_response();
         warn!("CSRF token is missing or invalid in form submission");
         res.return_bad_request().await?;
         return Ok(res);
     }
 
-    match action {
-        HtmlFormAction::Apply => {
+    match submission.action() {
+        FormAction::Apply => {
             info!("Form submission processed successfully");
+            // the other Wi-Fi settings are only applied after a reboot
+            crate::net::wifi::request_dns_update();
+            crate::net::wifi::request_sta_update();
             // Return a response indicating the form was processed
             return_processing_form_page(request).await
         }
-        HtmlFormAction::SaveAndReboot => {
+        FormAction::SaveAndReboot => {
             info!("Form submitted with 'Save and Reboot' action, rebooting now");
             // Return a response indicating the system is rebooting
             let mut res = return_reboot_page(request).await?;
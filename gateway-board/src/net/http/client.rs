@@ -1,15 +1,38 @@
 use super::{HttpMethod, SOCKET_TIMEOUT};
 use crate::net::tcp::BoxedTcpSocket;
+use crate::{FutureTimeoutExt, TimeoutError};
 use alloc::fmt;
-use core::ops::{Deref, DerefMut};
-use defmt::{error, info, trace};
+use core::future::Future;
+use core::ops::{Deref, DerefMut, Range};
+use defmt::{error, info, trace, warn};
 use embassy_net::dns::DnsQueryType;
 use embassy_net::tcp::ConnectError;
-use embassy_net::{IpEndpoint, Stack};
+use embassy_net::{IpAddress, IpEndpoint, Stack};
+use embassy_time::{Duration, Instant};
 use embedded_io_async::Write;
 use thiserror::Error;
-
-/// Basic HTTP 1.0 Client.
+use util::dns_cache::DnsCache;
+use util::http::{BodyLength, ResponseHeaders};
+
+/// Number of host names whose address is remembered by each client
+const DNS_CACHE_SIZE: usize = 4;
+/// How long a resolved address is used before looking it up again
+const DNS_CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+/// Delay asked by rate-limited responses without a usable `Retry-After` header
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(60);
+/// Largest request body sent by default, enough for the largest batches of the exporters
+pub const DEFAULT_MAX_BODY_LEN: usize = 8 * 1024;
+
+/// Basic HTTP Client.
+///
+/// By default, each request is sent as HTTP 1.0 over a new connection.
+/// With [`HttpClient::with_keep_alive`], requests are sent as HTTP 1.1 and consecutive requests to the same host and port
+/// reuse the connection, unless the server closes it.
+/// Resolved addresses are cached for 10 minutes, see also [`HttpClient::with_stale_dns_fallback`].
+///
+/// The request body is buffered on the heap, in an allocation reused by the next requests.
+/// Bodies longer than [`HttpClient::with_max_body_len`] are not sent, and the allocation is shrunk back to that length
+/// after them. Formatted writes to the body stop at that length instead of growing it further.
 ///
 /// # Usage Example
 ///
@@ -36,6 +59,14 @@ use thiserror::Error;
 pub struct HttpClient<'a> {
     stack: Stack<'a>,
     body_buf: alloc::vec::Vec<u8>,
+    max_body_len: usize,
+    /// Set when a formatted write was refused for making the body too long
+    body_overflowed: bool,
+    keep_alive: bool,
+    stale_dns_fallback: bool,
+    dns_cache: DnsCache<IpAddress, DNS_CACHE_SIZE>,
+    /// Connection left open after the last response
+    connection: Option<(IpEndpoint, BoxedTcpSocket<'a>)>,
 }
 
 #[derive(Debug, Error)]
@@ -46,21 +77,49 @@ pub enum HttpClientError {
     Connect(ConnectError),
     #[error("io error {0:?}")]
     Io(embassy_net::tcp::Error),
+    /// A response header didn't fit in the receive buffer, or the request body is over the maximum length
     #[error("io buffer overflow")]
     BufferOverflow,
     #[error("invalid HTTP response")]
     InvalidHttpResponse,
     #[error("DNS error")]
     DnsError,
+    #[error("timed out")]
+    Timeout,
+    /// The server answered `429` or `503`, and asked to wait this long before the next request
+    #[error("rate limited, retry after {} s", .0.as_secs())]
+    RateLimited(Duration),
 }
 
-pub struct HttpClientRequest<'a> {
+impl HttpClientError {
+    /// Whether sending the request again may succeed, as opposed to errors that will just happen again.
+    ///
+    /// Timeouts and dropped connections are retryable, DNS failures and refused connections are not.
+    /// Neither are rate-limited requests, which must wait for [`HttpClientError::retry_after`].
+    #[must_use]
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Self::Timeout | Self::Io(_))
+    }
+
+    /// How long to wait before sending another request to the same server, if it was rate limited.
+    #[must_use]
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::RateLimited(delay) => Some(*delay),
+            _ => None,
+        }
+    }
+}
+
+pub struct HttpClientRequest<'c, 'a> {
+    client: &'c mut HttpClient<'a>,
     socket: BoxedTcpSocket<'a>,
-    body: &'a mut HttpBody,
+    endpoint: IpEndpoint,
 }
 
 pub struct HttpClientResponse {
     status: u16,
+    retry_after_secs: Option<u32>,
 }
 
 impl<'a> HttpClient<'a> {
@@ -69,144 +128,294 @@ impl<'a> HttpClient<'a> {
         HttpClient {
             stack,
             body_buf: alloc::vec::Vec::new(),
+            max_body_len: DEFAULT_MAX_BODY_LEN,
+            body_overflowed: false,
+            keep_alive: false,
+            stale_dns_fallback: false,
+            dns_cache: DnsCache::new(),
+            connection: None,
         }
     }
 
-    pub async fn request<'b>(
-        &'b mut self,
+    /// Enables or disables persistent connections.
+    #[must_use]
+    pub fn with_keep_alive(mut self, keep_alive: bool) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// Largest request body, [`DEFAULT_MAX_BODY_LEN`] by default.
+    ///
+    /// Longer bodies fail with [`HttpClientError::BufferOverflow`] without being sent.
+    #[must_use]
+    pub fn with_max_body_len(mut self, max_body_len: usize) -> Self {
+        self.max_body_len = max_body_len;
+        self
+    }
+
+    /// When enabled, a failed DNS lookup falls back to the last known address of the host, even if it has expired.
+    #[must_use]
+    pub fn with_stale_dns_fallback(mut self, stale_dns_fallback: bool) -> Self {
+        self.stale_dns_fallback = stale_dns_fallback;
+        self
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn stack(&self) -> Stack<'a> {
+        self.stack
+    }
+
+    /// Closes the connection kept open by the last request, if any.
+    pub fn close(&mut self) {
+        self.connection = None;
+    }
+
+    pub async fn request<'c>(
+        &'c mut self,
         method: HttpMethod,
         host: &str,
         port: u16,
         path: impl AsRef<[u8]>,
-    ) -> Result<HttpClientRequest<'b>, HttpClientError> {
-        info!("http-client: DNS lookup for {}...", host);
-        let address = match self.stack.dns_query(host, DnsQueryType::A).await {
-            Ok(res) => res[0],
-            Err(_) => return Err(HttpClientError::DnsError),
-        };
-        info!("http-client: {} resolved to {}", host, address);
-
+    ) -> Result<HttpClientRequest<'c, 'a>, HttpClientError> {
+        let address = self.resolve(host).await?;
         let endpoint = IpEndpoint::new(address, port);
+        let version: &[u8] = if self.keep_alive {
+            b" HTTP/1.1\r\n"
+        } else {
+            b" HTTP/1.0\r\n"
+        };
 
-        info!("http-client: connecting to {}", endpoint);
-        let mut socket =
-            BoxedTcpSocket::new(self.stack).map_err(|()| HttpClientError::AllocationFailure)?;
-        socket.set_timeout(Some(SOCKET_TIMEOUT));
-        socket.connect(endpoint).await?;
-
-        socket.write_all(method.as_ref().as_bytes()).await?;
-        socket.write_all(b" ").await?;
-        socket.write_all(path.as_ref()).await?;
-        socket.write_all(b" HTTP/1.0\r\n").await?;
+        // drop the previous connection right away if it can't be reused
+        let kept = self
+            .connection
+            .take()
+            .filter(|(kept_endpoint, socket)| *kept_endpoint == endpoint && socket.may_recv());
+
+        let mut socket = match kept {
+            Some((_, mut socket)) => {
+                info!("http-client: reusing connection to {}", endpoint);
+                match Self::write_request_line(&mut socket, method, path.as_ref(), version).await {
+                    Ok(()) => socket,
+                    Err(_) => {
+                        drop(socket);
+                        info!("http-client: connection was closed by the server");
+                        let mut socket = self.connect(endpoint).await?;
+                        Self::write_request_line(&mut socket, method, path.as_ref(), version)
+                            .await?;
+                        socket
+                    }
+                }
+            }
+            None => {
+                let mut socket = self.connect(endpoint).await?;
+                Self::write_request_line(&mut socket, method, path.as_ref(), version).await?;
+                socket
+            }
+        };
 
-        self.body_buf.clear();
+        self.release_body();
 
+        if self.keep_alive {
+            Self::write_header(&mut socket, b"Connection", b"keep-alive").await?;
+        }
         let mut headers = HttpClientRequest {
+            client: self,
             socket,
-            body: HttpBody::from_mut_vec(&mut self.body_buf),
+            endpoint,
         };
         headers.header(b"Host", host.as_bytes()).await?;
         Ok(headers)
     }
+
+    /// Empties the body, giving back the memory of oversized ones.
+    fn release_body(&mut self) {
+        self.body_buf.clear();
+        self.body_buf.shrink_to(self.max_body_len);
+        self.body_overflowed = false;
+    }
+
+    async fn resolve(&mut self, host: &str) -> Result<IpAddress, HttpClientError> {
+        let now = Instant::now().as_millis();
+        if let Some(address) = self.dns_cache.get(host, now) {
+            trace!("http-client: using cached address {} for {}", address, host);
+            return Ok(address);
+        }
+
+        info!("http-client: DNS lookup for {}...", host);
+        let res = self.stack.dns_query(host, DnsQueryType::A).await;
+        match res.ok().and_then(|addresses| addresses.first().copied()) {
+            Some(address) => {
+                info!("http-client: {} resolved to {}", host, address);
+                self.dns_cache
+                    .insert(host, address, now, DNS_CACHE_TTL.as_millis());
+                Ok(address)
+            }
+            None => match self.dns_cache.get_stale(host) {
+                Some(address) if self.stale_dns_fallback => {
+                    warn!(
+                        "http-client: DNS lookup for {} failed, using previous address {}",
+                        host, address
+                    );
+                    Ok(address)
+                }
+                _ => Err(HttpClientError::DnsError),
+            },
+        }
+    }
+
+    async fn connect(&self, endpoint: IpEndpoint) -> Result<BoxedTcpSocket<'a>, HttpClientError> {
+        info!("http-client: connecting to {}", endpoint);
+        let mut socket =
+            BoxedTcpSocket::new(self.stack).map_err(|()| HttpClientError::AllocationFailure)?;
+        // operations time out on their own with a distinct error, this only aborts hanging connections
+        socket.set_timeout(Some(SOCKET_TIMEOUT * 2));
+        with_io_timeout(socket.connect(endpoint)).await?;
+        Ok(socket)
+    }
+
+    async fn write_request_line(
+        socket: &mut BoxedTcpSocket<'_>,
+        method: HttpMethod,
+        path: &[u8],
+        version: &[u8],
+    ) -> Result<(), HttpClientError> {
+        with_io_timeout(socket.write_all(method.as_ref().as_bytes())).await?;
+        with_io_timeout(socket.write_all(b" ")).await?;
+        with_io_timeout(socket.write_all(path)).await?;
+        with_io_timeout(socket.write_all(version)).await?;
+        Ok(())
+    }
+
+    async fn write_header(
+        socket: &mut BoxedTcpSocket<'_>,
+        name: &[u8],
+        value: &[u8],
+    ) -> Result<(), HttpClientError> {
+        with_io_timeout(socket.write_all(name)).await?;
+        with_io_timeout(socket.write_all(b": ")).await?;
+        with_io_timeout(socket.write_all(value)).await?;
+        with_io_timeout(socket.write_all(b"\r\n")).await?;
+        Ok(())
+    }
 }
 
-impl HttpClientRequest<'_> {
+impl HttpClientRequest<'_, '_> {
     pub async fn header(
         &mut self,
         name: impl AsRef<[u8]>,
         value: impl AsRef<[u8]>,
     ) -> Result<(), HttpClientError> {
-        self.socket.write_all(name.as_ref()).await?;
-        self.socket.write_all(b": ").await?;
-        self.socket.write_all(value.as_ref()).await?;
-        self.socket.write_all(b"\r\n").await?;
-        Ok(())
+        HttpClient::write_header(&mut self.socket, name.as_ref(), value.as_ref()).await
+    }
+
+    pub fn body(&mut self) -> HttpBody<'_> {
+        HttpBody {
+            buf: &mut self.client.body_buf,
+            max_len: self.client.max_body_len,
+            overflowed: &mut self.client.body_overflowed,
+        }
     }
 
-    pub fn body(&mut self) -> &mut HttpBody {
-        self.body
+    pub async fn finish(self) -> Result<HttpClientResponse, HttpClientError> {
+        self.finish_with_body(&mut [])
+            .await
+            .map(|(response, _)| response)
     }
 
-    pub async fn finish(mut self) -> Result<HttpClientResponse, HttpClientError> {
+    /// Same as [`Self::finish`], also copying the start of the response body into `body_buf`.
+    ///
+    /// Returns the response and the number of bytes written to `body_buf`, the rest of the body is discarded.
+    /// Rate-limited responses are returned as [`HttpClientError::RateLimited`] instead.
+    pub async fn finish_with_body(
+        mut self,
+        body_buf: &mut [u8],
+    ) -> Result<(HttpClientResponse, usize), HttpClientError> {
         use core::fmt::Write;
 
+        let body_len = self.client.body_buf.len();
+        if body_len > self.client.max_body_len || self.client.body_overflowed {
+            error!(
+                "http-client: request body is over the maximum of {=usize} bytes",
+                self.client.max_body_len
+            );
+            // the connection is dropped along with the unfinished request
+            self.client.release_body();
+            return Err(HttpClientError::BufferOverflow);
+        }
         let mut content_len_str: heapless::String<10> = heapless::String::new();
-        _ = write!(&mut content_len_str, "{}", self.body.len());
+        _ = write!(&mut content_len_str, "{}", body_len);
         self.header("Content-Length", content_len_str).await?;
-        self.socket.write_all(b"\r\n").await?;
-        self.socket.write_all(self.body).await?;
-        self.socket.flush().await?;
+        with_io_timeout(self.socket.write_all(b"\r\n")).await?;
+        with_io_timeout(self.socket.write_all(&self.client.body_buf)).await?;
+        with_io_timeout(self.socket.flush()).await?;
+        self.client.release_body();
         info!("http: request finished, waiting for response");
-        HttpClientResponse::read(self.socket).await
+
+        let (response, body_len, keep_alive) =
+            HttpClientResponse::read(&mut self.socket, body_buf).await?;
+        if self.client.keep_alive && keep_alive {
+            self.client.connection = Some((self.endpoint, self.socket));
+        }
+        if util::http::is_rate_limited(response.status) {
+            let retry_after = response
+                .retry_after_secs
+                .map_or(DEFAULT_RETRY_AFTER, |secs| Duration::from_secs(secs.into()));
+            warn!(
+                "http-client: rate limited with status {=u16}, retry after {=u64} s",
+                response.status,
+                retry_after.as_secs()
+            );
+            return Err(HttpClientError::RateLimited(retry_after));
+        }
+        Ok((response, body_len))
     }
 }
 
-#[repr(transparent)]
-pub struct HttpBody(alloc::vec::Vec<u8>);
+/// Body of an [`HttpClientRequest`], borrowed from the client's buffer.
+///
+/// Formatted writes fail with [`fmt::Error`] instead of growing the body past the maximum length of the client,
+/// and the request then fails with [`HttpClientError::BufferOverflow`].
+pub struct HttpBody<'b> {
+    buf: &'b mut alloc::vec::Vec<u8>,
+    max_len: usize,
+    overflowed: &'b mut bool,
+}
 
-impl fmt::Write for HttpBody {
+impl fmt::Write for HttpBody<'_> {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        self.as_mut().extend_from_slice(s.as_bytes());
+        if self.buf.len() + s.len() > self.max_len {
+            *self.overflowed = true;
+            return Err(fmt::Error);
+        }
+        self.buf.extend_from_slice(s.as_bytes());
         Ok(())
     }
 }
 
-impl HttpBody {
-    #[inline]
-    pub const fn from_mut_vec(vec: &mut alloc::vec::Vec<u8>) -> &mut HttpBody {
-        // SAFETY: HttpBody has the same memory layout as Vec<u8> due to repr(transparent)
-        unsafe { core::mem::transmute(vec) }
-    }
-
-    #[inline]
-    #[must_use]
-    pub const fn as_vec(&self) -> &alloc::vec::Vec<u8> {
-        // SAFETY: HttpBody has the same memory layout as Vec<u8> due to repr(transparent)
-        unsafe { core::mem::transmute(self) }
-    }
-
-    #[inline]
-    #[must_use]
-    pub const fn as_mut_vec(&mut self) -> &mut alloc::vec::Vec<u8> {
-        // SAFETY: HttpBody has the same memory layout as Vec<u8> due to repr(transparent)
-        unsafe { core::mem::transmute(self) }
-    }
-}
-
-impl Deref for HttpBody {
+impl Deref for HttpBody<'_> {
     type Target = alloc::vec::Vec<u8>;
 
     #[inline]
     fn deref(&self) -> &Self::Target {
-        self.as_vec()
+        self.buf
     }
 }
 
-impl DerefMut for HttpBody {
+impl DerefMut for HttpBody<'_> {
     #[inline]
     fn deref_mut(&mut self) -> &mut <Self as Deref>::Target {
-        self.as_mut_vec()
-    }
-}
-
-impl AsRef<alloc::vec::Vec<u8>> for HttpBody {
-    #[inline]
-    fn as_ref(&self) -> &alloc::vec::Vec<u8> {
-        self.as_vec()
-    }
-}
-
-impl AsMut<alloc::vec::Vec<u8>> for HttpBody {
-    #[inline]
-    fn as_mut(&mut self) -> &mut alloc::vec::Vec<u8> {
-        self.as_mut_vec()
+        self.buf
     }
 }
 
 impl From<ConnectError> for HttpClientError {
     #[inline]
     fn from(e: ConnectError) -> Self {
-        Self::Connect(e)
+        match e {
+            ConnectError::TimedOut => Self::Timeout,
+            e => Self::Connect(e),
+        }
     }
 }
 
@@ -217,49 +426,199 @@ impl From<embassy_net::tcp::Error> for HttpClientError {
     }
 }
 
-impl HttpClientResponse {
-    async fn read(mut socket: BoxedTcpSocket<'_>) -> Result<Self, HttpClientError> {
-        let mut buf = [0u8; 128];
+/// Runs a socket operation, failing with [`HttpClientError::Timeout`] if it takes longer than [`SOCKET_TIMEOUT`].
+async fn with_io_timeout<T, E: Into<HttpClientError>>(
+    operation: impl Future<Output = Result<T, E>>,
+) -> Result<T, HttpClientError> {
+    match operation.with_timeout(SOCKET_TIMEOUT).await {
+        Ok(res) => res.map_err(Into::into),
+        Err(TimeoutError) => Err(HttpClientError::Timeout),
+    }
+}
 
-        let res_line_len = Self::read_line(&mut socket, &mut buf).await?;
-        let mut res_line: &str = core::str::from_utf8(&buf[..res_line_len])
-            .map_err(|_| HttpClientError::InvalidHttpResponse)?;
+impl HttpClientResponse {
+    /// Reads a full response, copying the start of its body into `body_buf`.
+    ///
+    /// Returns the response, the number of bytes written to `body_buf`, and whether the connection can be reused.
+    async fn read(
+        socket: &mut BoxedTcpSocket<'_>,
+        body_buf: &mut [u8],
+    ) -> Result<(Self, usize, bool), HttpClientError> {
+        let mut reader = ResponseReader {
+            socket,
+            buf: [0u8; 128],
+            start: 0,
+            end: 0,
+        };
 
-        if !res_line.starts_with("HTTP/1.0 ") && !res_line.starts_with("HTTP/1.1 ") {
+        let res_line = reader
+            .read_line()
+            .await?
+            .ok_or(HttpClientError::BufferOverflow)?;
+        let Some(status_line) = util::http::parse_status_line(&reader.buf[res_line]) else {
             trace!("http-client: unsupported response method");
             return Err(HttpClientError::InvalidHttpResponse);
-        }
-        res_line = &res_line[9..];
+        };
 
-        let status_len = res_line
-            .find(' ')
-            .ok_or(HttpClientError::InvalidHttpResponse)?;
-        let status: u16 = res_line[0..status_len]
-            .parse()
-            .map_err(|_| HttpClientError::InvalidHttpResponse)?;
+        let mut headers = ResponseHeaders::default();
+        loop {
+            match reader.read_line().await? {
+                Some(line) if line.is_empty() => break,
+                Some(line) => headers.parse_header(&reader.buf[line]),
+                // too long to be one of the headers we care about
+                None => {}
+            }
+        }
 
-        while embedded_io_async::Read::read(&mut socket, &mut buf).await? > 0 {
-            // we have the status, consume the rest of the response
+        let mut body_len = 0usize;
+        match headers.body_length(status_line) {
+            BodyLength::Empty => {}
+            BodyLength::Fixed(len) => reader.read_body(len, body_buf, &mut body_len).await?,
+            BodyLength::Chunked => reader.read_chunked_body(body_buf, &mut body_len).await?,
+            BodyLength::UntilClose => {
+                reader
+                    .read_body_until_close(body_buf, &mut body_len)
+                    .await?
+            }
         }
 
-        Ok(HttpClientResponse { status })
+        let response = HttpClientResponse {
+            status: status_line.status,
+            retry_after_secs: headers.retry_after_secs(),
+        };
+        Ok((response, body_len, headers.keep_alive(status_line)))
     }
+}
 
-    async fn read_line(
-        socket: &mut BoxedTcpSocket<'_>,
-        buf: &mut [u8],
-    ) -> Result<usize, HttpClientError> {
-        let mut filled = embedded_io_async::Read::read(socket, buf).await?;
+/// Buffered reader over a response.
+struct ResponseReader<'s, 'a> {
+    socket: &'s mut BoxedTcpSocket<'a>,
+    buf: [u8; 128],
+    /// Start of the unconsumed data in `buf`
+    start: usize,
+    /// End of the data in `buf`
+    end: usize,
+}
+
+impl ResponseReader<'_, '_> {
+    /// Reads more data after the unconsumed bytes, the end of the stream is an error.
+    async fn fill(&mut self) -> Result<(), HttpClientError> {
+        self.buf.copy_within(self.start..self.end, 0);
+        self.end -= self.start;
+        self.start = 0;
+
+        let read = with_io_timeout(embedded_io_async::Read::read(
+            &mut *self.socket,
+            &mut self.buf[self.end..],
+        ))
+        .await?;
+        if read == 0 {
+            return Err(HttpClientError::InvalidHttpResponse);
+        }
+        self.end += read;
+        Ok(())
+    }
 
+    /// Returns the position of the next line in `buf`, without its line terminator.
+    ///
+    /// Lines too long to fit in `buf` are skipped, and `None` is returned instead.
+    async fn read_line(&mut self) -> Result<Option<Range<usize>>, HttpClientError> {
         loop {
-            match memchr::memchr2(b'\r', b'\n', &buf[..filled]) {
-                None => {
-                    if filled == buf.len() {
-                        return Err(HttpClientError::BufferOverflow);
+            if let Some(len) = memchr::memchr(b'\n', &self.buf[self.start..self.end]) {
+                let mut line = self.start..self.start + len;
+                self.start = line.end + 1;
+                if self.buf[line.clone()].ends_with(b"\r") {
+                    line.end -= 1;
+                }
+                return Ok(Some(line));
+            }
+
+            if self.start == 0 && self.end == self.buf.len() {
+                loop {
+                    self.start = self.end;
+                    self.fill().await?;
+                    if let Some(len) = memchr::memchr(b'\n', &self.buf[..self.end]) {
+                        self.start = len + 1;
+                        return Ok(None);
                     }
-                    filled += embedded_io_async::Read::read(socket, &mut buf[filled..]).await?;
                 }
-                Some(line_len) => break Ok(line_len),
+            }
+            self.fill().await?;
+        }
+    }
+
+    /// Consumes at most `max` buffered bytes, copying what fits at the end of `body_buf[..body_len]`.
+    ///
+    /// Returns the number of consumed bytes.
+    fn consume_body(&mut self, max: usize, body_buf: &mut [u8], body_len: &mut usize) -> usize {
+        let consumed = (self.end - self.start).min(max);
+        let copied = consumed.min(body_buf.len() - *body_len);
+
+        body_buf[*body_len..*body_len + copied]
+            .copy_from_slice(&self.buf[self.start..self.start + copied]);
+        *body_len += copied;
+        self.start += consumed;
+        consumed
+    }
+
+    async fn read_body(
+        &mut self,
+        mut len: usize,
+        body_buf: &mut [u8],
+        body_len: &mut usize,
+    ) -> Result<(), HttpClientError> {
+        loop {
+            len -= self.consume_body(len, body_buf, body_len);
+            if len == 0 {
+                return Ok(());
+            }
+            self.fill().await?;
+        }
+    }
+
+    async fn read_chunked_body(
+        &mut self,
+        body_buf: &mut [u8],
+        body_len: &mut usize,
+    ) -> Result<(), HttpClientError> {
+        loop {
+            let size_line = self
+                .read_line()
+                .await?
+                .ok_or(HttpClientError::InvalidHttpResponse)?;
+            let size = util::http::parse_chunk_size(&self.buf[size_line])
+                .ok_or(HttpClientError::InvalidHttpResponse)?;
+            if size == 0 {
+                break;
+            }
+            self.read_body(size, body_buf, body_len).await?;
+
+            // each chunk is followed by an empty line
+            if !matches!(self.read_line().await?, Some(line) if line.is_empty()) {
+                return Err(HttpClientError::InvalidHttpResponse);
+            }
+        }
+
+        // skip the trailer headers
+        while !matches!(self.read_line().await?, Some(line) if line.is_empty()) {}
+        Ok(())
+    }
+
+    async fn read_body_until_close(
+        &mut self,
+        body_buf: &mut [u8],
+        body_len: &mut usize,
+    ) -> Result<(), HttpClientError> {
+        loop {
+            self.consume_body(usize::MAX, body_buf, body_len);
+            self.start = 0;
+            self.end = with_io_timeout(embedded_io_async::Read::read(
+                &mut *self.socket,
+                &mut self.buf,
+            ))
+            .await?;
+            if self.end == 0 {
+                return Ok(());
             }
         }
     }
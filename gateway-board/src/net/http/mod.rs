@@ -2,12 +2,15 @@ use embassy_time::Duration;
 
 pub mod api;
 mod client;
+mod pages;
 mod server;
 
 pub use client::*;
 pub use server::*;
 
 const SOCKET_TIMEOUT: Duration = Duration::from_secs(10);
+/// Longest time to read a whole request, so that a client trickling bytes cannot keep the server busy.
+const REQUEST_READ_TIMEOUT: Duration = Duration::from_secs(20);
 
 #[derive(Clone, Copy)]
 pub enum HttpMethod {
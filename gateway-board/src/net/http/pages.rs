@@ -0,0 +1,16 @@
+//! HTML pages of the dashboard, compressed while building by `build.rs`.
+
+/// A page made of static parts, with dynamic values between them.
+pub struct Page {
+    /// Names of the values, in order: there is one more part than values
+    pub values: &'static [&'static str],
+    pub parts: &'static [PagePart],
+}
+
+pub struct PagePart {
+    pub text: &'static str,
+    /// `text` deflated into non-final blocks, see [`util::gzip`]
+    pub deflated: &'static [u8],
+}
+
+include!(concat!(env!("OUT_DIR"), "/pages.rs"));
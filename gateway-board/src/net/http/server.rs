@@ -1,13 +1,16 @@
-use super::{HttpMethod, SOCKET_TIMEOUT};
+use super::{HttpMethod, REQUEST_READ_TIMEOUT, SOCKET_TIMEOUT};
 use crate::{
+    config::HttpInterfaces,
     net::{tcp::BoxedTcpSocket, GATEWAY_IP},
+    watchdog::{self, WatchedTask},
     FutureTimeoutExt,
 };
-use core::net::Ipv4Addr;
+use alloc::boxed::Box;
+use core::{cell::Cell, net::Ipv4Addr};
 use defmt::{debug, error, info, warn, Format};
-use embassy_futures::select::Either;
-use embassy_net::{tcp::TcpSocket, IpListenEndpoint, Stack};
-use embassy_time::{Duration, Timer};
+use embassy_futures::join::join_array;
+use embassy_net::{tcp::TcpSocket, IpEndpoint, IpListenEndpoint, Stack};
+use embassy_time::{Duration, Instant, Timer};
 use embedded_io_async::Write;
 
 #[cfg(feature = "display-ssd1306")]
@@ -15,23 +18,66 @@ use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
 
 /// Dummy dual-stack HTTP server.
 ///
-/// Endpoints:
+/// Endpoints, either or both depending on the [`HttpInterfaces`]:
 /// - AP mode: server on the gateway IP
 /// - STA mode: server exposed on an IP got from DHCP
-pub struct HttpServer<'a> {
+///
+/// Each endpoint serves up to [`HTTP_CONNECTIONS_PER_INTERFACE`] clients at once, each connection having its own
+/// socket and request buffer.
+///
+/// Requests are read into a buffer of `BUFFER_SIZE` bytes: everything after the method until the end of the headers
+/// must fit in it, and so must the body, which is therefore at most `BUFFER_SIZE` bytes long.
+/// Larger bodies are answered with `413 Payload Too Large`.
+pub struct HttpServer<'a, const BUFFER_SIZE: usize = HTTP_DEFAULT_BUFFER_SIZE> {
     endpoint: IpListenEndpoint,
-    ap_socket: BoxedTcpSocket<'a>,
-    sta_socket: Option<(BoxedTcpSocket<'a>, Ipv4Addr)>,
+    /// Never empty, those of the AP first
+    connections: heapless::Vec<Connection<'a, BUFFER_SIZE>, HTTP_MAX_CONNECTIONS>,
+    serve_ap: bool,
+    sta_address: Option<Ipv4Addr>,
+}
+
+/// A listening socket and the buffer its requests are read into.
+struct Connection<'a, const BUFFER_SIZE: usize> {
+    socket: BoxedTcpSocket<'a>,
+    /// On the heap, so that the size of the server task does not grow with the number of connections
+    buffer: Box<heapless::Vec<u8, BUFFER_SIZE>>,
+    /// Name of the interface, for the logs
+    interface: &'static str,
 }
 
+/// Number of clients served at once on each interface, each one costing a socket and a request buffer.
+pub const HTTP_CONNECTIONS_PER_INTERFACE: usize = 2;
+
+const HTTP_MAX_CONNECTIONS: usize = 2 * HTTP_CONNECTIONS_PER_INTERFACE;
+
+/// Maximum length of a request path, query string excluded.
+pub const HTTP_MAX_PATH_LEN: usize = 64;
+
+/// Maximum length of the `Authorization` header value, longer values are ignored.
+pub const HTTP_MAX_AUTHORIZATION_LEN: usize = 128;
+
+/// Maximum length of the `Content-Type` header value, longer values are ignored.
+pub const HTTP_MAX_CONTENT_TYPE_LEN: usize = 64;
+
+/// Default size of the [`HttpServer`] request buffer.
+pub const HTTP_DEFAULT_BUFFER_SIZE: usize = 1024;
+
 pub struct HttpServerRequest<'a, 'r> {
     method: HttpMethod,
+    path: heapless::String<HTTP_MAX_PATH_LEN>,
+    authorization: Option<heapless::Vec<u8, HTTP_MAX_AUTHORIZATION_LEN>>,
+    accepts_gzip: bool,
+    content_type: Option<heapless::Vec<u8, HTTP_MAX_CONTENT_TYPE_LEN>>,
+    peer: Option<IpEndpoint>,
     body: &'r mut [u8],
     sock: &'r mut TcpSocket<'a>,
+    started: &'r mut bool,
 }
 
 pub struct HttpServerResponse<'a, 'r> {
     sock: &'r mut TcpSocket<'a>,
+    /// Set once anything has been written, after which the status can no longer be changed
+    started: &'r mut bool,
     pub status: u16,
 }
 
@@ -40,6 +86,8 @@ pub enum HttpServerError {
     SocketError,
     SocketEof,
     FullBuffer,
+    /// The request was not fully read within [`REQUEST_READ_TIMEOUT`]
+    RequestTimeout,
 }
 
 #[cfg(feature = "display-ssd1306")]
@@ -47,6 +95,7 @@ pub enum HttpServerError {
 pub enum DisplayStatus {
     Initializing,
     ApOnly(Ipv4Addr, u16),
+    StaOnly(Ipv4Addr, u16),
     DualStack(Ipv4Addr, u16),
 }
 
@@ -54,63 +103,107 @@ pub enum DisplayStatus {
 pub static CURRENT_STATUS: Mutex<CriticalSectionRawMutex, DisplayStatus> =
     Mutex::new(DisplayStatus::Initializing);
 
-impl<'a> HttpServer<'a> {
-    pub async fn new(ap_stack: Stack<'a>, sta_stack: Stack<'a>, port: u16) -> Self {
-        info!("http: waiting for AP and STA stacks...");
-
-        let sta_address: Option<Ipv4Addr> = loop {
-            if let Some(config) = sta_stack.config_v4() {
-                break Some(config.address.address());
-            }
-            if let Err(crate::TimeoutError) = sta_stack
-                .wait_config_up()
-                .with_timeout(Duration::from_secs(20))
-                .await
-            {
-                warn!("http: STA stack failed to configure after 20 seconds, disabling STA mode");
-                break None;
+impl<'a, const BUFFER_SIZE: usize> HttpServer<'a, BUFFER_SIZE> {
+    /// Creates a server listening on `port` of the given `interfaces`.
+    ///
+    /// Falls back to the AP if only the STA is requested but fails to configure, so that the dashboard stays reachable.
+    pub async fn new(
+        ap_stack: Stack<'a>,
+        sta_stack: Stack<'a>,
+        port: u16,
+        interfaces: HttpInterfaces,
+    ) -> Self {
+        info!(
+            "http: waiting for stacks, serving on interfaces '{}'...",
+            interfaces.name()
+        );
+
+        let sta_address: Option<Ipv4Addr> = if interfaces == HttpInterfaces::Ap {
+            None
+        } else {
+            loop {
+                if let Some(config) = sta_stack.config_v4() {
+                    break Some(config.address.address());
+                }
+                if let Err(crate::TimeoutError) = sta_stack
+                    .wait_config_up()
+                    .with_timeout(Duration::from_secs(20))
+                    .await
+                {
+                    warn!(
+                        "http: STA stack failed to configure after 20 seconds, disabling STA mode"
+                    );
+                    break None;
+                }
             }
         };
 
-        ap_stack.wait_link_up().await;
+        let serve_ap = interfaces != HttpInterfaces::Sta || sta_address.is_none();
+        if interfaces == HttpInterfaces::Sta && sta_address.is_none() {
+            warn!("http: serving on the AP instead of the STA");
+        }
+
+        if serve_ap {
+            ap_stack.wait_link_up().await;
+        }
 
         if sta_address.is_some() {
             sta_stack.wait_link_up().await;
         }
 
         let endpoint = IpListenEndpoint { addr: None, port };
-        let mut ap_socket = BoxedTcpSocket::new(ap_stack).expect("ap_socket: alloc failure");
-        let sta_socket = sta_address.map(|a| {
-            let mut socket = BoxedTcpSocket::new(sta_stack).expect("sta_socket: alloc failure");
-            socket.set_timeout(Some(SOCKET_TIMEOUT));
-            (socket, a)
-        });
-
-        ap_socket.set_timeout(Some(SOCKET_TIMEOUT));
+        let mut connections = heapless::Vec::new();
+        let served = [
+            (serve_ap, ap_stack, "AP"),
+            (sta_address.is_some(), sta_stack, "STA"),
+        ];
+        for (serve, stack, interface) in served {
+            if !serve {
+                continue;
+            }
+            for _ in 0..HTTP_CONNECTIONS_PER_INTERFACE {
+                let mut socket = BoxedTcpSocket::new(stack).expect("http-server: alloc failure");
+                socket.set_timeout(Some(SOCKET_TIMEOUT));
+                let connection = Connection {
+                    socket,
+                    buffer: Box::new(heapless::Vec::new()),
+                    interface,
+                };
+                // two interfaces at most
+                _ = connections.push(connection);
+            }
+        }
 
         HttpServer {
             endpoint,
-            ap_socket,
-            sta_socket,
+            connections,
+            serve_ap,
+            sta_address,
         }
     }
 
     /// Runs the HTTP server indefinitely.
-    /// Accepts a `handler` function for client requests and responses.
-    pub async fn run<H>(&mut self, mut handler: H) -> !
+    /// Accepts a `handler` function for client requests and responses, shared by all connections.
+    pub async fn run<H>(&mut self, handler: H) -> !
     where
-        H: for<'r> AsyncFnMut(
+        H: for<'r> AsyncFn(
             HttpServerRequest<'a, 'r>,
         ) -> Result<HttpServerResponse<'a, 'r>, HttpServerError>,
     {
-        match &self.sta_socket {
-            Some((_, sta_address)) => {
+        match (self.serve_ap, self.sta_address) {
+            (true, Some(sta_address)) => {
                 info!(
                     "http-server: running dual-stack on port {}, STA address is {}, gateway IP is {}",
                     self.endpoint.port, sta_address, GATEWAY_IP,
                 );
             }
-            None => {
+            (false, Some(sta_address)) => {
+                info!(
+                    "http-server: running single-stack on port {}, STA address is {}",
+                    self.endpoint.port, sta_address,
+                );
+            }
+            (_, None) => {
                 info!(
                     "http-server: running single-stack on port {}, gateway IP is {}",
                     self.endpoint.port, GATEWAY_IP,
@@ -120,142 +213,209 @@ impl<'a> HttpServer<'a> {
 
         #[cfg(feature = "display-ssd1306")]
         {
-            match &self.sta_socket {
-                Some((_, sta_address)) => {
-                    *CURRENT_STATUS.lock().await =
-                        DisplayStatus::DualStack(*sta_address, self.endpoint.port);
+            *CURRENT_STATUS.lock().await = match (self.serve_ap, self.sta_address) {
+                (true, Some(sta_address)) => {
+                    DisplayStatus::DualStack(sta_address, self.endpoint.port)
                 }
-                None => {
-                    *CURRENT_STATUS.lock().await =
-                        DisplayStatus::ApOnly(GATEWAY_IP, self.endpoint.port);
+                (false, Some(sta_address)) => {
+                    DisplayStatus::StaOnly(sta_address, self.endpoint.port)
                 }
-            }
+                (_, None) => DisplayStatus::ApOnly(GATEWAY_IP, self.endpoint.port),
+            };
         }
 
-        let mut buffer = heapless::Vec::<u8, 1024>::new();
-        loop {
-            info!("http-server: waiting for connection");
-
-            let Some(sock) = (match self.sta_socket {
-                Some((ref mut sta_socket, _)) => {
-                    Self::accept_socket_dual_stack(self.endpoint, &mut self.ap_socket, sta_socket)
-                        .await
+        // the task is only watched while at least one connection is being handled
+        watchdog::park(WatchedTask::Http);
+        let active_connections = Cell::new(0usize);
+        let (endpoint, handler, active_connections) =
+            (self.endpoint, &handler, &active_connections);
+        let mut connections = self.connections.iter_mut();
+        let workers = core::array::from_fn::<_, HTTP_MAX_CONNECTIONS, _>(|_| connections.next())
+            .map(|connection| async move {
+                if let Some(connection) = connection {
+                    Self::serve_connection(connection, endpoint, handler, active_connections).await
                 }
-                None => Self::accept_socket_single_stack(self.endpoint, &mut self.ap_socket).await,
-            }) else {
+            });
+        join_array(workers).await;
+        unreachable!("http-server: no socket to listen on")
+    }
+
+    /// Accepts and handles the clients of a single socket, one after the other.
+    async fn serve_connection<H>(
+        connection: &mut Connection<'a, BUFFER_SIZE>,
+        endpoint: IpListenEndpoint,
+        handler: &H,
+        active_connections: &Cell<usize>,
+    ) -> !
+    where
+        H: for<'r> AsyncFn(
+            HttpServerRequest<'a, 'r>,
+        ) -> Result<HttpServerResponse<'a, 'r>, HttpServerError>,
+    {
+        let sock = &mut *connection.socket;
+        let interface = connection.interface;
+        loop {
+            debug!("http-server: {} socket waiting for connection", interface);
+            if let Err(e) = sock.accept(endpoint).await {
+                error!("http-server: {} socket error: {:?}", interface, e);
+                // reset the socket so that it can listen again, the other ones are still listening
+                sock.abort();
                 continue;
-            };
+            }
+            active_connections.set(active_connections.get() + 1);
+            watchdog::heartbeat(WatchedTask::Http);
+            let accepted = Instant::now();
+            info!(
+                "http-server: connection from {:?} on {}",
+                sock.remote_endpoint(),
+                interface
+            );
 
-            match Self::handle_client_request(sock, &mut handler, &mut buffer).await {
-                Ok(res) => {
-                    info!("http-server: client response: {:?}", res.status);
+            let mut response_started = false;
+            let result = Self::handle_client_request(
+                sock,
+                handler,
+                &mut connection.buffer,
+                &mut response_started,
+            )
+            .await
+            .map(|res| res.status);
+            match result {
+                Ok(status) => {
+                    info!(
+                        "http-server: client response: {:?}, handled in {=u64} ms",
+                        status,
+                        accepted.elapsed().as_millis()
+                    );
                 }
                 Err(e) => {
-                    error!("http-server: client handling error: {:?}", e);
+                    error!(
+                        "http-server: client handling error after {=u64} ms: {:?}",
+                        accepted.elapsed().as_millis(),
+                        e
+                    );
+                    Self::return_error_response(sock, &e, &mut response_started).await;
                 }
             }
 
             // always terminate connection, regardless of errors
             Self::finish_connection(sock).await;
-        }
-    }
-
-    async fn accept_socket_dual_stack<'b>(
-        endpoint: IpListenEndpoint,
-        ap_socket: &'b mut TcpSocket<'a>,
-        sta_socket: &'b mut TcpSocket<'a>,
-    ) -> Option<&'b mut TcpSocket<'a>> {
-        let r = embassy_futures::select::select(
-            ap_socket.accept(endpoint),
-            sta_socket.accept(endpoint),
-        )
-        .await;
-
-        match r {
-            Either::First(Ok(())) => Some(ap_socket),
-            Either::Second(Ok(())) => Some(sta_socket),
-            Either::First(Err(e)) => {
-                error!("http-server: AP socket error: {:?}", e);
-                None
-            }
-            Either::Second(Err(e)) => {
-                error!("http-server: STA socket error: {:?}", e);
-                None
+            active_connections.set(active_connections.get() - 1);
+            if active_connections.get() == 0 {
+                watchdog::park(WatchedTask::Http);
+            } else {
+                watchdog::heartbeat(WatchedTask::Http);
             }
         }
     }
 
-    async fn accept_socket_single_stack<'b>(
-        endpoint: IpListenEndpoint,
-        socket: &'b mut TcpSocket<'a>,
-    ) -> Option<&'b mut TcpSocket<'a>> {
-        socket.accept(endpoint).await.map(|_| socket).ok()
+    /// Tells the client that its request failed, unless part of a response was already sent.
+    async fn return_error_response(
+        sock: &mut TcpSocket<'a>,
+        error: &HttpServerError,
+        response_started: &mut bool,
+    ) {
+        if *response_started {
+            // a second status line would be read as part of the body, the client will see a truncated response instead
+            warn!("http-server: response already started, closing connection");
+            return;
+        }
+        let mut res = HttpServerResponse::new(sock, response_started);
+        let sent = match error {
+            HttpServerError::RequestTimeout => res.return_request_timeout().await,
+            _ => res.return_internal_error().await,
+        };
+        if let Err(e) = sent {
+            debug!("http-server: failed to send error response: {:?}", e);
+        }
     }
 
     /// Called upon HTTP request to the given socket.
     /// This "parses" the incoming request and forwards them to the handler function.
+    ///
+    /// The request must be fully read within [`REQUEST_READ_TIMEOUT`], each read also being bounded by the socket
+    /// timeout.
     async fn handle_client_request<'r, H>(
         sock: &'r mut TcpSocket<'a>,
-        handler: &mut H,
-        buffer: &'r mut heapless::Vec<u8, 1024>,
+        handler: &H,
+        buffer: &'r mut heapless::Vec<u8, BUFFER_SIZE>,
+        response_started: &'r mut bool,
     ) -> Result<HttpServerResponse<'a, 'r>, HttpServerError>
     where
-        H: AsyncFnMut(
+        H: AsyncFn(
             HttpServerRequest<'a, 'r>,
         ) -> Result<HttpServerResponse<'a, 'r>, HttpServerError>,
     {
         debug!("http-server: handling client request");
+        let deadline = Instant::now() + REQUEST_READ_TIMEOUT;
+        let peer = sock.remote_endpoint();
         buffer.clear();
-        let method_end = Self::read_until_byte(sock, buffer, b' ').await?;
+        let method_end = Self::read_until_byte(sock, buffer, b' ', deadline).await?;
         let Ok(method) = HttpMethod::try_from(&buffer[..method_end]) else {
-            let mut res = HttpServerResponse::new(sock);
+            let mut res = HttpServerResponse::new(sock, response_started);
             res.return_bad_request().await?;
             return Ok(res);
         };
         Self::shift_buffer(buffer, method_end + 1);
         debug!("http-server: method: {}", AsRef::<str>::as_ref(&method));
 
-        // Read the Content-Length header, expecting 'Content-Length: ' (other formats are not supported).
-        // OR read until the end of the headers.
-        let (content_length, headers_end) = match Self::read_until_bytes(
-            sock,
-            buffer,
-            &[b"Content-Length: ", b"\r\n\r\n"],
-        )
-        .await?
-        {
-            // "Content-Length: " encountered first, read the content length
-            ReadUntilBytesMatch {
-                pattern: 0,
-                start: content_length_start,
-            } => {
-                Self::shift_buffer(buffer, content_length_start + 16); // 16 is the length of "Content-Length: "
-                let content_length_end = Self::read_until_byte(sock, buffer, b'\r').await?;
-
-                let Some(content_length) = core::str::from_utf8(&buffer[..content_length_end])
-                    .ok()
-                    .and_then(|s| s.parse::<usize>().ok())
-                else {
-                    info!("http-server: invalid Content-Length header");
-                    let mut res = HttpServerResponse::new(sock);
-                    res.return_bad_request().await?;
-                    return Ok(res);
-                };
-                debug!("http-server: content length: {}", content_length);
-
-                // then discard other headers
-                let headers_end = Self::read_until_bytes(sock, buffer, &[b"\r\n\r\n"])
-                    .await?
-                    .start;
-
-                (content_length, headers_end)
-            }
-            // "\r\n\r\n" encountered first, no Content-Length header, thus no body
-            ReadUntilBytesMatch {
-                pattern: _,
-                start: headers_end,
-            } => (0, headers_end),
+        let target_end = Self::read_until_byte(sock, buffer, b' ', deadline).await?;
+        let target = &buffer[..target_end];
+        // ignore the query string
+        let path_end = memchr::memchr(b'?', target).unwrap_or(target.len());
+        let Some(path) = core::str::from_utf8(&target[..path_end])
+            .ok()
+            .and_then(|p| heapless::String::<HTTP_MAX_PATH_LEN>::try_from(p).ok())
+        else {
+            info!("http-server: invalid or too long request path");
+            let mut res = HttpServerResponse::new(sock, response_started);
+            res.return_bad_request().await?;
+            return Ok(res);
         };
+        Self::shift_buffer(buffer, target_end + 1);
+        debug!("http-server: path: {}", path.as_str());
+
+        // read until the end of the headers, then look for the headers used by the server and handlers
+        let headers_end = Self::read_until_bytes(sock, buffer, b"\r\n\r\n", deadline).await?;
+        let mut content_length = 0usize;
+        let mut authorization = None;
+        let mut accepts_gzip = false;
+        let mut content_type = None;
+        for (name, value) in util::http::headers(&buffer[..headers_end]) {
+            if name.eq_ignore_ascii_case(b"Authorization") {
+                authorization = heapless::Vec::from_slice(value).ok();
+                continue;
+            }
+            if name.eq_ignore_ascii_case(b"Accept-Encoding") {
+                accepts_gzip = util::http::accepts_encoding(value, b"gzip");
+                continue;
+            }
+            if name.eq_ignore_ascii_case(b"Content-Type") {
+                content_type = heapless::Vec::from_slice(value).ok();
+                continue;
+            }
+            if !name.eq_ignore_ascii_case(b"Content-Length") {
+                continue;
+            }
+            let Some(len) = util::http::parse_content_length(value) else {
+                info!("http-server: invalid Content-Length header");
+                let mut res = HttpServerResponse::new(sock, response_started);
+                res.return_bad_request().await?;
+                return Ok(res);
+            };
+            content_length = len;
+        }
+        debug!("http-server: content length: {}", content_length);
+
+        if content_length > BUFFER_SIZE {
+            info!(
+                "http-server: body is too large ({=usize}/{=usize})",
+                content_length, BUFFER_SIZE
+            );
+            let mut res = HttpServerResponse::new(sock, response_started);
+            res.return_payload_too_large().await?;
+            return Ok(res);
+        }
 
         if content_length > 0 {
             Self::shift_buffer(buffer, headers_end + 4); // 4 is the length of "\r\n\r\n"
@@ -269,7 +429,7 @@ impl<'a> HttpServer<'a> {
                     buffer.len(),
                     content_length
                 );
-                Self::read_append(sock, buffer).await?;
+                Self::read_append(sock, buffer, deadline).await?;
                 remaining = content_length.saturating_sub(buffer.len());
             }
             info!(
@@ -281,8 +441,14 @@ impl<'a> HttpServer<'a> {
 
         let req = HttpServerRequest {
             method,
+            path,
+            authorization,
+            accepts_gzip,
+            content_type,
+            peer,
             body: &mut buffer[..content_length],
             sock,
+            started: response_started,
         };
         handler(req).await
     }
@@ -298,9 +464,12 @@ impl<'a> HttpServer<'a> {
     }
 
     /// Reads bytes from the socket and appends them to the buffer in a *very* safe way.
+    ///
+    /// Fails with [`HttpServerError::RequestTimeout`] if nothing is read before `deadline`.
     async fn read_append<const N: usize>(
         sock: &mut TcpSocket<'_>,
         buf: &mut heapless::Vec<u8, N>,
+        deadline: Instant,
     ) -> Result<(), HttpServerError> {
         if buf.is_full() {
             return Err(HttpServerError::FullBuffer);
@@ -309,7 +478,11 @@ impl<'a> HttpServer<'a> {
 
         let free: &mut [u8] =
             unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr().add(old_len), N - old_len) };
-        let count = sock.read(free).await?;
+        let count = sock
+            .read(free)
+            .with_timeout(deadline.saturating_duration_since(Instant::now()))
+            .await
+            .map_err(|_| HttpServerError::RequestTimeout)??;
 
         unsafe {
             buf.set_len(old_len + count);
@@ -328,6 +501,7 @@ impl<'a> HttpServer<'a> {
         sock: &mut TcpSocket<'_>,
         buf: &mut heapless::Vec<u8, N>,
         byte: u8,
+        deadline: Instant,
     ) -> Result<usize, HttpServerError> {
         let mut offset = 0usize;
         loop {
@@ -335,29 +509,26 @@ impl<'a> HttpServer<'a> {
                 break Ok(offset + pos);
             }
             offset = buf.len();
-            Self::read_append(sock, buf).await?;
+            Self::read_append(sock, buf, deadline).await?;
         }
     }
 
-    /// Reads the socket until one of the byte sequences are encountered, or there is a networking error, or the buffer is completely full.
-    /// Returns a match with the position of the first pattern found and its index in the `patterns` slice.
+    /// Reads the socket until a byte sequence is encountered, or there is a networking error, or the buffer is completely full.
+    /// Returns the position of the sequence from the start of the buffer.
     async fn read_until_bytes<const N: usize>(
         sock: &mut TcpSocket<'_>,
         buf: &mut heapless::Vec<u8, N>,
-        patterns: &[&[u8]],
-    ) -> Result<ReadUntilBytesMatch, HttpServerError> {
+        pattern: &[u8],
+        deadline: Instant,
+    ) -> Result<usize, HttpServerError> {
         let mut offset = 0usize;
         loop {
-            for (i, pattern) in patterns.iter().enumerate() {
-                if let Some(pos) = memchr::memmem::find(&buf[offset..], pattern) {
-                    return Ok(ReadUntilBytesMatch {
-                        start: offset + pos,
-                        pattern: i,
-                    });
-                }
+            if let Some(pos) = memchr::memmem::find(&buf[offset..], pattern) {
+                return Ok(offset + pos);
             }
-            offset = buf.len();
-            Self::read_append(sock, buf).await?;
+            // the pattern may be split between two reads
+            offset = buf.len().saturating_sub(pattern.len() - 1);
+            Self::read_append(sock, buf, deadline).await?;
         }
     }
 
@@ -368,11 +539,6 @@ impl<'a> HttpServer<'a> {
     }
 }
 
-struct ReadUntilBytesMatch {
-    start: usize,
-    pattern: usize,
-}
-
 impl From<embassy_net::tcp::Error> for HttpServerError {
     fn from(_: embassy_net::tcp::Error) -> Self {
         HttpServerError::SocketError
@@ -384,21 +550,50 @@ impl<'a, 'r> HttpServerRequest<'a, 'r> {
         self.method
     }
 
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Value of the `Authorization` header, if present and at most [`HTTP_MAX_AUTHORIZATION_LEN`] bytes long.
+    pub fn authorization(&self) -> Option<&[u8]> {
+        self.authorization.as_deref()
+    }
+
+    /// Whether the `Accept-Encoding` header allows gzip-compressed responses.
+    pub fn accepts_gzip(&self) -> bool {
+        self.accepts_gzip
+    }
+
+    /// Value of the `Content-Type` header, if present and at most [`HTTP_MAX_CONTENT_TYPE_LEN`] bytes long.
+    pub fn content_type(&self) -> Option<&[u8]> {
+        self.content_type.as_deref()
+    }
+
+    /// Address and port of the client, if the connection is still open.
+    pub fn peer(&self) -> Option<IpEndpoint> {
+        self.peer
+    }
+
     pub fn body(&mut self) -> &mut [u8] {
         self.body
     }
 
     pub fn new_response(self) -> HttpServerResponse<'a, 'r> {
-        HttpServerResponse::new(self.sock)
+        HttpServerResponse::new(self.sock, self.started)
     }
 }
 
 impl<'a, 'r> HttpServerResponse<'a, 'r> {
-    pub fn new(sock: &'r mut TcpSocket<'a>) -> Self {
-        HttpServerResponse { status: 200, sock }
+    pub fn new(sock: &'r mut TcpSocket<'a>, started: &'r mut bool) -> Self {
+        HttpServerResponse {
+            sock,
+            started,
+            status: 200,
+        }
     }
 
     pub async fn write_all(&mut self, data: &[u8]) -> Result<(), HttpServerError> {
+        *self.started = true;
         self.sock
             .write_all(data)
             .await
@@ -406,6 +601,7 @@ impl<'a, 'r> HttpServerResponse<'a, 'r> {
     }
 
     pub async fn write_all_vectored(&mut self, bufs: &[&[u8]]) -> Result<(), HttpServerError> {
+        *self.started = true;
         for buf in bufs {
             self.sock
                 .write_all(buf)
@@ -415,26 +611,62 @@ impl<'a, 'r> HttpServerResponse<'a, 'r> {
         Ok(())
     }
 
+    /// Sets the response status and writes the matching status line.
+    ///
+    /// Must be written first, the headers and the empty line ending them are up to the caller.
+    pub async fn write_status(&mut self, status: u16) -> Result<(), HttpServerError> {
+        self.status = status;
+        // "HTTP/1.0 XXX " followed by the longest known reason phrase
+        let mut line: heapless::String<40> = heapless::String::new();
+        _ = util::http::write_status_line(&mut line, status);
+        self.write_all(line.as_bytes()).await
+    }
+
     pub async fn return_bad_request(&mut self) -> Result<(), HttpServerError> {
-        self.status = 400;
-        self.sock
-            .write_all(b"HTTP/1.0 400 Bad Request\r\nConnection: close\r\n\r\n")
-            .await
-            .map_err(|_| HttpServerError::SocketError)
+        self.write_status(400).await?;
+        self.write_all(b"Connection: close\r\n\r\n").await
     }
 
     pub async fn return_not_found(&mut self) -> Result<(), HttpServerError> {
-        self.status = 404;
-        self.sock
-            .write_all(b"HTTP/1.0 400 Not Found\r\nConnection: close\r\n\r\n")
-            .await
-            .map_err(|_| HttpServerError::SocketError)
+        self.write_status(404).await?;
+        self.write_all(b"Connection: close\r\n\r\n").await
+    }
+
+    pub async fn return_payload_too_large(&mut self) -> Result<(), HttpServerError> {
+        self.write_status(413).await?;
+        self.write_all(b"Connection: close\r\n\r\n").await
+    }
+
+    /// Asks the client for credentials using the `Basic` scheme.
+    pub async fn return_unauthorized(&mut self, realm: &str) -> Result<(), HttpServerError> {
+        self.write_status(401).await?;
+        self.write_all_vectored(&[
+            b"WWW-Authenticate: Basic realm=\"",
+            realm.as_bytes(),
+            b"\", charset=\"UTF-8\"\r\nConnection: close\r\n\r\n",
+        ])
+        .await
+    }
+
+    pub async fn return_request_timeout(&mut self) -> Result<(), HttpServerError> {
+        self.write_status(408).await?;
+        self.write_all(b"Connection: close\r\n\r\n").await
+    }
+
+    pub async fn return_internal_error(&mut self) -> Result<(), HttpServerError> {
+        self.write_status(500).await?;
+        self.write_all(b"Connection: close\r\n\r\n").await
+    }
+
+    pub async fn return_service_unavailable(&mut self) -> Result<(), HttpServerError> {
+        self.write_status(503).await?;
+        self.write_all(b"Connection: close\r\n\r\n").await
     }
 
     pub async fn return_see_other(&mut self, location: &str) -> Result<(), HttpServerError> {
-        self.status = 303;
+        self.write_status(303).await?;
         self.write_all_vectored(&[
-            b"HTTP/1.0 303 See Other\r\nLocation: ",
+            b"Location: ",
             location.as_bytes(),
             b"\r\nConnection: close\r\n\r\n",
         ])
@@ -442,6 +674,6 @@ impl<'a, 'r> HttpServerResponse<'a, 'r> {
     }
 
     pub async fn finish_connection(&mut self) {
-        HttpServer::finish_connection(self.sock).await
+        <HttpServer>::finish_connection(self.sock).await
     }
 }
@@ -44,6 +44,7 @@ impl<'a> BoxedTcpSocket<'a> {
             } = buffers.as_mut();
 
             let sock = ManuallyDrop::new(TcpSocket::new(stack, rx_buffer, tx_buffer));
+            crate::diagnostics::tcp_socket_allocated();
             Ok(BoxedTcpSocket {
                 buffers: buffers.as_ptr(),
                 sock,
@@ -63,6 +64,7 @@ impl Drop for BoxedTcpSocket<'_> {
             core::mem::swap(&mut self.buffers, &mut buffers);
             esp_alloc::HEAP.dealloc(buffers.cast::<u8>(), BUFFERS_LAYOUT);
         }
+        crate::diagnostics::tcp_socket_freed();
     }
 }
 
@@ -1,34 +1,53 @@
+use core::cell::Cell;
+use core::net::Ipv4Addr;
 use core::ops::DerefMut;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use defmt::{error, info, warn, Debug2Format};
-use embassy_net::{DhcpConfig, Runner, Stack, StackResources, StaticConfigV4};
+use embassy_futures::select::{select, select4, Either, Either4};
+use embassy_net::{ConfigV4, DhcpConfig, Runner, Stack, StackResources, StaticConfigV4};
 use embassy_sync::{
     blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex},
-    mutex::{Mutex, MutexGuard},
+    mutex::Mutex,
+    signal::Signal,
 };
-use embassy_time::{Duration, Timer};
+use embassy_time::{Duration, Instant, Timer};
 use enumset::{enum_set, EnumSet};
-use esp_hal::{peripheral::Peripheral, peripherals::WIFI, rng::Rng};
+use esp_hal::{gpio::Input, peripheral::Peripheral, peripherals::WIFI, rng::Rng};
 use esp_wifi::{
     wifi::{
-        AccessPointConfiguration, ClientConfiguration, Configuration as WifiConfiguration,
-        WifiDevice, WifiError, WifiEvent, WifiMode, WifiState,
+        AccessPointConfiguration, AuthMethod, ClientConfiguration,
+        Configuration as WifiConfiguration, WifiDevice, WifiError, WifiEvent, WifiMode, WifiState,
     },
     EspWifiController,
 };
 use static_cell::StaticCell;
 
-use crate::config::CONFIG;
+use crate::config::{HttpInterfaces, CONFIG};
+use crate::watchdog::{self, WatchedTask};
+use crate::FutureTimeoutExt;
 
 use super::{GATEWAY_IP, GATEWAY_RANGE};
 
-const MAX_SOCKETS_AP: usize = 3;
-const MAX_SOCKETS_STA: usize = 4;
+// each interface also needs one socket per HTTP server connection, see `HTTP_CONNECTIONS_PER_INTERFACE`
+const MAX_SOCKETS_AP: usize = 4;
+const MAX_SOCKETS_STA: usize = 5;
 const DELAY: Duration = Duration::from_millis(2500);
+/// Time the button must be held to start the access point again, see [`run_ap_button`].
+const AP_BUTTON_HOLD: Duration = Duration::from_secs(3);
 
 static STACK_RESOURCES_AP: StaticCell<StackResources<MAX_SOCKETS_AP>> = StaticCell::new();
 static STACK_RESOURCES_STA: StaticCell<StackResources<MAX_SOCKETS_STA>> = StaticCell::new();
 
+/// Whether the station is connected to its access point, see [`is_sta_ready`].
+static STA_READY: AtomicBool = AtomicBool::new(false);
+/// Signaled when the DNS servers of the configuration change, see [`request_dns_update`].
+static DNS_UPDATE_REQUEST: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+/// Signaled to start the access point again after it was stopped, see [`request_ap_start`].
+static AP_START_REQUEST: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+/// Signaled when the station credentials of the configuration change, see [`request_sta_update`].
+static STA_UPDATE_REQUEST: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
 #[derive(Debug)]
 pub struct WifiConfigurationError;
 
@@ -48,6 +67,8 @@ pub enum StackStatus {
     Initializing,
     Connecting,
     Ready,
+    /// Stopped at runtime, only used by the access point
+    Stopped,
 }
 
 impl AsRef<str> for StackStatus {
@@ -56,10 +77,68 @@ impl AsRef<str> for StackStatus {
             StackStatus::Initializing => "init",
             StackStatus::Connecting => "connecting",
             StackStatus::Ready => "ready",
+            StackStatus::Stopped => "stopped",
         }
     }
 }
 
+/// Whether the station (STA mode) is connected to its access point, the uplink may still be waiting for an IP address.
+///
+/// Always `false` when the station is not configured.
+pub fn is_sta_ready() -> bool {
+    STA_READY.load(Ordering::Relaxed)
+}
+
+/// Asks the running Wi-Fi controller to apply the DNS servers of the configuration to the access point's stack.
+pub fn request_dns_update() {
+    DNS_UPDATE_REQUEST.signal(());
+}
+
+/// Asks the running Wi-Fi controller to start the access point again, if it was stopped once the station connected.
+///
+/// It is stopped again after the configured delay, see [`Config::wifi_ap_shutdown_delay_secs`](crate::config::Config).
+pub fn request_ap_start() {
+    AP_START_REQUEST.signal(());
+}
+
+/// Asks the running Wi-Fi controller to connect the station with the credentials of the configuration, if they changed.
+///
+/// The station is disconnected if they are no longer set.
+pub fn request_sta_update() {
+    STA_UPDATE_REQUEST.signal(());
+}
+
+/// Calls [`request_ap_start`] each time `button` is held low for a few seconds, in an infinite loop.
+pub async fn run_ap_button(mut button: Input<'_>) -> ! {
+    loop {
+        button.wait_for_low().await;
+        if button
+            .wait_for_high()
+            .with_timeout(AP_BUTTON_HOLD)
+            .await
+            .is_err()
+        {
+            info!("wifi AP: button held, starting the access point");
+            request_ap_start();
+            button.wait_for_high().await;
+        }
+    }
+}
+
+/// Static configuration of the access point's stack, serving `dns_servers`.
+fn ap_stack_config(dns_servers: [Ipv4Addr; 2]) -> StaticConfigV4 {
+    StaticConfigV4 {
+        address: GATEWAY_RANGE,
+        gateway: Some(GATEWAY_IP),
+        dns_servers: heapless::Vec::from_slice(&dns_servers).unwrap(),
+    }
+}
+
+async fn configured_dns_servers() -> [Ipv4Addr; 2] {
+    let config = CONFIG.lock().await;
+    [config.dns_server_1, config.dns_server_2]
+}
+
 pub async fn init_wifi<'d>(
     esp_wifi_ctrl: &'d mut EspWifiController<'_>,
     mut rng: Rng,
@@ -69,19 +148,8 @@ pub async fn init_wifi<'d>(
     let ap_device = interfaces.ap;
     let sta_device = interfaces.sta;
 
-    let mut dns_servers = heapless::Vec::new();
-
-    MutexGuard::map(CONFIG.lock().await, |config| {
-        dns_servers.push(config.dns_server_1).unwrap();
-        dns_servers.push(config.dns_server_2).unwrap();
-        config
-    });
-
-    let ap_config = embassy_net::Config::ipv4_static(StaticConfigV4 {
-        address: GATEWAY_RANGE,
-        gateway: Some(GATEWAY_IP),
-        dns_servers,
-    });
+    let ap_config =
+        embassy_net::Config::ipv4_static(ap_stack_config(configured_dns_servers().await));
     let sta_config = embassy_net::Config::dhcpv4(DhcpConfig::default());
 
     let seed = (u64::from(rng.random()) << 32) | u64::from(rng.random());
@@ -125,13 +193,24 @@ pub struct WifiController<'d> {
 type ControllerMutex<'a, 'd> = Mutex<NoopRawMutex, &'a mut esp_wifi::wifi::WifiController<'d>>;
 
 impl<'d> WifiController<'d> {
+    /// Configures the access point, protected with WPA2 if `password` is given and open otherwise.
     pub fn enable_ap(
         &mut self,
         ssid: impl TryInto<heapless::String<32>>,
+        password: Option<impl TryInto<heapless::String<64>>>,
     ) -> Result<(), WifiConfigurationError> {
-        self.ap_config = Some(AccessPointConfiguration {
-            ssid: ssid.try_into().map_err(|_| WifiConfigurationError)?,
-            ..Default::default()
+        let ssid = ssid.try_into().map_err(|_| WifiConfigurationError)?;
+        self.ap_config = Some(match password {
+            Some(password) => AccessPointConfiguration {
+                ssid,
+                auth_method: AuthMethod::WPA2Personal,
+                password: password.try_into().map_err(|_| WifiConfigurationError)?,
+                ..Default::default()
+            },
+            None => AccessPointConfiguration {
+                ssid,
+                ..Default::default()
+            },
         });
         Ok(())
     }
@@ -149,10 +228,44 @@ impl<'d> WifiController<'d> {
         Ok(())
     }
 
+    /// Removes the station configuration, the station is no longer connected.
+    pub fn disable_sta(&mut self) {
+        self.sta_config = None;
+    }
+
     /// Runs the Wi-Fi access point (AP mode) and/or a connection to an external access point (STA mode).
     /// Returns only when both AP and STA modes are externally stopped.
+    ///
+    /// DNS servers changed meanwhile are applied to the access point's stack, see [`request_dns_update`].
     pub async fn run(&mut self) -> Result<(), WifiError> {
-        let config = self.create_config();
+        let ap_stack = self.ap_stack;
+        match select(self.run_modes(), Self::apply_dns_updates(ap_stack)).await {
+            Either::First(res) => res,
+            Either::Second(never) => never,
+        }
+    }
+
+    async fn apply_dns_updates(ap_stack: Stack<'d>) -> ! {
+        loop {
+            DNS_UPDATE_REQUEST.wait().await;
+            let dns_servers = configured_dns_servers().await;
+            let config = ap_stack_config(dns_servers);
+            if ap_stack
+                .config_v4()
+                .is_some_and(|current| current.dns_servers == config.dns_servers)
+            {
+                continue;
+            }
+            info!(
+                "wifi AP: using DNS servers {} and {}",
+                dns_servers[0], dns_servers[1]
+            );
+            ap_stack.set_config_v4(ConfigV4::Static(config));
+        }
+    }
+
+    async fn run_modes(&mut self) -> Result<(), WifiError> {
+        let config = self.create_config(true);
         self.ctrl.set_configuration(&config)?;
 
         let mode: WifiMode = (&config).try_into()?;
@@ -164,17 +277,144 @@ impl<'d> WifiController<'d> {
             Debug2Format(&self.ctrl.capabilities())
         );
 
+        let ap_shutdown_delay = Self::ap_shutdown_delay().await;
+        let mut ap_shutdown_at = None;
+
         while ap_enabled | sta_enabled {
+            watchdog::heartbeat(WatchedTask::Wifi);
+            // a stopped access point is started again if the station fails to connect
+            let ap_fallback_config =
+                (!ap_enabled && self.ap_config.is_some()).then(|| self.create_config(true));
+            let ap_restarted = Cell::new(false);
             let ctrl: ControllerMutex = ControllerMutex::new(&mut self.ctrl);
             embassy_futures::join::join(
                 Self::ensure_ap_connected(&ctrl, self.ap_config.as_ref(), ap_enabled),
-                Self::ensure_sta_connected(&ctrl, self.sta_config.as_ref(), sta_enabled),
+                Self::ensure_sta_connected(
+                    &ctrl,
+                    self.sta_config.as_ref(),
+                    sta_enabled,
+                    ap_fallback_config.as_ref(),
+                    &ap_restarted,
+                ),
             )
             .await;
+            ap_enabled |= ap_restarted.get();
 
-            self.poll_events(&mut ap_enabled, &mut sta_enabled).await;
+            // the delay starts over each time the access point is started
+            ap_shutdown_at = match ap_shutdown_delay {
+                Some(delay) if ap_enabled && is_sta_ready() => {
+                    Some(ap_shutdown_at.unwrap_or_else(|| Instant::now() + delay))
+                }
+                _ => None,
+            };
+            let ap_shutdown = async {
+                match ap_shutdown_at {
+                    Some(at) => Timer::at(at).await,
+                    None => core::future::pending().await,
+                }
+            };
+
+            watchdog::park(WatchedTask::Wifi);
+            match select4(
+                self.poll_events(&mut ap_enabled, &mut sta_enabled),
+                ap_shutdown,
+                AP_START_REQUEST.wait(),
+                STA_UPDATE_REQUEST.wait(),
+            )
+            .await
+            {
+                Either4::First(()) => (),
+                Either4::Second(()) => match self.disable_ap().await {
+                    Ok(()) => ap_enabled = false,
+                    Err(e) => error!("wifi AP: failed to stop access point: {:?}", e),
+                },
+                Either4::Third(()) if !ap_enabled && self.ap_config.is_some() => {
+                    match self.enable_ap_runtime().await {
+                        Ok(()) => ap_enabled = true,
+                        Err(e) => error!("wifi AP: failed to start access point: {:?}", e),
+                    }
+                }
+                Either4::Third(()) => (),
+                Either4::Fourth(()) => {
+                    if let Err(e) = self
+                        .reconfigure_sta(&mut ap_enabled, &mut sta_enabled)
+                        .await
+                    {
+                        error!("wifi STA: failed to apply new credentials: {:?}", e);
+                    }
+                }
+            }
         }
+        watchdog::heartbeat(WatchedTask::Wifi);
+
+        Ok(())
+    }
+
+    /// Time the access point keeps running once the station is connected, `None` if it is never stopped.
+    async fn ap_shutdown_delay() -> Option<Duration> {
+        let config = CONFIG.lock().await;
+        let delay = config.wifi_ap_shutdown_delay_secs?;
+        if config.http_interfaces == HttpInterfaces::Ap {
+            warn!("wifi AP: the dashboard is only served on the access point, never stopping it");
+            return None;
+        }
+        Some(Duration::from_secs(delay.into()))
+    }
+
+    /// Applies the station credentials of the configuration at runtime, the next iteration of the loop connects.
+    async fn reconfigure_sta(
+        &mut self,
+        ap_enabled: &mut bool,
+        sta_enabled: &mut bool,
+    ) -> Result<(), WifiError> {
+        let (ssid, password) = {
+            let config = CONFIG.lock().await;
+            (config.wifi_sta_ssid.clone(), config.wifi_sta_pass.clone())
+        };
+        let credentials =
+            |config: &ClientConfiguration| (config.ssid.clone(), config.password.clone());
+        let previous = self.sta_config.as_ref().map(credentials);
+        match (ssid, password) {
+            // both always fit, they have the same capacity
+            (Some(ssid), Some(password)) => self.enable_sta(ssid, password).unwrap(),
+            _ => self.disable_sta(),
+        }
+        if self.sta_config.as_ref().map(credentials) == previous {
+            return Ok(());
+        }
+
+        if previous.is_some() {
+            info!("wifi STA: disconnecting to apply new credentials");
+            STA_READY.store(false, Ordering::Relaxed);
+            if let Err(e) = self.ctrl.disconnect_async().await {
+                warn!("wifi STA: disconnect failed: {:?}", e);
+            }
+        }
+        // without the station, the access point is the only way to reach the gateway
+        let with_ap = *ap_enabled || (self.sta_config.is_none() && self.ap_config.is_some());
+        self.ctrl.set_configuration(&self.create_config(with_ap))?;
+        *ap_enabled = with_ap;
+        *sta_enabled = self.sta_config.is_some();
+        if !*sta_enabled {
+            warn!("wifi STA: no credentials, not connecting");
+            update_status(|s| s.sta_status = StackStatus::Initializing).await;
+        }
+        Ok(())
+    }
+
+    /// Stops the access point at runtime, the station stays connected.
+    async fn disable_ap(&mut self) -> Result<(), WifiError> {
+        info!("wifi AP: station connected, stopping access point");
+        self.ctrl.set_configuration(&self.create_config(false))?;
+        update_status(|s| s.ap_status = StackStatus::Stopped).await;
+        Ok(())
+    }
 
+    /// Starts the access point again at runtime, after [`Self::disable_ap`].
+    async fn enable_ap_runtime(&mut self) -> Result<(), WifiError> {
+        info!("wifi AP: starting access point again");
+        self.ctrl.set_configuration(&self.create_config(true))?;
+        update_status(|s| s.ap_status = StackStatus::Initializing).await;
         Ok(())
     }
 
@@ -189,6 +429,7 @@ impl<'d> WifiController<'d> {
         let config = config.expect("broken: no AP config in AP mode!");
 
         while !matches!(esp_wifi::wifi::ap_state(), WifiState::ApStarted) {
+            watchdog::heartbeat(WatchedTask::Wifi);
             info!("wifi AP: starting access point...");
 
             update_status(|s| s.ap_status = StackStatus::Initializing).await;
@@ -200,15 +441,19 @@ impl<'d> WifiController<'d> {
                     "wifi AP: access point started, ssid=`{}`, auth_method=`{:?}`",
                     config.ssid, config.auth_method
                 );
-                update_status(|s| s.ap_status = StackStatus::Ready).await;
             }
         }
+        // also when started again by a configuration change, without going through the loop
+        update_status(|s| s.ap_status = StackStatus::Ready).await;
     }
 
+    /// Connects the station, applying `ap_fallback_config` after a failed attempt and setting `ap_restarted` then.
     async fn ensure_sta_connected<'a>(
         ctrl: &ControllerMutex<'a, 'd>,
         config: Option<&ClientConfiguration>,
         enabled: bool,
+        ap_fallback_config: Option<&WifiConfiguration>,
+        ap_restarted: &Cell<bool>,
     ) {
         if !enabled {
             return;
@@ -216,6 +461,8 @@ impl<'d> WifiController<'d> {
         let config = config.expect("broken: no STA config in STA mode!");
 
         loop {
+            // retrying may take a while, this is still progress
+            watchdog::heartbeat(WatchedTask::Wifi);
             match esp_wifi::wifi::sta_state() {
                 WifiState::StaStarted | WifiState::StaConnected | WifiState::StaDisconnected => {
                     // station mode stated, attempt to connect
@@ -230,9 +477,27 @@ impl<'d> WifiController<'d> {
                                 "wifi STA: connect failed, attempting after {}: {:?}",
                                 DELAY, e
                             );
+                            if let Some(fallback) =
+                                ap_fallback_config.filter(|_| !ap_restarted.get())
+                            {
+                                warn!(
+                                    "wifi AP: starting access point again to allow reconfiguration"
+                                );
+                                match ctrl.lock().await.set_configuration(fallback) {
+                                    Ok(()) => {
+                                        update_status(|s| s.ap_status = StackStatus::Initializing)
+                                            .await;
+                                        ap_restarted.set(true)
+                                    }
+                                    Err(e) => {
+                                        error!("wifi AP: failed to start access point: {:?}", e)
+                                    }
+                                }
+                            }
                             Timer::after(DELAY).await;
                         } else {
                             info!("wifi STA: connected to access point");
+                            STA_READY.store(true, Ordering::Relaxed);
                             update_status(|s| s.sta_status = StackStatus::Ready).await;
                             return;
                         }
@@ -267,10 +532,12 @@ impl<'d> WifiController<'d> {
         }
         if events.contains(WifiEvent::StaStop) {
             warn!("wifi STA mode stopped");
+            STA_READY.store(false, Ordering::Relaxed);
             *sta_enabled = false;
         }
         if events.contains(WifiEvent::StaDisconnected) {
             warn!("disconnected from AP");
+            STA_READY.store(false, Ordering::Relaxed);
         }
         if events.contains(WifiEvent::ApStaconnected) {
             info!("wifi AP: new client connected");
@@ -280,8 +547,10 @@ impl<'d> WifiController<'d> {
         }
     }
 
-    fn create_config(&self) -> WifiConfiguration {
-        match (self.ap_config.clone(), self.sta_config.clone()) {
+    /// Returns the configuration of the enabled modes, without the access point unless `with_ap` is set.
+    fn create_config(&self, with_ap: bool) -> WifiConfiguration {
+        let ap_config = self.ap_config.clone().filter(|_| with_ap);
+        match (ap_config, self.sta_config.clone()) {
             (None, None) => WifiConfiguration::None,
             (None, Some(sta)) => WifiConfiguration::Client(sta),
             (Some(ap), None) => WifiConfiguration::AccessPoint(ap),
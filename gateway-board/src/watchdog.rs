@@ -0,0 +1,102 @@
+//! Software watchdog: reboots the gateway when one of its tasks stops making progress.
+//!
+//! Watched tasks report a heartbeat every time they make progress.
+//! Before waiting on external events for an unbounded amount of time (e.g. incoming connections), a task
+//! *parks* itself so that it isn't considered stale while idle, the next heartbeat resumes the watch.
+//!
+//! The watchdog task keeps feeding the hardware RTC watchdog as long as no task is stale,
+//! once a task misses its heartbeat for longer than the configured threshold, it stops feeding and lets
+//! the hardware watchdog reset the board.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use defmt::{error, info, Format};
+use embassy_time::{Duration, Instant, Timer};
+use esp_hal::rtc_cntl::{Rtc, RwdtStage};
+
+/// Delay after which the hardware watchdog resets the board if it isn't fed.
+const WATCHDOG_HARDWARE_TIMEOUT_SECS: u64 = 10;
+/// Interval between two checks of the heartbeats (and two feedings of the hardware watchdog)
+const WATCHDOG_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Value of a heartbeat slot when the task is not watched (parked or not started)
+const NOT_WATCHED: u32 = u32::MAX;
+
+#[derive(Clone, Copy, Format)]
+pub enum WatchedTask {
+    Lora,
+    Wifi,
+    Http,
+    Export,
+}
+
+const WATCHED_TASKS: [WatchedTask; 4] = [
+    WatchedTask::Lora,
+    WatchedTask::Wifi,
+    WatchedTask::Http,
+    WatchedTask::Export,
+];
+
+/// Time of the last heartbeat of each task, in milliseconds since boot (wrapping)
+static HEARTBEATS: [AtomicU32; WATCHED_TASKS.len()] = [
+    AtomicU32::new(NOT_WATCHED),
+    AtomicU32::new(NOT_WATCHED),
+    AtomicU32::new(NOT_WATCHED),
+    AtomicU32::new(NOT_WATCHED),
+];
+
+/// Reports that `task` is making progress, (re)starting its watch.
+pub fn heartbeat(task: WatchedTask) {
+    let now = now_millis();
+    // keep the sentinel value reserved
+    let now = if now == NOT_WATCHED { now - 1 } else { now };
+    HEARTBEATS[task as usize].store(now, Ordering::Relaxed);
+}
+
+/// Stops watching `task` until its next heartbeat, to be called before idle waits.
+pub fn park(task: WatchedTask) {
+    HEARTBEATS[task as usize].store(NOT_WATCHED, Ordering::Relaxed);
+}
+
+/// Feeds the hardware watchdog until one of the watched tasks misses its heartbeat for more than `threshold`.
+pub async fn run(mut rtc: Rtc<'static>, threshold: Duration) -> ! {
+    let threshold_ms = threshold.as_millis().min(u64::from(u32::MAX / 2)) as u32;
+
+    rtc.rwdt.set_timeout(
+        RwdtStage::Stage0,
+        esp_hal::time::Duration::from_secs(WATCHDOG_HARDWARE_TIMEOUT_SECS),
+    );
+    rtc.rwdt.enable();
+    info!(
+        "watchdog: enabled, tasks must report within {=u32} ms",
+        threshold_ms
+    );
+
+    loop {
+        let now = now_millis();
+
+        for task in WATCHED_TASKS {
+            let last = HEARTBEATS[task as usize].load(Ordering::Relaxed);
+            if last == NOT_WATCHED {
+                continue;
+            }
+            let elapsed = now.wrapping_sub(last);
+
+            // tolerate heartbeats stored right after `now` was sampled
+            if elapsed > threshold_ms && elapsed < u32::MAX / 2 {
+                error!(
+                    "watchdog: task {} is stale (no heartbeat for {=u32} ms), rebooting in {=u64} seconds",
+                    task, elapsed, WATCHDOG_HARDWARE_TIMEOUT_SECS
+                );
+                // stop feeding and wait for the hardware watchdog to reset the board
+                core::future::pending::<()>().await;
+            }
+        }
+
+        rtc.rwdt.feed();
+        Timer::after(WATCHDOG_CHECK_INTERVAL).await;
+    }
+}
+
+fn now_millis() -> u32 {
+    Instant::now().as_millis() as u32
+}
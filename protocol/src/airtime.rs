@@ -0,0 +1,218 @@
+//! LoRa time on air, to keep transmissions within duty-cycle limits or to compare data rates.
+//!
+//! Follows the formula of the SX126x datasheet (section 6.1.4), which matches the one of the SX127x for SF7 to SF12.
+
+/// Channel width of a LoRa transmission.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Bandwidth {
+    Khz7_8,
+    Khz10_4,
+    Khz15_6,
+    Khz20_8,
+    Khz31_25,
+    Khz41_7,
+    Khz62_5,
+    Khz125,
+    Khz250,
+    Khz500,
+}
+
+impl Bandwidth {
+    pub const fn hz(self) -> u32 {
+        match self {
+            Bandwidth::Khz7_8 => 7_810,
+            Bandwidth::Khz10_4 => 10_420,
+            Bandwidth::Khz15_6 => 15_630,
+            Bandwidth::Khz20_8 => 20_830,
+            Bandwidth::Khz31_25 => 31_250,
+            Bandwidth::Khz41_7 => 41_670,
+            Bandwidth::Khz62_5 => 62_500,
+            Bandwidth::Khz125 => 125_000,
+            Bandwidth::Khz250 => 250_000,
+            Bandwidth::Khz500 => 500_000,
+        }
+    }
+}
+
+/// Forward error correction rate, as the ratio of data bits to transmitted bits.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CodingRate {
+    Cr4_5 = 1,
+    Cr4_6 = 2,
+    Cr4_7 = 3,
+    Cr4_8 = 4,
+}
+
+/// Modulation and packet parameters that the time on air depends on.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct AirtimeParams {
+    /// From 5 to 12, larger values are clamped to 12 and smaller ones to 5
+    pub spreading_factor: u8,
+    pub bandwidth: Bandwidth,
+    pub coding_rate: CodingRate,
+    /// Number of preamble symbols, not counting the sync word
+    pub preamble_len: u16,
+    /// Whether the packet starts with a header, as opposed to the implicit header mode
+    pub explicit_header: bool,
+    pub crc: bool,
+}
+
+/// Symbol duration from which the radios enable the low data rate optimization, in microseconds.
+const LOW_DATA_RATE_SYMBOL_US: u64 = 16_384;
+
+impl AirtimeParams {
+    /// Duration of a single symbol, in microseconds.
+    pub const fn symbol_time_us(&self) -> u32 {
+        ((1u64 << self.spreading_factor()) * 1_000_000 / self.bandwidth.hz() as u64) as u32
+    }
+
+    /// Whether the low data rate optimization is used, for symbols of at least 16.384 ms such as SF11 at 125 kHz.
+    pub const fn low_data_rate_optimize(&self) -> bool {
+        (1u64 << self.spreading_factor()) * 1_000_000
+            >= LOW_DATA_RATE_SYMBOL_US * self.bandwidth.hz() as u64
+    }
+
+    const fn spreading_factor(&self) -> u32 {
+        match self.spreading_factor {
+            ..5 => 5,
+            13.. => 12,
+            sf => sf as u32,
+        }
+    }
+}
+
+/// Returns the time on air of a packet of `payload_len` bytes, in microseconds, rounded up.
+pub const fn time_on_air_us(params: &AirtimeParams, payload_len: u8) -> u32 {
+    let sf = params.spreading_factor() as i64;
+    let crc_bits = if params.crc { 16 } else { 0 };
+    let header_bits = if params.explicit_header { 20 } else { 0 };
+
+    let bits = 8 * payload_len as i64 + crc_bits - 4 * sf + header_bits;
+    // SF5 and SF6 have a longer preamble, the others 8 more bits in their payload
+    let (preamble_quarters, payload_bits) = if sf < 7 { (25, bits) } else { (17, bits + 8) };
+    let payload_bits = if payload_bits > 0 {
+        payload_bits as u64
+    } else {
+        0
+    };
+    // the low data rate optimization carries 2 fewer bits per symbol
+    let bits_per_symbol = if params.low_data_rate_optimize() {
+        4 * (sf as u64 - 2)
+    } else {
+        4 * sf as u64
+    };
+    let payload_symbols =
+        8 + payload_bits.div_ceil(bits_per_symbol) * (params.coding_rate as u64 + 4);
+
+    // in quarters of symbols, because of the fractional preamble
+    let quarters = 4 * params.preamble_len as u64 + preamble_quarters + 4 * payload_symbols;
+    let numerator = quarters * (1u64 << sf) * 1_000_000;
+    let denominator = 4 * params.bandwidth.hz() as u64;
+    let us = numerator.div_ceil(denominator);
+    if us > u32::MAX as u64 {
+        u32::MAX
+    } else {
+        us as u32
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// The parameters of LoRaWAN uplinks in EU868
+    const fn lorawan(spreading_factor: u8) -> AirtimeParams {
+        AirtimeParams {
+            spreading_factor,
+            bandwidth: Bandwidth::Khz125,
+            coding_rate: CodingRate::Cr4_5,
+            preamble_len: 8,
+            explicit_header: true,
+            crc: true,
+        }
+    }
+
+    #[test]
+    fn test_time_on_air_lorawan() {
+        // 13 bytes, the smallest LoRaWAN uplink, as given by the Semtech and TTN calculators
+        assert_eq!(time_on_air_us(&lorawan(7), 13), 46_336);
+        assert_eq!(time_on_air_us(&lorawan(9), 13), 164_864);
+        // with the low data rate optimization
+        assert_eq!(time_on_air_us(&lorawan(12), 13), 1_155_072);
+        // 51 bytes, the largest payload of SF12 in EU868, with the 13 bytes of overhead
+        assert_eq!(time_on_air_us(&lorawan(12), 64), 2_793_472);
+        assert_eq!(time_on_air_us(&lorawan(7), 64), 118_016);
+        assert_eq!(time_on_air_us(&lorawan(7), 0), 25_856);
+    }
+
+    #[test]
+    fn test_time_on_air_boards() {
+        // what the boards use: SF10, 250 kHz, 4/8, 4 preamble symbols
+        let params = AirtimeParams {
+            spreading_factor: 10,
+            bandwidth: Bandwidth::Khz250,
+            coding_rate: CodingRate::Cr4_8,
+            preamble_len: 4,
+            explicit_header: true,
+            crc: true,
+        };
+        assert_eq!(params.symbol_time_us(), 4_096);
+        assert!(!params.low_data_rate_optimize());
+        assert_eq!(time_on_air_us(&params, 20), 230_400);
+
+        let sf12 = AirtimeParams {
+            spreading_factor: 12,
+            ..params
+        };
+        assert!(sf12.low_data_rate_optimize());
+        assert_eq!(time_on_air_us(&sf12, 20), 790_528);
+
+        let implicit = AirtimeParams {
+            explicit_header: false,
+            crc: false,
+            ..params
+        };
+        assert_eq!(time_on_air_us(&implicit, 20), 197_632);
+    }
+
+    #[test]
+    fn test_time_on_air_edge_cases() {
+        let sf5 = AirtimeParams {
+            spreading_factor: 5,
+            bandwidth: Bandwidth::Khz500,
+            ..lorawan(5)
+        };
+        assert_eq!(sf5.symbol_time_us(), 64);
+        assert_eq!(time_on_air_us(&sf5, 13), 3_344);
+
+        // out of range spreading factors are clamped
+        assert_eq!(
+            time_on_air_us(&lorawan(0), 13),
+            time_on_air_us(&lorawan(5), 13)
+        );
+        assert_eq!(
+            time_on_air_us(&lorawan(255), 13),
+            time_on_air_us(&lorawan(12), 13)
+        );
+
+        // rounded up to the next microsecond
+        let slow = AirtimeParams {
+            bandwidth: Bandwidth::Khz41_7,
+            ..lorawan(7)
+        };
+        assert_eq!(time_on_air_us(&slow, 1), 77_562);
+        assert_eq!(
+            time_on_air_us(
+                &AirtimeParams {
+                    bandwidth: Bandwidth::Khz7_8,
+                    preamble_len: u16::MAX,
+                    ..lorawan(12)
+                },
+                u8::MAX
+            ),
+            u32::MAX
+        );
+    }
+}
@@ -1,5 +1,10 @@
 use crate::codec::{AsyncDecode, AsyncDecoder, AsyncEncode, AsyncEncoder, ToLeb128Ext};
-use core::future::Future;
+use core::{
+    fmt::{Display, Formatter},
+    future::Future,
+};
+
+pub mod session;
 
 /// A version 1.0 packet. ([reference])
 ///
@@ -12,6 +17,10 @@ pub enum Packet {
     Ack = 2,
     SensorData(SensorData) = 3,
     ResetConnection = 4,
+    SensorDataCompressed(SensorDataCompressed) = 5,
+    Command(Command) = 6,
+    Ping = 7,
+    Pong = 8,
 }
 
 /// Payload of `HandshakeStart` packet. ([reference])
@@ -21,6 +30,8 @@ pub enum Packet {
 pub struct HandshakeStart {
     pub major: u8,
     pub minor: u8,
+    /// Features supported by the sensor board
+    pub capabilities: Capabilities,
 }
 
 /// Payload of `HandshakeEnd` packet. ([reference])
@@ -31,6 +42,57 @@ pub struct HandshakeEnd {
     pub major: u8,
     pub minor: u8,
     pub epoch: u64,
+    /// Features supported by both the sensor board and the gateway
+    pub capabilities: Capabilities,
+}
+
+/// Bitfield of optional protocol features, exchanged during the handshake. ([reference])
+///
+/// Unknown bits are kept as-is when decoding, so that they can be masked out by [`Capabilities::intersection`].
+///
+/// [reference]: https://github.com/MisterPeModder/T-IOT-902/blob/master/doc/protocol.md#44-capabilities
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(test, derive(Debug))]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+    pub const NONE: Self = Self(0);
+    /// The gateway may send commands to the sensor board
+    pub const DOWNLINK_COMMANDS: Self = Self(1 << 0);
+    /// The `SensorDataCompressed` packet is understood
+    pub const COMPRESSED_DATA: Self = Self(1 << 1);
+    /// The sensor board answers `Ping` packets while connected
+    pub const KEEPALIVE: Self = Self(1 << 2);
+    /// Both ends move to the next channel of their hopping schedule after each acknowledged uplink
+    pub const FREQUENCY_HOPPING: Self = Self(1 << 3);
+    /// The gateway acknowledges each data frame at the link layer, so that the sensor board may send it again
+    pub const LINK_ACKS: Self = Self(1 << 4);
+
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Whether all the capabilities of `other` are also in `self`.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    /// Capabilities that are in both `self` and `other`, used to compute the negotiated features.
+    pub const fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
 }
 
 /// Payload header of the `SensorData` packet. ([reference])  
@@ -40,10 +102,27 @@ pub struct HandshakeEnd {
 #[derive(Clone, Copy)]
 #[cfg_attr(test, derive(Debug, PartialEq))]
 pub struct SensorData {
-    /// The number of [`SensorValuePoint`] values that constitutes this packet.
+    /// The number of [`SensorValuePoint`] values that constitutes this packet, at most [`MAX_VALUES_PER_PACKET`].
     pub count: u8,
 }
 
+/// Most values a `SensorData` or `SensorDataCompressed` packet may announce, larger counts are rejected when reading.
+pub const MAX_VALUES_PER_PACKET: u8 = 64;
+
+/// Payload header of the `SensorDataCompressed` packet. ([reference])  
+/// Followed by `count` [`SensorValueDelta`] values, each time offset is relative to the previous one,
+/// starting from `base_time_offset`.
+///
+/// [reference]: https://github.com/MisterPeModder/T-IOT-902/blob/master/doc/protocol.md#438-sensordatacompressed
+#[derive(Clone, Copy)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub struct SensorDataCompressed {
+    /// The number of [`SensorValueDelta`] values that constitutes this packet, at most [`MAX_VALUES_PER_PACKET`].
+    pub count: u8,
+    /// Time offset the first delta is relative to, in seconds from the handshake epoch.
+    pub base_time_offset: i64,
+}
+
 #[derive(Clone, Copy)]
 #[cfg_attr(test, derive(Debug, PartialEq))]
 pub struct SensorValuePoint {
@@ -51,6 +130,31 @@ pub struct SensorValuePoint {
     pub time_offset: i64,
 }
 
+/// A [`SensorValuePoint`] whose time offset is relative to the previous point of a `SensorDataCompressed` packet.
+#[derive(Clone, Copy)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub struct SensorValueDelta {
+    pub value: SensorValue,
+    pub time_delta: i64,
+}
+
+/// Payload of the `Command` packet, an order from the gateway that also acknowledges the last values. ([reference])
+///
+/// [reference]: https://github.com/MisterPeModder/T-IOT-902/blob/master/doc/protocol.md#439-command
+#[repr(u32)]
+#[derive(Clone, Copy)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub enum Command {
+    /// Use this LoRa spreading factor for the next packets, recommended by the gateway from the link quality
+    SetSpreadingFactor(u8) = 0,
+    /// Take measurements every this many seconds from now on, to save power or report more often
+    SetMeasureInterval(u32) = 1,
+    Unknown {
+        id: u32,
+        arg_len: u32,
+    } = u32::MAX,
+}
+
 #[repr(u32)]
 #[derive(Clone, Copy)]
 #[cfg_attr(test, derive(Debug, PartialEq))]
@@ -59,7 +163,56 @@ pub enum SensorValue {
     Pressure(f32) = 1,
     Altitude(f32) = 2,
     AirQuality(f32) = 3,
-    Unknown { id: u32, value_len: u32 } = u32::MAX,
+    /// GPS position in decimal degrees (WGS 84)
+    Location {
+        lat: f32,
+        lon: f32,
+    } = 5,
+    /// Relative humidity in percent
+    Humidity(f32) = 6,
+    /// CO2 concentration in ppm
+    Co2(f32) = 7,
+    /// Volatile organic compounds concentration in ppb
+    Voc(f32) = 8,
+    /// A value of a type the receiver does not know about, its raw bytes are only kept when decoding with
+    /// [`AsyncDecoder::retains_unknown_values`], so that it can be encoded again as-is
+    Unknown {
+        id: u32,
+        value_len: u32,
+        raw: RawValue,
+    } = u32::MAX,
+}
+
+/// Longest value of an unknown type whose raw bytes are kept by [`SensorValue::Unknown`].
+pub const MAX_RAW_VALUE_LEN: usize = 16;
+
+/// Raw bytes of a [`SensorValue::Unknown`], at most [`MAX_RAW_VALUE_LEN`] of them.
+///
+/// A fixed-size buffer rather than a `heapless::Vec` so that sensor values stay `Copy`.
+#[derive(Clone, Copy, Default)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub struct RawValue {
+    len: u8,
+    bytes: [u8; MAX_RAW_VALUE_LEN],
+}
+
+impl RawValue {
+    pub const EMPTY: Self = Self {
+        len: 0,
+        bytes: [0; MAX_RAW_VALUE_LEN],
+    };
+
+    /// Returns `None` if `bytes` is longer than [`MAX_RAW_VALUE_LEN`].
+    pub fn from_slice(bytes: &[u8]) -> Option<Self> {
+        let mut raw = Self::EMPTY;
+        raw.bytes.get_mut(..bytes.len())?.copy_from_slice(bytes);
+        raw.len = bytes.len() as u8;
+        Some(raw)
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes[..usize::from(self.len)]
+    }
 }
 
 impl Packet {
@@ -82,6 +235,10 @@ impl<E: AsyncEncoder + ?Sized> AsyncEncode<E> for &Packet {
             Packet::Ack => Ok(()),
             Packet::SensorData(sensor_data) => encoder.emit(sensor_data).await,
             Packet::ResetConnection => Ok(()),
+            Packet::SensorDataCompressed(sensor_data) => encoder.emit(sensor_data).await,
+            Packet::Command(command) => encoder.emit(*command).await,
+            Packet::Ping => Ok(()),
+            Packet::Pong => Ok(()),
         }
     }
 }
@@ -95,6 +252,10 @@ impl<D: AsyncDecoder + ?Sized> AsyncDecode<D> for Packet {
             2 => Ok(Packet::Ack),
             3 => Ok(Packet::SensorData(decoder.read().await?)),
             4 => Ok(Packet::ResetConnection),
+            5 => Ok(Packet::SensorDataCompressed(decoder.read().await?)),
+            6 => Ok(Packet::Command(decoder.read().await?)),
+            7 => Ok(Packet::Ping),
+            8 => Ok(Packet::Pong),
             _ => Err(decoder.decoding_error()),
         }
     }
@@ -102,28 +263,48 @@ impl<D: AsyncDecoder + ?Sized> AsyncDecode<D> for Packet {
 
 impl<E: AsyncEncoder + ?Sized> AsyncEncode<E> for &HandshakeStart {
     async fn encode(self, encoder: &mut E) -> Result<(), E::Error> {
-        encoder.emit((self.major, self.minor, 0u32)).await
+        let mut buf: [u8; 5] = [0, 0, 0, 0, 0];
+        let tail = self.capabilities.to_tail(&mut buf);
+        encoder
+            .emit((self.major, self.minor, tail.len() as u32))
+            .await?;
+        encoder.emit_bytes(tail).await
     }
 }
 
 impl<D: AsyncDecoder + ?Sized> AsyncDecode<D> for HandshakeStart {
     async fn decode(decoder: &mut D) -> Result<Self, D::Error> {
         let (major, minor, tail_len): (u8, u8, u32) = decoder.read().await?;
+        let mut tail_len = tail_len as usize;
+
+        let capabilities = if major == 1 {
+            Capabilities::read_tail(decoder, &mut tail_len).await?
+        } else {
+            Capabilities::NONE
+        };
 
         // forward compat: discard `tail_len` bytes
-        decoder.read_discard(tail_len as usize).await?;
-        Ok(Self { major, minor })
+        decoder.read_discard(tail_len).await?;
+        Ok(Self {
+            major,
+            minor,
+            capabilities,
+        })
     }
 }
 
 impl<E: AsyncEncoder + ?Sized> AsyncEncode<E> for &HandshakeEnd {
     async fn encode(self, encoder: &mut E) -> Result<(), E::Error> {
-        let mut tail: [u8; 10] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let mut tail: [u8; 15] = [0; 15];
         let epoch_len = self
             .epoch
             .to_leb128((&mut tail[0..10]).try_into().unwrap())
             .len();
-        let tail_len = epoch_len;
+        let capabilities_len = self
+            .capabilities
+            .to_tail((&mut tail[epoch_len..epoch_len + 5]).try_into().unwrap())
+            .len();
+        let tail_len = epoch_len + capabilities_len;
         encoder
             .emit((self.major, self.minor, tail_len as u32))
             .await?;
@@ -137,10 +318,10 @@ impl<D: AsyncDecoder + ?Sized> AsyncDecode<D> for HandshakeEnd {
         let minor: u8 = decoder.read().await?;
         let mut tail_len: usize = decoder.read::<u32>().await? as usize;
 
-        let epoch: u64 = if major == 1 {
+        let (epoch, capabilities) = if major == 1 {
             let pos: usize = decoder.current_offset();
             let epoch: u64 = decoder.read().await?;
-            let epoch_len = decoder.current_offset().wrapping_sub(pos);
+            let epoch_len = decoder.bytes_read_since(pos);
 
             // remove already read bytes from total
             tail_len = tail_len
@@ -148,9 +329,12 @@ impl<D: AsyncDecoder + ?Sized> AsyncDecode<D> for HandshakeEnd {
                 // if epoch_len is somehow greater than the reported payload length:
                 // the sender is fake news, and this is an error
                 .ok_or_else(|| decoder.decoding_error())?;
-            epoch
+            (
+                epoch,
+                Capabilities::read_tail(decoder, &mut tail_len).await?,
+            )
         } else {
-            0
+            (0, Capabilities::NONE)
         };
 
         // forward compat: discard `tail_len` bytes
@@ -161,10 +345,43 @@ impl<D: AsyncDecoder + ?Sized> AsyncDecode<D> for HandshakeEnd {
             major,
             minor,
             epoch,
+            capabilities,
         })
     }
 }
 
+impl Capabilities {
+    /// Encodes the capabilities at the end of a handshake tail, empty capabilities take no space at all.
+    fn to_tail(self, buf: &mut [u8; 5]) -> &[u8] {
+        if self.is_empty() {
+            &buf[..0]
+        } else {
+            self.0.to_leb128(buf)
+        }
+    }
+
+    /// Reads the capabilities at the end of a handshake tail, if the remaining `tail_len` allows for them.
+    ///
+    /// Peers that predate capabilities don't send them, which is the same as having none.
+    async fn read_tail<D: AsyncDecoder + ?Sized>(
+        decoder: &mut D,
+        tail_len: &mut usize,
+    ) -> Result<Self, D::Error> {
+        if *tail_len == 0 {
+            return Ok(Self::NONE);
+        }
+
+        let pos: usize = decoder.current_offset();
+        let bits: u32 = decoder.read().await?;
+        let bits_len = decoder.bytes_read_since(pos);
+
+        *tail_len = tail_len
+            .checked_sub(bits_len)
+            .ok_or_else(|| decoder.decoding_error())?;
+        Ok(Self(bits))
+    }
+}
+
 impl<E: AsyncEncoder + ?Sized> AsyncEncode<E> for SensorData {
     fn encode(self, encoder: &mut E) -> impl Future<Output = Result<(), E::Error>> {
         encoder.emit(self.count)
@@ -179,6 +396,22 @@ impl<D: AsyncDecoder + ?Sized> AsyncDecode<D> for SensorData {
     }
 }
 
+impl<E: AsyncEncoder + ?Sized> AsyncEncode<E> for SensorDataCompressed {
+    fn encode(self, encoder: &mut E) -> impl Future<Output = Result<(), E::Error>> {
+        encoder.emit((self.count, self.base_time_offset))
+    }
+}
+
+impl<D: AsyncDecoder + ?Sized> AsyncDecode<D> for SensorDataCompressed {
+    async fn decode(decoder: &mut D) -> Result<Self, D::Error> {
+        let (count, base_time_offset) = decoder.read().await?;
+        Ok(Self {
+            count,
+            base_time_offset,
+        })
+    }
+}
+
 impl<E: AsyncEncoder + ?Sized> AsyncEncode<E> for SensorValuePoint {
     fn encode(self, encoder: &mut E) -> impl Future<Output = Result<(), E::Error>> {
         encoder.emit((self.time_offset, self.value))
@@ -193,6 +426,112 @@ impl<D: AsyncDecoder + ?Sized> AsyncDecode<D> for SensorValuePoint {
     }
 }
 
+/// Farthest a [`SensorValuePoint`] may be from the handshake epoch when computing its timestamp, ten years either way.
+pub const MAX_TIME_OFFSET_SECS: i64 = 10 * 365 * 24 * 60 * 60;
+
+impl SensorValuePoint {
+    /// Time of the point in seconds since the UNIX epoch, given the time of the handshake epoch in the same unit.
+    ///
+    /// The time offset is clamped to [`MAX_TIME_OFFSET_SECS`], so that a sensor board with a broken clock cannot move
+    /// its values centuries away.
+    pub const fn unix_timestamp(self, epoch_offset_secs: i64) -> i64 {
+        let time_offset = if self.time_offset > MAX_TIME_OFFSET_SECS {
+            MAX_TIME_OFFSET_SECS
+        } else if self.time_offset < -MAX_TIME_OFFSET_SECS {
+            -MAX_TIME_OFFSET_SECS
+        } else {
+            self.time_offset
+        };
+        epoch_offset_secs.saturating_add(time_offset)
+    }
+}
+
+impl SensorValueDelta {
+    /// Encodes `point` relative to the time offset of the previous point.
+    pub const fn between(previous_time_offset: i64, point: SensorValuePoint) -> Self {
+        Self {
+            value: point.value,
+            time_delta: point.time_offset.wrapping_sub(previous_time_offset),
+        }
+    }
+
+    /// Restores the absolute point, given the time offset of the previous point.
+    pub const fn apply(self, previous_time_offset: i64) -> SensorValuePoint {
+        SensorValuePoint {
+            value: self.value,
+            time_offset: previous_time_offset.wrapping_add(self.time_delta),
+        }
+    }
+}
+
+impl<E: AsyncEncoder + ?Sized> AsyncEncode<E> for SensorValueDelta {
+    fn encode(self, encoder: &mut E) -> impl Future<Output = Result<(), E::Error>> {
+        encoder.emit((self.time_delta, self.value))
+    }
+}
+
+impl<D: AsyncDecoder + ?Sized> AsyncDecode<D> for SensorValueDelta {
+    async fn decode(decoder: &mut D) -> Result<Self, D::Error> {
+        let time_delta: i64 = decoder.read().await?;
+        let value: SensorValue = decoder.read().await?;
+        Ok(Self { value, time_delta })
+    }
+}
+
+impl Command {
+    pub const fn id(&self) -> u32 {
+        match self {
+            Command::SetSpreadingFactor(_) => 0,
+            Command::SetMeasureInterval(_) => 1,
+            Command::Unknown { id, .. } => *id,
+        }
+    }
+}
+
+impl<E: AsyncEncoder + ?Sized> AsyncEncode<E> for Command {
+    async fn encode(self, encoder: &mut E) -> Result<(), E::Error> {
+        encoder.emit(self.id()).await?;
+        match self {
+            Command::SetSpreadingFactor(spreading_factor) => {
+                encoder.emit((1u32, spreading_factor)).await
+            }
+            Command::SetMeasureInterval(secs) => {
+                let mut buf = [0u8; 5];
+                let arg = secs.to_leb128(&mut buf);
+                encoder.emit(arg.len() as u32).await?;
+                encoder.emit_bytes(arg).await
+            }
+            Command::Unknown { arg_len, .. } => encoder.emit(arg_len).await,
+        }
+    }
+}
+
+impl<D: AsyncDecoder + ?Sized> AsyncDecode<D> for Command {
+    async fn decode(decoder: &mut D) -> Result<Self, D::Error> {
+        let kind: u32 = decoder.read().await?;
+        let mut arg_len: usize = decoder.read::<u32>().await? as usize;
+        let pos: usize = decoder.current_offset();
+
+        let command = match kind {
+            0 => Command::SetSpreadingFactor(decoder.read().await?),
+            1 => Command::SetMeasureInterval(decoder.read().await?),
+            id => Command::Unknown {
+                id,
+                arg_len: arg_len as u32,
+            },
+        };
+
+        // same forward compatibility rules as sensor values
+        let actual_arg_len = decoder.bytes_read_since(pos);
+        arg_len = arg_len
+            .checked_sub(actual_arg_len)
+            .ok_or_else(|| decoder.decoding_error())?;
+
+        decoder.read_discard(arg_len).await?;
+        Ok(command)
+    }
+}
+
 impl SensorValue {
     pub const fn id(&self) -> u32 {
         unsafe {
@@ -202,6 +541,18 @@ impl SensorValue {
             *core::mem::transmute::<*const SensorValue, *const u32>(self as *const _)
         }
     }
+
+    /// Returns the raw bytes of a value of an unknown type, if all of them were kept when decoding.
+    pub fn raw_bytes(&self) -> Option<&[u8]> {
+        match self {
+            SensorValue::Unknown { value_len, raw, .. }
+                if raw.as_slice().len() == *value_len as usize =>
+            {
+                Some(raw.as_slice())
+            }
+            _ => None,
+        }
+    }
 }
 
 impl<E: AsyncEncoder + ?Sized> AsyncEncode<E> for SensorValue {
@@ -216,7 +567,18 @@ impl<E: AsyncEncoder + ?Sized> AsyncEncode<E> for SensorValue {
             SensorValue::Pressure(value) => encoder.emit((4u32, value)).await,
             SensorValue::Altitude(value) => encoder.emit((4u32, value)).await,
             SensorValue::AirQuality(value) => encoder.emit((4u32, value)).await,
-            SensorValue::Unknown { value_len, .. } => encoder.emit(value_len).await,
+            SensorValue::Location { lat, lon } => encoder.emit((8u32, lat, lon)).await,
+            SensorValue::Humidity(value) => encoder.emit((4u32, value)).await,
+            SensorValue::Co2(value) => encoder.emit((4u32, value)).await,
+            SensorValue::Voc(value) => encoder.emit((4u32, value)).await,
+            SensorValue::Unknown { value_len, .. } => {
+                encoder.emit(value_len).await?;
+                // the tail is only known if it was kept when decoding, the caller writes it otherwise
+                match self.raw_bytes() {
+                    Some(raw) => encoder.emit_bytes(raw).await,
+                    None => Ok(()),
+                }
+            }
         }
     }
 }
@@ -232,14 +594,29 @@ impl<D: AsyncDecoder + ?Sized> AsyncDecode<D> for SensorValue {
             1 => SensorValue::Pressure(decoder.read().await?),
             2 => SensorValue::Altitude(decoder.read().await?),
             3 => SensorValue::AirQuality(decoder.read().await?),
-            id => SensorValue::Unknown {
-                id,
-                value_len: value_len as u32,
-            },
+            5 => {
+                let (lat, lon) = decoder.read().await?;
+                SensorValue::Location { lat, lon }
+            }
+            6 => SensorValue::Humidity(decoder.read().await?),
+            7 => SensorValue::Co2(decoder.read().await?),
+            8 => SensorValue::Voc(decoder.read().await?),
+            id => {
+                let mut raw = RawValue::EMPTY;
+                if decoder.retains_unknown_values() && value_len <= MAX_RAW_VALUE_LEN {
+                    decoder.read_bytes(&mut raw.bytes[..value_len]).await?;
+                    raw.len = value_len as u8;
+                }
+                SensorValue::Unknown {
+                    id,
+                    value_len: value_len as u32,
+                    raw,
+                }
+            }
         };
 
         // remove already read bytes from total
-        let actual_value_len = decoder.current_offset().wrapping_sub(pos);
+        let actual_value_len = decoder.bytes_read_since(pos);
         value_len = value_len
             .checked_sub(actual_value_len)
             // if the actual length is somehow greater than the reported payload length:
@@ -251,67 +628,174 @@ impl<D: AsyncDecoder + ?Sized> AsyncDecode<D> for SensorValue {
     }
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use crate::test::RunBlockingExt;
-    use std::{error::Error, sync::Arc};
-
-    #[derive(Default)]
-    struct AllocatingTestCodec {
-        buf: Vec<u8>,
-        offset: usize,
-    }
-
-    impl AsyncEncoder for AllocatingTestCodec {
-        type Error = std::collections::TryReserveError;
-
-        async fn emit_bytes(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
-            self.buf.try_reserve(buf.len())?;
-            self.buf.extend_from_slice(buf);
-            Ok(())
-        }
-    }
-
-    impl AsyncDecoder for AllocatingTestCodec {
-        type Error = Arc<dyn std::error::Error>;
-
-        async fn read_bytes(&mut self, buf: &mut [u8]) -> Result<(), Self::Error> {
-            if buf.len() > self.buf.len() {
-                Err(Box::<dyn Error>::from(format!(
-                    "tried to read {} bytes from buffer of {} bytes",
-                    buf.len(),
-                    self.buf.len()
-                ))
-                .into())
-            } else {
-                let mut new_buf = self.buf.split_off(buf.len());
-                std::mem::swap(&mut new_buf, &mut self.buf);
-                buf.copy_from_slice(&new_buf);
-                self.offset += buf.len();
-                Ok(())
+impl Display for Packet {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Packet::HandshakeStart(HandshakeStart { major, minor, .. }) => {
+                write!(f, "HandshakeStart({major}.{minor})")
+            }
+            Packet::HandshakeEnd(HandshakeEnd {
+                major,
+                minor,
+                epoch,
+                ..
+            }) => write!(f, "HandshakeEnd({major}.{minor}, epoch {epoch})"),
+            Packet::Ack => f.write_str("Ack"),
+            Packet::SensorData(SensorData { count }) => write!(f, "SensorData({count} values)"),
+            Packet::ResetConnection => f.write_str("ResetConnection"),
+            Packet::SensorDataCompressed(SensorDataCompressed { count, .. }) => {
+                write!(f, "SensorDataCompressed({count} values)")
             }
+            Packet::Command(command) => write!(f, "Command({command})"),
+            Packet::Ping => f.write_str("Ping"),
+            Packet::Pong => f.write_str("Pong"),
         }
+    }
+}
 
-        fn current_offset(&self) -> usize {
-            self.offset
+#[cfg(feature = "defmt")]
+impl defmt::Format for Packet {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Packet::HandshakeStart(HandshakeStart { major, minor, .. }) => {
+                defmt::write!(f, "HandshakeStart({=u8}.{=u8})", major, minor)
+            }
+            Packet::HandshakeEnd(HandshakeEnd {
+                major,
+                minor,
+                epoch,
+                ..
+            }) => defmt::write!(
+                f,
+                "HandshakeEnd({=u8}.{=u8}, epoch {=u64})",
+                major,
+                minor,
+                epoch
+            ),
+            Packet::Ack => defmt::write!(f, "Ack"),
+            Packet::SensorData(SensorData { count }) => {
+                defmt::write!(f, "SensorData({=u8} values)", count)
+            }
+            Packet::ResetConnection => defmt::write!(f, "ResetConnection"),
+            Packet::SensorDataCompressed(SensorDataCompressed { count, .. }) => {
+                defmt::write!(f, "SensorDataCompressed({=u8} values)", count)
+            }
+            Packet::Command(command) => defmt::write!(f, "Command({})", command),
+            Packet::Ping => defmt::write!(f, "Ping"),
+            Packet::Pong => defmt::write!(f, "Pong"),
         }
+    }
+}
 
-        fn decoding_error(&self) -> Self::Error {
-            Box::<dyn Error>::from("decoding error").into()
+impl Display for Command {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Command::SetSpreadingFactor(spreading_factor) => {
+                write!(f, "SetSpreadingFactor({spreading_factor})")
+            }
+            Command::SetMeasureInterval(secs) => write!(f, "SetMeasureInterval({secs}s)"),
+            Command::Unknown { id, arg_len } => write!(f, "Unknown(#{id}, {arg_len} bytes)"),
         }
     }
+}
 
-    impl AllocatingTestCodec {
-        pub fn emit_alloc<T: AsyncEncode<Self>>(
-            &mut self,
-            value: T,
-        ) -> Result<Box<[u8]>, <Self as AsyncEncoder>::Error> {
-            self.buf.clear();
-            self.emit(value).run_blocking()?;
-            Ok(self.buf.clone().into_boxed_slice())
+#[cfg(feature = "defmt")]
+impl defmt::Format for Command {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Command::SetSpreadingFactor(spreading_factor) => {
+                defmt::write!(f, "SetSpreadingFactor({=u8})", spreading_factor)
+            }
+            Command::SetMeasureInterval(secs) => {
+                defmt::write!(f, "SetMeasureInterval({=u32}s)", secs)
+            }
+            Command::Unknown { id, arg_len } => {
+                defmt::write!(f, "Unknown(#{=u32}, {=u32} bytes)", id, arg_len)
+            }
         }
     }
+}
+
+impl Display for SensorValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SensorValue::Temperature(value) => write!(f, "Temperature({value})"),
+            SensorValue::Pressure(value) => write!(f, "Pressure({value})"),
+            SensorValue::Altitude(value) => write!(f, "Altitude({value})"),
+            SensorValue::AirQuality(value) => write!(f, "AirQuality({value})"),
+            SensorValue::Location { lat, lon } => write!(f, "Location({lat}, {lon})"),
+            SensorValue::Humidity(value) => write!(f, "Humidity({value})"),
+            SensorValue::Co2(value) => write!(f, "Co2({value})"),
+            SensorValue::Voc(value) => write!(f, "Voc({value})"),
+            SensorValue::Unknown { id, value_len, .. } => {
+                write!(f, "Unknown(#{id}, {value_len} bytes)")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for SensorValue {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            SensorValue::Temperature(value) => defmt::write!(f, "Temperature({=f32})", value),
+            SensorValue::Pressure(value) => defmt::write!(f, "Pressure({=f32})", value),
+            SensorValue::Altitude(value) => defmt::write!(f, "Altitude({=f32})", value),
+            SensorValue::AirQuality(value) => defmt::write!(f, "AirQuality({=f32})", value),
+            SensorValue::Location { lat, lon } => {
+                defmt::write!(f, "Location({=f32}, {=f32})", lat, lon)
+            }
+            SensorValue::Humidity(value) => defmt::write!(f, "Humidity({=f32})", value),
+            SensorValue::Co2(value) => defmt::write!(f, "Co2({=f32})", value),
+            SensorValue::Voc(value) => defmt::write!(f, "Voc({=f32})", value),
+            SensorValue::Unknown { id, value_len, .. } => {
+                defmt::write!(f, "Unknown(#{=u32}, {=u32} bytes)", id, value_len)
+            }
+        }
+    }
+}
+
+impl Display for Capabilities {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        const NAMES: [(Capabilities, &str); 5] = [
+            (Capabilities::DOWNLINK_COMMANDS, "downlink-commands"),
+            (Capabilities::COMPRESSED_DATA, "compressed-data"),
+            (Capabilities::KEEPALIVE, "keepalive"),
+            (Capabilities::FREQUENCY_HOPPING, "frequency-hopping"),
+            (Capabilities::LINK_ACKS, "link-acks"),
+        ];
+
+        if self.is_empty() {
+            return f.write_str("none");
+        }
+
+        let mut unknown = *self;
+        let mut separator = "";
+        for (capability, name) in NAMES {
+            if self.contains(capability) {
+                write!(f, "{separator}{name}")?;
+                unknown = Self(unknown.0 & !capability.0);
+                separator = "+";
+            }
+        }
+        if !unknown.is_empty() {
+            write!(f, "{separator}{:#x}", unknown.0)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Capabilities {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "Capabilities({=u32:#x})", self.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{AllocatingTestCodec, RunBlockingExt};
 
     #[test]
     fn test_codec_uleb128() {
@@ -394,6 +878,98 @@ mod test {
         assert_eq!(codec.current_offset(), 8);
     }
 
+    #[test]
+    fn test_codec_tuple() {
+        let mut codec = AllocatingTestCodec::default();
+        let value: (u8, u32, f32, i64, u8) = (7, 275, 22.3, -12, 0xff);
+        let encoded = [0x07, 0x93, 0x02, 0x66, 0x66, 0xb2, 0x41, 0x74, 0xff];
+
+        assert_eq!(&codec.emit_alloc(value).unwrap()[..], encoded);
+        assert_eq!(
+            codec
+                .read::<(u8, u32, f32, i64, u8)>()
+                .run_blocking()
+                .unwrap(),
+            value
+        );
+        assert_eq!(codec.current_offset(), encoded.len());
+
+        let value: (u32, u8, u8, u64) = (1, 2, 3, 4);
+        assert_eq!(&codec.emit_alloc(value).unwrap()[..], [1, 2, 3, 4]);
+        assert_eq!(
+            codec.read::<(u32, u8, u8, u64)>().run_blocking().unwrap(),
+            value
+        );
+        assert_eq!(codec.current_offset(), encoded.len() + 4);
+    }
+
+    #[test]
+    fn test_codec_array() {
+        let mut codec = AllocatingTestCodec::default();
+        let mac: [u8; 6] = [0x24, 0x0a, 0xc4, 0x12, 0x34, 0x56];
+
+        assert_eq!(&codec.emit_alloc(mac).unwrap()[..], mac);
+        assert_eq!(codec.read::<[u8; 6]>().run_blocking().unwrap(), mac);
+        assert_eq!(codec.current_offset(), 6);
+
+        let values: [u32; 3] = [1, 275, u32::MAX];
+        let encoded = [0x01, 0x93, 0x02, 0xff, 0xff, 0xff, 0xff, 0x0f];
+
+        assert_eq!(&codec.emit_alloc(values).unwrap()[..], encoded);
+        assert_eq!(codec.read::<[u32; 3]>().run_blocking().unwrap(), values);
+        assert_eq!(codec.current_offset(), 6 + encoded.len());
+
+        assert!(codec.emit_alloc([0u32; 0]).unwrap().is_empty());
+        assert_eq!(codec.read::<[u32; 0]>().run_blocking().unwrap(), []);
+    }
+
+    #[test]
+    fn test_codec_string() {
+        let mut codec = AllocatingTestCodec::default();
+
+        let label: heapless::String<16> = heapless::String::try_from("café").unwrap();
+        let encoded = [0x05, b'c', b'a', b'f', 0xc3, 0xa9];
+        assert_eq!(&codec.emit_alloc(label.clone()).unwrap()[..], encoded);
+        assert_eq!(
+            codec.read::<heapless::String<16>>().run_blocking().unwrap(),
+            label
+        );
+        assert_eq!(codec.current_offset(), encoded.len());
+
+        assert_eq!(&codec.emit_alloc("").unwrap()[..], [0x00]);
+        assert_eq!(
+            codec.read::<heapless::String<16>>().run_blocking().unwrap(),
+            ""
+        );
+        assert_eq!(codec.current_offset(), encoded.len() + 1);
+
+        // exactly fits
+        let encoded = codec.emit_alloc("café").unwrap();
+        assert_eq!(
+            codec.read::<heapless::String<5>>().run_blocking().unwrap(),
+            "café"
+        );
+        assert_eq!(codec.current_offset(), 2 * encoded.len() + 1);
+    }
+
+    #[test]
+    fn test_decode_string_too_long() {
+        let mut codec = AllocatingTestCodec::default();
+
+        codec.emit_alloc("gateway").unwrap();
+        assert!(codec.read::<heapless::String<4>>().run_blocking().is_err());
+        // rejected upfront, only the length was read
+        assert_eq!(codec.current_offset(), 1);
+    }
+
+    #[test]
+    fn test_decode_string_invalid_utf8() {
+        let mut codec = AllocatingTestCodec::default();
+
+        codec.buf.extend(&[0x02, 0xc3, 0x28]);
+        assert!(codec.read::<heapless::String<8>>().run_blocking().is_err());
+    }
+
     #[test]
     fn test_decode_unknown_packet() {
         let mut codec = AllocatingTestCodec::default();
@@ -410,6 +986,7 @@ mod test {
         let packet = Packet::HandshakeStart(HandshakeStart {
             major: 1,
             minor: 21,
+            capabilities: Capabilities::NONE,
         });
         let encoded = [0x00, 0x01, 0x15, 0x00];
 
@@ -424,8 +1001,9 @@ mod test {
         let packet = Packet::HandshakeStart(HandshakeStart {
             major: 1,
             minor: 21,
+            capabilities: Capabilities::COMPRESSED_DATA,
         });
-        let encoded = [0x00, 0x01, 0x15, 0x03, 0xca, 0xfe, 0x99];
+        let encoded = [0x00, 0x01, 0x15, 0x03, 0x02, 0xfe, 0x99];
 
         codec.buf.extend(&encoded);
         assert_eq!(codec.read::<Packet>().run_blocking().unwrap(), packet);
@@ -439,6 +1017,7 @@ mod test {
             major: 1,
             minor: 0,
             epoch: 1744854025,
+            capabilities: Capabilities::NONE,
         });
         let encoded = [0x01, 0x01, 0x00, 0x05, 0x89, 0xb8, 0x81, 0xc0, 0x6];
 
@@ -447,6 +1026,46 @@ mod test {
         assert_eq!(codec.current_offset(), encoded.len());
     }
 
+    #[test]
+    fn test_codec_handshake_end_packet_capabilities() {
+        let mut codec = AllocatingTestCodec::default();
+        let packet = Packet::HandshakeEnd(HandshakeEnd {
+            major: 1,
+            minor: 0,
+            epoch: 1744854025,
+            capabilities: Capabilities::DOWNLINK_COMMANDS.union(Capabilities::COMPRESSED_DATA),
+        });
+        let encoded = [0x01, 0x01, 0x00, 0x06, 0x89, 0xb8, 0x81, 0xc0, 0x6, 0x03];
+
+        assert_eq!(&codec.emit_alloc(&packet).unwrap()[..], encoded,);
+        assert_eq!(codec.read::<Packet>().run_blocking().unwrap(), packet);
+        assert_eq!(codec.current_offset(), encoded.len());
+    }
+
+    #[test]
+    fn test_codec_handshake_start_packet_unknown_capabilities() {
+        let mut codec = AllocatingTestCodec::default();
+        let packet = Packet::HandshakeStart(HandshakeStart {
+            major: 1,
+            minor: 0,
+            capabilities: Capabilities::from_bits(0x102),
+        });
+        let encoded = [0x00, 0x01, 0x00, 0x02, 0x82, 0x02];
+
+        assert_eq!(&codec.emit_alloc(&packet).unwrap()[..], encoded,);
+        let Packet::HandshakeStart(decoded) = codec.read::<Packet>().run_blocking().unwrap() else {
+            panic!("expected a handshake start");
+        };
+        // unknown bits are kept, but never part of the negotiated features
+        assert_eq!(decoded.capabilities.bits(), 0x102);
+        assert_eq!(
+            decoded
+                .capabilities
+                .intersection(Capabilities::DOWNLINK_COMMANDS.union(Capabilities::COMPRESSED_DATA)),
+            Capabilities::COMPRESSED_DATA
+        );
+    }
+
     #[test]
     fn test_decode_handshake_end_packet_trailing_bytes() {
         let mut codec = AllocatingTestCodec::default();
@@ -454,6 +1073,7 @@ mod test {
             major: 1,
             minor: 21,
             epoch: 1744854025,
+            capabilities: Capabilities::DOWNLINK_COMMANDS,
         });
         let encoded = [
             0x01, 0x01, 0x15, 0x08, 0x89, 0xb8, 0x81, 0xc0, 0x6, 0x01, 0x02, 0x03,
@@ -471,6 +1091,7 @@ mod test {
             major: 2,
             minor: 3,
             epoch: 0,
+            capabilities: Capabilities::NONE,
         });
         let encoded = [0x01, 0x02, 0x03, 0x02, 0xba, 0xbe];
 
@@ -534,11 +1155,46 @@ mod test {
                 },
                 &[0x06, 0x03, 0x04, 0xb8, 0x1e, 0x05, 0x3f],
             ),
+            (
+                SensorValuePoint {
+                    // Place de la Bourse, Bordeaux
+                    value: SensorValue::Location {
+                        lat: 44.8412,
+                        lon: -0.5701,
+                    },
+                    time_offset: 8,
+                },
+                &[
+                    0x08, 0x05, 0x08, 0x64, 0x5d, 0x33, 0x42, 0x13, 0xf2, 0x11, 0xbf,
+                ],
+            ),
+            (
+                SensorValuePoint {
+                    value: SensorValue::Humidity(45.5),
+                    time_offset: 10,
+                },
+                &[0x0a, 0x06, 0x04, 0x00, 0x00, 0x36, 0x42],
+            ),
+            (
+                SensorValuePoint {
+                    value: SensorValue::Co2(415.0),
+                    time_offset: 11,
+                },
+                &[0x0b, 0x07, 0x04, 0x00, 0x80, 0xcf, 0x43],
+            ),
+            (
+                SensorValuePoint {
+                    value: SensorValue::Voc(120.0),
+                    time_offset: 12,
+                },
+                &[0x0c, 0x08, 0x04, 0x00, 0x00, 0xf0, 0x42],
+            ),
             (
                 SensorValuePoint {
                     value: SensorValue::Unknown {
                         id: 999,
                         value_len: 0,
+                        raw: RawValue::EMPTY,
                     },
                     time_offset: 9,
                 },
@@ -549,7 +1205,7 @@ mod test {
         let packet_header = Packet::SensorData(SensorData {
             count: values.len() as u8,
         });
-        let encoded_packet_header = [0x03, 0x05];
+        let encoded_packet_header = [0x03, 0x09];
 
         // Header
         assert_eq!(
@@ -582,10 +1238,12 @@ mod test {
             value: SensorValue::Unknown {
                 id: 999,
                 value_len: 3,
+                raw: RawValue::EMPTY,
             },
             time_offset: 9,
         };
         let encoded = [0x09, 0xe7, 0x07, 0x03];
+        assert_eq!(value.value.raw_bytes(), None);
 
         assert_eq!(&codec.emit_alloc(value).unwrap()[..], encoded);
         let pos = codec.current_offset();
@@ -598,6 +1256,134 @@ mod test {
         assert_eq!(codec.current_offset() - pos, encoded.len() + 3);
     }
 
+    #[test]
+    fn test_codec_sensor_data_packet_unknown_retained_tail() {
+        let mut codec = AllocatingTestCodec {
+            retain_unknown_values: true,
+            ..Default::default()
+        };
+        let encoded = [0x09, 0xe7, 0x07, 0x03, 0x01, 0x02, 0x03];
+
+        codec.buf.extend(&encoded);
+        let value = codec.read::<SensorValuePoint>().run_blocking().unwrap();
+        assert_eq!(
+            value,
+            SensorValuePoint {
+                value: SensorValue::Unknown {
+                    id: 999,
+                    value_len: 3,
+                    raw: RawValue::from_slice(&[0x01, 0x02, 0x03]).unwrap(),
+                },
+                time_offset: 9,
+            }
+        );
+        assert_eq!(codec.current_offset(), encoded.len());
+        assert_eq!(value.value.raw_bytes(), Some(&[0x01, 0x02, 0x03][..]));
+        // forwarded verbatim, tail included
+        assert_eq!(&codec.emit_alloc(value).unwrap()[..], encoded);
+
+        // too long to be kept, skipped as without retention
+        let mut encoded = vec![0x09, 0xe7, 0x07, MAX_RAW_VALUE_LEN as u8 + 1];
+        encoded.extend([0xaa; MAX_RAW_VALUE_LEN + 1]);
+        codec.buf.clear();
+        codec.buf.extend(&encoded);
+        let pos = codec.current_offset();
+        assert_eq!(
+            codec.read::<SensorValuePoint>().run_blocking().unwrap(),
+            SensorValuePoint {
+                value: SensorValue::Unknown {
+                    id: 999,
+                    value_len: MAX_RAW_VALUE_LEN as u32 + 1,
+                    raw: RawValue::EMPTY,
+                },
+                time_offset: 9,
+            }
+        );
+        assert_eq!(codec.current_offset() - pos, encoded.len());
+        assert_eq!(SensorValue::Temperature(20.0).raw_bytes(), None);
+    }
+
+    #[test]
+    fn test_raw_value() {
+        assert_eq!(RawValue::from_slice(&[]), Some(RawValue::EMPTY));
+        assert_eq!(
+            RawValue::from_slice(&[0x2a; MAX_RAW_VALUE_LEN])
+                .unwrap()
+                .as_slice(),
+            [0x2a; MAX_RAW_VALUE_LEN]
+        );
+        assert_eq!(RawValue::from_slice(&[0; MAX_RAW_VALUE_LEN + 1]), None);
+    }
+
+    #[test]
+    fn test_decode_with_wrapping_offset() {
+        let mut codec = AllocatingTestCodec::default();
+        let value = SensorValuePoint {
+            value: SensorValue::Location {
+                lat: 44.8412,
+                lon: -0.5701,
+            },
+            time_offset: 9,
+        };
+        let packet = Packet::HandshakeEnd(HandshakeEnd {
+            major: 1,
+            minor: 0,
+            epoch: 1744854025,
+            capabilities: Capabilities::COMPRESSED_DATA,
+        });
+        let command = Packet::Command(Command::SetSpreadingFactor(9));
+
+        // the offset wraps around in the middle of each value
+        for start in [usize::MAX - 5, usize::MAX, 0] {
+            let encoded = codec.emit_alloc(value).unwrap();
+            codec.offset = start;
+            assert_eq!(
+                codec.read::<SensorValuePoint>().run_blocking().unwrap(),
+                value
+            );
+            assert_eq!(codec.bytes_read_since(start), encoded.len());
+
+            let encoded = codec.emit_alloc(&packet).unwrap();
+            codec.offset = start;
+            assert_eq!(codec.read::<Packet>().run_blocking().unwrap(), packet);
+            assert_eq!(codec.bytes_read_since(start), encoded.len());
+
+            let encoded = codec.emit_alloc(&command).unwrap();
+            codec.offset = start;
+            assert_eq!(codec.read::<Packet>().run_blocking().unwrap(), command);
+            assert_eq!(codec.bytes_read_since(start), encoded.len());
+        }
+
+        // a value longer than declared is still rejected
+        codec.buf.extend(&[0x00, 0x03, 0x00, 0x00, 0x80, 0x3f]);
+        codec.offset = usize::MAX - 2;
+        assert!(codec.read::<SensorValue>().run_blocking().is_err());
+    }
+
+    #[test]
+    fn test_decode_sensor_value_huge_length() {
+        let mut codec = AllocatingTestCodec::default();
+        // unknown value type 999 declaring u32::MAX bytes of payload
+        let encoded = [0xe7, 0x07, 0xff, 0xff, 0xff, 0xff, 0x0f];
+
+        codec.buf.extend(&encoded);
+        codec.buf.extend(&[0x00; 64]);
+        assert!(codec.read::<SensorValue>().run_blocking().is_err());
+        // rejected upfront, nothing was discarded
+        assert_eq!(codec.current_offset(), encoded.len());
+    }
+
+    #[test]
+    fn test_decode_handshake_start_packet_huge_tail() {
+        let mut codec = AllocatingTestCodec::default();
+        let encoded = [0x00, 0x02, 0x00, 0xff, 0xff, 0xff, 0xff, 0x0f];
+
+        codec.buf.extend(&encoded);
+        codec.buf.extend(&[0x00; 64]);
+        assert!(codec.read::<Packet>().run_blocking().is_err());
+        assert_eq!(codec.current_offset(), encoded.len());
+    }
+
     #[test]
     fn test_codec_reset_connection_packet() {
         let mut codec = AllocatingTestCodec::default();
@@ -608,4 +1394,452 @@ mod test {
         assert_eq!(codec.read::<Packet>().run_blocking().unwrap(), packet);
         assert_eq!(codec.current_offset(), encoded.len());
     }
+
+    #[test]
+    fn test_codec_ping_pong_packets() {
+        let mut codec = AllocatingTestCodec::default();
+
+        assert_eq!(&codec.emit_alloc(&Packet::Ping).unwrap()[..], &[0x07]);
+        assert_eq!(codec.read::<Packet>().run_blocking().unwrap(), Packet::Ping);
+        assert_eq!(&codec.emit_alloc(&Packet::Pong).unwrap()[..], &[0x08]);
+        assert_eq!(codec.read::<Packet>().run_blocking().unwrap(), Packet::Pong);
+        assert_eq!(codec.current_offset(), 2);
+    }
+
+    #[test]
+    fn test_codec_sensor_data_compressed_packet() {
+        let mut codec = AllocatingTestCodec::default();
+        let packet = Packet::SensorDataCompressed(SensorDataCompressed {
+            count: 3,
+            base_time_offset: 86_400,
+        });
+        let encoded = [0x05, 0x03, 0x80, 0xa3, 0x05];
+
+        assert_eq!(&codec.emit_alloc(&packet).unwrap()[..], encoded);
+        assert_eq!(codec.read::<Packet>().run_blocking().unwrap(), packet);
+        assert_eq!(codec.current_offset(), encoded.len());
+    }
+
+    #[test]
+    fn test_codec_command_packet() {
+        let mut codec = AllocatingTestCodec::default();
+        let packet = Packet::Command(Command::SetSpreadingFactor(7));
+        let encoded = [0x06, 0x00, 0x01, 0x07];
+
+        assert_eq!(&codec.emit_alloc(&packet).unwrap()[..], encoded);
+        assert_eq!(codec.read::<Packet>().run_blocking().unwrap(), packet);
+        assert_eq!(codec.current_offset(), encoded.len());
+    }
+
+    #[test]
+    fn test_codec_set_measure_interval_command() {
+        let mut codec = AllocatingTestCodec::default();
+        let packet = Packet::Command(Command::SetMeasureInterval(600));
+        let encoded = [0x06, 0x01, 0x02, 0xd8, 0x04];
+
+        assert_eq!(&codec.emit_alloc(&packet).unwrap()[..], encoded);
+        assert_eq!(codec.read::<Packet>().run_blocking().unwrap(), packet);
+        assert_eq!(codec.current_offset(), encoded.len());
+    }
+
+    #[test]
+    fn test_decode_command_from_the_future() {
+        let mut codec = AllocatingTestCodec::default();
+        // unknown command 300 with 2 bytes of arguments, then a known command with an extra byte
+        let encoded = [
+            0x06, 0xac, 0x02, 0x02, 0xaa, 0xbb, 0x06, 0x00, 0x02, 0x09, 0xcc,
+        ];
+
+        codec.buf.extend(&encoded);
+        assert_eq!(
+            codec.read::<Packet>().run_blocking().unwrap(),
+            Packet::Command(Command::Unknown {
+                id: 300,
+                arg_len: 2
+            })
+        );
+        assert_eq!(
+            codec.read::<Packet>().run_blocking().unwrap(),
+            Packet::Command(Command::SetSpreadingFactor(9))
+        );
+        assert_eq!(codec.current_offset(), encoded.len());
+    }
+
+    #[test]
+    fn test_codec_sensor_value_deltas() {
+        let mut codec = AllocatingTestCodec::default();
+        let base_time_offset = 86_400;
+
+        let points: [(SensorValuePoint, &[u8]); 4] = [
+            (
+                SensorValuePoint {
+                    value: SensorValue::Temperature(22.3),
+                    time_offset: 86_400,
+                },
+                &[0x00, 0x00, 0x04, 0x66, 0x66, 0xb2, 0x41],
+            ),
+            (
+                SensorValuePoint {
+                    value: SensorValue::Pressure(1.01),
+                    time_offset: 86_410,
+                },
+                &[0x0a, 0x01, 0x04, 0xae, 0x47, 0x81, 0x3f],
+            ),
+            // negative delta: values are not necessarily sorted by time
+            (
+                SensorValuePoint {
+                    value: SensorValue::Altitude(0.9),
+                    time_offset: 86_395,
+                },
+                &[0x71, 0x02, 0x04, 0x66, 0x66, 0x66, 0x3f],
+            ),
+            (
+                SensorValuePoint {
+                    value: SensorValue::Unknown {
+                        id: 999,
+                        value_len: 0,
+                        raw: RawValue::EMPTY,
+                    },
+                    time_offset: 0,
+                },
+                &[0x85, 0xdd, 0x7a, 0xe7, 0x07, 0x00],
+            ),
+        ];
+
+        let mut encode_prev = base_time_offset;
+        let mut decode_prev = base_time_offset;
+
+        for (point, encoded) in points {
+            let delta = SensorValueDelta::between(encode_prev, point);
+            encode_prev = point.time_offset;
+
+            assert_eq!(&codec.emit_alloc(delta).unwrap()[..], encoded);
+            let pos = codec.current_offset();
+            let decoded = codec.read::<SensorValueDelta>().run_blocking().unwrap();
+            assert_eq!(decoded, delta);
+            assert_eq!(codec.current_offset() - pos, encoded.len());
+
+            let restored = decoded.apply(decode_prev);
+            decode_prev = restored.time_offset;
+            assert_eq!(restored, point);
+        }
+    }
+
+    #[test]
+    fn test_sensor_value_point_unix_timestamp() {
+        let epoch = 1744854025;
+        let point = |time_offset| SensorValuePoint {
+            value: SensorValue::Temperature(21.5),
+            time_offset,
+        };
+
+        assert_eq!(point(0).unix_timestamp(epoch), epoch);
+        assert_eq!(point(90).unix_timestamp(epoch), epoch + 90);
+        // measured before the handshake, e.g. values buffered across a reconnection
+        assert_eq!(point(-3600).unix_timestamp(epoch), epoch - 3600);
+
+        // absurd offsets are clamped
+        assert_eq!(
+            point(i64::MAX).unix_timestamp(epoch),
+            epoch + MAX_TIME_OFFSET_SECS
+        );
+        assert_eq!(
+            point(i64::MIN).unix_timestamp(epoch),
+            epoch - MAX_TIME_OFFSET_SECS
+        );
+        assert_eq!(
+            point(MAX_TIME_OFFSET_SECS + 1).unix_timestamp(0),
+            MAX_TIME_OFFSET_SECS
+        );
+        assert_eq!(point(1).unix_timestamp(i64::MAX), i64::MAX);
+    }
+
+    #[test]
+    fn test_sensor_value_delta_extremes() {
+        let point = SensorValuePoint {
+            value: SensorValue::AirQuality(0.52),
+            time_offset: i64::MIN,
+        };
+
+        for previous in [i64::MAX, i64::MIN, 0, -1] {
+            let delta = SensorValueDelta::between(previous, point);
+            assert_eq!(delta.apply(previous), point);
+        }
+    }
+
+    #[test]
+    fn test_display_packet() {
+        let packets = [
+            (
+                Packet::HandshakeStart(HandshakeStart {
+                    major: 1,
+                    minor: 0,
+                    capabilities: Capabilities::NONE,
+                }),
+                "HandshakeStart(1.0)",
+            ),
+            (
+                Packet::HandshakeEnd(HandshakeEnd {
+                    major: 1,
+                    minor: 0,
+                    epoch: 1234,
+                    capabilities: Capabilities::COMPRESSED_DATA,
+                }),
+                "HandshakeEnd(1.0, epoch 1234)",
+            ),
+            (Packet::Ack, "Ack"),
+            (
+                Packet::SensorData(SensorData { count: 3 }),
+                "SensorData(3 values)",
+            ),
+            (Packet::ResetConnection, "ResetConnection"),
+            (
+                Packet::SensorDataCompressed(SensorDataCompressed {
+                    count: 2,
+                    base_time_offset: 60,
+                }),
+                "SensorDataCompressed(2 values)",
+            ),
+            (
+                Packet::Command(Command::SetSpreadingFactor(8)),
+                "Command(SetSpreadingFactor(8))",
+            ),
+            (
+                Packet::Command(Command::SetMeasureInterval(60)),
+                "Command(SetMeasureInterval(60s))",
+            ),
+            (Packet::Ping, "Ping"),
+            (Packet::Pong, "Pong"),
+        ];
+
+        for (packet, expected) in packets {
+            assert_eq!(packet.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_display_sensor_value() {
+        let values = [
+            (SensorValue::Temperature(22.3), "Temperature(22.3)"),
+            (SensorValue::Pressure(101325.0), "Pressure(101325)"),
+            (SensorValue::Altitude(-3.5), "Altitude(-3.5)"),
+            (SensorValue::AirQuality(0.52), "AirQuality(0.52)"),
+            (
+                SensorValue::Location {
+                    lat: 44.8412,
+                    lon: -0.5701,
+                },
+                "Location(44.8412, -0.5701)",
+            ),
+            (SensorValue::Humidity(45.5), "Humidity(45.5)"),
+            (SensorValue::Co2(415.0), "Co2(415)"),
+            (SensorValue::Voc(120.0), "Voc(120)"),
+            (
+                SensorValue::Unknown {
+                    id: 999,
+                    value_len: 3,
+                    raw: RawValue::EMPTY,
+                },
+                "Unknown(#999, 3 bytes)",
+            ),
+        ];
+
+        for (value, expected) in values {
+            assert_eq!(value.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_display_capabilities() {
+        assert_eq!(Capabilities::NONE.to_string(), "none");
+        assert_eq!(
+            Capabilities::DOWNLINK_COMMANDS
+                .union(Capabilities::COMPRESSED_DATA)
+                .to_string(),
+            "downlink-commands+compressed-data"
+        );
+        assert_eq!(
+            Capabilities::from_bits(0x102).to_string(),
+            "compressed-data+0x100"
+        );
+        assert_eq!(Capabilities::KEEPALIVE.to_string(), "keepalive");
+        assert_eq!(
+            Capabilities::KEEPALIVE
+                .union(Capabilities::FREQUENCY_HOPPING)
+                .to_string(),
+            "keepalive+frequency-hopping"
+        );
+        assert_eq!(
+            Capabilities::LINK_ACKS
+                .union(Capabilities::from_bits(0x20))
+                .to_string(),
+            "link-acks+0x20"
+        );
+    }
+
+    /// Deterministic xorshift generator, so that a failing case can be replayed from its seed.
+    struct TestRng(u64);
+
+    impl TestRng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: u64) -> u64 {
+            self.next() % n
+        }
+
+        fn bytes(&mut self, len: usize) -> Vec<u8> {
+            (0..len).map(|_| self.next() as u8).collect()
+        }
+
+        /// Not NaN, so that values can be compared
+        fn f32(&mut self) -> f32 {
+            self.next() as i32 as f32 / 1000.0
+        }
+
+        fn capabilities(&mut self) -> Capabilities {
+            // unknown bits included, empty half of the time
+            match self.below(2) {
+                0 => Capabilities::NONE,
+                _ => Capabilities::from_bits(self.next() as u32),
+            }
+        }
+
+        /// A sensor value, along with the bytes after its encoding that are only part of its `value_len`.
+        fn sensor_value(&mut self) -> (SensorValue, Vec<u8>) {
+            let value = match self.below(9) {
+                0 => SensorValue::Temperature(self.f32()),
+                1 => SensorValue::Pressure(self.f32()),
+                2 => SensorValue::Altitude(self.f32()),
+                3 => SensorValue::AirQuality(self.f32()),
+                4 => SensorValue::Location {
+                    lat: self.f32(),
+                    lon: self.f32(),
+                },
+                5 => SensorValue::Humidity(self.f32()),
+                6 => SensorValue::Co2(self.f32()),
+                7 => SensorValue::Voc(self.f32()),
+                _ => {
+                    let value_len = self.below(20) as u32;
+                    let id = match self.next() as u32 {
+                        // 4 is not assigned
+                        id @ (0..=3 | 5..=8) => id + 9,
+                        id => id,
+                    };
+                    let tail = self.bytes(value_len as usize);
+                    return (
+                        SensorValue::Unknown {
+                            id,
+                            value_len,
+                            raw: RawValue::EMPTY,
+                        },
+                        tail,
+                    );
+                }
+            };
+            (value, Vec::new())
+        }
+
+        /// A packet, along with the bytes after its encoding that are only part of a command's `arg_len`.
+        fn packet(&mut self) -> (Packet, Vec<u8>) {
+            let packet = match self.below(9) {
+                0 => Packet::HandshakeStart(HandshakeStart {
+                    // capabilities are only decoded for this major version
+                    major: 1,
+                    minor: self.next() as u8,
+                    capabilities: self.capabilities(),
+                }),
+                1 => Packet::HandshakeEnd(HandshakeEnd {
+                    major: 1,
+                    minor: self.next() as u8,
+                    epoch: self.next() >> self.below(64),
+                    capabilities: self.capabilities(),
+                }),
+                2 => Packet::Ack,
+                3 => Packet::SensorData(SensorData {
+                    count: self.next() as u8,
+                }),
+                4 => Packet::ResetConnection,
+                5 => Packet::SensorDataCompressed(SensorDataCompressed {
+                    count: self.next() as u8,
+                    base_time_offset: self.next() as i64 >> self.below(64),
+                }),
+                6 => Packet::Command(match self.below(3) {
+                    0 => Command::SetSpreadingFactor(self.next() as u8),
+                    1 => Command::SetMeasureInterval(self.next() as u32 >> self.below(32)),
+                    _ => {
+                        let arg_len = self.below(20) as u32;
+                        let command = Command::Unknown {
+                            id: (self.next() as u32).max(2),
+                            arg_len,
+                        };
+                        return (Packet::Command(command), self.bytes(arg_len as usize));
+                    }
+                }),
+                7 => Packet::Ping,
+                _ => Packet::Pong,
+            };
+            (packet, Vec::new())
+        }
+    }
+
+    /// Encodes `value` followed by `tail`, decodes it back and checks that exactly all of it was read.
+    fn round_trip<T: AsyncDecode<AllocatingTestCodec>>(
+        codec: &mut AllocatingTestCodec,
+        value: impl AsyncEncode<AllocatingTestCodec>,
+        tail: &[u8],
+    ) -> T {
+        let encoded_len = codec.emit_alloc(value).unwrap().len() + tail.len();
+        codec.buf.extend_from_slice(tail);
+        let pos = codec.current_offset();
+        let decoded = codec.read::<T>().run_blocking().unwrap();
+        assert_eq!(codec.current_offset().wrapping_sub(pos), encoded_len);
+        assert!(codec.buf.is_empty(), "{} bytes left", codec.buf.len());
+        decoded
+    }
+
+    #[test]
+    fn test_codec_round_trip_generated_packets() {
+        for seed in 1..=500u64 {
+            let mut rng = TestRng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+            let mut codec = AllocatingTestCodec {
+                // also covers offsets wrapping around
+                offset: usize::MAX - rng.below(64) as usize,
+                ..Default::default()
+            };
+
+            let (packet, tail) = rng.packet();
+            let decoded: Packet = round_trip(&mut codec, &packet, &tail);
+            assert_eq!(decoded, packet, "seed {seed}");
+
+            let mut time_offset: i64 = 0;
+            match packet {
+                Packet::SensorData(SensorData { count }) => {
+                    for _ in 0..count {
+                        let (value, tail) = rng.sensor_value();
+                        time_offset = time_offset.wrapping_add(rng.below(1000) as i64 - 500);
+                        let point = SensorValuePoint { value, time_offset };
+                        let decoded: SensorValuePoint = round_trip(&mut codec, point, &tail);
+                        assert_eq!(decoded, point, "seed {seed}");
+                    }
+                }
+                Packet::SensorDataCompressed(SensorDataCompressed { count, .. }) => {
+                    for _ in 0..count {
+                        let (value, tail) = rng.sensor_value();
+                        let delta = SensorValueDelta {
+                            value,
+                            time_delta: rng.next() as i64 >> rng.below(64),
+                        };
+                        let decoded: SensorValueDelta = round_trip(&mut codec, delta, &tail);
+                        assert_eq!(decoded, delta, "seed {seed}");
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
 }
@@ -0,0 +1,880 @@
+//! Application layer state machine shared by the gateway and the sensor boards.
+//!
+//! Timeouts and delays are configured through [`AppLayerTimings`], but applied by the boards along with
+//! what to do with the received values: the functions of this module only take care of the packet exchanges.
+
+use crate::{
+    app::v1::{
+        Capabilities, Command, HandshakeEnd, HandshakeStart, Packet, SensorData,
+        SensorDataCompressed, SensorValue, SensorValueDelta, SensorValuePoint,
+        MAX_VALUES_PER_PACKET,
+    },
+    codec::{AsyncDecoder, AsyncEncoder},
+    link::v1::LinkLayer,
+};
+use core::fmt::{Display, Formatter};
+use embassy_time::Duration;
+use thiserror::Error;
+
+/// Encodes and decodes application packets on top of a link layer.
+///
+/// Written data is sent to the peer that sent the last read bytes, or broadcast if nothing was received yet.
+pub struct AppLayer<LINK: LinkLayer> {
+    link: LINK,
+    offset: usize,
+    /// Sender of the last bytes read
+    last_peer: Option<LINK::PeerId>,
+    /// Features negotiated during the last handshake
+    capabilities: Capabilities,
+    timings: AppLayerTimings,
+    /// See [`Self::with_unknown_values_retained`]
+    retain_unknown_values: bool,
+}
+
+/// Timeouts and delays of the packet exchanges, slow or long-range links may need longer ones.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(test, derive(Debug))]
+pub struct AppLayerTimings {
+    /// Sensor board: maximum time to wait for the gateway's `HandshakeEnd`
+    pub handshake_timeout: Duration,
+    /// Sensor board: maximum time to wait for the gateway's `Ack`
+    pub ack_timeout: Duration,
+    /// Sensor board: delay before sending values
+    pub send_delay: Duration,
+    /// Gateway: delay before answering a `HandshakeStart`
+    pub handshake_reply_delay: Duration,
+    /// Gateway: delay before acknowledging received values
+    pub ack_delay: Duration,
+    /// Gateway: maximum time without hearing from a connected sensor board before dropping its connection
+    pub uplink_timeout: Duration,
+}
+
+impl Default for AppLayerTimings {
+    fn default() -> Self {
+        Self {
+            handshake_timeout: Duration::from_secs(5),
+            ack_timeout: Duration::from_secs(5),
+            send_delay: Duration::from_millis(1000),
+            handshake_reply_delay: Duration::from_millis(100),
+            ack_delay: Duration::from_secs(2),
+            uplink_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum AppLayerError<LINK: core::error::Error> {
+    Decoding,
+    UnexpectedPacket(u8),
+    IncompatibleProtocol(u8, u8),
+    Timeout,
+    /// The peer asked to tear down the connection
+    ResetRequested,
+    /// The gateway answered the handshake with a reset, most likely because of a protocol mismatch
+    HandshakeRejected,
+    /// A sensor data packet announced more than [`MAX_VALUES_PER_PACKET`] values
+    TooManyValues(u8),
+    Link(LINK),
+}
+
+impl<LINK: LinkLayer> AppLayer<LINK> {
+    pub fn new(link: LINK) -> Self {
+        Self::with_timings(link, AppLayerTimings::default())
+    }
+
+    pub fn with_timings(link: LINK, timings: AppLayerTimings) -> Self {
+        Self {
+            link,
+            offset: 0,
+            last_peer: None,
+            capabilities: Capabilities::NONE,
+            timings,
+            retain_unknown_values: false,
+        }
+    }
+
+    /// Keeps the raw bytes of values of unknown types, so that they can be forwarded as-is.
+    ///
+    /// They are skipped by default, see [`SensorValue::Unknown`].
+    pub fn with_unknown_values_retained(mut self, retain: bool) -> Self {
+        self.retain_unknown_values = retain;
+        self
+    }
+
+    pub fn reset(&mut self) {
+        self.link.reset();
+        self.offset = 0;
+        self.last_peer = None;
+        self.capabilities = Capabilities::NONE;
+    }
+
+    /// Features supported by both ends, as negotiated during the last handshake.
+    ///
+    /// On the sensor board, this is empty until the handshake completes.
+    /// On the gateway, this refers to the last sensor board whose handshake was accepted.
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    /// Sender of the last bytes read, if any.
+    pub fn last_peer(&self) -> Option<LINK::PeerId> {
+        self.last_peer
+    }
+
+    pub fn timings(&self) -> &AppLayerTimings {
+        &self.timings
+    }
+
+    pub fn link(&self) -> &LINK {
+        &self.link
+    }
+
+    pub fn link_mut(&mut self) -> &mut LINK {
+        &mut self.link
+    }
+
+    pub async fn flush(&mut self) -> Result<(), AppLayerError<LINK::Error>> {
+        self.link
+            .flush(self.last_peer)
+            .await
+            .map_err(AppLayerError::Link)
+    }
+}
+
+impl<LINK: LinkLayer> AsyncEncoder for AppLayer<LINK> {
+    type Error = AppLayerError<LINK::Error>;
+
+    async fn emit_bytes(&mut self, mut buf: &[u8]) -> Result<(), Self::Error> {
+        while !buf.is_empty() {
+            let written = self
+                .link
+                .write(self.last_peer, buf)
+                .await
+                .map_err(AppLayerError::Link)?;
+            buf = &buf[written..];
+        }
+        Ok(())
+    }
+}
+
+impl<LINK: LinkLayer> AsyncDecoder for AppLayer<LINK> {
+    type Error = AppLayerError<LINK::Error>;
+
+    async fn read_bytes(&mut self, buf: &mut [u8]) -> Result<(), Self::Error> {
+        let mut bytes_read = 0usize;
+
+        while bytes_read < buf.len() {
+            let (read, from) = self
+                .link
+                .read(&mut buf[bytes_read..])
+                .await
+                .map_err(AppLayerError::Link)?;
+            self.last_peer = Some(from);
+            self.offset = self.offset.wrapping_add(read);
+            bytes_read += read;
+        }
+        Ok(())
+    }
+
+    fn current_offset(&self) -> usize {
+        self.offset
+    }
+
+    fn decoding_error(&self) -> Self::Error {
+        AppLayerError::Decoding
+    }
+
+    fn retains_unknown_values(&self) -> bool {
+        self.retain_unknown_values
+    }
+}
+
+/// Sensor board: sends a `HandshakeStart` packet announcing the given protocol version and supported features.
+pub async fn start_handshake<LINK: LinkLayer>(
+    app: &mut AppLayer<LINK>,
+    major: u8,
+    minor: u8,
+    capabilities: Capabilities,
+) -> Result<(), AppLayerError<LINK::Error>> {
+    app.capabilities = Capabilities::NONE;
+    app.emit(&Packet::HandshakeStart(HandshakeStart {
+        major,
+        minor,
+        capabilities,
+    }))
+    .await?;
+    app.flush().await
+}
+
+/// Sensor board: waits for the gateway's `HandshakeEnd`, returns the gateway epoch in milliseconds.
+///
+/// The negotiated features are then available through [`AppLayer::capabilities`],
+/// they are restricted to `capabilities` in case the gateway reports more than what was announced.
+pub async fn wait_handshake_end<LINK: LinkLayer>(
+    app: &mut AppLayer<LINK>,
+    major: u8,
+    minor: u8,
+    capabilities: Capabilities,
+) -> Result<u64, AppLayerError<LINK::Error>> {
+    match app.read::<Packet>().await? {
+        Packet::HandshakeEnd(end) if end.major != major || end.minor != minor => {
+            Err(AppLayerError::IncompatibleProtocol(end.major, end.minor))
+        }
+        Packet::HandshakeEnd(end) => {
+            app.capabilities = end.capabilities.intersection(capabilities);
+            Ok(end.epoch)
+        }
+        Packet::ResetConnection => Err(AppLayerError::HandshakeRejected),
+        pkt => Err(AppLayerError::UnexpectedPacket(pkt.id())),
+    }
+}
+
+/// Sensor board: sends `values` in a single `SensorData` packet.
+pub async fn send_values<LINK: LinkLayer>(
+    app: &mut AppLayer<LINK>,
+    values: &[SensorValue],
+    time_offset: i64,
+) -> Result<(), AppLayerError<LINK::Error>> {
+    app.emit(&Packet::SensorData(SensorData {
+        count: values.len() as u8,
+    }))
+    .await?;
+
+    for &value in values {
+        app.emit(SensorValuePoint { value, time_offset }).await?;
+    }
+    app.flush().await
+}
+
+/// Sensor board: sends `points` in a single `SensorDataCompressed` packet, with delta-encoded time offsets.
+pub async fn send_compressed_values<LINK: LinkLayer>(
+    app: &mut AppLayer<LINK>,
+    points: &[SensorValuePoint],
+) -> Result<(), AppLayerError<LINK::Error>> {
+    let base_time_offset = points.first().map_or(0, |point| point.time_offset);
+
+    app.emit(&Packet::SensorDataCompressed(SensorDataCompressed {
+        count: points.len() as u8,
+        base_time_offset,
+    }))
+    .await?;
+
+    let mut previous = base_time_offset;
+    for &point in points {
+        app.emit(SensorValueDelta::between(previous, point)).await?;
+        previous = point.time_offset;
+    }
+    app.flush().await
+}
+
+/// Sensor board: waits for the gateway to acknowledge the last values.
+///
+/// Returns the command sent along with the acknowledgment, if the downlink-commands feature was negotiated.
+/// Pings received in the meantime are answered if the keepalive feature was negotiated.
+pub async fn wait_ack<LINK: LinkLayer>(
+    app: &mut AppLayer<LINK>,
+) -> Result<Option<Command>, AppLayerError<LINK::Error>> {
+    loop {
+        match app.read::<Packet>().await? {
+            Packet::Ack => return Ok(None),
+            Packet::Command(command)
+                if app.capabilities.contains(Capabilities::DOWNLINK_COMMANDS) =>
+            {
+                return Ok(Some(command))
+            }
+            Packet::Ping if app.capabilities.contains(Capabilities::KEEPALIVE) => {
+                send_pong(app).await?
+            }
+            Packet::ResetConnection => return Err(AppLayerError::ResetRequested),
+            pkt => return Err(AppLayerError::UnexpectedPacket(pkt.id())),
+        }
+    }
+}
+
+/// Sensor board: waits for the next downlink packet while idle, answering it if it is a `Ping`.
+///
+/// Must only be used if the keepalive feature was negotiated, see [`AppLayer::capabilities`].
+pub async fn answer_ping<LINK: LinkLayer>(
+    app: &mut AppLayer<LINK>,
+) -> Result<(), AppLayerError<LINK::Error>> {
+    match app.read::<Packet>().await? {
+        Packet::Ping => send_pong(app).await,
+        Packet::ResetConnection => Err(AppLayerError::ResetRequested),
+        pkt => Err(AppLayerError::UnexpectedPacket(pkt.id())),
+    }
+}
+
+async fn send_pong<LINK: LinkLayer>(
+    app: &mut AppLayer<LINK>,
+) -> Result<(), AppLayerError<LINK::Error>> {
+    app.emit(&Packet::Pong).await?;
+    app.flush().await
+}
+
+/// Gateway: answers a `HandshakeStart` packet with the given protocol version and epoch (in milliseconds).
+///
+/// The features supported by both ends are sent back to the sensor board,
+/// and are then available through [`AppLayer::capabilities`].
+///
+/// Sensor boards with a different major version are sent a `ResetConnection` packet instead,
+/// to let them know that they should not retry right away.
+pub async fn accept_handshake<LINK: LinkLayer>(
+    app: &mut AppLayer<LINK>,
+    pkt: HandshakeStart,
+    major: u8,
+    minor: u8,
+    epoch: u64,
+    capabilities: Capabilities,
+) -> Result<(), AppLayerError<LINK::Error>> {
+    app.capabilities = Capabilities::NONE;
+    if pkt.major != major {
+        app.emit(&Packet::ResetConnection).await?;
+        app.flush().await?;
+        return Err(AppLayerError::IncompatibleProtocol(pkt.major, pkt.minor));
+    }
+
+    app.capabilities = pkt.capabilities.intersection(capabilities);
+    app.emit(&Packet::HandshakeEnd(HandshakeEnd {
+        major,
+        minor,
+        epoch,
+        capabilities: app.capabilities,
+    }))
+    .await?;
+    app.flush().await
+}
+
+/// Gateway: reads the values announced by a `SensorData` or `SensorDataCompressed` packet one at a time.
+///
+/// Useful when each value needs to be processed asynchronously before reading the next one.
+pub struct ValuesReader {
+    remaining: u8,
+    /// Time offset of the previous value, for delta-encoded values only
+    previous_time_offset: Option<i64>,
+}
+
+impl ValuesReader {
+    pub const fn new(pkt: SensorData) -> Self {
+        Self {
+            remaining: pkt.count,
+            previous_time_offset: None,
+        }
+    }
+
+    pub const fn new_compressed(pkt: SensorDataCompressed) -> Self {
+        Self {
+            remaining: pkt.count,
+            previous_time_offset: Some(pkt.base_time_offset),
+        }
+    }
+
+    /// Reads the next value, returns `None` once all values of the packet were read.
+    ///
+    /// Fails with [`AppLayerError::TooManyValues`] before reading anything if the packet announces more than
+    /// [`MAX_VALUES_PER_PACKET`] values.
+    pub async fn next<LINK: LinkLayer>(
+        &mut self,
+        app: &mut AppLayer<LINK>,
+    ) -> Result<Option<SensorValuePoint>, AppLayerError<LINK::Error>> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        if self.remaining > MAX_VALUES_PER_PACKET {
+            return Err(AppLayerError::TooManyValues(self.remaining));
+        }
+
+        let point = match self.previous_time_offset {
+            None => app.read::<SensorValuePoint>().await?,
+            Some(previous) => {
+                let point = app.read::<SensorValueDelta>().await?.apply(previous);
+                self.previous_time_offset = Some(point.time_offset);
+                point
+            }
+        };
+        self.remaining -= 1;
+        Ok(Some(point))
+    }
+}
+
+/// Gateway: reads the values announced by a `SensorData` packet, passing each of them to `on_value`.
+pub async fn receive_values<LINK: LinkLayer>(
+    app: &mut AppLayer<LINK>,
+    pkt: SensorData,
+    on_value: impl FnMut(SensorValuePoint),
+) -> Result<(), AppLayerError<LINK::Error>> {
+    read_all_values(app, ValuesReader::new(pkt), on_value).await
+}
+
+/// Gateway: same as [`receive_values`], for `SensorDataCompressed` packets.
+pub async fn receive_compressed_values<LINK: LinkLayer>(
+    app: &mut AppLayer<LINK>,
+    pkt: SensorDataCompressed,
+    on_value: impl FnMut(SensorValuePoint),
+) -> Result<(), AppLayerError<LINK::Error>> {
+    read_all_values(app, ValuesReader::new_compressed(pkt), on_value).await
+}
+
+async fn read_all_values<LINK: LinkLayer>(
+    app: &mut AppLayer<LINK>,
+    mut reader: ValuesReader,
+    mut on_value: impl FnMut(SensorValuePoint),
+) -> Result<(), AppLayerError<LINK::Error>> {
+    while let Some(point) = reader.next(app).await? {
+        on_value(point);
+    }
+    Ok(())
+}
+
+/// Gateway: acknowledges the last values received.
+pub async fn send_ack<LINK: LinkLayer>(
+    app: &mut AppLayer<LINK>,
+) -> Result<(), AppLayerError<LINK::Error>> {
+    app.emit(&Packet::Ack).await?;
+    app.flush().await
+}
+
+/// Gateway: acknowledges the last values received with a `Command` packet.
+///
+/// Must only be used if the downlink-commands feature was negotiated, see [`AppLayer::capabilities`].
+pub async fn send_command<LINK: LinkLayer>(
+    app: &mut AppLayer<LINK>,
+    command: Command,
+) -> Result<(), AppLayerError<LINK::Error>> {
+    app.emit(&Packet::Command(command)).await?;
+    app.flush().await
+}
+
+/// Gateway: checks that the last sensor board is still connected, it answers with a `Pong` packet.
+///
+/// Must only be used if the keepalive feature was negotiated, see [`AppLayer::capabilities`].
+pub async fn send_ping<LINK: LinkLayer>(
+    app: &mut AppLayer<LINK>,
+) -> Result<(), AppLayerError<LINK::Error>> {
+    app.emit(&Packet::Ping).await?;
+    app.flush().await
+}
+
+impl<LINK: core::error::Error> Display for AppLayerError<LINK> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match &self {
+            AppLayerError::Decoding => f.write_str("decoding error"),
+            AppLayerError::Link(err) => write!(f, "{}", err),
+            AppLayerError::UnexpectedPacket(id) => write!(f, "unexpected packet: {}", id),
+            AppLayerError::IncompatibleProtocol(major, minor) => {
+                write!(f, "incompatible protocol: {}.{}", major, minor)
+            }
+            AppLayerError::Timeout => f.write_str("timeout exceeded"),
+            AppLayerError::ResetRequested => f.write_str("connection reset by peer"),
+            AppLayerError::HandshakeRejected => f.write_str("handshake rejected by gateway"),
+            AppLayerError::TooManyValues(count) => write!(f, "too many values: {}", count),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        link::v1::{GatewayId, SensorBoardId},
+        mock::{link_pair, MockError, MockLink},
+        test_util::RunBlockingExt,
+    };
+    use embassy_futures::join::join;
+
+    type Error = AppLayerError<MockError>;
+
+    fn app_pair() -> (
+        AppLayer<MockLink<SensorBoardId>>,
+        AppLayer<MockLink<GatewayId>>,
+    ) {
+        let (gateway, sensor) = link_pair(SensorBoardId(2), GatewayId);
+        (AppLayer::new(gateway), AppLayer::new(sensor))
+    }
+
+    /// Gateway side of one connection, returns the received values.
+    async fn gateway_session(
+        app: &mut AppLayer<MockLink<SensorBoardId>>,
+    ) -> Result<Vec<SensorValuePoint>, Error> {
+        let Packet::HandshakeStart(start) = app.read::<Packet>().await? else {
+            panic!("expected a handshake start");
+        };
+        accept_handshake(app, start, 1, 0, 1234, Capabilities::NONE).await?;
+
+        let Packet::SensorData(data) = app.read::<Packet>().await? else {
+            panic!("expected sensor data");
+        };
+        let mut values = Vec::new();
+        receive_values(app, data, |point| values.push(point)).await?;
+        send_ack(app).await?;
+        Ok(values)
+    }
+
+    #[test]
+    fn test_session_handshake_and_values() {
+        let (mut gateway, mut sensor) = app_pair();
+        let values = [
+            SensorValue::Temperature(22.3),
+            SensorValue::Pressure(1013.2),
+        ];
+
+        let (received, sent) = join(gateway_session(&mut gateway), async {
+            start_handshake(&mut sensor, 1, 0, Capabilities::NONE).await?;
+            let epoch = wait_handshake_end(&mut sensor, 1, 0, Capabilities::NONE).await?;
+            send_values(&mut sensor, &values, 42).await?;
+            wait_ack(&mut sensor).await?;
+            Ok::<_, Error>(epoch)
+        })
+        .run_blocking();
+
+        assert_eq!(sent.unwrap(), 1234);
+        assert_eq!(
+            received.unwrap(),
+            values.map(|value| SensorValuePoint {
+                value,
+                time_offset: 42
+            })
+        );
+        // replies are addressed to the sensor board that sent the request
+        assert_eq!(gateway.last_peer(), Some(SensorBoardId(2)));
+        assert!(gateway
+            .link()
+            .flushed_to
+            .iter()
+            .all(|&dest| dest == Some(SensorBoardId(2))));
+    }
+
+    #[test]
+    fn test_session_handshake_rejected() {
+        let (mut gateway, mut sensor) = app_pair();
+
+        let (gateway_res, sensor_res) = join(
+            async {
+                let Packet::HandshakeStart(start) = gateway.read::<Packet>().await? else {
+                    panic!("expected a handshake start");
+                };
+                accept_handshake(&mut gateway, start, 1, 0, 1234, Capabilities::NONE).await
+            },
+            async {
+                start_handshake(&mut sensor, 2, 0, Capabilities::NONE).await?;
+                wait_handshake_end(&mut sensor, 2, 0, Capabilities::NONE).await
+            },
+        )
+        .run_blocking();
+
+        assert!(matches!(
+            gateway_res,
+            Err(AppLayerError::IncompatibleProtocol(2, 0))
+        ));
+        assert!(matches!(sensor_res, Err(AppLayerError::HandshakeRejected)));
+    }
+
+    #[test]
+    fn test_session_capabilities_negotiation() {
+        let (mut gateway, mut sensor) = app_pair();
+        let sensor_capabilities =
+            Capabilities::DOWNLINK_COMMANDS.union(Capabilities::COMPRESSED_DATA);
+
+        let (gateway_res, sensor_res) = join(
+            async {
+                let Packet::HandshakeStart(start) = gateway.read::<Packet>().await? else {
+                    panic!("expected a handshake start");
+                };
+                accept_handshake(
+                    &mut gateway,
+                    start,
+                    1,
+                    0,
+                    1234,
+                    Capabilities::COMPRESSED_DATA,
+                )
+                .await
+            },
+            async {
+                start_handshake(&mut sensor, 1, 0, sensor_capabilities).await?;
+                wait_handshake_end(&mut sensor, 1, 0, sensor_capabilities).await
+            },
+        )
+        .run_blocking();
+
+        assert!(gateway_res.is_ok());
+        assert_eq!(sensor_res.unwrap(), 1234);
+        assert_eq!(gateway.capabilities(), Capabilities::COMPRESSED_DATA);
+        assert_eq!(sensor.capabilities(), Capabilities::COMPRESSED_DATA);
+
+        sensor.reset();
+        assert_eq!(sensor.capabilities(), Capabilities::NONE);
+    }
+
+    #[test]
+    fn test_session_timings_kept_across_resets() {
+        let (link, _) = link_pair(GatewayId, SensorBoardId(1));
+        let timings = AppLayerTimings {
+            handshake_timeout: Duration::from_secs(30),
+            ..AppLayerTimings::default()
+        };
+        let mut app = AppLayer::with_timings(link, timings);
+
+        app.reset();
+        assert_eq!(*app.timings(), timings);
+        assert_eq!(
+            app.timings().ack_timeout,
+            AppLayerTimings::default().ack_timeout
+        );
+    }
+
+    #[test]
+    fn test_session_reset_while_waiting_for_ack() {
+        let (mut gateway, mut sensor) = app_pair();
+
+        let (gateway_res, sensor_res) = join(
+            async {
+                let Packet::SensorData(data) = gateway.read::<Packet>().await? else {
+                    panic!("expected sensor data");
+                };
+                receive_values(&mut gateway, data, |_| ()).await?;
+                gateway.emit(&Packet::ResetConnection).await?;
+                gateway.flush().await
+            },
+            async {
+                send_values(&mut sensor, &[SensorValue::AirQuality(12.0)], 0).await?;
+                wait_ack(&mut sensor).await
+            },
+        )
+        .run_blocking();
+
+        assert!(gateway_res.is_ok());
+        assert!(matches!(sensor_res, Err(AppLayerError::ResetRequested)));
+    }
+
+    #[test]
+    fn test_session_command_instead_of_ack() {
+        let (mut gateway, mut sensor) = app_pair();
+        let capabilities = Capabilities::DOWNLINK_COMMANDS;
+
+        let (gateway_res, sensor_res) = join(
+            async {
+                let Packet::HandshakeStart(start) = gateway.read::<Packet>().await? else {
+                    panic!("expected a handshake start");
+                };
+                accept_handshake(&mut gateway, start, 1, 0, 1234, capabilities).await?;
+                let Packet::SensorData(data) = gateway.read::<Packet>().await? else {
+                    panic!("expected sensor data");
+                };
+                receive_values(&mut gateway, data, |_| ()).await?;
+                send_command(&mut gateway, Command::SetSpreadingFactor(8)).await
+            },
+            async {
+                start_handshake(&mut sensor, 1, 0, capabilities).await?;
+                wait_handshake_end(&mut sensor, 1, 0, capabilities).await?;
+                send_values(&mut sensor, &[SensorValue::Humidity(40.0)], 0).await?;
+                wait_ack(&mut sensor).await
+            },
+        )
+        .run_blocking();
+
+        assert!(gateway_res.is_ok());
+        assert_eq!(sensor_res.unwrap(), Some(Command::SetSpreadingFactor(8)));
+    }
+
+    #[test]
+    fn test_session_keepalive() {
+        let (mut gateway, mut sensor) = app_pair();
+        let capabilities = Capabilities::KEEPALIVE;
+
+        let (gateway_res, sensor_res) = join(
+            async {
+                let Packet::HandshakeStart(start) = gateway.read::<Packet>().await? else {
+                    panic!("expected a handshake start");
+                };
+                accept_handshake(&mut gateway, start, 1, 0, 1234, capabilities).await?;
+                let Packet::SensorData(data) = gateway.read::<Packet>().await? else {
+                    panic!("expected sensor data");
+                };
+                receive_values(&mut gateway, data, |_| ()).await?;
+                // while the sensor waits for the acknowledgment
+                send_ping(&mut gateway).await?;
+                assert!(matches!(gateway.read::<Packet>().await?, Packet::Pong));
+                send_ack(&mut gateway).await?;
+                // while the sensor is idle
+                send_ping(&mut gateway).await?;
+                gateway.read::<Packet>().await
+            },
+            async {
+                start_handshake(&mut sensor, 1, 0, capabilities).await?;
+                wait_handshake_end(&mut sensor, 1, 0, capabilities).await?;
+                send_values(&mut sensor, &[SensorValue::Humidity(40.0)], 0).await?;
+                assert_eq!(wait_ack(&mut sensor).await?, None);
+                answer_ping(&mut sensor).await
+            },
+        )
+        .run_blocking();
+
+        assert!(matches!(gateway_res, Ok(Packet::Pong)));
+        assert!(sensor_res.is_ok());
+    }
+
+    #[test]
+    fn test_session_reset_while_idle() {
+        let (mut gateway, mut sensor) = app_pair();
+        let capabilities = Capabilities::KEEPALIVE;
+
+        let (gateway_res, sensor_res) = join(
+            async {
+                let Packet::HandshakeStart(start) = gateway.read::<Packet>().await? else {
+                    panic!("expected a handshake start");
+                };
+                accept_handshake(&mut gateway, start, 1, 0, 1234, capabilities).await?;
+                gateway.emit(&Packet::ResetConnection).await?;
+                gateway.flush().await?;
+                // the sensor board starts over
+                let Packet::HandshakeStart(start) = gateway.read::<Packet>().await? else {
+                    panic!("expected a handshake start");
+                };
+                accept_handshake(&mut gateway, start, 1, 0, 5678, capabilities).await
+            },
+            async {
+                start_handshake(&mut sensor, 1, 0, capabilities).await?;
+                wait_handshake_end(&mut sensor, 1, 0, capabilities).await?;
+                let reset = answer_ping(&mut sensor).await;
+                assert!(matches!(reset, Err(AppLayerError::ResetRequested)));
+                sensor.reset();
+                assert_eq!(sensor.capabilities(), Capabilities::NONE);
+                start_handshake(&mut sensor, 1, 0, capabilities).await?;
+                wait_handshake_end(&mut sensor, 1, 0, capabilities).await
+            },
+        )
+        .run_blocking();
+
+        assert!(gateway_res.is_ok());
+        assert_eq!(sensor_res.unwrap(), 5678);
+    }
+
+    #[test]
+    fn test_session_command_not_negotiated() {
+        let (mut gateway, mut sensor) = app_pair();
+
+        let (gateway_res, sensor_res) = join(
+            async {
+                let Packet::SensorData(data) = gateway.read::<Packet>().await? else {
+                    panic!("expected sensor data");
+                };
+                receive_values(&mut gateway, data, |_| ()).await?;
+                send_command(&mut gateway, Command::SetSpreadingFactor(8)).await
+            },
+            async {
+                send_values(&mut sensor, &[SensorValue::Humidity(40.0)], 0).await?;
+                wait_ack(&mut sensor).await
+            },
+        )
+        .run_blocking();
+
+        assert!(gateway_res.is_ok());
+        assert!(matches!(
+            sensor_res,
+            Err(AppLayerError::UnexpectedPacket(6))
+        ));
+    }
+
+    #[test]
+    fn test_session_compressed_values() {
+        let (mut gateway, mut sensor) = app_pair();
+        let points = [
+            SensorValuePoint {
+                value: SensorValue::Temperature(22.3),
+                time_offset: 600,
+            },
+            SensorValuePoint {
+                value: SensorValue::Temperature(22.1),
+                time_offset: 610,
+            },
+            SensorValuePoint {
+                value: SensorValue::Pressure(1013.2),
+                time_offset: 590,
+            },
+        ];
+
+        let (gateway_res, sensor_res) = join(
+            async {
+                let Packet::SensorDataCompressed(data) = gateway.read::<Packet>().await? else {
+                    panic!("expected compressed sensor data");
+                };
+                let mut values = Vec::new();
+                receive_compressed_values(&mut gateway, data, |point| values.push(point)).await?;
+                send_ack(&mut gateway).await?;
+                Ok::<_, Error>(values)
+            },
+            async {
+                send_compressed_values(&mut sensor, &points).await?;
+                wait_ack(&mut sensor).await
+            },
+        )
+        .run_blocking();
+
+        assert_eq!(gateway_res.unwrap(), points);
+        assert!(sensor_res.is_ok());
+    }
+
+    #[test]
+    fn test_session_values_count_checked() {
+        let (mut gateway, mut sensor) = app_pair();
+        let point = SensorValuePoint {
+            value: SensorValue::Temperature(22.3),
+            time_offset: 42,
+        };
+
+        // announces more values than allowed, none of them is read
+        let (gateway_res, sensor_res) = join(
+            async {
+                let Packet::SensorData(data) = gateway.read::<Packet>().await? else {
+                    panic!("expected sensor data");
+                };
+                let mut values = Vec::new();
+                receive_values(&mut gateway, data, |point| values.push(point)).await?;
+                Ok::<_, Error>(values)
+            },
+            async {
+                sensor
+                    .emit(&Packet::SensorData(SensorData {
+                        count: MAX_VALUES_PER_PACKET + 1,
+                    }))
+                    .await?;
+                sensor.emit(point).await?;
+                sensor.flush().await
+            },
+        )
+        .run_blocking();
+        assert!(matches!(
+            gateway_res,
+            Err(AppLayerError::TooManyValues(count)) if count == MAX_VALUES_PER_PACKET + 1
+        ));
+        assert!(sensor_res.is_ok());
+
+        // announces more values than it contains, reading stops once the link is closed
+        let (mut gateway, mut sensor) = app_pair();
+        let mut values = Vec::new();
+        let (gateway_res, ()) = join(
+            async {
+                let Packet::SensorData(data) = gateway.read::<Packet>().await? else {
+                    panic!("expected sensor data");
+                };
+                receive_values(&mut gateway, data, |point| values.push(point)).await
+            },
+            async {
+                sensor
+                    .emit(&Packet::SensorData(SensorData { count: 3 }))
+                    .await
+                    .unwrap();
+                sensor.emit(point).await.unwrap();
+                sensor.emit(point).await.unwrap();
+                sensor.flush().await.unwrap();
+                drop(sensor);
+            },
+        )
+        .run_blocking();
+        assert!(matches!(
+            gateway_res,
+            Err(AppLayerError::Link(MockError::Disconnected))
+        ));
+        assert_eq!(values, [point, point]);
+    }
+}
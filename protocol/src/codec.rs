@@ -11,6 +11,11 @@ pub trait AsyncEncoder {
     }
 }
 
+/// Maximum number of bytes [`AsyncDecoder::read_discard`] accepts to skip.
+///
+/// Well above the size of a LoRa frame, larger lengths can only come from a corrupt or malicious sender.
+pub const MAX_DISCARD_LEN: usize = 4096;
+
 pub trait AsyncDecoder {
     type Error: core::error::Error;
 
@@ -21,11 +26,27 @@ pub trait AsyncDecoder {
     /// Returns the number of bytes read from the first call to [`Self::read_bytes`].  
     /// Successive calls to this function will yield a value that is always equal or greater than the previous call,
     /// except in the case of overflow.
-    /// Note: this number is allowed to overflow (in case of *really* long-running programs).
+    /// Note: this number is allowed to wrap around (in case of *really* long-running programs),
+    /// use [`Self::bytes_read_since`] to compute the number of bytes between two offsets.
     fn current_offset(&self) -> usize;
 
+    /// Returns the number of bytes read since [`Self::current_offset`] returned `pos`.
+    ///
+    /// Correct even if the offset wrapped around in between, as long as fewer than `usize::MAX` bytes were read.
+    #[inline]
+    fn bytes_read_since(&self, pos: usize) -> usize {
+        self.current_offset().wrapping_sub(pos)
+    }
+
     fn decoding_error(&self) -> Self::Error;
 
+    /// Whether values of unknown types keep their raw bytes when decoded, instead of skipping them,
+    /// see [`SensorValue::Unknown`](crate::app::v1::SensorValue::Unknown).
+    #[inline]
+    fn retains_unknown_values(&self) -> bool {
+        false
+    }
+
     /// Reads a value of type `F` from the stream.  
     /// Returns the value and the number of bytes that were read.
     #[inline]
@@ -34,8 +55,13 @@ pub trait AsyncDecoder {
     }
 
     /// Reads exactly `n` bytes from a stream, discarding them.
+    ///
+    /// Fails with [`Self::decoding_error`] without reading anything if `n` is greater than [`MAX_DISCARD_LEN`].
     async fn read_discard(&mut self, mut n: usize) -> Result<(), Self::Error> {
-        let mut buf = [0u8; 16];
+        if n > MAX_DISCARD_LEN {
+            return Err(self.decoding_error());
+        }
+        let mut buf = [0u8; 64];
 
         while n > 0 {
             let to_discard = n.min(buf.len());
@@ -100,6 +126,69 @@ macro_rules! tuple_encoding {
 
 tuple_encoding!(T0, T1);
 tuple_encoding!(T0, T1, T2);
+tuple_encoding!(T0, T1, T2, T3);
+tuple_encoding!(T0, T1, T2, T3, T4);
+
+/// Arrays are encoded as their elements in sequence, without any length prefix.
+impl<E, T, const N: usize> AsyncEncode<E> for [T; N]
+where
+    E: AsyncEncoder + ?Sized,
+    T: AsyncEncode<E>,
+{
+    async fn encode(self, encoder: &mut E) -> Result<(), E::Error> {
+        for item in self {
+            item.encode(encoder).await?;
+        }
+        Ok(())
+    }
+}
+
+impl<D, T, const N: usize> AsyncDecode<D> for [T; N]
+where
+    D: AsyncDecoder + ?Sized,
+    T: AsyncDecode<D> + Default,
+{
+    async fn decode(decoder: &mut D) -> Result<Self, D::Error> {
+        let mut array: [T; N] = core::array::from_fn(|_| T::default());
+        for item in &mut array {
+            *item = T::decode(decoder).await?;
+        }
+        Ok(array)
+    }
+}
+
+/// Strings are encoded as their length in bytes (ULEB128) followed by their UTF-8 bytes.
+impl<E: AsyncEncoder + ?Sized> AsyncEncode<E> for &str {
+    async fn encode(self, encoder: &mut E) -> Result<(), E::Error> {
+        // strings sent over LoRa are way shorter than 4 GiB
+        encoder.emit(self.len() as u32).await?;
+        encoder.emit_bytes(self.as_bytes()).await
+    }
+}
+
+impl<E: AsyncEncoder + ?Sized, const N: usize> AsyncEncode<E> for heapless::String<N> {
+    #[inline]
+    async fn encode(self, encoder: &mut E) -> Result<(), E::Error> {
+        encoder.emit(self.as_str()).await
+    }
+}
+
+/// Fails with [`AsyncDecoder::decoding_error`] without reading the string if it is longer than `N` bytes,
+/// or after reading it if it is not valid UTF-8.
+impl<D: AsyncDecoder + ?Sized, const N: usize> AsyncDecode<D> for heapless::String<N> {
+    async fn decode(decoder: &mut D) -> Result<Self, D::Error> {
+        let len = decoder.read::<u32>().await? as usize;
+        if len > N {
+            return Err(decoder.decoding_error());
+        }
+
+        let mut bytes: heapless::Vec<u8, N> = heapless::Vec::new();
+        // cannot fail, `len` was checked above
+        _ = bytes.resize_default(len);
+        decoder.read_bytes(&mut bytes).await?;
+        heapless::String::from_utf8(bytes).map_err(|_| decoder.decoding_error())
+    }
+}
 
 pub trait ToLeb128Ext<const N: usize> {
     fn to_leb128(self, buf: &mut [u8; N]) -> &[u8];
@@ -1,4 +1,5 @@
 use crate::phy::PhysicalLayer;
+use embassy_time::{Duration, Instant};
 use hmac::{Hmac, Mac};
 use sha2::Sha256;
 
@@ -36,22 +37,30 @@ pub trait LinkLayer {
     /// This is needed because `write()` may buffer its data instead of sending it.
     async fn flush(&mut self, dest: Option<Self::PeerId>) -> Result<(), Self::Error>;
 
+    /// Drops any buffered data and goes back to the handshake phase, if any.
+    ///
+    /// Called by [`AppLayer::reset`](crate::app::v1::session::AppLayer::reset) when the connection is re-initiated.
     fn reset(&mut self);
 }
 
 /// *The* Gateway ID, version 1 of the protocol only supports one gateway.
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(test, derive(Debug))]
 pub struct GatewayId;
 
 /// 4-bit ID of a sensor board.
 #[repr(transparent)]
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(test, derive(Debug))]
 pub struct SensorBoardId(pub u8);
 
 #[derive(Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(test, derive(Debug))]
 pub enum LinkPhase {
     Handshake,
     Data,
+    /// Acknowledges a data packet, whose first [`LINK_ACK_PAYLOAD_LEN`] bytes are the payload
+    Ack,
 }
 
 impl LinkPhase {
@@ -59,17 +68,241 @@ impl LinkPhase {
         match self {
             Self::Handshake => 0b10,
             Self::Data => 0b00,
+            Self::Ack => 0b01,
         }
     }
-    fn from_bits(bits: u8) -> Self {
-        if bits == 0b10 {
-            Self::Handshake
-        } else {
-            Self::Data
+    /// Returns `None` for `0b11`, which is reserved: acks are only sent during the data phase.
+    fn from_bits(bits: u8) -> Option<Self> {
+        match bits {
+            0b10 => Some(Self::Handshake),
+            0b00 => Some(Self::Data),
+            0b01 => Some(Self::Ack),
+            _ => None,
         }
     }
 }
 
+/// Size of the link header: action bits, sensor board ID, signature and total length.
+pub const LINK_HEADER_LEN: usize = 6;
+/// Size of the smallest valid frame, payloads cannot be empty.
+pub const LINK_MIN_FRAME_LEN: usize = LINK_HEADER_LEN + 1;
+/// Size of the payload of an ack packet: the action bits, ID and signature of the acknowledged packet.
+pub const LINK_ACK_PAYLOAD_LEN: usize = 5;
+/// Longest wait for the ack of a data packet before sending it again.
+pub const LINK_ACK_TIMEOUT: Duration = Duration::from_secs(1);
+/// Size of the fingerprint that sensor boards send in their handshake, their MAC address.
+pub const LINK_FINGERPRINT_LEN: usize = 6;
+/// Size of the nonce that the gateway appends to its handshake reply, see [`LinkKeys`].
+pub const LINK_NONCE_LEN: usize = 8;
+/// Size of the payload of the gateway's handshake reply: the fingerprint of the sensor board, then the nonce.
+pub const LINK_HANDSHAKE_REPLY_LEN: usize = LINK_FINGERPRINT_LEN + LINK_NONCE_LEN;
+
+/// Key that link frames are signed with, which may depend on their phase.
+pub trait LinkSigKey {
+    fn key(&self, phase: LinkPhase) -> &[u8];
+}
+
+/// The same key for every frame.
+impl LinkSigKey for [u8] {
+    fn key(&self, _phase: LinkPhase) -> &[u8] {
+        self
+    }
+}
+
+impl<const N: usize> LinkSigKey for [u8; N] {
+    fn key(&self, _phase: LinkPhase) -> &[u8] {
+        self
+    }
+}
+
+/// Keys of a link session.
+///
+/// Handshakes are signed with the key shared by the gateway and every sensor board, the other frames with a key
+/// derived from it and the nonce of the last handshake reply of the gateway.
+/// A recorded handshake reply or data frame is thus useless once the gateway has picked a new nonce.
+///
+/// Before the first handshake, every frame is signed with the shared key.
+#[derive(Clone)]
+pub struct LinkKeys<'a> {
+    shared: &'a [u8],
+    session: Option<[u8; 32]>,
+}
+
+impl<'a> LinkKeys<'a> {
+    pub const fn new(shared: &'a [u8]) -> Self {
+        Self {
+            shared,
+            session: None,
+        }
+    }
+
+    /// Switches to the session key of a handshake reply with the given nonce.
+    pub fn start_session(&mut self, nonce: &[u8; LINK_NONCE_LEN]) {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.shared).expect("HMAC should not fail");
+        mac.update(b"session");
+        mac.update(nonce);
+        self.session = Some(mac.finalize().into_bytes().into());
+    }
+
+    /// Goes back to the shared key, until the next handshake.
+    pub fn end_session(&mut self) {
+        self.session = None;
+    }
+
+    pub fn has_session(&self) -> bool {
+        self.session.is_some()
+    }
+}
+
+impl LinkSigKey for LinkKeys<'_> {
+    fn key(&self, phase: LinkPhase) -> &[u8] {
+        match (phase, &self.session) {
+            (LinkPhase::Handshake, _) | (_, None) => self.shared,
+            (_, Some(session)) => session,
+        }
+    }
+}
+
+/// Payload of the gateway's reply to the handshake of the sensor board with the given fingerprint.
+pub fn handshake_reply_payload(
+    fingerprint: &[u8; LINK_FINGERPRINT_LEN],
+    nonce: &[u8; LINK_NONCE_LEN],
+) -> [u8; LINK_HANDSHAKE_REPLY_LEN] {
+    let mut payload = [0; LINK_HANDSHAKE_REPLY_LEN];
+    payload[..LINK_FINGERPRINT_LEN].copy_from_slice(fingerprint);
+    payload[LINK_FINGERPRINT_LEN..].copy_from_slice(nonce);
+    payload
+}
+
+/// Nonce of a handshake reply, `None` if it is addressed to another fingerprint or has no nonce.
+pub fn handshake_reply_nonce(
+    payload: &[u8],
+    fingerprint: &[u8; LINK_FINGERPRINT_LEN],
+) -> Option<[u8; LINK_NONCE_LEN]> {
+    let payload: &[u8; LINK_HANDSHAKE_REPLY_LEN] = payload.try_into().ok()?;
+    let (reply_fingerprint, nonce) = payload.split_first_chunk::<LINK_FINGERPRINT_LEN>()?;
+    if reply_fingerprint != fingerprint {
+        return None;
+    }
+    nonce.try_into().ok()
+}
+
+/// Header fields of a received frame, see [`LinkPacket::inspect`].
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(test, derive(Debug))]
+pub struct LinkFrameInfo {
+    /// `None` for the reserved action bits
+    pub phase: Option<LinkPhase>,
+    pub id: u8,
+    /// Total length declared in the header, the frame is dropped if it doesn't match the actual length
+    pub declared_len: u8,
+    /// Whether the signature matches the payload
+    pub signature_valid: bool,
+}
+
+/// Keys of the session of each sensor board, learned from the handshake replies of the gateway, for debugging purposes.
+///
+/// Lets a third party holding the shared key check the signature of data frames, see [`LinkSniffer::inspect`].
+pub struct LinkSniffer<'a> {
+    /// Indexed by sensor board ID
+    keys: [LinkKeys<'a>; 16],
+}
+
+impl<'a> LinkSniffer<'a> {
+    pub fn new(shared: &'a [u8]) -> Self {
+        Self {
+            keys: core::array::from_fn(|_| LinkKeys::new(shared)),
+        }
+    }
+
+    /// Same as [`LinkPacket::inspect`], with the key of the session of the sensor board the frame is addressed to.
+    ///
+    /// A valid handshake reply starts a new session for its sensor board.
+    pub fn inspect(&mut self, bytes: &[u8]) -> Option<LinkFrameInfo> {
+        let id = usize::from((bytes.first()? >> 2) & 0xf);
+        let frame = LinkPacket::inspect(bytes, &self.keys[id])?;
+
+        let valid = frame.signature_valid && usize::from(frame.declared_len) == bytes.len();
+        if frame.phase == Some(LinkPhase::Handshake) && valid {
+            let reply = <&[u8; LINK_HANDSHAKE_REPLY_LEN]>::try_from(&bytes[LINK_HEADER_LEN..]);
+            if let Ok(reply) = reply {
+                let (_, nonce) = reply.split_last_chunk::<LINK_NONCE_LEN>()?;
+                self.keys[id].start_session(nonce);
+            }
+        }
+        Some(frame)
+    }
+}
+
+/// Payload of the next outgoing frame of a link layer, filled by `write()` and sent once full or flushed.
+///
+/// Writes are split across as many frames as needed by [`LinkTxBuffer::write`], each of them full except for the
+/// last one, which waits for the next write or flush.
+#[derive(Clone, Default)]
+pub struct LinkTxBuffer<const N: usize> {
+    buf: heapless::Vec<u8, N>,
+}
+
+impl<const N: usize> LinkTxBuffer<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: heapless::Vec::new(),
+        }
+    }
+
+    /// Appends the start of `data` to the buffer, up to `max_len` bytes in total.
+    ///
+    /// `max_len` is the largest payload of a frame, clamped between 1 and `N`.
+    /// Returns the number of bytes appended, 0 only if `data` is empty or the buffer is full.
+    pub fn push(&mut self, data: &[u8], max_len: usize) -> usize {
+        let free = max_len.clamp(1, N).saturating_sub(self.buf.len());
+        let len = free.min(data.len());
+        // cannot fail: the buffer has at least `free` bytes of spare capacity
+        _ = self.buf.extend_from_slice(&data[..len]);
+        len
+    }
+
+    /// Appends `data` to the buffer, passing each frame of `max_len` bytes to `send` once full and clearing it.
+    ///
+    /// A frame left incomplete at the end of `data` stays in the buffer, to be completed by the next write or sent
+    /// as is by a flush. Stops at the first error of `send`, with the frame that failed still in the buffer.
+    pub async fn write<E>(
+        &mut self,
+        data: &[u8],
+        max_len: usize,
+        mut send: impl AsyncFnMut(&[u8]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let mut rest = data;
+        while !rest.is_empty() {
+            let pushed = self.push(rest, max_len);
+            rest = &rest[pushed..];
+            if self.is_full(max_len) {
+                send(self.as_slice()).await?;
+                self.clear();
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the buffer holds a full frame of `max_len` bytes, which should be sent before pushing more data.
+    pub fn is_full(&self, max_len: usize) -> bool {
+        self.buf.len() >= max_len.clamp(1, N)
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.buf
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.buf.clear();
+    }
+}
+
+#[derive(Copy, Clone)]
 pub struct LinkPacket<'a> {
     pub phase: LinkPhase,
     pub id: u8,
@@ -77,20 +310,39 @@ pub struct LinkPacket<'a> {
 }
 
 impl<'a> LinkPacket<'a> {
-    pub async fn write<PHY: PhysicalLayer>(
+    pub async fn write<PHY: PhysicalLayer, K: LinkSigKey + ?Sized>(
         self,
         mut phy: PHY,
-        sig_key: &[u8],
+        sig_key: &K,
     ) -> Result<(), PHY::Error> {
+        // oversized frames end up with a mismatched length and are dropped by the receiver
+        let total_len = u8::try_from(LINK_HEADER_LEN + self.payload.len()).unwrap_or(u8::MAX);
+
+        phy.write(&self.ack_payload(sig_key)).await?;
+        phy.write(&[total_len]).await?;
+        phy.write(self.payload).await?;
+        phy.flush().await
+    }
+
+    /// Action bits, ID and signature of this packet, which are also the payload of its ack.
+    pub fn ack_payload<K: LinkSigKey + ?Sized>(&self, sig_key: &K) -> [u8; LINK_ACK_PAYLOAD_LEN] {
         let action_bits: u8 = self.phase.to_bits();
         let header_meta: u8 = (action_bits << 6) | ((self.id & 0b1111) << 2); // id (4 bits)
-        let sig_bits: u64 = Self::sign_payload(self.payload, sig_key);
+        let sig_bits: u64 = Self::sign_payload(self.payload, sig_key.key(self.phase));
 
         let header: u64 = (header_meta as u64) << 56 | (sig_bits >> 6);
+        let [a, b, c, d, e, ..] = header.to_be_bytes();
+        [a, b, c, d, e]
+    }
 
-        phy.write(&header.to_be_bytes()[..5]).await?;
-        phy.write(self.payload).await?;
-        phy.flush().await
+    /// Payload of the ack of the packet in the rx buffer of `phy`, see [`LinkPacket::ack_payload`].
+    ///
+    /// Only meaningful after a successful `read()`, it is all zeroes if the rx buffer is too small.
+    pub fn received_ack_payload<PHY: PhysicalLayer>(phy: &PHY) -> [u8; LINK_ACK_PAYLOAD_LEN] {
+        phy.rx_buffer()
+            .first_chunk()
+            .copied()
+            .unwrap_or([0; LINK_ACK_PAYLOAD_LEN])
     }
 
     /// Read the next link packet, ignoring malformed packets.
@@ -98,48 +350,113 @@ impl<'a> LinkPacket<'a> {
     ///
     /// Implentation note: I had to split read() and get_payload() because of the weirdest lifetime errors I've ever seen.
     /// if you have an afternoon and some sanity to spare, I'd be happy to hear how to fix this.
-    pub async fn read<PHY: PhysicalLayer>(
+    pub async fn read<PHY: PhysicalLayer, K: LinkSigKey + ?Sized>(
         mut phy: PHY,
-        sig_key: &[u8],
+        sig_key: &K,
     ) -> Result<(LinkPhase, u8), PHY::Error> {
         loop {
             phy.read().await?;
-            let bytes: &[u8] = phy.rx_buffer();
-            if bytes.len() < 6 {
-                #[cfg(feature = "defmt")]
-                defmt::trace!("link: packet too small: {}", bytes.len());
-                continue;
+            if let Some(res) = Self::parse_header(phy.rx_buffer(), sig_key) {
+                break Ok(res);
             }
+        }
+    }
 
-            let header_meta: u8 = bytes[0];
-            let sig_bits: u64 =
-                u64::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], 0, 0, 0])
-                    << 6;
-            let payload = &bytes[5..];
-
-            // first 34 bits of the signature of the actual payload
-            let actual_sig = Self::sign_payload(payload, sig_key) & 0xffffffffc0000000;
+    /// Same as `read()`, but gives up if no valid packet is received within `timeout`.
+    /// Returns `None` on timeout.
+    pub async fn read_timeout<PHY: PhysicalLayer, K: LinkSigKey + ?Sized>(
+        mut phy: PHY,
+        sig_key: &K,
+        timeout: Duration,
+    ) -> Result<Option<(LinkPhase, u8)>, PHY::Error> {
+        let deadline = Instant::now() + timeout;
 
-            if actual_sig == sig_bits {
-                break Ok((
-                    LinkPhase::from_bits(header_meta >> 6),
-                    (header_meta >> 2) & 0xf,
-                ));
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::from_ticks(0) || !phy.read_timeout(remaining).await? {
+                break Ok(None);
             }
-            // wrong signature
+            if let Some(res) = Self::parse_header(phy.rx_buffer(), sig_key) {
+                break Ok(Some(res));
+            }
+        }
+    }
+
+    /// Checks the header of a received packet, returns `None` for malformed packets.
+    ///
+    /// Valid frames are at least [`LINK_MIN_FRAME_LEN`] bytes long, so that `get_payload()` is never empty.
+    fn parse_header<K: LinkSigKey + ?Sized>(bytes: &[u8], sig_key: &K) -> Option<(LinkPhase, u8)> {
+        if bytes.len() < LINK_MIN_FRAME_LEN {
+            #[cfg(feature = "defmt")]
+            defmt::trace!("link: packet too small: {}", bytes.len());
+            return None;
+        }
+        let (header, payload) = bytes.split_first_chunk::<LINK_HEADER_LEN>()?;
+        let [header_meta, .., declared_len] = *header;
+
+        if declared_len as usize != bytes.len() {
+            #[cfg(feature = "defmt")]
+            defmt::trace!(
+                "link: length mismatch: declared {}, received {}",
+                declared_len,
+                bytes.len()
+            );
+            return None;
+        }
+        let Some(phase) = LinkPhase::from_bits(header_meta >> 6) else {
+            #[cfg(feature = "defmt")]
+            defmt::trace!("link: reserved action bits");
+            return None;
+        };
 
+        // first 34 bits of the signature of the actual payload
+        let actual_sig = Self::sign_payload(payload, sig_key.key(phase)) & 0xffffffffc0000000;
+        let sig_bits = Self::header_sig_bits(header);
+
+        if actual_sig != sig_bits {
             #[cfg(feature = "defmt")]
             defmt::trace!(
                 "link: signature mismatch: expected {=u64:x}, got {=u64:x}",
                 actual_sig,
                 sig_bits
             );
+            return None;
         }
+
+        Some((phase, (header_meta >> 2) & 0xf))
+    }
+
+    /// Decodes the header of a received frame without rejecting it, for debugging purposes.
+    ///
+    /// Unlike `read()`, frames with a bad signature or length are reported instead of being skipped.
+    /// Returns `None` if `bytes` is too small to contain a header.
+    /// Frames with reserved action bits are checked against the key of handshakes.
+    pub fn inspect<K: LinkSigKey + ?Sized>(bytes: &[u8], sig_key: &K) -> Option<LinkFrameInfo> {
+        let (header, payload) = bytes.split_first_chunk::<LINK_HEADER_LEN>()?;
+        let [header_meta, .., declared_len] = *header;
+        let phase = LinkPhase::from_bits(header_meta >> 6);
+        let key = sig_key.key(phase.unwrap_or(LinkPhase::Handshake));
+        let actual_sig = Self::sign_payload(payload, key) & 0xffffffffc0000000;
+
+        Some(LinkFrameInfo {
+            phase,
+            id: (header_meta >> 2) & 0xf,
+            declared_len,
+            signature_valid: actual_sig == Self::header_sig_bits(header),
+        })
     }
 
     /// Ugly hack to get around lifetime issues. See the comment in `read()`.
+    ///
+    /// Empty if the rx buffer doesn't hold a valid frame.
     pub fn get_payload<PHY: PhysicalLayer>(phy: &'a PHY) -> &'a [u8] {
-        &phy.rx_buffer()[5..]
+        phy.rx_buffer().get(LINK_HEADER_LEN..).unwrap_or_default()
+    }
+
+    /// The 34 bits of signature of `header`, aligned like the ones of [`LinkPacket::sign_payload`].
+    fn header_sig_bits(header: &[u8; LINK_HEADER_LEN]) -> u64 {
+        let [a, b, c, d, e, _] = *header;
+        u64::from_be_bytes([a, b, c, d, e, 0, 0, 0]) << 6
     }
 
     fn sign_payload(payload: &[u8], sig_key: &[u8]) -> u64 {
@@ -164,7 +481,7 @@ impl<'a> LinkPacket<'a> {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::{phy::PhysicalLayer, test::RunBlockingExt};
+    use crate::{phy::PhysicalLayer, test_util::RunBlockingExt};
     use core::{
         error::Error,
         fmt::{Debug, Display, Formatter},
@@ -176,6 +493,8 @@ mod test {
     struct TestingPhy {
         read_bufs: &'static [&'static [u8]],
         current_read_buf: AtomicUsize,
+        /// Never complete reads once `read_bufs` is exhausted, instead of failing
+        stall_when_empty: bool,
         buf: Vec<u8>,
         sent: Vec<u8>,
     }
@@ -203,12 +522,17 @@ mod test {
                     self.current_read_buf.fetch_add(1, Ordering::Relaxed);
                     Ok(())
                 }
+                None if self.stall_when_empty => core::future::pending().await,
                 None => Err(TestingError),
             }
         }
 
         fn rx_buffer(&self) -> &[u8] {
-            &self.read_bufs[self.current_read_buf.load(Ordering::Relaxed) - 1]
+            self.read_bufs[self.current_read_buf.load(Ordering::Relaxed) - 1]
+        }
+
+        fn max_payload_len(&self) -> usize {
+            u8::MAX.into()
         }
 
         async fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
@@ -240,10 +564,10 @@ mod test {
         assert_eq!(packet.write(&mut phy, secret_key).run_blocking(), Ok(()));
 
         let encoded: &[u8] = &phy.sent;
-        assert_eq!(encoded.len(), 5 + payload.len());
+        assert_eq!(encoded.len(), LINK_HEADER_LEN + payload.len());
 
         // action + id
-        assert_eq!(encoded[0] & 0b11111100, 0b10_0101_00);
+        assert_eq!(encoded[0] & 0b11111100, 0b1001_0100);
 
         let actual_sig: u64 = u64::from_be_bytes([
             encoded[0], encoded[1], encoded[2], encoded[3], encoded[4], 0, 0, 0,
@@ -252,14 +576,59 @@ mod test {
         // signature
         assert_eq!(actual_sig, signature);
 
+        // total length
+        assert_eq!(encoded[5] as usize, encoded.len());
+
         // payload
-        assert_eq!(&encoded[5..], payload.as_ref());
+        assert_eq!(&encoded[LINK_HEADER_LEN..], payload.as_ref());
 
         println!("{:x?}", actual_sig);
     }
 
-    const LINK_PACKET_VALID: [u8; 24] = hex!("961b1998ae7468697320697320746865207061796c6f6164");
-    const LINK_PACKET_BAD_SIG: [u8; 24] = hex!("932b1998ae7468697320697320746865207061796c6f6164");
+    #[test]
+    fn test_link_packet_ack() {
+        let mut phy = TestingPhy::default();
+        let secret_key = b"secret key";
+
+        let packet = LinkPacket {
+            phase: LinkPhase::Data,
+            id: 3,
+            payload: b"some data",
+        };
+        packet.write(&mut phy, secret_key).run_blocking().unwrap();
+        assert_eq!(
+            packet.ack_payload(secret_key),
+            phy.sent[..LINK_ACK_PAYLOAD_LEN]
+        );
+
+        let ack_payload = packet.ack_payload(secret_key);
+        phy.sent.clear();
+        let ack = LinkPacket {
+            phase: LinkPhase::Ack,
+            id: 3,
+            payload: &ack_payload,
+        };
+        ack.write(&mut phy, secret_key).run_blocking().unwrap();
+        assert_eq!(phy.sent[0] >> 6, 0b01);
+        assert_eq!(phy.sent.len(), LINK_HEADER_LEN + LINK_ACK_PAYLOAD_LEN);
+
+        let frame = LinkPacket::inspect(&phy.sent, secret_key).unwrap();
+        assert_eq!(frame.phase, Some(LinkPhase::Ack));
+        assert_eq!(frame.id, 3);
+        assert!(frame.signature_valid);
+        assert_eq!(&phy.sent[LINK_HEADER_LEN..], ack_payload);
+    }
+
+    const LINK_PACKET_VALID: [u8; 25] = hex!("961b1998ae197468697320697320746865207061796c6f6164");
+    const LINK_PACKET_BAD_SIG: [u8; 25] =
+        hex!("932b1998ae197468697320697320746865207061796c6f6164");
+    const LINK_PACKET_LENGTH_TOO_BIG: [u8; 25] =
+        hex!("961b1998ae1a7468697320697320746865207061796c6f6164");
+    const LINK_PACKET_LENGTH_TOO_SMALL: [u8; 25] =
+        hex!("961b1998ae187468697320697320746865207061796c6f6164");
+    /// Valid packet with one extra byte at the end, as if the frame length was corrupted
+    const LINK_PACKET_TRAILING_BYTE: [u8; 26] =
+        hex!("961b1998ae197468697320697320746865207061796c6f616400");
 
     #[test]
     fn test_link_packet_decoding_bad_packets() {
@@ -272,6 +641,32 @@ mod test {
             .is_err());
     }
 
+    #[test]
+    fn test_link_packet_decoding_length_mismatch() {
+        let mut phy = TestingPhy::default();
+        let secret_key = b"secret key";
+
+        phy.read_bufs = &[
+            &LINK_PACKET_LENGTH_TOO_BIG,
+            &LINK_PACKET_LENGTH_TOO_SMALL,
+            &LINK_PACKET_TRAILING_BYTE,
+        ];
+        assert!(LinkPacket::read(&mut phy, secret_key.as_ref())
+            .run_blocking()
+            .is_err());
+    }
+
+    #[test]
+    fn test_link_packet_decoding_header_only() {
+        let mut phy = TestingPhy::default();
+        let secret_key = b"secret key";
+
+        phy.read_bufs = &[&hex!("961b1998ae06")];
+        assert!(LinkPacket::read(&mut phy, secret_key.as_ref())
+            .run_blocking()
+            .is_err());
+    }
+
     #[test]
     fn test_link_packet_decoding_invalid_key() {
         let mut phy = TestingPhy::default();
@@ -288,7 +683,13 @@ mod test {
         let mut phy = TestingPhy::default();
         let secret_key = b"secret key";
 
-        phy.read_bufs = &[b"", b"short", &LINK_PACKET_BAD_SIG, &LINK_PACKET_VALID];
+        phy.read_bufs = &[
+            b"",
+            b"short",
+            &LINK_PACKET_BAD_SIG,
+            &LINK_PACKET_LENGTH_TOO_BIG,
+            &LINK_PACKET_VALID,
+        ];
 
         let Ok(packet) = LinkPacket::read(&mut phy, secret_key.as_ref()).run_blocking() else {
             panic!("Failed to read valid packet");
@@ -298,4 +699,483 @@ mod test {
         assert_eq!(packet.1, 5);
         assert_eq!(LinkPacket::get_payload(&phy), b"this is the payload");
     }
+
+    #[test]
+    fn test_link_packet_inspect() {
+        let secret_key = b"secret key";
+
+        assert_eq!(LinkPacket::inspect(b"short", secret_key), None);
+        assert_eq!(
+            LinkPacket::inspect(&LINK_PACKET_VALID, secret_key),
+            Some(LinkFrameInfo {
+                phase: Some(LinkPhase::Handshake),
+                id: 5,
+                declared_len: 25,
+                signature_valid: true,
+            })
+        );
+        assert_eq!(
+            LinkPacket::inspect(&LINK_PACKET_LENGTH_TOO_BIG, secret_key),
+            Some(LinkFrameInfo {
+                phase: Some(LinkPhase::Handshake),
+                id: 5,
+                declared_len: 26,
+                signature_valid: true,
+            })
+        );
+
+        let bad_sig = LinkPacket::inspect(&LINK_PACKET_BAD_SIG, secret_key).unwrap();
+        assert!(!bad_sig.signature_valid);
+        let bad_key = LinkPacket::inspect(&LINK_PACKET_VALID, b"not the secret key").unwrap();
+        assert!(!bad_key.signature_valid);
+    }
+
+    #[test]
+    fn test_link_sniffer() {
+        let shared = b"SECRET";
+        let fingerprint = [1, 2, 3, 4, 5, 6];
+        let nonce = [7; LINK_NONCE_LEN];
+        let frame = |phase, id, payload: &[u8], keys: &LinkKeys| {
+            let mut phy = TestingPhy::default();
+            LinkPacket { phase, id, payload }
+                .write(&mut phy, keys)
+                .run_blocking()
+                .unwrap();
+            phy.sent
+        };
+        let mut session = LinkKeys::new(shared);
+        session.start_session(&nonce);
+        let reply = handshake_reply_payload(&fingerprint, &nonce);
+        let handshake = frame(LinkPhase::Handshake, 3, &reply, &LinkKeys::new(shared));
+        let data = frame(LinkPhase::Data, 3, b"sensor values", &session);
+
+        // the session of a sensor board is unknown until its handshake reply is seen
+        let mut sniffer = LinkSniffer::new(shared);
+        assert!(!sniffer.inspect(&data).unwrap().signature_valid);
+        assert!(sniffer.inspect(&handshake).unwrap().signature_valid);
+        assert_eq!(
+            sniffer.inspect(&data),
+            Some(LinkFrameInfo {
+                phase: Some(LinkPhase::Data),
+                id: 3,
+                declared_len: data.len() as u8,
+                signature_valid: true,
+            })
+        );
+        let ack = frame(LinkPhase::Ack, 3, &[0; LINK_ACK_PAYLOAD_LEN], &session);
+        assert!(sniffer.inspect(&ack).unwrap().signature_valid);
+
+        // other sensor boards keep their own session
+        let other = frame(LinkPhase::Data, 4, b"sensor values", &session);
+        assert!(!sniffer.inspect(&other).unwrap().signature_valid);
+        let request = frame(
+            LinkPhase::Handshake,
+            0,
+            &fingerprint,
+            &LinkKeys::new(shared),
+        );
+        assert!(sniffer.inspect(&request).unwrap().signature_valid);
+        assert!(sniffer.inspect(&data).unwrap().signature_valid);
+        assert_eq!(sniffer.inspect(b"short"), None);
+    }
+
+    #[test]
+    fn test_link_packet_header_boundaries() {
+        let secret_key = b"secret key";
+        let mut phy = TestingPhy::default();
+
+        // a header whose signature is valid for an empty payload
+        LinkPacket {
+            phase: LinkPhase::Data,
+            id: 5,
+            payload: b"",
+        }
+        .write(&mut phy, secret_key)
+        .run_blocking()
+        .unwrap();
+        let header_only = phy.sent.clone();
+        assert_eq!(header_only.len(), LINK_HEADER_LEN);
+        assert_eq!(header_only[5] as usize, LINK_HEADER_LEN);
+
+        // one byte short of a header, then a header without payload
+        assert_eq!(
+            LinkPacket::parse_header(&header_only[..5], secret_key),
+            None
+        );
+        assert_eq!(LinkPacket::parse_header(&header_only, secret_key), None);
+        assert_eq!(LinkPacket::inspect(&header_only[..5], secret_key), None);
+        assert_eq!(
+            LinkPacket::inspect(&header_only, secret_key),
+            Some(LinkFrameInfo {
+                phase: Some(LinkPhase::Data),
+                id: 5,
+                declared_len: 6,
+                signature_valid: true,
+            })
+        );
+    }
+
+    #[test]
+    fn test_link_packet_reserved_action_bits() {
+        let secret_key = b"secret key";
+
+        let mut frame = LINK_PACKET_VALID;
+        assert!(LinkPacket::parse_header(&frame, secret_key).is_some());
+        // the signature doesn't cover the action bits
+        frame[0] |= 0b1100_0000;
+        assert_eq!(LinkPacket::parse_header(&frame, secret_key), None);
+
+        let info = LinkPacket::inspect(&frame, secret_key).unwrap();
+        assert_eq!(info.phase, None);
+        assert!(info.signature_valid);
+    }
+
+    #[test]
+    fn test_link_keys_session() {
+        let fingerprint = [0x24, 0x6f, 0x28, 0x01, 0x02, 0x03];
+        let nonce = [1, 2, 3, 4, 5, 6, 7, 8];
+        let mut gateway = LinkKeys::new(b"secret key");
+        let mut sensor = LinkKeys::new(b"secret key");
+        assert!(!gateway.has_session());
+
+        // the reply is signed with the shared key, even once the gateway started the session
+        let reply_payload = handshake_reply_payload(&fingerprint, &nonce);
+        gateway.start_session(&nonce);
+        let mut phy = TestingPhy::default();
+        LinkPacket {
+            phase: LinkPhase::Handshake,
+            id: 2,
+            payload: &reply_payload,
+        }
+        .write(&mut phy, &gateway)
+        .run_blocking()
+        .unwrap();
+        let reply = phy.sent.clone();
+        assert_eq!(
+            LinkPacket::parse_header(&reply, &sensor),
+            Some((LinkPhase::Handshake, 2))
+        );
+        let received_nonce = handshake_reply_nonce(&reply[LINK_HEADER_LEN..], &fingerprint);
+        assert_eq!(received_nonce, Some(nonce));
+        sensor.start_session(&nonce);
+
+        phy.sent.clear();
+        let data = LinkPacket {
+            phase: LinkPhase::Data,
+            id: 2,
+            payload: b"some data",
+        };
+        data.write(&mut phy, &sensor).run_blocking().unwrap();
+        assert_eq!(data.ack_payload(&sensor), data.ack_payload(&gateway));
+        assert_ne!(data.ack_payload(&sensor), data.ack_payload(b"secret key"));
+        assert_eq!(
+            LinkPacket::parse_header(&phy.sent, &gateway),
+            Some((LinkPhase::Data, 2))
+        );
+        // the session key is not the shared key
+        assert_eq!(LinkPacket::parse_header(&phy.sent, b"secret key"), None);
+        assert!(
+            LinkPacket::inspect(&phy.sent, &gateway)
+                .unwrap()
+                .signature_valid
+        );
+
+        sensor.end_session();
+        assert!(!sensor.has_session());
+        assert_eq!(LinkPacket::parse_header(&phy.sent, &sensor), None);
+    }
+
+    #[test]
+    fn test_link_keys_replayed_handshake() {
+        let fingerprint = [0x24, 0x6f, 0x28, 0x01, 0x02, 0x03];
+        let mut gateway = LinkKeys::new(b"secret key");
+        let mut sensor = LinkKeys::new(b"secret key");
+
+        // the sensor board accepts a recorded reply, but the gateway moved on to a new nonce
+        gateway.start_session(&[8, 7, 6, 5, 4, 3, 2, 1]);
+        sensor.start_session(&[1, 2, 3, 4, 5, 6, 7, 8]);
+
+        let mut phy = TestingPhy::default();
+        LinkPacket {
+            phase: LinkPhase::Data,
+            id: 2,
+            payload: b"some data",
+        }
+        .write(&mut phy, &sensor)
+        .run_blocking()
+        .unwrap();
+        assert_eq!(LinkPacket::parse_header(&phy.sent, &gateway), None);
+        assert!(
+            !LinkPacket::inspect(&phy.sent, &gateway)
+                .unwrap()
+                .signature_valid
+        );
+
+        // nor can a recorded data frame be replayed into a new session
+        gateway.start_session(&[1, 2, 3, 4, 5, 6, 7, 9]);
+        assert_eq!(LinkPacket::parse_header(&phy.sent, &gateway), None);
+
+        // replies to other boards, or without a nonce, are rejected
+        let reply = handshake_reply_payload(&[0; LINK_FINGERPRINT_LEN], &[1; LINK_NONCE_LEN]);
+        assert_eq!(handshake_reply_nonce(&reply, &fingerprint), None);
+        assert_eq!(handshake_reply_nonce(&fingerprint, &fingerprint), None);
+        let reply = handshake_reply_payload(&fingerprint, &[1; LINK_NONCE_LEN]);
+        assert_eq!(
+            handshake_reply_nonce(&reply[..reply.len() - 1], &fingerprint),
+            None
+        );
+    }
+
+    #[test]
+    fn test_link_loopback_handshake_and_data() {
+        use crate::mock::{phy_pair, MockError};
+        use embassy_futures::join::join;
+
+        let (mut sensor_phy, mut gateway_phy) = phy_pair();
+        let fingerprint = [0x24, 0x6f, 0x28, 0x01, 0x02, 0x03];
+
+        let (sensor_res, gateway_res) = join(
+            async {
+                let mut keys = LinkKeys::new(b"secret key");
+                LinkPacket {
+                    phase: LinkPhase::Handshake,
+                    id: 0,
+                    payload: &fingerprint,
+                }
+                .write(&mut sensor_phy, &keys)
+                .await?;
+
+                let (phase, id) = LinkPacket::read(&mut sensor_phy, &keys).await?;
+                assert_eq!(phase, LinkPhase::Handshake);
+                let nonce =
+                    handshake_reply_nonce(LinkPacket::get_payload(&sensor_phy), &fingerprint)
+                        .expect("handshake reply without a nonce");
+                keys.start_session(&nonce);
+
+                let data = LinkPacket {
+                    phase: LinkPhase::Data,
+                    id,
+                    payload: b"sensor values",
+                };
+                data.write(&mut sensor_phy, &keys).await?;
+                let ack = LinkPacket::read(&mut sensor_phy, &keys).await?;
+                assert_eq!(ack, (LinkPhase::Ack, id));
+                assert_eq!(
+                    LinkPacket::get_payload(&sensor_phy),
+                    data.ack_payload(&keys)
+                );
+
+                let downlink = LinkPacket::read(&mut sensor_phy, &keys).await?;
+                assert_eq!(downlink, (LinkPhase::Data, id));
+                Ok::<_, MockError>((id, LinkPacket::get_payload(&sensor_phy).to_vec()))
+            },
+            async {
+                let mut keys = LinkKeys::new(b"secret key");
+                let handshake = LinkPacket::read(&mut gateway_phy, &keys).await?;
+                assert_eq!(handshake, (LinkPhase::Handshake, 0));
+                let fingerprint: [u8; LINK_FINGERPRINT_LEN] = LinkPacket::get_payload(&gateway_phy)
+                    .try_into()
+                    .expect("handshake without a fingerprint");
+
+                let nonce = [1, 2, 3, 4, 5, 6, 7, 8];
+                keys.start_session(&nonce);
+                LinkPacket {
+                    phase: LinkPhase::Handshake,
+                    id: 3,
+                    payload: &handshake_reply_payload(&fingerprint, &nonce),
+                }
+                .write(&mut gateway_phy, &keys)
+                .await?;
+
+                let data = LinkPacket::read(&mut gateway_phy, &keys).await?;
+                assert_eq!(data, (LinkPhase::Data, 3));
+                let uplink = LinkPacket::get_payload(&gateway_phy).to_vec();
+                LinkPacket {
+                    phase: LinkPhase::Ack,
+                    id: 3,
+                    payload: &LinkPacket::received_ack_payload(&gateway_phy),
+                }
+                .write(&mut gateway_phy, &keys)
+                .await?;
+
+                LinkPacket {
+                    phase: LinkPhase::Data,
+                    id: 3,
+                    payload: b"downlink command",
+                }
+                .write(&mut gateway_phy, &keys)
+                .await?;
+                Ok::<_, MockError>((fingerprint, uplink))
+            },
+        )
+        .run_blocking();
+
+        assert_eq!(sensor_res, Ok((3, b"downlink command".to_vec())));
+        assert_eq!(gateway_res, Ok((fingerprint, b"sensor values".to_vec())));
+    }
+
+    /// Writes `data` through `tx_buf`, returns the frames sent.
+    fn write_buffered<const N: usize>(
+        tx_buf: &mut LinkTxBuffer<N>,
+        max_len: usize,
+        data: &[u8],
+    ) -> Vec<Vec<u8>> {
+        let mut frames = Vec::new();
+        tx_buf
+            .write(data, max_len, async |frame| {
+                frames.push(frame.to_vec());
+                Ok::<_, TestingError>(())
+            })
+            .run_blocking()
+            .unwrap();
+        frames
+    }
+
+    #[test]
+    fn test_link_tx_buffer() {
+        let data: Vec<u8> = (0..200).collect();
+        let mut tx_buf = LinkTxBuffer::<64>::new();
+
+        let frames = write_buffered(&mut tx_buf, 64, &data);
+        assert_eq!(frames, [&data[..64], &data[64..128], &data[128..192]]);
+        // the rest waits for the next write or flush
+        assert_eq!(tx_buf.as_slice(), &data[192..]);
+
+        // the next write completes the pending frame first
+        let frames = write_buffered(&mut tx_buf, 64, &data[..60]);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0][..8], data[192..]);
+        assert_eq!(frames[0][8..], data[..56]);
+        assert_eq!(tx_buf.as_slice(), &data[56..60]);
+
+        // a write of exactly one frame is sent right away
+        tx_buf.clear();
+        let frames = write_buffered(&mut tx_buf, 64, &data[..64]);
+        assert_eq!(frames, [&data[..64]]);
+        assert!(tx_buf.is_empty());
+
+        assert!(write_buffered(&mut tx_buf, 64, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_link_tx_buffer_max_len() {
+        let data: Vec<u8> = (0..200).collect();
+
+        // frames of the physical layer smaller than the buffer
+        let mut tx_buf = LinkTxBuffer::<64>::new();
+        let frames = write_buffered(&mut tx_buf, 50, &data);
+        assert_eq!(
+            frames,
+            [&data[..50], &data[50..100], &data[100..150], &data[150..]]
+        );
+
+        // and larger ones, or none at all
+        assert_eq!(tx_buf.push(&data, 255), 64);
+        assert!(tx_buf.is_full(255));
+        assert_eq!(tx_buf.push(&data, 255), 0);
+        tx_buf.clear();
+        assert_eq!(tx_buf.push(&data, 0), 1);
+        assert!(tx_buf.is_full(0));
+    }
+
+    #[test]
+    fn test_link_tx_buffer_frames_to_other_sensor() {
+        use crate::mock::phy_pair;
+
+        let (mut gateway_phy, mut sensor_phy) = phy_pair();
+        let keys = LinkKeys::new(b"secret key");
+        let data: Vec<u8> = (0..200).collect();
+        // addressed to another sensor board than the last one to complete a handshake
+        let dest = 5;
+
+        async {
+            let mut send = async |frame: &[u8]| {
+                LinkPacket {
+                    phase: LinkPhase::Data,
+                    id: dest,
+                    payload: frame,
+                }
+                .write(&mut gateway_phy, &keys)
+                .await
+            };
+            let mut tx_buf = LinkTxBuffer::<64>::new();
+            tx_buf.write(&data, 64, &mut send).await.unwrap();
+            // the rest goes out with the next flush, to the same sensor board
+            send(tx_buf.as_slice()).await.unwrap();
+
+            let mut received = Vec::new();
+            while received.len() < data.len() {
+                let header = LinkPacket::read(&mut sensor_phy, &keys).await.unwrap();
+                assert_eq!(header, (LinkPhase::Data, dest));
+                received.extend_from_slice(LinkPacket::get_payload(&sensor_phy));
+            }
+            assert_eq!(received, data);
+        }
+        .run_blocking();
+    }
+
+    #[test]
+    fn test_link_packet_payload_of_short_buffers() {
+        let mut phy = TestingPhy {
+            read_bufs: &[b"abc"],
+            ..Default::default()
+        };
+        phy.read().run_blocking().unwrap();
+
+        assert_eq!(LinkPacket::get_payload(&phy), b"");
+        assert_eq!(
+            LinkPacket::received_ack_payload(&phy),
+            [0; LINK_ACK_PAYLOAD_LEN]
+        );
+    }
+
+    #[test]
+    fn test_link_packet_read_timeout_valid() {
+        let mut phy = TestingPhy {
+            stall_when_empty: true,
+            ..Default::default()
+        };
+        let secret_key = b"secret key";
+
+        phy.read_bufs = &[b"short", &LINK_PACKET_BAD_SIG, &LINK_PACKET_VALID];
+
+        let res = LinkPacket::read_timeout(&mut phy, secret_key.as_ref(), Duration::from_secs(5))
+            .run_blocking();
+
+        let Ok(Some(packet)) = res else {
+            panic!("Failed to read valid packet");
+        };
+
+        assert!(packet.0 == LinkPhase::Handshake);
+        assert_eq!(packet.1, 5);
+        assert_eq!(LinkPacket::get_payload(&phy), b"this is the payload");
+    }
+
+    #[test]
+    fn test_link_packet_read_timeout_expired() {
+        let mut phy = TestingPhy {
+            stall_when_empty: true,
+            ..Default::default()
+        };
+        let secret_key = b"secret key";
+
+        // only malformed packets, then nothing
+        phy.read_bufs = &[b"short", &LINK_PACKET_BAD_SIG];
+
+        let res =
+            LinkPacket::read_timeout(&mut phy, secret_key.as_ref(), Duration::from_millis(50))
+                .run_blocking();
+        assert!(matches!(res, Ok(None)));
+    }
+
+    #[test]
+    fn test_link_packet_read_timeout_error() {
+        let mut phy = TestingPhy::default();
+        let secret_key = b"secret key";
+
+        phy.read_bufs = &[b"short"];
+        let res = LinkPacket::read_timeout(&mut phy, secret_key.as_ref(), Duration::from_secs(5))
+            .run_blocking();
+        assert!(matches!(res, Err(TestingError)));
+    }
 }
@@ -0,0 +1,288 @@
+//! In-memory physical and link layers, to exercise the protocol on the host.
+//!
+//! Both ends of a pair share two queues of frames (one per direction): a frame is queued when the writer flushes
+//! and dequeued by the reader on the other end.
+//! Reads wait until a frame is available, or fail with [`MockError::Disconnected`] once the other end is dropped.
+
+extern crate alloc;
+
+use crate::{link::v1::LinkLayer, phy::PhysicalLayer};
+use alloc::{collections::VecDeque, rc::Rc, vec::Vec};
+use core::{
+    cell::RefCell,
+    fmt::{Display, Formatter},
+    future::poll_fn,
+    task::{Poll, Waker},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MockError {
+    /// The other end of the pipe was dropped and no frames are left to be read
+    Disconnected,
+}
+
+impl Display for MockError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            MockError::Disconnected => f.write_str("other end disconnected"),
+        }
+    }
+}
+
+impl core::error::Error for MockError {}
+
+/// Frames flowing in one direction.
+#[derive(Default)]
+struct Queue {
+    frames: VecDeque<Vec<u8>>,
+    reader: Option<Waker>,
+}
+
+/// One end of a bidirectional pipe of frames.
+struct Pipe {
+    rx: Rc<RefCell<Queue>>,
+    tx: Rc<RefCell<Queue>>,
+}
+
+impl Pipe {
+    fn pair() -> (Self, Self) {
+        let a_to_b: Rc<RefCell<Queue>> = Rc::default();
+        let b_to_a: Rc<RefCell<Queue>> = Rc::default();
+
+        (
+            Self {
+                rx: b_to_a.clone(),
+                tx: a_to_b.clone(),
+            },
+            Self {
+                rx: a_to_b,
+                tx: b_to_a,
+            },
+        )
+    }
+
+    async fn recv(&self) -> Result<Vec<u8>, MockError> {
+        poll_fn(|cx| {
+            let mut rx = self.rx.borrow_mut();
+
+            if let Some(frame) = rx.frames.pop_front() {
+                Poll::Ready(Ok(frame))
+            } else if Rc::strong_count(&self.rx) == 1 {
+                Poll::Ready(Err(MockError::Disconnected))
+            } else {
+                rx.reader = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    fn send(&self, frame: Vec<u8>) {
+        let mut tx = self.tx.borrow_mut();
+        tx.frames.push_back(frame);
+        if let Some(waker) = tx.reader.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        // wake up the other end so that it notices the disconnection
+        if let Some(waker) = self.tx.borrow_mut().reader.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// In-memory [`PhysicalLayer`], each flush sends one frame to the other end.
+pub struct MockPhy {
+    pipe: Pipe,
+    rx_buf: Vec<u8>,
+    tx_buf: Vec<u8>,
+}
+
+/// Creates two connected physical layers.
+pub fn phy_pair() -> (MockPhy, MockPhy) {
+    let (a, b) = Pipe::pair();
+    let new = |pipe| MockPhy {
+        pipe,
+        rx_buf: Vec::new(),
+        tx_buf: Vec::new(),
+    };
+    (new(a), new(b))
+}
+
+impl PhysicalLayer for MockPhy {
+    type Error = MockError;
+
+    async fn read(&mut self) -> Result<(), Self::Error> {
+        self.rx_buf.clear();
+        self.rx_buf = self.pipe.recv().await?;
+        Ok(())
+    }
+
+    fn rx_buffer(&self) -> &[u8] {
+        &self.rx_buf
+    }
+
+    /// Frames have no size limit, reports the largest one a link packet header can describe.
+    fn max_payload_len(&self) -> usize {
+        u8::MAX.into()
+    }
+
+    async fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.tx_buf.extend_from_slice(data);
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        if !self.tx_buf.is_empty() {
+            self.pipe.send(core::mem::take(&mut self.tx_buf));
+        }
+        Ok(())
+    }
+}
+
+/// In-memory [`LinkLayer`] talking to a single peer.
+///
+/// Data is buffered until `flush()`, which sends it to the other end as one payload.
+/// All received data is reported as coming from `peer`.
+pub struct MockLink<PEER> {
+    pipe: Pipe,
+    peer: PEER,
+    rx_buf: Vec<u8>,
+    rx_pos: usize,
+    tx_buf: Vec<u8>,
+    /// Destination of every flush, in order
+    pub flushed_to: Vec<Option<PEER>>,
+}
+
+/// Creates two connected link layers.
+///
+/// `first_peer` is the ID the first link layer reports for the data it receives, `second_peer` is for the second one.
+pub fn link_pair<A, B>(first_peer: A, second_peer: B) -> (MockLink<A>, MockLink<B>) {
+    let (a, b) = Pipe::pair();
+    (MockLink::new(a, first_peer), MockLink::new(b, second_peer))
+}
+
+impl<PEER> MockLink<PEER> {
+    fn new(pipe: Pipe, peer: PEER) -> Self {
+        Self {
+            pipe,
+            peer,
+            rx_buf: Vec::new(),
+            rx_pos: 0,
+            tx_buf: Vec::new(),
+            flushed_to: Vec::new(),
+        }
+    }
+}
+
+impl<PEER: Copy + Eq + core::hash::Hash> LinkLayer for MockLink<PEER> {
+    type Error = MockError;
+    type PeerId = PEER;
+
+    async fn read(&mut self, buf: &mut [u8]) -> Result<(usize, Self::PeerId), Self::Error> {
+        if self.rx_pos >= self.rx_buf.len() {
+            self.rx_buf = self.pipe.recv().await?;
+            self.rx_pos = 0;
+        }
+
+        let available = &self.rx_buf[self.rx_pos..];
+        let len = buf.len().min(available.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.rx_pos += len;
+        Ok((len, self.peer))
+    }
+
+    async fn write(
+        &mut self,
+        _dest: Option<Self::PeerId>,
+        buf: &[u8],
+    ) -> Result<usize, Self::Error> {
+        self.tx_buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    async fn flush(&mut self, dest: Option<Self::PeerId>) -> Result<(), Self::Error> {
+        self.flushed_to.push(dest);
+        if !self.tx_buf.is_empty() {
+            self.pipe.send(core::mem::take(&mut self.tx_buf));
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.rx_buf.clear();
+        self.rx_pos = 0;
+        self.tx_buf.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        link::v1::{LinkPacket, LinkPhase},
+        test_util::RunBlockingExt,
+    };
+    use embassy_futures::join::join;
+
+    #[test]
+    fn test_mock_phy_link_packet() {
+        let (mut a, mut b) = phy_pair();
+        let secret_key = b"secret key";
+
+        let packet = LinkPacket {
+            phase: LinkPhase::Data,
+            id: 3,
+            payload: b"hello",
+        };
+        assert_eq!(packet.write(&mut a, secret_key).run_blocking(), Ok(()));
+
+        let Ok((phase, id)) = LinkPacket::read(&mut b, secret_key).run_blocking() else {
+            panic!("Failed to read packet");
+        };
+        assert!(phase == LinkPhase::Data);
+        assert_eq!(id, 3);
+        assert_eq!(LinkPacket::get_payload(&b), b"hello");
+    }
+
+    #[test]
+    fn test_mock_link_partial_reads() {
+        let (mut a, mut b) = link_pair('b', 'a');
+
+        let (sent, received) = join(
+            async {
+                a.write(Some('b'), b"abc").await?;
+                a.write(Some('b'), b"de").await?;
+                a.flush(Some('b')).await
+            },
+            async {
+                let mut buf = [0u8; 3];
+                let first = b.read(&mut buf).await?;
+                let mut rest = [0u8; 4];
+                let second = b.read(&mut rest).await?;
+                Ok::<_, MockError>((first, buf, second, rest))
+            },
+        )
+        .run_blocking();
+
+        assert_eq!(sent, Ok(()));
+        assert_eq!(received, Ok(((3, 'a'), *b"abc", (2, 'a'), *b"de\0\0")));
+        assert_eq!(a.flushed_to, [Some('b')]);
+    }
+
+    #[test]
+    fn test_mock_link_disconnected() {
+        let (a, mut b) = link_pair((), ());
+        drop(a);
+
+        let mut buf = [0u8; 1];
+        assert_eq!(
+            b.read(&mut buf).run_blocking(),
+            Err(MockError::Disconnected)
+        );
+    }
+}
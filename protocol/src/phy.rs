@@ -1,4 +1,6 @@
 use core::future::Future;
+use embassy_futures::select::{select, Either};
+use embassy_time::{Duration, Timer};
 
 /// Physical Layer abstraction: provides raw read/write access to radio hardware
 pub trait PhysicalLayer {
@@ -7,9 +9,22 @@ pub trait PhysicalLayer {
     /// Read the next full physical packet.
     async fn read(&mut self) -> Result<(), Self::Error>;
 
+    /// Read the next full physical packet, giving up after `timeout`.
+    ///
+    /// Returns whether a packet was received, the content of the rx buffer is unspecified otherwise.
+    async fn read_timeout(&mut self, timeout: Duration) -> Result<bool, Self::Error> {
+        match select(self.read(), Timer::after(timeout)).await {
+            Either::First(res) => res.map(|()| true),
+            Either::Second(()) => Ok(false),
+        }
+    }
+
     /// Returns the buffer containing the received data.
     fn rx_buffer(&self) -> &[u8];
 
+    /// Largest physical packet that can be sent or received, in bytes.
+    fn max_payload_len(&self) -> usize;
+
     /// Appends `data` to the buffer for sending.
     async fn write(&mut self, data: &[u8]) -> Result<(), Self::Error>;
 
@@ -24,10 +39,21 @@ impl<PHY: PhysicalLayer> PhysicalLayer for &mut PHY {
         (*self).read()
     }
 
+    fn read_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> impl Future<Output = Result<bool, Self::Error>> {
+        (*self).read_timeout(timeout)
+    }
+
     fn rx_buffer(&self) -> &[u8] {
         (*self as &PHY).rx_buffer()
     }
 
+    fn max_payload_len(&self) -> usize {
+        (*self as &PHY).max_payload_len()
+    }
+
     fn write(&mut self, buf: &[u8]) -> impl Future<Output = Result<(), Self::Error>> {
         (*self).write(buf)
     }
@@ -0,0 +1,208 @@
+//! Helpers for host tests of the protocol and of the crates using it, enabled by the `test-util` feature.
+//!
+//! Timers must be driven by the mock driver of `embassy_time`, which is enabled along with this module.
+//! Its clock is shared by the whole test process, so futures run by concurrent tests take turns advancing it.
+
+use crate::codec::{AsyncDecoder, AsyncEncode, AsyncEncoder};
+use core::future::Future;
+use embassy_time::{Duration, MockDriver};
+use std::{
+    cell::Cell,
+    error::Error,
+    pin::pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    task::{Context, Poll, Wake, Waker},
+};
+
+/// Amount of time the mock clock is advanced by whenever the future under test is stuck.
+pub const TIME_STEP: Duration = Duration::from_millis(1);
+/// Amount of time after which a future that is never woken up is considered stalled.
+pub const STALL_TIMEOUT: Duration = Duration::from_secs(3600);
+
+/// Held by the thread running a future, so that other tests don't advance the mock clock meanwhile.
+static CLOCK: Mutex<()> = Mutex::new(());
+
+thread_local! {
+    /// Whether this thread holds [`CLOCK`], so that nested calls of `run_blocking()` don't wait for themselves.
+    static HOLDS_CLOCK: Cell<bool> = const { Cell::new(false) };
+}
+
+struct ClockGuard {
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl ClockGuard {
+    /// Waits for the other tests to be done with the clock, `None` if this thread already holds it.
+    fn lock() -> Option<Self> {
+        if HOLDS_CLOCK.get() {
+            return None;
+        }
+        // a test that panicked while holding the clock left it in a valid state regardless
+        let guard = CLOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        HOLDS_CLOCK.set(true);
+        Some(Self { _guard: guard })
+    }
+}
+
+impl Drop for ClockGuard {
+    fn drop(&mut self) {
+        HOLDS_CLOCK.set(false);
+    }
+}
+
+/// Remembers whether the future under test was woken up since its last poll.
+struct Flag(AtomicBool);
+
+impl Wake for Flag {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::Release);
+    }
+}
+
+pub trait RunBlockingExt: Future {
+    /// Evaluates this future on the current thread.
+    ///
+    /// Whenever the future is pending without having been woken up, the mock clock of `embassy_time`
+    /// is advanced by [`TIME_STEP`], so that timers expire deterministically and without actually waiting.
+    /// Futures run from other threads wait for this one to complete, instead of advancing the clock under its feet.
+    ///
+    /// # Panics
+    ///
+    /// If the future is not woken up for [`STALL_TIMEOUT`] of mock time, e.g. because of a deadlock.
+    fn run_blocking(self) -> Self::Output
+    where
+        Self: Sized,
+    {
+        let _clock = ClockGuard::lock();
+        let flag = Arc::new(Flag(AtomicBool::new(true)));
+        let waker = Waker::from(flag.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut this = pin!(self);
+        let mut stalled_for = Duration::from_ticks(0);
+
+        loop {
+            if flag.0.swap(false, Ordering::Acquire) {
+                stalled_for = Duration::from_ticks(0);
+                if let Poll::Ready(res) = this.as_mut().poll(&mut cx) {
+                    break res;
+                }
+            } else {
+                assert!(stalled_for < STALL_TIMEOUT, "future stalled");
+                MockDriver::get().advance(TIME_STEP);
+                stalled_for += TIME_STEP;
+            }
+        }
+    }
+}
+
+impl<F: Future> RunBlockingExt for F {}
+
+/// Encoder and decoder over an in-memory buffer: encoded bytes are appended to `buf`, decoded ones are taken from its
+/// beginning.
+#[derive(Default)]
+pub struct AllocatingTestCodec {
+    pub buf: Vec<u8>,
+    pub offset: usize,
+    /// Returned by [`AsyncDecoder::retains_unknown_values`]
+    pub retain_unknown_values: bool,
+}
+
+impl AsyncEncoder for AllocatingTestCodec {
+    type Error = std::collections::TryReserveError;
+
+    async fn emit_bytes(&mut self, buf: &[u8]) -> Result<(), Self::Error> {
+        self.buf.try_reserve(buf.len())?;
+        self.buf.extend_from_slice(buf);
+        Ok(())
+    }
+}
+
+impl AsyncDecoder for AllocatingTestCodec {
+    type Error = Arc<dyn std::error::Error>;
+
+    async fn read_bytes(&mut self, buf: &mut [u8]) -> Result<(), Self::Error> {
+        if buf.len() > self.buf.len() {
+            Err(Box::<dyn Error>::from(format!(
+                "tried to read {} bytes from buffer of {} bytes",
+                buf.len(),
+                self.buf.len()
+            ))
+            .into())
+        } else {
+            let mut new_buf = self.buf.split_off(buf.len());
+            std::mem::swap(&mut new_buf, &mut self.buf);
+            buf.copy_from_slice(&new_buf);
+            self.offset = self.offset.wrapping_add(buf.len());
+            Ok(())
+        }
+    }
+
+    fn current_offset(&self) -> usize {
+        self.offset
+    }
+
+    fn decoding_error(&self) -> Self::Error {
+        Box::<dyn Error>::from("decoding error").into()
+    }
+
+    fn retains_unknown_values(&self) -> bool {
+        self.retain_unknown_values
+    }
+}
+
+impl AllocatingTestCodec {
+    /// Encodes `value` alone, returning its bytes. They are also left in `buf`, to be decoded next.
+    pub fn emit_alloc<T: AsyncEncode<Self>>(
+        &mut self,
+        value: T,
+    ) -> Result<Box<[u8]>, <Self as AsyncEncoder>::Error> {
+        self.buf.clear();
+        self.emit(value).run_blocking()?;
+        Ok(self.buf.clone().into_boxed_slice())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_run_blocking_advances_timers() {
+        use embassy_time::{Instant, Timer};
+
+        let start = Instant::now();
+        Timer::after(Duration::from_secs(60)).run_blocking();
+        assert!(start.elapsed() >= Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_run_blocking_owns_the_clock() {
+        use embassy_time::{Instant, Timer};
+
+        let elapsed = || {
+            async {
+                let start = Instant::now();
+                Timer::after(Duration::from_secs(10)).await;
+                start.elapsed()
+            }
+            .run_blocking()
+        };
+        let threads: Vec<_> = (0..4).map(|_| std::thread::spawn(elapsed)).collect();
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), Duration::from_secs(10));
+        }
+
+        // nested calls don't wait for themselves
+        let nested = async { Timer::after(Duration::from_secs(1)).run_blocking() };
+        nested.run_blocking();
+    }
+
+    #[test]
+    #[should_panic(expected = "future stalled")]
+    fn test_run_blocking_detects_stalls() {
+        core::future::pending::<()>().run_blocking();
+    }
+}
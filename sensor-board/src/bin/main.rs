@@ -2,17 +2,31 @@
 #![no_main]
 
 use bmp280_ehal::BMP280;
-use defmt::info;
+use defmt::{error, info};
 use dust_sensor_gp2y1014au::{Gp2y1014au, Gp2y1014auHardware};
 use embassy_executor::Spawner;
-use esp_hal::gpio::GpioPin;
-use esp_hal::peripherals::{ADC2, I2C0};
 use esp_hal::{clock::CpuClock, i2c::master::I2c, time::Rate, timer::timg::TimerGroup};
 use esp_println as _;
-use heapless::spsc::{Consumer, Producer, Queue};
-use protocol::app::v1::SensorValue;
-use sensor_board::comm::app::{VALUES_MEASURE_INTERVAL, VALUES_QUEUE_SIZE};
-use sensor_board::lora::{LoraController, LoraHardware};
+use heapless::spsc::{Consumer, Producer};
+use protocol::app::v1::{session::AppLayerTimings, SensorValue};
+use sensor_board::bmp::{self, Bmp280Preset};
+use sensor_board::comm::app::{measure_interval, try_take_values_queue, DEFAULT_VALUES_QUEUE_SIZE};
+#[cfg(not(feature = "uart-phy"))]
+use sensor_board::lora::{LoraConfig, LoraController, LoraHardware};
+use sensor_board::self_test::self_test;
+use sensor_board::sensor::{AnySensor, Sensor};
+
+/// Physical layer of the communication task.
+#[cfg(not(feature = "uart-phy"))]
+type Phy = LoraController;
+/// Physical layer of the communication task, a serial link instead of the radio.
+#[cfg(feature = "uart-phy")]
+type Phy = util::uart_phy::UartPhy<esp_hal::uart::Uart<'static, esp_hal::Async>>;
+
+/// Oversampling and filter settings of the pressure and temperature sensor.
+const BMP280_PRESET: Bmp280Preset = Bmp280Preset::Weather;
+/// Maximum number of sensors measured by the `take_measurements` task.
+const SENSOR_COUNT: usize = 2;
 
 #[esp_hal_embassy::main]
 async fn main(spawner: Spawner) {
@@ -20,108 +34,136 @@ async fn main(spawner: Spawner) {
     let peripherals = esp_hal::init(esp_hal::Config::default().with_cpu_clock(CpuClock::max()));
     let timer_group = TimerGroup::new(peripherals.TIMG0);
     esp_hal_embassy::init(timer_group.timer1);
+    let pins = sensor_board::board_pins!(peripherals);
 
-    let lora = LoraController::new(LoraHardware {
-        spi: peripherals.SPI2,
-        spi_nss: peripherals.GPIO18,
-        spi_scl: peripherals.GPIO5,
-        spi_mosi: peripherals.GPIO27,
-        spi_miso: peripherals.GPIO19,
-        reset: peripherals.GPIO23,
-        dio1: peripherals.GPIO26,
-    })
+    #[cfg(not(feature = "uart-phy"))]
+    let phy: Option<Phy> = match LoraController::new(
+        LoraHardware {
+            spi: peripherals.SPI2,
+            pins: pins.lora,
+        },
+        LoraConfig::default(),
+    )
     .await
-    .unwrap();
-
-    let values_queue: &'static mut Queue<SensorValue, VALUES_QUEUE_SIZE> = {
-        static mut Q: Queue<SensorValue, VALUES_QUEUE_SIZE> = Queue::new();
-        // SAFETY:
-        // This looks very janky, but it is the recommended way to create a queue in the `heapless` docs
-        // It is not undefined behavior because this is the *only* possible reference to this global variable.
-        #[allow(static_mut_refs)]
-        unsafe {
-            &mut Q
+    {
+        Ok(lora) => Some(lora),
+        Err(e) => {
+            error!("Failed to initialize LoRa: {}", e);
+            None
         }
     };
+    #[cfg(feature = "uart-phy")]
+    let phy: Option<Phy> =
+        match esp_hal::uart::Uart::new(peripherals.UART1, esp_hal::uart::Config::default()) {
+            Ok(uart) => Some(util::uart_phy::UartPhy::new(
+                uart.with_tx(pins.uart_phy.tx)
+                    .with_rx(pins.uart_phy.rx)
+                    .into_async(),
+            )),
+            Err(e) => {
+                error!("Failed to set up the UART: {}", defmt::Debug2Format(&e));
+                None
+            }
+        };
 
-    let (producer, consumer) = values_queue.split();
-
-    spawner.must_spawn(take_measurements(
-        producer,
-        peripherals.I2C0,
-        peripherals.GPIO22,
-        peripherals.GPIO21,
-        peripherals.ADC2,
-        peripherals.GPIO13,
-        peripherals.GPIO4,
-    ));
-    spawner.must_spawn(communicate(lora, consumer));
-}
-
-#[embassy_executor::task]
-async fn take_measurements(
-    mut producer: Producer<'static, SensorValue, VALUES_QUEUE_SIZE>,
-    i2c: I2C0,
-    scl: GpioPin<22>,
-    sda: GpioPin<21>,
-    adci: ADC2,
-    dust_led: GpioPin<13>,
-    dust_data: GpioPin<4>,
-) -> ! {
     let i2c = I2c::new(
-        i2c,
+        peripherals.I2C0,
         esp_hal::i2c::master::Config::default().with_frequency(Rate::from_hz(500000)),
     )
     .unwrap()
-    .with_scl(scl)
-    .with_sda(sda)
+    .with_scl(pins.i2c_scl)
+    .with_sda(pins.i2c_sda)
     .into_async();
 
-    let mut bmp = BMP280::new(i2c).unwrap();
-
-    info!("ID of BMP chip, {}", bmp.id());
+    // keep going without the pressure and temperature sensor when it is missing
+    let mut bmp = match BMP280::new(i2c) {
+        Ok(mut bmp) => {
+            info!("ID of BMP chip, {}", bmp.id());
+            bmp::configure(&mut bmp, BMP280_PRESET);
+            Some(bmp)
+        }
+        Err(e) => {
+            error!(
+                "Failed to initialize the BMP280: {}",
+                defmt::Debug2Format(&e)
+            );
+            None
+        }
+    };
 
     let mut dust_sensor = Gp2y1014au::new(
         Gp2y1014auHardware {
-            adci,
-            pin_led: dust_led,
-            pin_data: dust_data,
+            adci: peripherals.ADC2,
+            pin_led: pins.dust.led,
+            pin_data: pins.dust.data,
         },
         1024,
     );
 
+    self_test(bmp.as_mut(), &mut dust_sensor, phy.is_some()).await;
+
+    let (producer, consumer) = try_take_values_queue().expect("values queue already taken");
+
+    let mut sensors = heapless::Vec::new();
+    // the capacity fits every sensor of the board
+    _ = sensors.push(AnySensor::Dust(dust_sensor));
+    if let Some(bmp) = bmp {
+        _ = sensors.push(AnySensor::Bmp280(bmp));
+    }
+    spawner.must_spawn(take_measurements(producer, sensors));
+    match phy {
+        Some(phy) => spawner.must_spawn(communicate(phy, consumer)),
+        None => error!("No link to the gateway, measurements will not be sent"),
+    }
+}
+
+#[embassy_executor::task]
+async fn take_measurements(
+    mut producer: Producer<'static, SensorValue, DEFAULT_VALUES_QUEUE_SIZE>,
+    mut sensors: heapless::Vec<AnySensor, SENSOR_COUNT>,
+) -> ! {
     loop {
-        info!("Taking measurements...");
-        match dust_sensor.read().await {
-            Ok(value) => {
-                let density = dust_sensor.convert_analog_to_density(value);
-                info!("Measured dust density: {}mg/m3", density);
-                _ = producer.enqueue(SensorValue::AirQuality(density));
+        measure_and_sleep(&mut producer, &mut sensors).await;
+    }
+}
+
+/// Takes one round of measurements, then sleeps until the next measurement window.
+///
+/// The sensors are left unpowered between rounds:
+/// - the dust sensor LED is only switched on for the duration of a reading
+/// - the BMP280 is used in one-shot (forced) mode, it goes back to sleep after each conversion
+///
+/// The radio is handled by the communication task, which puts it to sleep between two sends.
+/// The SoC itself is not put in light sleep: the timer below lets the executor idle the CPU (`waiti`)
+/// while keeping the communication task able to run.
+async fn measure_and_sleep(
+    producer: &mut Producer<'static, SensorValue, DEFAULT_VALUES_QUEUE_SIZE>,
+    sensors: &mut [AnySensor],
+) {
+    info!("Taking measurements...");
+    for sensor in sensors {
+        match sensor.measure().await {
+            Ok(values) => {
+                for value in values {
+                    _ = producer.enqueue(value);
+                }
             }
             Err(e) => {
-                info!("Error reading sensor: {:?}", e);
+                info!("Error reading sensor {}: {}", sensor.name(), e);
             }
         }
-        // Read BMP280 sensor
-        let pressure = bmp.pressure_one_shot() as f32;
-        let temperature = bmp.temp_one_shot() as f32;
-        info!("Measured pressure: {}Pa", pressure);
-        info!("Measured temperature: {}°C", temperature);
-        _ = producer.enqueue(SensorValue::Pressure(pressure));
-        _ = producer.enqueue(SensorValue::Temperature(temperature));
-
-        // sleep
-        embassy_time::Timer::after(embassy_time::Duration::from_secs(VALUES_MEASURE_INTERVAL))
-            .await;
     }
+
+    // sleep, the gateway may have changed the interval since the last measurement
+    embassy_time::Timer::after(measure_interval()).await;
 }
 
 #[embassy_executor::task]
 async fn communicate(
-    lora: LoraController,
-    consumer: Consumer<'static, SensorValue, VALUES_QUEUE_SIZE>,
+    phy: Phy,
+    consumer: Consumer<'static, SensorValue, DEFAULT_VALUES_QUEUE_SIZE>,
 ) -> ! {
-    sensor_board::comm::app::run(lora, consumer).await;
+    sensor_board::comm::app::run(phy, consumer, AppLayerTimings::default()).await;
 }
 
 #[panic_handler]
@@ -0,0 +1,54 @@
+//! Oversampling and IIR filter settings of the BMP280, from the recommendations of its datasheet.
+
+use bmp280_ehal::{Config, Control, Filter, Oversampling, PowerMode, Standby};
+use defmt::{info, Debug2Format, Format};
+
+use crate::sensor::Bmp280;
+
+/// Named combinations of oversampling and filter settings.
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+pub enum Bmp280Preset {
+    /// Highest resolution and strong filtering, to smooth out drafts and doors slamming
+    Indoor,
+    /// Standard resolution without filtering, the weather changes slowly compared to the measurement interval
+    Weather,
+    /// Single samples without filtering, shortest conversions
+    LowPower,
+}
+
+impl Bmp280Preset {
+    fn settings(self) -> (Oversampling, Oversampling, Filter) {
+        // (pressure, temperature, filter)
+        match self {
+            Bmp280Preset::Indoor => (Oversampling::x16, Oversampling::x2, Filter::c16),
+            Bmp280Preset::Weather => (Oversampling::x4, Oversampling::x1, Filter::off),
+            Bmp280Preset::LowPower => (Oversampling::x1, Oversampling::x1, Filter::off),
+        }
+    }
+}
+
+/// Applies `preset`, keeping the sensor asleep between one-shot (forced) measurements.
+///
+/// The IIR filter runs across successive measurements, so it also smooths one-shot readings.
+pub fn configure(bmp: &mut Bmp280, preset: Bmp280Preset) {
+    let (osrs_p, osrs_t, filter) = preset.settings();
+
+    bmp.set_control(Control {
+        osrs_t,
+        osrs_p,
+        mode: PowerMode::Sleep,
+    });
+    bmp.set_config(Config {
+        // only used in normal mode
+        t_sb: Standby::ms1000,
+        filter,
+    });
+
+    info!(
+        "BMP280 preset: {}, pressure oversampling: {}, temperature oversampling: {}, filter: {}",
+        preset,
+        Debug2Format(&osrs_p),
+        Debug2Format(&osrs_t),
+        Debug2Format(&filter)
+    );
+}
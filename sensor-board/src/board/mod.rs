@@ -0,0 +1,47 @@
+//! Pin maps of the supported boards, selected by the `board-*` features.
+//!
+//! Each board gets its own module, defining the types of its pins and a `board_pins!` macro that moves them out of
+//! the peripherals. Supporting another board only takes a new module and feature.
+
+#[cfg(feature = "board-tbeam")]
+mod tbeam;
+#[cfg(feature = "board-tbeam")]
+pub use tbeam::*;
+
+#[cfg(not(feature = "board-tbeam"))]
+compile_error!("no board selected, enable one of the `board-*` features");
+
+/// Pins used by the sensor board, taken from the peripherals with [`board_pins!`](crate::board_pins).
+pub struct BoardPins {
+    #[cfg(feature = "lora")]
+    pub lora: LoraPins,
+    #[cfg(feature = "uart-phy")]
+    pub uart_phy: UartPhyPins,
+    /// I2C bus of the BMP280
+    pub i2c_scl: I2cScl,
+    pub i2c_sda: I2cSda,
+    pub dust: DustSensorPins,
+}
+
+#[cfg(feature = "lora")]
+pub struct LoraPins {
+    pub spi_nss: LoraSpiNss,
+    pub spi_scl: LoraSpiScl,
+    pub spi_mosi: LoraSpiMosi,
+    pub spi_miso: LoraSpiMiso,
+    pub reset: LoraReset,
+    pub dio1: LoraDio1,
+}
+
+/// Serial link replacing the radio, see [`util::uart_phy`]
+#[cfg(feature = "uart-phy")]
+pub struct UartPhyPins {
+    pub tx: UartPhyTx,
+    pub rx: UartPhyRx,
+}
+
+pub struct DustSensorPins {
+    pub led: DustSensorLed,
+    /// Analog output of the sensor, read through ADC2
+    pub data: DustSensorData,
+}
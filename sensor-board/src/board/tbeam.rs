@@ -0,0 +1,48 @@
+//! LILYGO T-Beam: ESP32 with an SX1276 radio, the sensors being wired to its headers.
+
+use esp_hal::gpio::GpioPin;
+
+pub type LoraSpiNss = GpioPin<18>;
+pub type LoraSpiScl = GpioPin<5>;
+pub type LoraSpiMosi = GpioPin<27>;
+pub type LoraSpiMiso = GpioPin<19>;
+pub type LoraReset = GpioPin<23>;
+pub type LoraDio1 = GpioPin<26>;
+
+pub type UartPhyTx = GpioPin<25>;
+pub type UartPhyRx = GpioPin<14>;
+
+pub type I2cScl = GpioPin<22>;
+pub type I2cSda = GpioPin<21>;
+
+pub type DustSensorLed = GpioPin<13>;
+pub type DustSensorData = GpioPin<4>;
+
+/// Moves the pins of the board out of `peripherals`, into a [`BoardPins`](crate::board::BoardPins).
+#[macro_export]
+macro_rules! board_pins {
+    ($peripherals:ident) => {
+        $crate::board::BoardPins {
+            #[cfg(feature = "lora")]
+            lora: $crate::board::LoraPins {
+                spi_nss: $peripherals.GPIO18,
+                spi_scl: $peripherals.GPIO5,
+                spi_mosi: $peripherals.GPIO27,
+                spi_miso: $peripherals.GPIO19,
+                reset: $peripherals.GPIO23,
+                dio1: $peripherals.GPIO26,
+            },
+            #[cfg(feature = "uart-phy")]
+            uart_phy: $crate::board::UartPhyPins {
+                tx: $peripherals.GPIO25,
+                rx: $peripherals.GPIO14,
+            },
+            i2c_scl: $peripherals.GPIO22,
+            i2c_sda: $peripherals.GPIO21,
+            dust: $crate::board::DustSensorPins {
+                led: $peripherals.GPIO13,
+                data: $peripherals.GPIO4,
+            },
+        }
+    };
+}
@@ -1,37 +1,85 @@
-use core::fmt::{Display, Formatter};
+use core::sync::atomic::{AtomicU32, Ordering};
 
 use defmt::{error, info, warn, Display2Format};
 use embassy_futures::select::Either;
 use embassy_time::{Duration, Instant, Timer};
-use heapless::spsc::Consumer;
+use heapless::spsc::{Consumer, Producer, Queue};
 use protocol::{
-    app::v1::{HandshakeEnd, HandshakeStart, Packet, SensorData, SensorValue, SensorValuePoint},
-    codec::{AsyncDecoder, AsyncEncoder},
+    app::v1::{
+        session::{self, AppLayer, AppLayerError, AppLayerTimings},
+        Capabilities, Command, SensorValue, MAX_VALUES_PER_PACKET,
+    },
     link::v1::LinkLayer,
 };
-use thiserror::Error;
+use static_cell::StaticCell;
 
 use crate::{
-    comm::link::SensorBoardLinkLayer, lora::LoraController, PROTOCOL_VERSION_MAJOR,
-    PROTOCOL_VERSION_MINOR,
+    comm::{
+        link::{SensorBoardLinkError, SensorBoardLinkLayer},
+        reconnect_backoff, Radio,
+    },
+    lora::LORA_SPREADING_FACTOR,
+    PROTOCOL_CAPABILITIES, PROTOCOL_VERSION_MAJOR, PROTOCOL_VERSION_MINOR,
 };
 
-pub const VALUES_QUEUE_SIZE: usize = 4;
-pub const VALUES_MEASURE_INTERVAL: u64 = 10;
-pub const VALUES_SEND_INTERVAL: u64 = 5;
+/// Default size of the queue between the measurement and communication tasks.
+///
+/// A `heapless::spsc::Queue` of size `N` holds at most `N - 1` values.
+pub const DEFAULT_VALUES_QUEUE_SIZE: usize = 8;
+/// Takes both ends of the queue between the measurement and communication tasks.
+///
+/// The queue is a singleton allocated in static memory, returns `None` if it was already taken.
+pub fn try_take_values_queue() -> Option<(
+    Producer<'static, SensorValue, DEFAULT_VALUES_QUEUE_SIZE>,
+    Consumer<'static, SensorValue, DEFAULT_VALUES_QUEUE_SIZE>,
+)> {
+    static VALUES_QUEUE: StaticCell<Queue<SensorValue, DEFAULT_VALUES_QUEUE_SIZE>> =
+        StaticCell::new();
+
+    Some(VALUES_QUEUE.try_init(Queue::new())?.split())
+}
+
+/// Default delay in seconds between two measurements
+pub const DEFAULT_MEASURE_INTERVAL: u32 = 10;
+/// Delay in seconds between two measurements, changed by the gateway with a `SetMeasureInterval` command
+static MEASURE_INTERVAL: AtomicU32 = AtomicU32::new(DEFAULT_MEASURE_INTERVAL);
+/// Delay in seconds before trying again after the gateway rejected a handshake
+pub const HANDSHAKE_REJECTED_RETRY_DELAY: u64 = 300;
+
+/// Number of times a data packet is sent again when the gateway doesn't acknowledge it, none by default.
+///
+/// Set with the `LINK_DATA_RETRIES` environment variable while building.
+fn link_data_retries() -> u8 {
+    option_env!("LINK_DATA_RETRIES")
+        .and_then(|retries| retries.parse().ok())
+        .unwrap_or(0)
+}
+
+/// [`PROTOCOL_CAPABILITIES`], along with link acks if data packets are sent again when they are missing.
+fn capabilities() -> Capabilities {
+    if link_data_retries() > 0 {
+        PROTOCOL_CAPABILITIES.union(Capabilities::LINK_ACKS)
+    } else {
+        PROTOCOL_CAPABILITIES
+    }
+}
 
-pub struct SensorBoardAppLayer<LINK> {
-    link: LINK,
-    offset: usize,
+/// Current delay between two measurements, read again before each one.
+pub fn measure_interval() -> Duration {
+    Duration::from_secs(MEASURE_INTERVAL.load(Ordering::Relaxed).into())
 }
 
-#[derive(Debug, Error)]
-pub enum SensorBoardAppLayerError<LINK: core::error::Error> {
-    Decoding,
-    UnexpectedPacket(u8),
-    IncompatibleProtocol(u8, u8),
-    Timeout,
-    Link(LINK),
+/// Current delay between two sends, half the measurement interval so that values don't wait for too long.
+fn send_interval() -> Duration {
+    Duration::from_secs((MEASURE_INTERVAL.load(Ordering::Relaxed) / 2).max(1).into())
+}
+
+/// How a communication cycle went, when it didn't fail.
+enum CycleOutcome {
+    Connected,
+    NothingToSend,
+    /// The gateway answered the values sent, with an ack or a command
+    Answered(Option<Command>),
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -40,39 +88,193 @@ enum AppLayerPhase {
     Uplink { sensor_epoch: Instant, diff: i64 },
 }
 
-pub async fn run(
-    lora: LoraController,
-    mut consumer: Consumer<'static, SensorValue, VALUES_QUEUE_SIZE>,
+/// Sends the measured values to the gateway in an infinite loop, over LoRa or any other [`Radio`].
+pub async fn run<PHY: Radio, const N: usize>(
+    phy: PHY,
+    mut consumer: Consumer<'static, SensorValue, N>,
+    timings: AppLayerTimings,
 ) -> ! {
-    let link = SensorBoardLinkLayer::new(lora);
+    let link = SensorBoardLinkLayer::new(phy).with_data_retries(link_data_retries());
     let mut phase = AppLayerPhase::Handshake;
-    let mut app = SensorBoardAppLayer::new(link);
+    let mut app = AppLayer::with_timings(link, timings);
+    let mut backoff = reconnect_backoff();
 
     loop {
+        reinit_radio_if_stuck(&mut app).await;
         match comm_cycle(&mut app, &mut phase, &mut consumer).await {
-            Err(SensorBoardAppLayerError::Timeout) => {
-                warn!("app: Timeout exceeded, re-initiating handshake...");
-                app.reset();
+            Err(AppLayerError::Timeout | AppLayerError::Link(SensorBoardLinkError::NoAck(_))) => {
+                let delay = backoff.next_delay_ms();
+                warn!(
+                    "app: Timeout exceeded, re-initiating handshake in {=u64} ms...",
+                    delay
+                );
+                reset_connection(&mut app);
                 phase = AppLayerPhase::Handshake;
+                Timer::after(Duration::from_millis(delay)).await;
+            }
+            Err(AppLayerError::ResetRequested) => {
+                warn!("app: Gateway requested a connection reset, re-initiating handshake...");
+                reset_connection(&mut app);
+                phase = AppLayerPhase::Handshake;
+            }
+            Err(AppLayerError::HandshakeRejected) => {
+                error!(
+                    "app: Gateway rejected the handshake, retrying in {=u64} seconds...",
+                    HANDSHAKE_REJECTED_RETRY_DELAY
+                );
+                reset_connection(&mut app);
+                phase = AppLayerPhase::Handshake;
+                Timer::after(Duration::from_secs(HANDSHAKE_REJECTED_RETRY_DELAY)).await;
             }
             Err(err) => {
                 error!("app: comm error: {}", Display2Format(&err));
             }
-            _ => (),
+            Ok(outcome) => {
+                backoff.reset();
+                match outcome {
+                    CycleOutcome::Connected => {
+                        let data_acks = app.capabilities().contains(Capabilities::LINK_ACKS);
+                        app.link_mut().set_data_acks(data_acks);
+                        continue;
+                    }
+                    CycleOutcome::NothingToSend => (),
+                    CycleOutcome::Answered(command) => {
+                        hop_channel(&mut app);
+                        if let Some(command) = command {
+                            apply_command(&mut app, command);
+                        }
+                    }
+                }
+                match sleep_until_next_send(&mut app).await {
+                    Ok(()) => (),
+                    Err(AppLayerError::ResetRequested) => {
+                        warn!(
+                            "app: Gateway requested a connection reset, re-initiating handshake..."
+                        );
+                        reset_connection(&mut app);
+                        phase = AppLayerPhase::Handshake;
+                    }
+                    Err(err) => {
+                        error!("app: comm error while idle: {}", Display2Format(&err));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Resets the radio after repeated errors, instead of failing every cycle.
+async fn reinit_radio_if_stuck<PHY: Radio>(app: &mut AppLayer<SensorBoardLinkLayer<PHY>>) {
+    let phy = app.link_mut().phy_mut();
+    if !phy.needs_reinit() {
+        return;
+    }
+    match phy.reinit().await {
+        Ok(()) => info!("app: radio reset"),
+        Err(e) => error!("app: failed to reset the radio: {}", Display2Format(&e)),
+    }
+}
+
+/// Forgets the current connection, including the spreading factor recommended by the gateway and the channel.
+fn reset_connection<PHY: Radio>(app: &mut AppLayer<SensorBoardLinkLayer<PHY>>) {
+    app.reset();
+    let phy = app.link_mut().phy_mut();
+    if phy.spreading_factor() != LORA_SPREADING_FACTOR {
+        info!("app: going back to SF{=u8}", LORA_SPREADING_FACTOR);
+        if let Err(e) = phy.set_spreading_factor(LORA_SPREADING_FACTOR) {
+            error!(
+                "app: failed to change spreading factor: {}",
+                Display2Format(&e)
+            );
+        }
+    }
+    if let Err(e) = phy.reset_channel() {
+        error!("app: failed to change channel: {}", Display2Format(&e));
+    }
+}
+
+/// Moves to the next channel once the gateway answered, if frequency hopping was negotiated.
+fn hop_channel<PHY: Radio>(app: &mut AppLayer<SensorBoardLinkLayer<PHY>>) {
+    if !app.capabilities().contains(Capabilities::FREQUENCY_HOPPING) {
+        return;
+    }
+    let phy = app.link_mut().phy_mut();
+    match phy.hop() {
+        Ok(()) => info!("app: hopped to {=u32} Hz", phy.frequency()),
+        Err(e) => error!("app: failed to change channel: {}", Display2Format(&e)),
+    }
+}
+
+fn apply_command<PHY: Radio>(app: &mut AppLayer<SensorBoardLinkLayer<PHY>>, command: Command) {
+    match command {
+        Command::SetSpreadingFactor(spreading_factor) => {
+            info!(
+                "app: gateway recommended SF{=u8}, switching",
+                spreading_factor
+            );
+            if let Err(e) = app
+                .link_mut()
+                .phy_mut()
+                .set_spreading_factor(spreading_factor)
+            {
+                error!(
+                    "app: failed to change spreading factor: {}",
+                    Display2Format(&e)
+                );
+            }
+        }
+        Command::SetMeasureInterval(0) => {
+            warn!("app: ignoring measurement interval of 0 seconds");
+        }
+        Command::SetMeasureInterval(secs) => {
+            info!("app: gateway set the measurement interval to {=u32}s", secs);
+            MEASURE_INTERVAL.store(secs, Ordering::Relaxed);
+        }
+        Command::Unknown { id, .. } => {
+            warn!("app: ignoring unknown command {=u32}", id);
+        }
+    }
+}
+
+/// Puts the radio to sleep while waiting for the next values to be sent.
+///
+/// The radio is woken up automatically by the next transmission, keeping its configuration (warm start).
+/// If the keepalive feature was negotiated, the radio keeps listening instead to answer the pings of the gateway.
+async fn sleep_until_next_send<PHY: Radio>(
+    app: &mut AppLayer<SensorBoardLinkLayer<PHY>>,
+) -> Result<(), AppLayerError<SensorBoardLinkError<PHY::Error>>> {
+    let next_send = Instant::now() + send_interval();
+
+    if !app.capabilities().contains(Capabilities::KEEPALIVE) {
+        if let Err(e) = app.link_mut().phy_mut().sleep(true).await {
+            warn!("app: failed to put radio to sleep: {}", Display2Format(&e));
+        }
+        Timer::at(next_send).await;
+        return Ok(());
+    }
+
+    loop {
+        match embassy_futures::select::select(session::answer_ping(app), Timer::at(next_send)).await
+        {
+            Either::First(res) => {
+                res?;
+                info!("app: answered ping");
+            }
+            Either::Second(()) => return Ok(()),
         }
     }
 }
 
-async fn comm_cycle<LINK: LinkLayer>(
-    app: &mut SensorBoardAppLayer<LINK>,
+async fn comm_cycle<LINK: LinkLayer, const N: usize>(
+    app: &mut AppLayer<LINK>,
     phase: &mut AppLayerPhase,
-    consumer: &mut Consumer<'static, SensorValue, VALUES_QUEUE_SIZE>,
-) -> Result<(), SensorBoardAppLayerError<LINK::Error>> {
+    consumer: &mut Consumer<'static, SensorValue, N>,
+) -> Result<CycleOutcome, AppLayerError<LINK::Error>> {
     match phase {
         AppLayerPhase::Handshake => {
             let (sensor_epoch, diff) = app_initiate_handshake(app).await?;
             *phase = AppLayerPhase::Uplink { sensor_epoch, diff };
-            Ok(())
+            Ok(CycleOutcome::Connected)
         }
         AppLayerPhase::Uplink { sensor_epoch, diff } => {
             app_send_values(app, consumer, *sensor_epoch, *diff).await
@@ -81,39 +283,38 @@ async fn comm_cycle<LINK: LinkLayer>(
 }
 
 async fn app_initiate_handshake<LINK: LinkLayer>(
-    app: &mut SensorBoardAppLayer<LINK>,
-) -> Result<(Instant, i64), SensorBoardAppLayerError<LINK::Error>> {
+    app: &mut AppLayer<LINK>,
+) -> Result<(Instant, i64), AppLayerError<LINK::Error>> {
     info!("Initiating handshake...");
 
-    app.emit(&Packet::HandshakeStart(HandshakeStart {
-        major: PROTOCOL_VERSION_MAJOR,
-        minor: PROTOCOL_VERSION_MINOR,
-    }))
+    session::start_handshake(
+        app,
+        PROTOCOL_VERSION_MAJOR,
+        PROTOCOL_VERSION_MINOR,
+        capabilities(),
+    )
     .await?;
-    app.flush().await?;
     info!("Handshake initiated, waiting for handshake end...");
 
+    let timeout = app.timings().handshake_timeout;
     let res = embassy_futures::select::select(
-        app.read::<Packet>(),
-        embassy_time::Timer::after(embassy_time::Duration::from_secs(5)),
+        session::wait_handshake_end(
+            app,
+            PROTOCOL_VERSION_MAJOR,
+            PROTOCOL_VERSION_MINOR,
+            capabilities(),
+        ),
+        Timer::after(timeout),
     )
     .await;
 
     let gw_epoch = match res {
-        Either::First(Ok(Packet::HandshakeEnd(HandshakeEnd { major, minor, .. })))
-            if major != PROTOCOL_VERSION_MAJOR || minor != PROTOCOL_VERSION_MINOR =>
-        {
-            return Err(SensorBoardAppLayerError::IncompatibleProtocol(major, minor))
-        }
-        Either::First(Ok(Packet::HandshakeEnd(HandshakeEnd { epoch, .. }))) => {
-            Instant::from_millis(epoch)
-        }
-        Either::First(Ok(pkt)) => return Err(SensorBoardAppLayerError::UnexpectedPacket(pkt.id())),
-        Either::First(Err(e)) => return Err(e),
-        Either::Second(()) => return Err(SensorBoardAppLayerError::Timeout),
+        Either::First(epoch) => Instant::from_millis(epoch?),
+        Either::Second(()) => return Err(AppLayerError::Timeout),
     };
 
     info!("Gateway epoch millis: {}", gw_epoch.as_millis());
+    info!("Negotiated capabilities: {}", app.capabilities());
     let s_epoch = Instant::now();
     let diff = (s_epoch.as_micros() as i64).wrapping_sub(gw_epoch.as_micros() as i64);
     info!(
@@ -125,15 +326,23 @@ async fn app_initiate_handshake<LINK: LinkLayer>(
     Ok((s_epoch, diff))
 }
 
-async fn app_send_values<LINK: LinkLayer>(
-    app: &mut SensorBoardAppLayer<LINK>,
-    consumer: &mut Consumer<'static, SensorValue, VALUES_QUEUE_SIZE>,
+async fn app_send_values<LINK: LinkLayer, const N: usize>(
+    app: &mut AppLayer<LINK>,
+    consumer: &mut Consumer<'static, SensorValue, N>,
     sensor_epoch: Instant,
     diff: i64,
-) -> Result<(), SensorBoardAppLayerError<LINK::Error>> {
-    let mut values: heapless::Vec<SensorValue, VALUES_QUEUE_SIZE> = heapless::Vec::new();
+) -> Result<CycleOutcome, AppLayerError<LINK::Error>> {
+    // all values are sent in a single packet, the gateway rejects those with too many of them
+    const {
+        assert!(
+            N <= MAX_VALUES_PER_PACKET as usize + 1,
+            "values queue is too large"
+        );
+    }
+
+    let mut values: heapless::Vec<SensorValue, N> = heapless::Vec::new();
     while let Some(value) = consumer.dequeue() {
-        // SAFETY: the queue and the vec have the same max size (VALUES_QUEUE_SIZE)
+        // SAFETY: the vec can hold N values and the queue at most N - 1
         unsafe { values.push_unchecked(value) }
     }
 
@@ -142,113 +351,20 @@ async fn app_send_values<LINK: LinkLayer>(
         let time_offset: i64 = (sensor_epoch.elapsed().as_micros() as i64 - diff) / 1_000_000;
 
         // FIXME: artificial delay, remove if LBT is implemented
-        Timer::after(Duration::from_millis(1000)).await;
-        app.emit(&Packet::SensorData(SensorData {
-            count: values.len() as u8,
-        }))
-        .await?;
-
-        for value in values {
-            app.emit(SensorValuePoint { value, time_offset }).await?;
-        }
-        app.flush().await?;
+        Timer::after(app.timings().send_delay).await;
+        session::send_values(app, &values, time_offset).await?;
 
-        info!("Waiting for ack...");
+        info!("Waiting for ack or command...");
 
-        let res = embassy_futures::select::select(
-            app.read::<Packet>(),
-            embassy_time::Timer::after(embassy_time::Duration::from_secs(5)),
-        )
-        .await;
+        let timeout = app.timings().ack_timeout;
+        let res =
+            embassy_futures::select::select(session::wait_ack(app), Timer::after(timeout)).await;
 
-        match res {
-            Either::First(Ok(Packet::Ack)) => {}
-            Either::First(Ok(pkt)) => {
-                return Err(SensorBoardAppLayerError::UnexpectedPacket(pkt.id()))
-            }
-            Either::First(Err(e)) => return Err(e),
-            Either::Second(()) => return Err(SensorBoardAppLayerError::Timeout),
-        }
-    }
-
-    embassy_time::Timer::after(embassy_time::Duration::from_secs(VALUES_SEND_INTERVAL)).await;
-
-    Ok(())
-}
-
-impl<LINK: LinkLayer> SensorBoardAppLayer<LINK> {
-    pub fn new(link: LINK) -> Self {
-        Self { link, offset: 0 }
-    }
-
-    pub fn reset(&mut self) {
-        self.link.reset();
-        self.offset = 0;
+        return match res {
+            Either::First(res) => res.map(CycleOutcome::Answered),
+            Either::Second(()) => Err(AppLayerError::Timeout),
+        };
     }
 
-    pub async fn flush(&mut self) -> Result<(), SensorBoardAppLayerError<LINK::Error>> {
-        self.link
-            .flush(None)
-            .await
-            .map_err(SensorBoardAppLayerError::Link)
-    }
-}
-
-impl<LINK: LinkLayer> AsyncEncoder for SensorBoardAppLayer<LINK> {
-    type Error = SensorBoardAppLayerError<LINK::Error>;
-
-    async fn emit_bytes(&mut self, mut buf: &[u8]) -> Result<(), Self::Error> {
-        while !buf.is_empty() {
-            let written = self
-                .link
-                .write(None, buf)
-                .await
-                .map_err(SensorBoardAppLayerError::Link)?;
-            buf = &buf[written..];
-        }
-        Ok(())
-    }
-}
-
-impl<LINK: LinkLayer> AsyncDecoder for SensorBoardAppLayer<LINK> {
-    type Error = SensorBoardAppLayerError<LINK::Error>;
-
-    async fn read_bytes(&mut self, buf: &mut [u8]) -> Result<(), Self::Error> {
-        let mut bytes_read = 0usize;
-
-        while bytes_read < buf.len() {
-            let (read, _from) = self
-                .link
-                .read(&mut buf[bytes_read..])
-                .await
-                .map_err(SensorBoardAppLayerError::Link)?;
-            self.offset += read;
-            bytes_read += read;
-        }
-        Ok(())
-    }
-
-    fn current_offset(&self) -> usize {
-        self.offset
-    }
-
-    fn decoding_error(&self) -> Self::Error {
-        SensorBoardAppLayerError::Decoding
-    }
-}
-
-impl<LINK: core::error::Error> Display for SensorBoardAppLayerError<LINK> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
-        match &self {
-            SensorBoardAppLayerError::Decoding => f.write_str("decoding error"),
-            SensorBoardAppLayerError::Link(err) => write!(f, "{}", err),
-            SensorBoardAppLayerError::UnexpectedPacket(id) => {
-                write!(f, "unexpected packet: {}", id)
-            }
-            SensorBoardAppLayerError::IncompatibleProtocol(major, minor) => {
-                write!(f, "incompatible protocol: {}.{}", major, minor)
-            }
-            SensorBoardAppLayerError::Timeout => f.write_str("timeout exceeded"),
-        }
-    }
+    Ok(CycleOutcome::NothingToSend)
 }
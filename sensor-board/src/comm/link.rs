@@ -1,11 +1,18 @@
 use defmt::{info, trace, warn};
-use embassy_futures::select::Either;
+use embassy_time::{Duration, Instant};
 use esp_hal::efuse::Efuse;
-use protocol::link::v1::LinkPacket;
+use protocol::link::v1::{
+    handshake_reply_nonce, LinkKeys, LinkPacket, LinkTxBuffer, LINK_ACK_PAYLOAD_LEN,
+    LINK_ACK_TIMEOUT, LINK_HEADER_LEN,
+};
 use protocol::{
     link::v1::{GatewayId, LinkLayer, LinkPhase, SensorBoardId},
     phy::PhysicalLayer,
 };
+use thiserror::Error;
+use util::backoff::Backoff;
+
+use crate::comm::reconnect_backoff;
 
 #[derive(Copy, Clone)]
 enum SensorBoardLinkPhase {
@@ -13,12 +20,33 @@ enum SensorBoardLinkPhase {
     Data(SensorBoardId),
 }
 
+#[derive(Debug, Error)]
+pub enum SensorBoardLinkError<E> {
+    #[error(transparent)]
+    Phy(E),
+    /// The gateway acknowledged none of the attempts to send a data packet
+    #[error("no ack after {0} retries")]
+    NoAck(u8),
+}
+
+impl<E> From<E> for SensorBoardLinkError<E> {
+    fn from(e: E) -> Self {
+        Self::Phy(e)
+    }
+}
+
 pub struct SensorBoardLinkLayer<PHY> {
     phase: SensorBoardLinkPhase,
     phy: PHY,
-    tx_buf: heapless::Vec<u8, 64>,
+    /// Switched to the session key of each accepted handshake reply
+    keys: LinkKeys<'static>,
+    backoff: Backoff,
+    tx_buf: LinkTxBuffer<64>,
     payload_start: usize,
     payload_end: usize,
+    data_retries: u8,
+    /// Whether the gateway acknowledges data packets, see [`Self::set_data_acks`]
+    data_acks: bool,
 }
 
 impl<PHY: PhysicalLayer> SensorBoardLinkLayer<PHY> {
@@ -26,12 +54,41 @@ impl<PHY: PhysicalLayer> SensorBoardLinkLayer<PHY> {
         Self {
             phase: SensorBoardLinkPhase::Handshake,
             phy,
-            tx_buf: heapless::Vec::new(),
+            keys: LinkKeys::new(b"SECRET"),
+            backoff: reconnect_backoff(),
+            tx_buf: LinkTxBuffer::new(),
             payload_start: 0,
             payload_end: 0,
+            data_retries: 0,
+            data_acks: false,
         }
     }
 
+    /// Waits for the gateway to acknowledge each data packet, sending it again up to `retries` times.
+    ///
+    /// Packets are sent once without waiting for acks by default, leaving it to the app layer.
+    pub fn with_data_retries(mut self, retries: u8) -> Self {
+        self.data_retries = retries;
+        self
+    }
+
+    /// Whether the gateway acknowledges data packets, once the link-acks feature is negotiated.
+    ///
+    /// Packets are sent once until then whatever the number of retries, and again after each reset.
+    pub fn set_data_acks(&mut self, enabled: bool) {
+        self.data_acks = enabled;
+    }
+
+    /// Gives access to the underlying physical layer, e.g. to put the radio to sleep.
+    pub fn phy_mut(&mut self) -> &mut PHY {
+        &mut self.phy
+    }
+
+    /// Largest payload of a link packet allowed by the physical layer, `tx_buf` limits it further.
+    fn max_payload_len(&self) -> usize {
+        self.phy.max_payload_len().saturating_sub(LINK_HEADER_LEN)
+    }
+
     async fn connect(&mut self) -> Result<SensorBoardId, PHY::Error> {
         if let SensorBoardLinkPhase::Data(id) = self.phase {
             // Already connected, no need to do anything
@@ -40,22 +97,34 @@ impl<PHY: PhysicalLayer> SensorBoardLinkLayer<PHY> {
 
         loop {
             info!("link: connecting to gateway...");
-            self.phase = Self::try_connect(&mut self.phy).await?;
+            self.phase = Self::try_connect(&mut self.phy, &mut self.keys).await?;
 
             match self.phase {
                 SensorBoardLinkPhase::Handshake => {
-                    info!("link: handshake failed, trying again in 2 seconds...");
-                    embassy_time::Timer::after(embassy_time::Duration::from_secs(2)).await;
+                    let delay = self.backoff.next_delay_ms();
+                    info!(
+                        "link: handshake failed, trying again in {=u64} ms...",
+                        delay
+                    );
+                    embassy_time::Timer::after(embassy_time::Duration::from_millis(delay)).await;
                 }
                 SensorBoardLinkPhase::Data(id) => {
                     info!("link: connected to gateway with ID: {}", id.0);
+                    self.backoff.reset();
                     break Ok(id);
                 }
             }
         }
     }
 
-    async fn try_connect(phy: &mut PHY) -> Result<SensorBoardLinkPhase, PHY::Error> {
+    /// Sends a handshake and starts a session with the nonce of the gateway's reply.
+    ///
+    /// A recorded reply is accepted too, but the gateway has moved on to another nonce since: its acks and data
+    /// packets no longer match and the sensor board eventually connects again.
+    async fn try_connect(
+        phy: &mut PHY,
+        keys: &mut LinkKeys<'static>,
+    ) -> Result<SensorBoardLinkPhase, PHY::Error> {
         // TODO(protocol): specify that MAC address is 6 bytes
         let mac = Efuse::read_base_mac_address();
 
@@ -65,38 +134,97 @@ impl<PHY: PhysicalLayer> SensorBoardLinkLayer<PHY> {
             id: 0,
             payload: &mac,
         }
-        .write(&mut *phy, b"SECRET")
+        .write(&mut *phy, &*keys)
         .await?;
 
         info!("link: reading handshake response...");
 
-        let res = embassy_futures::select::select(
-            LinkPacket::read(&mut *phy, b"SECRET"),
-            embassy_time::Timer::after(embassy_time::Duration::from_secs(5)),
-        )
-        .await;
-
-        let (res_phase, res_id) = match res {
-            Either::First(res) => res?,
-            Either::Second(()) => {
-                warn!("link: timeout while waiting for handshake response");
-                return Ok(SensorBoardLinkPhase::Handshake);
+        let res = LinkPacket::read_timeout(&mut *phy, &*keys, embassy_time::Duration::from_secs(5))
+            .await?;
+
+        let Some((res_phase, res_id)) = res else {
+            warn!("link: timeout while waiting for handshake response");
+            return Ok(SensorBoardLinkPhase::Handshake);
+        };
+        if res_phase != LinkPhase::Handshake {
+            return Ok(SensorBoardLinkPhase::Handshake);
+        }
+        let Some(nonce) = handshake_reply_nonce(LinkPacket::get_payload(phy), &mac) else {
+            warn!("link: ignoring handshake response without a nonce or for another MAC");
+            return Ok(SensorBoardLinkPhase::Handshake);
+        };
+        keys.start_session(&nonce);
+        Ok(SensorBoardLinkPhase::Data(SensorBoardId(res_id)))
+    }
+
+    /// Waits at most [`LINK_ACK_TIMEOUT`] for the gateway to acknowledge the data packet whose ack payload is `ack`.
+    ///
+    /// A data packet from the gateway acknowledges it as well, and is kept for the next call to `read()`.
+    async fn wait_ack(
+        &mut self,
+        id: SensorBoardId,
+        ack: [u8; LINK_ACK_PAYLOAD_LEN],
+    ) -> Result<bool, PHY::Error> {
+        let deadline = Instant::now() + LINK_ACK_TIMEOUT;
+
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::from_ticks(0) {
+                return Ok(false);
+            }
+            let Some((res_phase, res_id)) =
+                LinkPacket::read_timeout(&mut self.phy, &self.keys, remaining).await?
+            else {
+                return Ok(false);
+            };
+            if res_id != id.0 {
+                continue;
+            }
+
+            match res_phase {
+                LinkPhase::Ack if LinkPacket::get_payload(&self.phy) == ack => return Ok(true),
+                LinkPhase::Data => {
+                    trace!("link: data from gateway, taking it as an ack");
+                    self.payload_start = 0;
+                    self.payload_end = LinkPacket::get_payload(&self.phy).len();
+                    return Ok(true);
+                }
+                _ => continue,
             }
+        }
+    }
+
+    /// Sends `payload` as a data frame to the gateway, sending it again until acknowledged when acks are enabled.
+    async fn send_data(&mut self, payload: &[u8]) -> Result<(), SensorBoardLinkError<PHY::Error>> {
+        let id = self.connect().await?;
+        let packet = LinkPacket {
+            phase: LinkPhase::Data,
+            id: id.0,
+            payload,
         };
-        let payload = LinkPacket::get_payload(phy);
+        let ack = packet.ack_payload(&self.keys);
+        let retries = if self.data_acks { self.data_retries } else { 0 };
 
-        Ok(if res_phase == LinkPhase::Handshake && payload == mac {
-            SensorBoardLinkPhase::Data(SensorBoardId(res_id))
-        } else {
-            SensorBoardLinkPhase::Handshake
-        })
+        for attempt in 0..=retries {
+            if attempt > 0 {
+                warn!(
+                    "link: no ack from gateway, sending again ({=u8}/{=u8})",
+                    attempt, retries
+                );
+            }
+            packet.write(&mut self.phy, &self.keys).await?;
+            if retries == 0 || self.wait_ack(id, ack).await? {
+                return Ok(());
+            }
+        }
+        Err(SensorBoardLinkError::NoAck(retries))
     }
 
     /// Requests the next payload from the PHY, clearing the rx buffer.
     async fn read_payload(&mut self) -> Result<(), PHY::Error> {
         loop {
             let id = self.connect().await?;
-            let (res_phase, res_id) = LinkPacket::read(&mut self.phy, b"SECRET").await?;
+            let (res_phase, res_id) = LinkPacket::read(&mut self.phy, &self.keys).await?;
 
             if res_id != id.0 {
                 trace!(
@@ -107,6 +235,12 @@ impl<PHY: PhysicalLayer> SensorBoardLinkLayer<PHY> {
                 continue;
             }
 
+            if res_phase == LinkPhase::Ack {
+                // late ack of a packet sent again, or acks nobody waited for
+                trace!("link: ignoring ack from gateway");
+                continue;
+            }
+
             if res_phase != LinkPhase::Data {
                 warn!("link: unexpected phase from gateway, reconnecting");
                 self.phase = SensorBoardLinkPhase::Handshake;
@@ -119,7 +253,7 @@ impl<PHY: PhysicalLayer> SensorBoardLinkLayer<PHY> {
 }
 
 impl<PHY: PhysicalLayer> LinkLayer for SensorBoardLinkLayer<PHY> {
-    type Error = PHY::Error;
+    type Error = SensorBoardLinkError<PHY::Error>;
     type PeerId = GatewayId;
 
     async fn read(&mut self, buf: &mut [u8]) -> Result<(usize, Self::PeerId), Self::Error> {
@@ -145,46 +279,35 @@ impl<PHY: PhysicalLayer> LinkLayer for SensorBoardLinkLayer<PHY> {
 
     async fn write(
         &mut self,
-        dest: Option<Self::PeerId>,
+        _dest: Option<Self::PeerId>,
         buf: &[u8],
     ) -> Result<usize, Self::Error> {
-        let mut bytes_sent = 0usize;
-
-        for chunk in buf.chunks(self.tx_buf.capacity()) {
-            if let Err(()) = self.tx_buf.extend_from_slice(chunk) {
-                self.flush(dest).await?;
-                // SAFETY:
-                // - size of `chunk` is lower or equal to tx_buf's capacity
-                // - `buf` and `chunk` cannot overlap, tx_buf is private to this struct
-                unsafe {
-                    self.tx_buf
-                        .as_mut_ptr()
-                        .copy_from_nonoverlapping(chunk.as_ptr(), chunk.len());
-                    self.tx_buf.set_len(chunk.len());
-                }
-            }
-            bytes_sent += chunk.len();
-        }
-        Ok(bytes_sent)
+        let max_len = self.max_payload_len();
+        // taken out so that the frames don't borrow `self` while waiting for their ack
+        let mut tx_buf = core::mem::take(&mut self.tx_buf);
+        let res = tx_buf
+            .write(buf, max_len, async |frame| self.send_data(frame).await)
+            .await;
+        self.tx_buf = tx_buf;
+        res.map(|()| buf.len())
     }
 
     async fn flush(&mut self, _dest: Option<Self::PeerId>) -> Result<(), Self::Error> {
-        let id = self.connect().await?;
-        LinkPacket {
-            phase: LinkPhase::Data,
-            id: id.0,
-            payload: &self.tx_buf,
+        if self.tx_buf.is_empty() {
+            // already sent by `write()` once full, empty frames are invalid
+            return Ok(());
         }
-        .write(&mut self.phy, b"SECRET")
-        .await?;
-        self.tx_buf.clear();
-        self.phy.flush().await
+        // taken out so that the packet doesn't borrow `self` while waiting for its ack
+        let tx_buf = core::mem::take(&mut self.tx_buf);
+        self.send_data(tx_buf.as_slice()).await
     }
 
     fn reset(&mut self) {
         self.phase = SensorBoardLinkPhase::Handshake;
+        self.keys.end_session();
         self.payload_start = 0;
         self.payload_end = 0;
         self.tx_buf.clear();
+        self.data_acks = false;
     }
 }
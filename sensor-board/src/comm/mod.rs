@@ -1,2 +1,107 @@
+use esp_hal::efuse::Efuse;
+use protocol::phy::PhysicalLayer;
+use util::backoff::{seed_from_bytes, Backoff};
+
+use crate::lora::{LoraController, LoraError, LORA_SPREADING_FACTOR};
+
 pub mod app;
 pub mod link;
+
+/// Radio settings changed by the app layer, on top of sending and receiving packets.
+///
+/// Physical layers without a radio, like [`util::uart_phy::UartPhy`], keep the defaults, which do nothing.
+pub trait Radio: PhysicalLayer {
+    /// Current spreading factor.
+    fn spreading_factor(&self) -> u8 {
+        LORA_SPREADING_FACTOR
+    }
+
+    /// Changes the spreading factor, applied from the next transmission or reception onwards.
+    fn set_spreading_factor(&mut self, _spreading_factor: u8) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Current channel, in Hz.
+    fn frequency(&self) -> u32 {
+        0
+    }
+
+    /// Moves to the next channel of the hopping schedule.
+    fn hop(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Goes back to the default channel and to the beginning of the hopping schedule.
+    fn reset_channel(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Whether repeated errors may have left the radio stuck, in which case it should go through [`Self::reinit`].
+    fn needs_reinit(&self) -> bool {
+        false
+    }
+
+    /// Resets the radio and applies the current settings again.
+    async fn reinit(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Puts the radio to sleep until the next transmission, keeping its configuration if `wakeup` is set.
+    async fn sleep(&mut self, _wakeup: bool) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl Radio for LoraController {
+    fn spreading_factor(&self) -> u8 {
+        LoraController::spreading_factor(self)
+    }
+
+    fn set_spreading_factor(&mut self, spreading_factor: u8) -> Result<(), LoraError> {
+        LoraController::set_spreading_factor(self, spreading_factor)
+    }
+
+    fn frequency(&self) -> u32 {
+        LoraController::frequency(self)
+    }
+
+    fn hop(&mut self) -> Result<(), LoraError> {
+        LoraController::hop(self)
+    }
+
+    fn reset_channel(&mut self) -> Result<(), LoraError> {
+        LoraController::reset_channel(self)
+    }
+
+    fn needs_reinit(&self) -> bool {
+        LoraController::needs_reinit(self)
+    }
+
+    async fn reinit(&mut self) -> Result<(), LoraError> {
+        LoraController::reinit(self).await
+    }
+
+    async fn sleep(&mut self, wakeup: bool) -> Result<(), LoraError> {
+        LoraController::sleep(self, wakeup).await
+    }
+}
+
+#[cfg(feature = "uart-phy")]
+impl<U: embedded_io_async::Read + embedded_io_async::Write> Radio for util::uart_phy::UartPhy<U> {}
+
+/// Initial delay in milliseconds before retrying to connect to the gateway
+pub const RECONNECT_BACKOFF_BASE_MS: u64 = 2_000;
+/// Maximum delay in milliseconds between two attempts to connect to the gateway
+pub const RECONNECT_BACKOFF_CAP_MS: u64 = 120_000;
+
+/// Creates the backoff policy used when (re)connecting to the gateway.
+///
+/// The jitter is seeded from the MAC address so that boards powered up together drift apart.
+pub fn reconnect_backoff() -> Backoff {
+    let mac = Efuse::read_base_mac_address();
+    Backoff::new(
+        RECONNECT_BACKOFF_BASE_MS,
+        RECONNECT_BACKOFF_CAP_MS,
+        seed_from_bytes(&mac),
+    )
+}
@@ -1,9 +1,32 @@
 #![no_std]
+#![allow(async_fn_in_trait)]
 
+use protocol::app::v1::Capabilities;
+
+pub mod bmp;
+pub mod board;
 #[cfg(feature = "lora")]
 pub mod comm;
 #[cfg(feature = "lora")]
 pub mod lora;
+pub mod self_test;
+pub mod sensor;
 
 pub const PROTOCOL_VERSION_MAJOR: u8 = 1;
 pub const PROTOCOL_VERSION_MINOR: u8 = 0;
+/// Optional protocol features supported by the sensor board, negotiated with the gateway during the handshake.
+///
+/// Downlink commands let the gateway adjust the spreading factor to the link quality.
+/// With the `keepalive` feature, pings let it notice when the sensor board goes away,
+/// and with the `frequency-hopping` feature, uplinks are spread over several channels.
+pub const PROTOCOL_CAPABILITIES: Capabilities = Capabilities::DOWNLINK_COMMANDS
+    .union(if cfg!(feature = "keepalive") {
+        Capabilities::KEEPALIVE
+    } else {
+        Capabilities::NONE
+    })
+    .union(if cfg!(feature = "frequency-hopping") {
+        Capabilities::FREQUENCY_HOPPING
+    } else {
+        Capabilities::NONE
+    });
@@ -1,10 +1,10 @@
-use defmt::{error, trace, Format};
+use defmt::{error, trace, warn, Debug2Format, Format};
 use embassy_embedded_hal::shared_bus::asynch::spi::SpiDevice;
 use embassy_futures::select::Either;
 use embassy_sync::{blocking_mutex::raw::NoopRawMutex, mutex::Mutex};
-use embassy_time::Delay;
+use embassy_time::{Delay, Duration};
 use esp_hal::{
-    gpio::{GpioPin, Input, InputConfig, Level, Output, OutputConfig},
+    gpio::{Input, InputConfig, Level, Output, OutputConfig},
     peripherals::SPI2,
     spi::Mode,
     time::Rate,
@@ -21,8 +21,13 @@ use lora_phy::{
 use protocol::phy::PhysicalLayer;
 use static_cell::StaticCell;
 use thiserror::Error;
+use util::hopping::{default_hop_channels, parse_hop_channels, HopChannels, HopSchedule};
+
+use crate::board::LoraPins;
 
 /// Channel to use, should be "unique". Use same frequencies as other devices causes spurious packets.
+///
+/// Used for handshakes, frequency hopping may switch to other channels afterwards.
 const LORA_FREQUENCY_IN_HZ: u32 = 868_200_000;
 /// Channel width. Lower values increase time on air, but may be able to find clear frequencies.
 const LORA_BANDWITH: Bandwidth = Bandwidth::_250KHz;
@@ -30,17 +35,30 @@ const LORA_BANDWITH: Bandwidth = Bandwidth::_250KHz;
 /// of actual data in transmissions.
 const LORA_CODING_RATE: CodingRate = CodingRate::_4_8;
 /// Controls the chirp rate. Lower values are slower bandwidth (longer time on air), but more robust.
-const LORA_SPREADING_FACTOR: SpreadingFactor = SpreadingFactor::_10;
+///
+/// Used for handshakes, the gateway may recommend another one afterwards.
+pub const LORA_SPREADING_FACTOR: u8 = 10;
 const LORA_RX_BUF_SIZE: usize = 128;
+/// Receive timeout of `PhysicalLayer::read()`, after which an empty packet is returned
+const LORA_RX_TIMEOUT: Duration = Duration::from_secs(5);
+/// Consecutive radio errors after which [`LoraController::needs_reinit`] asks for the radio to be reset.
+pub const LORA_MAX_CONSECUTIVE_RADIO_ERRORS: u8 = 3;
+/// Transmit power used when none is configured, in dBm.
+///
+/// This is above [`LORA_BAND_MAX_ERP_DBM`], it only stays within the EU868 limit when the antenna
+/// and cable losses make up for the difference.
+pub const LORA_DEFAULT_TX_POWER_DBM: i32 = 20;
+/// Range of transmit power supported by the SX1276, in dBm.
+pub const LORA_TX_POWER_RANGE_DBM: core::ops::RangeInclusive<i32> = 2..=20;
+/// Maximum radiated power allowed in the EU868 sub-band used by [`LORA_FREQUENCY_IN_HZ`] (25 mW ERP), in dBm.
+///
+/// The actual radiated power depends on the antenna, so a power set above it with
+/// [`LoraController::set_tx_power`] only triggers a warning.
+pub const LORA_BAND_MAX_ERP_DBM: i32 = 14;
 
 pub struct LoraHardware {
     pub spi: SPI2,
-    pub spi_nss: GpioPin<18>,
-    pub spi_scl: GpioPin<5>,
-    pub spi_mosi: GpioPin<27>,
-    pub spi_miso: GpioPin<19>,
-    pub reset: GpioPin<23>,
-    pub dio1: GpioPin<26>,
+    pub pins: LoraPins,
 }
 
 type AsyncSpi = esp_hal::spi::master::Spi<'static, Async>;
@@ -50,13 +68,80 @@ type TBeamLora32Lora = LoRa<
     Delay,
 >;
 
+/// LoRa sync word, radios only receive packets sent with the same one as theirs.
+///
+/// The radio driver only lets the sensor board pick between the private and the public (LoRaWAN) sync words.
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+pub enum LoraSyncWord {
+    /// `0x12`, used by default
+    Private,
+    /// `0x34`, shared with LoRaWAN networks
+    Public,
+}
+
+/// Radio settings that may differ between deployments.
+#[derive(Clone)]
+pub struct LoraConfig {
+    /// Transmit power in dBm
+    pub tx_power: i32,
+    /// Channels hopped through when frequency hopping is negotiated, the same as the gateway's
+    pub hop_channels: HopChannels,
+    /// Sync word, the same as the gateway's
+    pub sync_word: LoraSyncWord,
+}
+
+impl Default for LoraConfig {
+    fn default() -> Self {
+        Self {
+            tx_power: LORA_DEFAULT_TX_POWER_DBM,
+            hop_channels: hop_channels_from_env(),
+            sync_word: sync_word_from_env(),
+        }
+    }
+}
+
+/// Sync word set with the `LORA_SYNC_WORD` environment variable while building, `private` or `public`.
+fn sync_word_from_env() -> LoraSyncWord {
+    match option_env!("LORA_SYNC_WORD") {
+        None | Some("private") => LoraSyncWord::Private,
+        Some("public") => LoraSyncWord::Public,
+        Some(other) => {
+            warn!(
+                "phy: invalid LORA_SYNC_WORD '{}' (expected 'private' or 'public'), using the private one",
+                other
+            );
+            LoraSyncWord::Private
+        }
+    }
+}
+
+/// Channels set with the `LORA_HOP_CHANNELS` environment variable while building, the EU868 defaults otherwise.
+fn hop_channels_from_env() -> HopChannels {
+    let Some(channels) = option_env!("LORA_HOP_CHANNELS") else {
+        return default_hop_channels();
+    };
+    parse_hop_channels(channels).unwrap_or_else(|e| {
+        warn!(
+            "phy: invalid LORA_HOP_CHANNELS ({}), using the defaults",
+            Debug2Format(&e)
+        );
+        default_hop_channels()
+    })
+}
+
 pub struct LoraController {
     lora: TBeamLora32Lora,
     modulation_params: ModulationParams,
     tx_packet_params: PacketParams,
     rx_packet_params: PacketParams,
+    tx_power: i32,
+    spreading_factor: u8,
+    frequency: u32,
+    hop_schedule: HopSchedule,
     rx_buffer: heapless::Vec<u8, LORA_RX_BUF_SIZE>,
     tx_buffer: heapless::Vec<u8, LORA_RX_BUF_SIZE>,
+    /// Radio errors since the last successful transmission or reception
+    consecutive_radio_errors: u8,
 }
 
 /// One-stop shop for LoRa-related errors
@@ -68,6 +153,10 @@ pub enum LoraError {
     Radio(RadioError),
     #[error("buffer overflow")]
     BufferOverflow,
+    #[error("unsupported TX power: {0} dBm")]
+    InvalidTxPower(i32),
+    #[error("unsupported spreading factor: SF{0}")]
+    InvalidSpreadingFactor(u8),
 }
 
 impl From<RadioError> for LoraError {
@@ -79,7 +168,9 @@ impl From<RadioError> for LoraError {
 static SPI_BUS: StaticCell<Mutex<NoopRawMutex, AsyncSpi>> = StaticCell::new();
 
 impl LoraController {
-    pub async fn new(hardware: LoraHardware) -> Result<Self, LoraError> {
+    pub async fn new(hardware: LoraHardware, config: LoraConfig) -> Result<Self, LoraError> {
+        check_tx_power(config.tx_power)?;
+
         // The SPI bus used by the lora dio is exclusive to it.
         // No need to use mutexes or other synchronization
         let spi: AsyncSpi = esp_hal::spi::master::Spi::new(
@@ -88,9 +179,9 @@ impl LoraController {
                 .with_frequency(Rate::from_khz(100))
                 .with_mode(Mode::_0),
         )?
-        .with_sck(hardware.spi_scl)
-        .with_mosi(hardware.spi_mosi)
-        .with_miso(hardware.spi_miso)
+        .with_sck(hardware.pins.spi_scl)
+        .with_mosi(hardware.pins.spi_mosi)
+        .with_miso(hardware.pins.spi_miso)
         .into_async();
 
         // Create the SX126x configuration
@@ -102,9 +193,9 @@ impl LoraController {
         };
 
         // Initialize GPIO pins
-        let nss = Output::new(hardware.spi_nss, Level::High, OutputConfig::default());
-        let reset = Output::new(hardware.reset, Level::Low, OutputConfig::default());
-        let dio1 = Input::new(hardware.dio1, InputConfig::default());
+        let nss = Output::new(hardware.pins.spi_nss, Level::High, OutputConfig::default());
+        let reset = Output::new(hardware.pins.reset, Level::Low, OutputConfig::default());
+        let dio1 = Input::new(hardware.pins.dio1, InputConfig::default());
 
         // Initialize the SPI bus
         let spi_bus: &mut Mutex<NoopRawMutex, AsyncSpi> = SPI_BUS.init_with(|| Mutex::new(spi));
@@ -118,14 +209,138 @@ impl LoraController {
         // Create the radio instance
         let iv: GenericSx127xInterfaceVariant<Output<'static>, Input<'static>> =
             GenericSx127xInterfaceVariant::new(reset, dio1, None, None)?;
-        let mut lora: TBeamLora32Lora =
-            LoRa::new(Sx127x::new(spi_device, iv, sx127x_config), false, Delay).await?;
+        let enable_public_network = config.sync_word == LoraSyncWord::Public;
+        let mut lora: TBeamLora32Lora = LoRa::new(
+            Sx127x::new(spi_device, iv, sx127x_config),
+            enable_public_network,
+            Delay,
+        )
+        .await?;
+
+        let (modulation_params, tx_packet_params, rx_packet_params) =
+            Self::create_params(&mut lora, LORA_SPREADING_FACTOR, LORA_FREQUENCY_IN_HZ)?;
+
+        Ok(LoraController {
+            lora,
+            modulation_params,
+            tx_packet_params,
+            rx_packet_params,
+            tx_power: config.tx_power,
+            spreading_factor: LORA_SPREADING_FACTOR,
+            frequency: LORA_FREQUENCY_IN_HZ,
+            hop_schedule: HopSchedule::new(config.hop_channels),
+            rx_buffer: heapless::Vec::new(),
+            tx_buffer: heapless::Vec::new(),
+            consecutive_radio_errors: 0,
+        })
+    }
+
+    /// Current transmit power in dBm.
+    pub fn tx_power(&self) -> i32 {
+        self.tx_power
+    }
+
+    /// Changes the transmit power, applied from the next transmission onwards.
+    ///
+    /// Warns if it may exceed the band's limits.
+    pub fn set_tx_power(&mut self, tx_power: i32) -> Result<(), LoraError> {
+        check_tx_power(tx_power)?;
+        if tx_power > LORA_BAND_MAX_ERP_DBM {
+            warn!(
+                "phy: TX power {=i32} dBm exceeds the band limit of {=i32} dBm ERP, make sure the antenna compensates",
+                tx_power, LORA_BAND_MAX_ERP_DBM
+            );
+        }
+        self.tx_power = tx_power;
+        Ok(())
+    }
+
+    /// Current spreading factor.
+    pub fn spreading_factor(&self) -> u8 {
+        self.spreading_factor
+    }
+
+    /// Changes the spreading factor, applied from the next transmission or reception onwards.
+    pub fn set_spreading_factor(&mut self, spreading_factor: u8) -> Result<(), LoraError> {
+        self.set_params(spreading_factor, self.frequency)
+    }
+
+    /// Current channel, in Hz.
+    pub fn frequency(&self) -> u32 {
+        self.frequency
+    }
 
+    /// Moves to the next channel of the hopping schedule, applied from the next transmission or reception onwards.
+    pub fn hop(&mut self) -> Result<(), LoraError> {
+        let Some(frequency) = self.hop_schedule.advance() else {
+            return Ok(());
+        };
+        trace!("phy: hopping to {=u32} Hz", frequency);
+        self.set_params(self.spreading_factor, frequency)
+    }
+
+    /// Goes back to [`LORA_FREQUENCY_IN_HZ`] and to the beginning of the hopping schedule.
+    pub fn reset_channel(&mut self) -> Result<(), LoraError> {
+        self.hop_schedule.reset();
+        if self.frequency == LORA_FREQUENCY_IN_HZ {
+            return Ok(());
+        }
+        self.set_params(self.spreading_factor, LORA_FREQUENCY_IN_HZ)
+    }
+
+    /// Whether the last [`LORA_MAX_CONSECUTIVE_RADIO_ERRORS`] transmissions or receptions all failed,
+    /// in which case the radio may be stuck and should go through [`Self::reinit`].
+    pub fn needs_reinit(&self) -> bool {
+        self.consecutive_radio_errors >= LORA_MAX_CONSECUTIVE_RADIO_ERRORS
+    }
+
+    /// Resets the radio through its reset pin and applies the current settings again,
+    /// to recover from failed SPI transactions that may have left it in an inconsistent state.
+    pub async fn reinit(&mut self) -> Result<(), LoraError> {
+        warn!(
+            "phy: resetting the radio after {=u8} consecutive errors",
+            self.consecutive_radio_errors
+        );
+        self.consecutive_radio_errors = 0;
+        self.tx_buffer.clear();
+        self.lora.init().await?;
+        self.set_params(self.spreading_factor, self.frequency)
+    }
+
+    /// Keeps track of consecutive radio errors, see [`Self::needs_reinit`].
+    fn count_radio_errors<T>(&mut self, res: Result<T, LoraError>) -> Result<T, LoraError> {
+        match &res {
+            Ok(_) => self.consecutive_radio_errors = 0,
+            Err(LoraError::Radio(_)) => {
+                self.consecutive_radio_errors = self.consecutive_radio_errors.saturating_add(1);
+            }
+            Err(_) => (),
+        }
+        res
+    }
+
+    fn set_params(&mut self, spreading_factor: u8, frequency: u32) -> Result<(), LoraError> {
+        let (modulation_params, tx_packet_params, rx_packet_params) =
+            Self::create_params(&mut self.lora, spreading_factor, frequency)?;
+        self.modulation_params = modulation_params;
+        self.tx_packet_params = tx_packet_params;
+        self.rx_packet_params = rx_packet_params;
+        self.spreading_factor = spreading_factor;
+        self.frequency = frequency;
+        Ok(())
+    }
+
+    /// Creates the modulation and packet parameters for `spreading_factor` and `frequency`, the latter depend on the former.
+    fn create_params(
+        lora: &mut TBeamLora32Lora,
+        spreading_factor: u8,
+        frequency: u32,
+    ) -> Result<(ModulationParams, PacketParams, PacketParams), LoraError> {
         let modulation_params = lora.create_modulation_params(
-            LORA_SPREADING_FACTOR,
+            to_spreading_factor(spreading_factor)?,
             LORA_BANDWITH,
             LORA_CODING_RATE,
-            LORA_FREQUENCY_IN_HZ,
+            frequency,
         )?;
 
         // Don't ask: I don't know what that is either
@@ -141,14 +356,7 @@ impl LoraController {
             &modulation_params,
         )?;
 
-        Ok(LoraController {
-            lora,
-            modulation_params,
-            tx_packet_params,
-            rx_packet_params,
-            rx_buffer: heapless::Vec::new(),
-            tx_buffer: heapless::Vec::new(),
-        })
+        Ok((modulation_params, tx_packet_params, rx_packet_params))
     }
 
     async fn send(&mut self) -> Result<(), LoraError> {
@@ -156,7 +364,7 @@ impl LoraController {
             .prepare_for_tx(
                 &self.modulation_params,
                 &mut self.tx_packet_params,
-                20,
+                self.tx_power,
                 &self.tx_buffer,
             )
             .await?;
@@ -168,7 +376,8 @@ impl LoraController {
         Ok(())
     }
 
-    async fn recv(&mut self) -> Result<(), LoraError> {
+    /// Waits for a packet for at most `timeout`, returns whether one was received.
+    async fn recv(&mut self, timeout: Duration) -> Result<bool, LoraError> {
         self.lora
             .prepare_for_rx(
                 lora_phy::RxMode::Continuous,
@@ -180,11 +389,14 @@ impl LoraController {
         unsafe {
             self.rx_buffer.set_len(LORA_RX_BUF_SIZE);
         }
-        trace!("phy: waiting for data (timeout in 5 seconds)");
+        trace!(
+            "phy: waiting for data (timeout in {=u64} ms)",
+            timeout.as_millis()
+        );
 
         let res = embassy_futures::select::select(
             self.lora.rx(&self.rx_packet_params, &mut self.rx_buffer),
-            embassy_time::Timer::after(embassy_time::Duration::from_secs(5)),
+            embassy_time::Timer::after(timeout),
         )
         .await;
 
@@ -200,34 +412,75 @@ impl LoraController {
                     rx_pkt_status.rssi,
                     rx_pkt_status.snr
                 );
+                Ok(true)
             }
             Either::Second(()) => {
                 trace!("phy: timeout while waiting for data");
                 self.rx_buffer.clear();
+                Ok(false)
             }
         }
-
-        Ok(())
     }
 
     pub async fn sleep(&mut self, wakeup: bool) -> Result<(), LoraError> {
-        Ok(self.lora.sleep(wakeup).await?)
+        let res = self.lora.sleep(wakeup).await.map_err(LoraError::from);
+        self.count_radio_errors(res)
     }
 }
 
+/// Converts a spreading factor number to its radio setting, the SX1276 doesn't support SF5.
+fn to_spreading_factor(spreading_factor: u8) -> Result<SpreadingFactor, LoraError> {
+    Ok(match spreading_factor {
+        6 => SpreadingFactor::_6,
+        7 => SpreadingFactor::_7,
+        8 => SpreadingFactor::_8,
+        9 => SpreadingFactor::_9,
+        10 => SpreadingFactor::_10,
+        11 => SpreadingFactor::_11,
+        12 => SpreadingFactor::_12,
+        _ => return Err(LoraError::InvalidSpreadingFactor(spreading_factor)),
+    })
+}
+
+/// Checks that `tx_power` is supported by the radio.
+fn check_tx_power(tx_power: i32) -> Result<(), LoraError> {
+    if !LORA_TX_POWER_RANGE_DBM.contains(&tx_power) {
+        error!(
+            "phy: TX power {=i32} dBm is outside of the supported range ({=i32} to {=i32} dBm)",
+            tx_power,
+            *LORA_TX_POWER_RANGE_DBM.start(),
+            *LORA_TX_POWER_RANGE_DBM.end()
+        );
+        return Err(LoraError::InvalidTxPower(tx_power));
+    }
+    Ok(())
+}
+
 impl PhysicalLayer for LoraController {
     type Error = LoraError;
 
     async fn read(&mut self) -> Result<(), Self::Error> {
         self.rx_buffer.clear();
-        self.recv().await?;
+        // an empty buffer on timeout lets callers poll in a loop
+        let res = self.recv(LORA_RX_TIMEOUT).await;
+        self.count_radio_errors(res)?;
         Ok(())
     }
 
+    async fn read_timeout(&mut self, timeout: Duration) -> Result<bool, Self::Error> {
+        self.rx_buffer.clear();
+        let res = self.recv(timeout).await;
+        self.count_radio_errors(res)
+    }
+
     fn rx_buffer(&self) -> &[u8] {
         &self.rx_buffer
     }
 
+    fn max_payload_len(&self) -> usize {
+        LORA_RX_BUF_SIZE
+    }
+
     async fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
         self.tx_buffer
             .extend_from_slice(data)
@@ -238,6 +491,7 @@ impl PhysicalLayer for LoraController {
         if self.tx_buffer.is_empty() {
             return Ok(());
         }
-        self.send().await
+        let res = self.send().await;
+        self.count_radio_errors(res)
     }
 }
@@ -0,0 +1,107 @@
+//! Checks run on boot, so that a broken peripheral is reported instead of producing garbage values.
+
+use defmt::{error, info, Format};
+
+use crate::sensor::{Bmp280, DustSensor};
+
+/// Value of the BMP280 `id` register.
+pub const BMP280_CHIP_ID: u8 = 0x58;
+/// Highest raw value of the 12-bit ADC reading the dust sensor.
+pub const DUST_SENSOR_ADC_FULL_SCALE: u16 = 4095;
+
+/// Outcome of the self-test of one subsystem.
+#[derive(Clone, Copy, PartialEq, Eq, Format)]
+pub enum TestResult {
+    Passed,
+    Failed,
+}
+
+impl TestResult {
+    fn from_passed(passed: bool) -> Self {
+        if passed {
+            TestResult::Passed
+        } else {
+            TestResult::Failed
+        }
+    }
+}
+
+/// Outcome of [`self_test`] for each subsystem.
+#[derive(Clone, Copy, Format)]
+pub struct SelfTestReport {
+    /// Pressure and temperature sensor answers with the expected chip ID
+    pub bmp280: TestResult,
+    /// Dust sensor reading is neither railed at 0 nor at full scale
+    pub dust_sensor: TestResult,
+    /// Radio was initialized
+    pub lora: TestResult,
+}
+
+impl SelfTestReport {
+    /// Whether every subsystem passed.
+    pub fn passed(&self) -> bool {
+        [self.bmp280, self.dust_sensor, self.lora]
+            .iter()
+            .all(|&res| res == TestResult::Passed)
+    }
+}
+
+/// Checks the peripherals, logging the ones that failed.
+///
+/// The BMP280 and the radio are initialized beforehand, `bmp` is `None` and `lora_initialized`
+/// is `false` when that failed.
+pub async fn self_test(
+    bmp: Option<&mut Bmp280>,
+    dust_sensor: &mut DustSensor,
+    lora_initialized: bool,
+) -> SelfTestReport {
+    let bmp280 = match bmp {
+        Some(bmp) => {
+            let chip_id = bmp.id();
+            let res = TestResult::from_passed(chip_id == BMP280_CHIP_ID);
+            if res == TestResult::Failed {
+                error!(
+                    "self-test: unexpected BMP280 chip ID {=u8:#x}, expected {=u8:#x}",
+                    chip_id, BMP280_CHIP_ID
+                );
+            }
+            res
+        }
+        None => {
+            error!("self-test: BMP280 failed to initialize");
+            TestResult::Failed
+        }
+    };
+
+    let dust_sensor = match dust_sensor.read().await {
+        Ok(value) if value == 0 || value >= DUST_SENSOR_ADC_FULL_SCALE => {
+            error!(
+                "self-test: dust sensor reading is railed at {=u16}, check its wiring",
+                value
+            );
+            TestResult::Failed
+        }
+        Ok(_) => TestResult::Passed,
+        Err(e) => {
+            error!("self-test: failed to read dust sensor: {:?}", e);
+            TestResult::Failed
+        }
+    };
+
+    let lora = TestResult::from_passed(lora_initialized);
+    if lora == TestResult::Failed {
+        error!("self-test: LoRa radio failed to initialize");
+    }
+
+    let report = SelfTestReport {
+        bmp280,
+        dust_sensor,
+        lora,
+    };
+    if report.passed() {
+        info!("self-test: all subsystems passed");
+    } else {
+        error!("self-test: {}", report);
+    }
+    report
+}
@@ -0,0 +1,92 @@
+//! Common interface of the sensors attached to the board.
+//!
+//! Adding a sensor means implementing [`Sensor`] and adding a variant to [`AnySensor`].
+
+use bmp280_ehal::BMP280;
+use defmt::{info, Format};
+use dust_sensor_gp2y1014au::Gp2y1014au;
+use esp_hal::{i2c::master::I2c, peripherals::ADC2, Async};
+use protocol::app::v1::SensorValue;
+use thiserror::Error;
+
+use crate::board::DustSensorData;
+
+pub type Bmp280 = BMP280<I2c<'static, Async>>;
+pub type DustSensor = Gp2y1014au<'static, ADC2, DustSensorData>;
+
+/// Maximum number of values produced by a single measurement of one sensor.
+pub const MAX_VALUES_PER_SENSOR: usize = 4;
+
+pub type SensorValues = heapless::Vec<SensorValue, MAX_VALUES_PER_SENSOR>;
+
+#[derive(Debug, Error, Format)]
+pub enum SensorError {
+    #[error("failed to read the dust sensor")]
+    DustSensorRead,
+}
+
+pub trait Sensor {
+    /// Name used in logs.
+    fn name(&self) -> &'static str;
+
+    /// Takes one measurement, returns every value it produced.
+    async fn measure(&mut self) -> Result<SensorValues, SensorError>;
+}
+
+impl Sensor for Bmp280 {
+    fn name(&self) -> &'static str {
+        "BMP280"
+    }
+
+    async fn measure(&mut self) -> Result<SensorValues, SensorError> {
+        let pressure = self.pressure_one_shot() as f32;
+        let temperature = self.temp_one_shot() as f32;
+        info!("Measured pressure: {}Pa", pressure);
+        info!("Measured temperature: {}°C", temperature);
+
+        let mut values = SensorValues::new();
+        // the capacity fits the values of every sensor
+        _ = values.push(SensorValue::Pressure(pressure));
+        _ = values.push(SensorValue::Temperature(temperature));
+        Ok(values)
+    }
+}
+
+impl Sensor for DustSensor {
+    fn name(&self) -> &'static str {
+        "GP2Y1014AU"
+    }
+
+    async fn measure(&mut self) -> Result<SensorValues, SensorError> {
+        let density = Gp2y1014au::measure(self)
+            .await
+            .map_err(|_| SensorError::DustSensorRead)?;
+        info!("Measured dust density: {}mg/m3", density);
+
+        let mut values = SensorValues::new();
+        _ = values.push(SensorValue::AirQuality(density));
+        Ok(values)
+    }
+}
+
+/// Static dispatch over the sensors of the board, avoiding trait objects and allocations.
+pub enum AnySensor {
+    Bmp280(Bmp280),
+    Dust(DustSensor),
+}
+
+impl Sensor for AnySensor {
+    fn name(&self) -> &'static str {
+        match self {
+            AnySensor::Bmp280(sensor) => sensor.name(),
+            AnySensor::Dust(sensor) => sensor.name(),
+        }
+    }
+
+    async fn measure(&mut self) -> Result<SensorValues, SensorError> {
+        match self {
+            AnySensor::Bmp280(sensor) => sensor.measure().await,
+            AnySensor::Dust(sensor) => sensor.measure().await,
+        }
+    }
+}
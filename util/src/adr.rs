@@ -0,0 +1,147 @@
+//! Adaptive data rate: picks the LoRa spreading factor of a link from the SNR of its packets.
+
+/// Lowest spreading factor recommended, supported by every LoRa radio.
+pub const MIN_SPREADING_FACTOR: u8 = 7;
+/// Highest spreading factor recommended, the most robust one.
+pub const MAX_SPREADING_FACTOR: u8 = 12;
+/// Margin in dB kept above the demodulation floor, to absorb fading.
+pub const ADR_MARGIN_DB: i16 = 10;
+/// Extra margin in dB required before lowering the spreading factor, so that it doesn't oscillate around a threshold.
+pub const ADR_HYSTERESIS_DB: i16 = 3;
+/// Number of packets whose SNR is averaged for each decision.
+pub const ADR_WINDOW: u8 = 8;
+
+/// Lowest SNR in dB at which packets can be demodulated, for each spreading factor from SF7 to SF12.
+///
+/// Values of the SX126x and SX127x datasheets, rounded towards zero.
+const DEMODULATION_FLOOR_DB: [i16; 6] = [-7, -10, -12, -15, -17, -20];
+
+/// Recommends a spreading factor for a single link.
+///
+/// Raising the spreading factor is done as soon as the link degrades, while lowering it needs
+/// an additional [`ADR_HYSTERESIS_DB`] of margin.
+#[derive(Clone, Copy, Debug)]
+pub struct Adr {
+    spreading_factor: u8,
+    snr_sum: i32,
+    samples: u8,
+}
+
+impl Adr {
+    /// Starts from `spreading_factor`, the one currently used by the link.
+    pub const fn new(spreading_factor: u8) -> Self {
+        Self {
+            spreading_factor,
+            snr_sum: 0,
+            samples: 0,
+        }
+    }
+
+    /// Spreading factor currently used by the link, as far as this policy knows.
+    pub fn spreading_factor(&self) -> u8 {
+        self.spreading_factor
+    }
+
+    /// Forgets the collected samples, to be called when the link goes back to `spreading_factor`.
+    pub fn reset(&mut self, spreading_factor: u8) {
+        *self = Self::new(spreading_factor);
+    }
+
+    /// Accounts for the SNR in dB of a received packet.
+    ///
+    /// Returns the spreading factor the link should switch to, every [`ADR_WINDOW`] packets at most.
+    pub fn update(&mut self, snr: i16) -> Option<u8> {
+        self.snr_sum += i32::from(snr);
+        self.samples += 1;
+        if self.samples < ADR_WINDOW {
+            return None;
+        }
+
+        let snr = self.snr_sum.div_euclid(i32::from(self.samples)) as i16;
+        self.snr_sum = 0;
+        self.samples = 0;
+
+        let needed = lowest_spreading_factor(snr, ADR_MARGIN_DB);
+        let lowered = lowest_spreading_factor(snr, ADR_MARGIN_DB + ADR_HYSTERESIS_DB);
+        let target = if needed > self.spreading_factor {
+            needed
+        } else if lowered < self.spreading_factor {
+            lowered
+        } else {
+            return None;
+        };
+
+        self.spreading_factor = target;
+        Some(target)
+    }
+}
+
+/// Lowest spreading factor that keeps `margin` dB between `snr` and its demodulation floor.
+fn lowest_spreading_factor(snr: i16, margin: i16) -> u8 {
+    (MIN_SPREADING_FACTOR..=MAX_SPREADING_FACTOR)
+        .zip(DEMODULATION_FLOOR_DB)
+        .find(|&(_, floor)| snr - floor >= margin)
+        .map_or(MAX_SPREADING_FACTOR, |(spreading_factor, _)| {
+            spreading_factor
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn feed(adr: &mut Adr, snr: i16) -> Option<u8> {
+        (0..ADR_WINDOW).fold(None, |_, _| adr.update(snr))
+    }
+
+    #[test]
+    fn test_adr_waits_for_window() {
+        let mut adr = Adr::new(10);
+        for _ in 1..ADR_WINDOW {
+            assert_eq!(adr.update(10), None);
+        }
+        assert_eq!(adr.update(10), Some(7));
+        assert_eq!(adr.spreading_factor(), 7);
+    }
+
+    #[test]
+    fn test_adr_strong_and_weak_links() {
+        let mut adr = Adr::new(10);
+        assert_eq!(feed(&mut adr, 10), Some(7));
+        // already the lowest
+        assert_eq!(feed(&mut adr, 15), None);
+
+        // SF12 keeps less than the margin, but there is nothing more robust
+        assert_eq!(feed(&mut adr, -15), Some(12));
+        assert_eq!(feed(&mut adr, -25), None);
+    }
+
+    #[test]
+    fn test_adr_hysteresis() {
+        let mut adr = Adr::new(10);
+        // SF9 would keep the margin, but not the hysteresis on top of it
+        assert_eq!(feed(&mut adr, 0), None);
+        assert_eq!(feed(&mut adr, 1), Some(9));
+        // back and forth around the threshold
+        assert_eq!(feed(&mut adr, 0), None);
+        assert_eq!(feed(&mut adr, -2), None);
+        assert_eq!(feed(&mut adr, 1), None);
+        // below the margin of SF9
+        assert_eq!(feed(&mut adr, -3), Some(10));
+    }
+
+    #[test]
+    fn test_adr_averages_samples() {
+        let mut adr = Adr::new(10);
+        for i in 0..ADR_WINDOW {
+            // a single bad packet doesn't outweigh the rest of the window
+            let snr = if i == 0 { -15 } else { 0 };
+            adr.update(snr);
+        }
+        assert_eq!(adr.spreading_factor(), 10);
+
+        adr.reset(12);
+        assert_eq!(adr.spreading_factor(), 12);
+        assert_eq!(adr.update(10), None);
+    }
+}
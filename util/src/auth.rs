@@ -0,0 +1,51 @@
+//! Salted password hashing for the configuration dashboard.
+
+use sha2::{Digest, Sha256};
+
+/// Number of SHA-256 rounds used to hash a password, slows down brute-forcing a leaked hash
+pub const PASSWORD_HASH_ROUNDS: u32 = 1024;
+
+/// Hashes `password` with `salt`, the result can be kept instead of the password itself.
+pub fn hash_password(salt: &[u8; 16], password: &[u8]) -> [u8; 32] {
+    let mut hash: [u8; 32] = Sha256::new()
+        .chain_update(salt)
+        .chain_update(password)
+        .finalize()
+        .into();
+    for _ in 1..PASSWORD_HASH_ROUNDS {
+        hash = Sha256::new()
+            .chain_update(salt)
+            .chain_update(hash)
+            .finalize()
+            .into();
+    }
+    hash
+}
+
+/// Compares two byte strings in constant time for a given length.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hash_password() {
+        let salt = [7u8; 16];
+        let hash = hash_password(&salt, b"hunter2");
+
+        assert_eq!(hash, hash_password(&salt, b"hunter2"));
+        assert_ne!(hash, hash_password(&salt, b"hunter3"));
+        assert_ne!(hash, hash_password(&[8u8; 16], b"hunter2"));
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"admin", b"admin"));
+        assert!(!constant_time_eq(b"admin", b"admim"));
+        assert!(!constant_time_eq(b"admin", b"admin2"));
+        assert!(constant_time_eq(b"", b""));
+    }
+}
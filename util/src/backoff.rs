@@ -0,0 +1,125 @@
+//! Retry delay computation.
+
+/// Capped exponential backoff with jitter.
+///
+/// Each call to [`Backoff::next_delay_ms`] doubles the nominal delay, starting from `base_ms`
+/// and never exceeding `cap_ms`.
+/// The returned delay is picked at random between half the nominal delay and the nominal delay,
+/// so that devices that started at the same time do not keep retrying in lockstep.
+pub struct Backoff {
+    base_ms: u64,
+    cap_ms: u64,
+    attempt: u32,
+    rng_state: u32,
+}
+
+impl Backoff {
+    /// Creates a new backoff policy, `seed` is used to initialize the jitter generator.
+    pub const fn new(base_ms: u64, cap_ms: u64, seed: u32) -> Self {
+        Self {
+            base_ms,
+            cap_ms,
+            attempt: 0,
+            // xorshift gets stuck on zero
+            rng_state: if seed == 0 { 0x9e37_79b9 } else { seed },
+        }
+    }
+
+    /// Returns the delay to wait before the next attempt, in milliseconds.
+    pub fn next_delay_ms(&mut self) -> u64 {
+        let nominal = self.nominal_delay_ms();
+        self.attempt = self.attempt.saturating_add(1);
+
+        let min = nominal / 2;
+        let spread = nominal - min + 1;
+        min + u64::from(self.next_random()) % spread
+    }
+
+    /// Starts over from the base delay, to be called after a successful attempt.
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+
+    fn nominal_delay_ms(&self) -> u64 {
+        let factor = 1u64.checked_shl(self.attempt).unwrap_or(u64::MAX);
+        self.base_ms.saturating_mul(factor).min(self.cap_ms)
+    }
+
+    fn next_random(&mut self) -> u32 {
+        // xorshift32
+        let mut x = self.rng_state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.rng_state = x;
+        x
+    }
+}
+
+/// Derives a jitter seed from arbitrary bytes, such as a MAC address (FNV-1a).
+pub fn seed_from_bytes(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5u32, |hash, &b| {
+        (hash ^ u32::from(b)).wrapping_mul(0x0100_0193)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_backoff_grows_and_caps() {
+        let mut backoff = Backoff::new(1000, 10_000, 42);
+        let expected_nominal = [1000, 2000, 4000, 8000, 10_000, 10_000];
+
+        for nominal in expected_nominal {
+            let delay = backoff.next_delay_ms();
+            assert!(
+                delay >= nominal / 2 && delay <= nominal,
+                "delay {delay} is not within [{}, {nominal}]",
+                nominal / 2
+            );
+        }
+    }
+
+    #[test]
+    fn test_backoff_reset() {
+        let mut backoff = Backoff::new(1000, 60_000, 1);
+
+        for _ in 0..10 {
+            backoff.next_delay_ms();
+        }
+        backoff.reset();
+        assert!(backoff.next_delay_ms() <= 1000);
+    }
+
+    #[test]
+    fn test_backoff_many_attempts() {
+        let mut backoff = Backoff::new(u64::MAX / 2, u64::MAX, 7);
+
+        for _ in 0..100 {
+            assert!(backoff.next_delay_ms() >= u64::MAX / 4);
+        }
+    }
+
+    #[test]
+    fn test_backoff_jitter_depends_on_seed() {
+        let delays = |seed| {
+            let mut backoff = Backoff::new(10_000, 10_000, seed);
+            [(); 8].map(|()| backoff.next_delay_ms())
+        };
+
+        assert_eq!(delays(1), delays(1));
+        assert_ne!(delays(1), delays(2));
+        // a zero seed must still produce jitter
+        assert!(delays(0).iter().any(|&d| d != delays(0)[0]));
+    }
+
+    #[test]
+    fn test_seed_from_bytes() {
+        let a = seed_from_bytes(&[0x24, 0x0a, 0xc4, 0x00, 0x00, 0x01]);
+        let b = seed_from_bytes(&[0x24, 0x0a, 0xc4, 0x00, 0x00, 0x02]);
+        assert_ne!(a, b);
+        assert_eq!(seed_from_bytes(&[]), 0x811c_9dc5);
+    }
+}
@@ -0,0 +1,152 @@
+//! Pressure and altitude conversions, using the International Standard Atmosphere (ISA) model.
+//!
+//! Pressures are in pascals, altitudes in meters above mean sea level.
+//! The model is valid in the troposphere, that is up to 11 000 meters.
+
+/// Standard pressure at mean sea level
+pub const STANDARD_SEA_LEVEL_PRESSURE: f32 = 101_325.0;
+
+/// Standard temperature at mean sea level, in kelvins
+const SEA_LEVEL_TEMPERATURE: f32 = 288.15;
+/// Standard temperature lapse rate, in kelvins per meter
+const LAPSE_RATE: f32 = 0.0065;
+/// `g * M / (R * L)`, with the standard gravity, molar mass of dry air, universal gas constant and lapse rate
+const PRESSURE_EXPONENT: f32 = 5.255_877;
+
+/// Ratio between the pressure at `altitude` and the pressure at sea level.
+fn pressure_ratio(altitude: f32) -> f32 {
+    powf(
+        1.0 - LAPSE_RATE * altitude / SEA_LEVEL_TEMPERATURE,
+        PRESSURE_EXPONENT,
+    )
+}
+
+/// Converts the pressure measured at `altitude` into the equivalent pressure at sea level (QNH),
+/// so that readings from stations at different elevations can be compared.
+pub fn sea_level_pressure(station_pressure: f32, altitude: f32) -> f32 {
+    station_pressure / pressure_ratio(altitude)
+}
+
+/// Inverse of [`sea_level_pressure`]: returns the pressure expected at `altitude` given the sea level pressure.
+pub fn station_pressure(sea_level_pressure: f32, altitude: f32) -> f32 {
+    sea_level_pressure * pressure_ratio(altitude)
+}
+
+/// Computes the altitude at which `pressure` is measured, given the current sea level pressure.
+///
+/// Use [`STANDARD_SEA_LEVEL_PRESSURE`] when the actual sea level pressure is unknown (pressure altitude).
+pub fn altitude(pressure: f32, sea_level_pressure: f32) -> f32 {
+    let ratio = powf(pressure / sea_level_pressure, 1.0 / PRESSURE_EXPONENT);
+    (1.0 - ratio) * SEA_LEVEL_TEMPERATURE / LAPSE_RATE
+}
+
+// `f32::powf` and friends are not available in `core`, the approximations below are accurate to about 1e-6.
+
+/// `base` raised to `exp`, for positive values of `base` only.
+fn powf(base: f32, exp: f32) -> f32 {
+    if base <= 0.0 {
+        return if base == 0.0 { 0.0 } else { f32::NAN };
+    }
+    expf(exp * lnf(base))
+}
+
+fn lnf(x: f32) -> f32 {
+    // x = m * 2^e, with m in [sqrt(1/2), sqrt(2)) so that the series converges quickly
+    let bits = x.to_bits();
+    let mut e = ((bits >> 23) & 0xff) as i32 - 127;
+    let mut m = f32::from_bits((bits & 0x007f_ffff) | 0x3f80_0000);
+    if m > core::f32::consts::SQRT_2 {
+        m *= 0.5;
+        e += 1;
+    }
+
+    // ln(m) = 2 * atanh(t) with t = (m - 1) / (m + 1)
+    let t = (m - 1.0) / (m + 1.0);
+    let t2 = t * t;
+    let series = t * (1.0 + t2 * (1.0 / 3.0 + t2 * (1.0 / 5.0 + t2 * (1.0 / 7.0 + t2 / 9.0))));
+    2.0 * series + e as f32 * core::f32::consts::LN_2
+}
+
+fn expf(x: f32) -> f32 {
+    if x > 88.0 {
+        return f32::INFINITY;
+    }
+    if x < -87.0 {
+        return 0.0;
+    }
+
+    // x = k * ln(2) + r, with |r| <= ln(2) / 2
+    let k = (x * core::f32::consts::LOG2_E + if x < 0.0 { -0.5 } else { 0.5 }) as i32;
+    let r = x - k as f32 * core::f32::consts::LN_2;
+
+    let mut term = 1.0f32;
+    let mut sum = 1.0f32;
+    for n in 1..=8 {
+        term *= r / n as f32;
+        sum += term;
+    }
+    sum * f32::from_bits(((k + 127) as u32) << 23)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assert_close(actual: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{actual} is not within {tolerance} of {expected}"
+        );
+    }
+
+    #[test]
+    fn test_standard_atmosphere() {
+        // ICAO standard atmosphere table
+        assert_close(
+            station_pressure(STANDARD_SEA_LEVEL_PRESSURE, 0.0),
+            101_325.0,
+            0.5,
+        );
+        assert_close(
+            station_pressure(STANDARD_SEA_LEVEL_PRESSURE, 1000.0),
+            89_874.6,
+            5.0,
+        );
+        // FL100
+        assert_close(
+            station_pressure(STANDARD_SEA_LEVEL_PRESSURE, 3048.0),
+            69_681.7,
+            5.0,
+        );
+        assert_close(altitude(50_000.0, STANDARD_SEA_LEVEL_PRESSURE), 5574.0, 1.0);
+    }
+
+    #[test]
+    fn test_sea_level_pressure() {
+        // 1013 hPa QNH at an airfield elevation of 500 ft, about 18 hPa less at the station
+        let station = station_pressure(101_300.0, 152.4);
+        assert_close(station, 99_483.0, 5.0);
+        assert_close(sea_level_pressure(station, 152.4), 101_300.0, 1.0);
+    }
+
+    #[test]
+    fn test_altitude_roundtrip() {
+        for altitude_m in [-400.0, 0.0, 44.0, 1500.0, 8848.0] {
+            let pressure = station_pressure(100_000.0, altitude_m);
+            assert_close(altitude(pressure, 100_000.0), altitude_m, 0.5);
+        }
+    }
+
+    #[test]
+    fn test_math_approximations() {
+        for x in [1e-3, 0.5, 0.70710677, 1.0, 1.5, 2.0, 10.0, 101_325.0] {
+            assert_close(lnf(x), x.ln(), 1e-5);
+        }
+        for x in [-20.0, -1.0, -0.1, 0.0, 0.3, 1.0, 5.255877, 20.0] {
+            assert_close(expf(x) / x.exp(), 1.0, 1e-6);
+        }
+        assert_close(powf(0.5, 5.255877), 0.5f32.powf(5.255877), 1e-6);
+        assert_eq!(powf(0.0, 2.0), 0.0);
+        assert!(powf(-1.0, 2.0).is_nan());
+    }
+}
@@ -0,0 +1,207 @@
+//! Line-based command interface over a serial port.
+
+/// Why a line could not be read, see [`LineBuffer::push`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LineError {
+    /// The line does not fit in the buffer, it is discarded up to the next line ending
+    TooLong,
+    InvalidUtf8,
+}
+
+/// Collects received bytes into lines.
+///
+/// Lines end with `\r` or `\n`, empty lines are skipped. Backspace and delete remove the last byte.
+pub struct LineBuffer<const N: usize> {
+    buf: heapless::Vec<u8, N>,
+    overflowed: bool,
+    complete: bool,
+}
+
+impl<const N: usize> LineBuffer<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: heapless::Vec::new(),
+            overflowed: false,
+            complete: false,
+        }
+    }
+
+    /// Accounts for a received byte, returns the line it completes if it is a line ending.
+    pub fn push(&mut self, byte: u8) -> Option<Result<&str, LineError>> {
+        if self.complete {
+            self.buf.clear();
+            self.overflowed = false;
+            self.complete = false;
+        }
+
+        match byte {
+            b'\r' | b'\n' if self.buf.is_empty() && !self.overflowed => None,
+            b'\r' | b'\n' => {
+                self.complete = true;
+                if self.overflowed {
+                    return Some(Err(LineError::TooLong));
+                }
+                Some(core::str::from_utf8(&self.buf).map_err(|_| LineError::InvalidUtf8))
+            }
+            0x08 | 0x7f => {
+                self.buf.pop();
+                None
+            }
+            _ => {
+                if self.buf.push(byte).is_err() {
+                    self.overflowed = true;
+                }
+                None
+            }
+        }
+    }
+}
+
+impl<const N: usize> Default for LineBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A command typed in the console, see [`parse_command`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ConsoleCommand<'a> {
+    Help,
+    /// Show the value of a single variable, or of all of them if `None`
+    Get(Option<&'a str>),
+    /// Assign a value to a variable, the value is empty to clear it
+    Set {
+        name: &'a str,
+        value: &'a str,
+    },
+    Save,
+    Reboot,
+    /// Zero the connection statistics
+    ResetStats,
+}
+
+/// Why a line is not a valid command, see [`parse_command`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CommandError<'a> {
+    Unknown(&'a str),
+    MissingArgument,
+    UnexpectedArgument,
+}
+
+/// Parses a line of the form `<command> [arguments]`.
+///
+/// `get` and `get config` show every variable. The value of `set <name> <value>` is the rest of the line,
+/// so that it may contain spaces, with surrounding whitespace trimmed.
+pub fn parse_command(line: &str) -> Result<ConsoleCommand<'_>, CommandError<'_>> {
+    let line = line.trim();
+    let (command, args) = line
+        .split_once(char::is_whitespace)
+        .map_or((line, ""), |(command, args)| (command, args.trim_start()));
+
+    let no_args = |command| {
+        if args.is_empty() {
+            Ok(command)
+        } else {
+            Err(CommandError::UnexpectedArgument)
+        }
+    };
+
+    match command {
+        "help" => no_args(ConsoleCommand::Help),
+        "get" if args.is_empty() || args == "config" => Ok(ConsoleCommand::Get(None)),
+        "get" if args.contains(char::is_whitespace) => Err(CommandError::UnexpectedArgument),
+        "get" => Ok(ConsoleCommand::Get(Some(args))),
+        "set" if args.is_empty() => Err(CommandError::MissingArgument),
+        "set" => {
+            let (name, value) = args
+                .split_once(char::is_whitespace)
+                .map_or((args, ""), |(name, value)| (name, value.trim()));
+            Ok(ConsoleCommand::Set { name, value })
+        }
+        "save" => no_args(ConsoleCommand::Save),
+        "reboot" => no_args(ConsoleCommand::Reboot),
+        "reset-stats" => no_args(ConsoleCommand::ResetStats),
+        command => Err(CommandError::Unknown(command)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn feed<const N: usize>(
+        lines: &mut LineBuffer<N>,
+        input: &[u8],
+    ) -> Vec<Result<String, LineError>> {
+        input
+            .iter()
+            .filter_map(|&byte| Some(lines.push(byte)?.map(str::to_owned)))
+            .collect()
+    }
+
+    #[test]
+    fn test_line_buffer() {
+        let mut lines = LineBuffer::<8>::new();
+
+        assert_eq!(
+            feed(&mut lines, b"save\r\n\nget\n"),
+            [Ok("save".to_owned()), Ok("get".to_owned())]
+        );
+        // backspace, then a line too long followed by a valid one
+        assert_eq!(
+            feed(&mut lines, b"rebooo\x08t\r123456789\rhelp\n"),
+            [
+                Ok("reboot".to_owned()),
+                Err(LineError::TooLong),
+                Ok("help".to_owned())
+            ]
+        );
+        assert_eq!(
+            feed(&mut lines, b"\xff\xfe\n"),
+            [Err(LineError::InvalidUtf8)]
+        );
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command("help"), Ok(ConsoleCommand::Help));
+        assert_eq!(parse_command("  get "), Ok(ConsoleCommand::Get(None)));
+        assert_eq!(parse_command("get config"), Ok(ConsoleCommand::Get(None)));
+        assert_eq!(
+            parse_command("get wifi_sta_ssid"),
+            Ok(ConsoleCommand::Get(Some("wifi_sta_ssid")))
+        );
+        assert_eq!(
+            parse_command("set wifi_sta_ssid  my home wifi "),
+            Ok(ConsoleCommand::Set {
+                name: "wifi_sta_ssid",
+                value: "my home wifi"
+            })
+        );
+        assert_eq!(
+            parse_command("set influx_db_host"),
+            Ok(ConsoleCommand::Set {
+                name: "influx_db_host",
+                value: ""
+            })
+        );
+        assert_eq!(parse_command("save"), Ok(ConsoleCommand::Save));
+        assert_eq!(parse_command("reboot"), Ok(ConsoleCommand::Reboot));
+        assert_eq!(parse_command("reset-stats"), Ok(ConsoleCommand::ResetStats));
+    }
+
+    #[test]
+    fn test_parse_invalid_command() {
+        assert_eq!(parse_command("set"), Err(CommandError::MissingArgument));
+        assert_eq!(
+            parse_command("reboot now"),
+            Err(CommandError::UnexpectedArgument)
+        );
+        assert_eq!(
+            parse_command("get a b"),
+            Err(CommandError::UnexpectedArgument)
+        );
+        assert_eq!(parse_command("sav"), Err(CommandError::Unknown("sav")));
+        assert_eq!(parse_command(""), Err(CommandError::Unknown("")));
+    }
+}
@@ -0,0 +1,127 @@
+//! Fixed-size cache of DNS lookup results.
+//!
+//! Times are given by the caller in milliseconds, from any monotonic clock.
+
+/// Maximum length of a cached host name, longer names are never cached.
+pub const MAX_HOST_LEN: usize = 64;
+
+struct Entry<A> {
+    host: heapless::String<MAX_HOST_LEN>,
+    address: A,
+    /// The entry is stale after this time
+    expires_at: u64,
+    /// Time of the last lookup, the least recently used entry is evicted first
+    last_used: u64,
+}
+
+/// Caches up to `N` addresses of type `A`.
+pub struct DnsCache<A, const N: usize> {
+    entries: heapless::Vec<Entry<A>, N>,
+}
+
+impl<A: Copy, const N: usize> DnsCache<A, N> {
+    pub const fn new() -> Self {
+        Self {
+            entries: heapless::Vec::new(),
+        }
+    }
+
+    /// Returns the address of `host` if it is cached and has not expired at `now`.
+    pub fn get(&mut self, host: &str, now: u64) -> Option<A> {
+        let entry = self.entries.iter_mut().find(|entry| entry.host == host)?;
+        if now >= entry.expires_at {
+            return None;
+        }
+        entry.last_used = now;
+        Some(entry.address)
+    }
+
+    /// Returns the address of `host` if it is cached, even if it has expired.
+    pub fn get_stale(&self, host: &str) -> Option<A> {
+        self.entries
+            .iter()
+            .find(|entry| entry.host == host)
+            .map(|entry| entry.address)
+    }
+
+    /// Caches the address of `host` for `ttl` milliseconds from `now`.
+    ///
+    /// Replaces the previous address of `host`, or evicts the least recently used entry if the cache is full.
+    pub fn insert(&mut self, host: &str, address: A, now: u64, ttl: u64) {
+        let Ok(host) = heapless::String::try_from(host) else {
+            return;
+        };
+        let entry = Entry {
+            host,
+            address,
+            expires_at: now.saturating_add(ttl),
+            last_used: now,
+        };
+
+        if let Some(i) = self.entries.iter().position(|e| e.host == entry.host) {
+            self.entries[i] = entry;
+        } else if let Err(entry) = self.entries.push(entry) {
+            if let Some(lru) = self.entries.iter_mut().min_by_key(|e| e.last_used) {
+                *lru = entry;
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+impl<A: Copy, const N: usize> Default for DnsCache<A, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_dns_cache_ttl() {
+        let mut cache: DnsCache<[u8; 4], 2> = DnsCache::new();
+        assert_eq!(cache.get("example.com", 0), None);
+
+        cache.insert("example.com", [93, 184, 215, 14], 1000, 500);
+        assert_eq!(cache.get("example.com", 1000), Some([93, 184, 215, 14]));
+        assert_eq!(cache.get("example.com", 1499), Some([93, 184, 215, 14]));
+        assert_eq!(cache.get("example.com", 1500), None);
+        assert_eq!(cache.get_stale("example.com"), Some([93, 184, 215, 14]));
+        assert_eq!(cache.get("example.org", 1000), None);
+
+        cache.insert("example.com", [1, 2, 3, 4], 2000, 500);
+        assert_eq!(cache.get("example.com", 2000), Some([1, 2, 3, 4]));
+
+        cache.clear();
+        assert_eq!(cache.get_stale("example.com"), None);
+    }
+
+    #[test]
+    fn test_dns_cache_evicts_least_recently_used() {
+        let mut cache: DnsCache<u8, 2> = DnsCache::new();
+
+        cache.insert("a", 1, 0, 1000);
+        cache.insert("b", 2, 10, 1000);
+        // "a" is now more recently used than "b"
+        assert_eq!(cache.get("a", 20), Some(1));
+
+        cache.insert("c", 3, 30, 1000);
+        assert_eq!(cache.get("a", 40), Some(1));
+        assert_eq!(cache.get_stale("b"), None);
+        assert_eq!(cache.get("c", 40), Some(3));
+    }
+
+    #[test]
+    fn test_dns_cache_long_host() {
+        let mut cache: DnsCache<u8, 2> = DnsCache::new();
+        let host = "a".repeat(MAX_HOST_LEN + 1);
+
+        cache.insert(&host, 1, 0, 1000);
+        assert_eq!(cache.get(&host, 0), None);
+    }
+}
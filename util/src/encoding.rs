@@ -9,7 +9,7 @@ pub struct DecodeFormUrlEncoded<'a> {
 /// URL-encoded strings are always longer or have the same size as the decoded string.
 ///
 /// See the unit tests for usage examples.
-pub fn decode_form_url_encoded(data: &mut [u8]) -> DecodeFormUrlEncoded {
+pub fn decode_form_url_encoded(data: &mut [u8]) -> DecodeFormUrlEncoded<'_> {
     DecodeFormUrlEncoded { data }
 }
 
@@ -97,6 +97,48 @@ impl<'a> Iterator for DecodeFormUrlEncoded<'a> {
     }
 }
 
+/// Decodes standard base64 (RFC 4648, `+` and `/` alphabet) into `out`, padding is optional.
+///
+/// Returns the number of decoded bytes, or `None` if `data` is not valid base64 or `out` is too small.
+pub fn decode_base64(data: &[u8], out: &mut [u8]) -> Option<usize> {
+    let data = match data {
+        // padding completes the last chunk to 4 characters
+        [rest @ .., b'=', b'='] if rest.len() % 4 == 2 => rest,
+        [rest @ .., b'='] if rest.len() % 4 == 3 => rest,
+        _ => data,
+    };
+    if data.len() % 4 == 1 {
+        return None;
+    }
+
+    let mut len = 0usize;
+    for chunk in data.chunks(4) {
+        let mut bits = 0u32;
+        for (i, &c) in chunk.iter().enumerate() {
+            bits |= u32::from(base64_digit_to_value(c)?) << (18 - 6 * i);
+        }
+        // a chunk of n characters holds n - 1 bytes, the remaining bits must be zero
+        let bytes = &bits.to_be_bytes()[1..chunk.len()];
+        if chunk.len() < 4 && bits & (0xff_ffff >> (8 * bytes.len())) != 0 {
+            return None;
+        }
+        out.get_mut(len..len + bytes.len())?.copy_from_slice(bytes);
+        len += bytes.len();
+    }
+    Some(len)
+}
+
+fn base64_digit_to_value(char: u8) -> Option<u8> {
+    match char {
+        b'A'..=b'Z' => Some(char - b'A'),
+        b'a'..=b'z' => Some(char - b'a' + 26),
+        b'0'..=b'9' => Some(char - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -175,4 +217,38 @@ mod test {
         let mut encoded: Vec<u8> = br#"csrf_token=%7B%7B+csrf_token+%7D%7D&wifi_sta_ssid=external+ssid&wifi_sta_password=1234&wifi_ap_ssid=apSEE+D&dns_server_1=1.1.1.1&dns_server_2=1.0.0.1&action=apply"#.to_vec();
         assert_eq!(url_decode(&mut encoded), b"csrf_token={{ csrf_token }}&wifi_sta_ssid=external ssid&wifi_sta_password=1234&wifi_ap_ssid=apSEE D&dns_server_1=1.1.1.1&dns_server_2=1.0.0.1&action=apply".as_ref());
     }
+
+    #[test]
+    fn test_decode_base64() {
+        let mut out = [0u8; 16];
+
+        for (encoded, decoded) in [
+            (b"".as_ref(), b"".as_ref()),
+            (b"Zg==", b"f"),
+            (b"Zm8=", b"fo"),
+            (b"Zm9v", b"foo"),
+            (b"Zm9vYg", b"foob"),
+            (b"Zm9vYmE=", b"fooba"),
+            (b"Zm9vYmFy", b"foobar"),
+            (b"YWRtaW46cGFzcw==", b"admin:pass"),
+            (b"//8=", b"\xff\xff"),
+        ] {
+            let len = decode_base64(encoded, &mut out);
+            assert_eq!(len.map(|len| &out[..len]), Some(decoded));
+        }
+    }
+
+    #[test]
+    fn test_decode_base64_invalid() {
+        let mut out = [0u8; 4];
+
+        assert_eq!(decode_base64(b"Z", &mut out), None);
+        assert_eq!(decode_base64(b"Zm9v!", &mut out), None);
+        assert_eq!(decode_base64(b"Zm=v", &mut out), None);
+        assert_eq!(decode_base64(b"Zg=", &mut out), None);
+        // non-zero trailing bits
+        assert_eq!(decode_base64(b"Zh==", &mut out), None);
+        // output buffer is too small
+        assert_eq!(decode_base64(b"Zm9vYmFy", &mut out), None);
+    }
 }
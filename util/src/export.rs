@@ -0,0 +1,814 @@
+//! Request bodies of the gateway exporters.
+//!
+//! Kept apart from the HTTP client so that the exact output can be checked on the host.
+
+use crate::json::{write_json_f32, write_json_str};
+use core::fmt::{self, Write};
+use protocol::app::v1::{SensorValue, SensorValuePoint};
+
+/// Returns the raw value and its type name, or `None` for unknown and multi-component values.
+pub fn value_with_type(value: SensorValue) -> Option<(f32, &'static str)> {
+    match value {
+        SensorValue::Temperature(v) => Some((v, "temperature")),
+        SensorValue::Pressure(v) => Some((v, "pressure")),
+        SensorValue::Altitude(v) => Some((v, "altitude")),
+        SensorValue::AirQuality(v) => Some((v, "dust_density")),
+        SensorValue::Humidity(v) => Some((v, "humidity")),
+        SensorValue::Co2(v) => Some((v, "co2_ppm")),
+        SensorValue::Voc(v) => Some((v, "voc")),
+        SensorValue::Location { .. } | SensorValue::Unknown { .. } => None,
+    }
+}
+
+/// Maximum number of values sent to sensor.community in a single request, larger batches are split.
+pub const SENSOR_COMMUNITY_MAX_VALUES_PER_REQUEST: usize = 8;
+
+/// Sensor types known to sensor.community, identified by the "pin" they are sent with.
+///
+/// `Altitude` and `Voc` values have no matching sensor type and are not sent to sensor.community.
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SensorCommunitySensor {
+    // sensor.community expects certain "pin" values for each sensor type
+    ParticulateMatter = 1,
+    TemperaturePressure = 3,
+    /// DHT22/SHT3x-like sensors
+    Humidity = 7,
+    Gps = 9,
+    /// SCD30-like sensors
+    Co2 = 17,
+}
+
+impl SensorCommunitySensor {
+    pub const ALL: [Self; 5] = [
+        Self::ParticulateMatter,
+        Self::TemperaturePressure,
+        Self::Humidity,
+        Self::Gps,
+        Self::Co2,
+    ];
+
+    /// Value of the `X-Pin` header.
+    pub const fn pin(self) -> u8 {
+        self as u8
+    }
+
+    pub fn supports_value(self, value: SensorValue) -> bool {
+        match self {
+            Self::ParticulateMatter => matches!(value, SensorValue::AirQuality(_)),
+            Self::TemperaturePressure => matches!(
+                value,
+                SensorValue::Temperature(_) | SensorValue::Pressure(_)
+            ),
+            Self::Humidity => matches!(value, SensorValue::Humidity(_)),
+            Self::Gps => matches!(value, SensorValue::Location { .. }),
+            Self::Co2 => matches!(value, SensorValue::Co2(_)),
+        }
+    }
+}
+
+/// Writes the JSON body of a sensor.community request for `sensor`, with at most
+/// [`SENSOR_COMMUNITY_MAX_VALUES_PER_REQUEST`] values taken from the start of `values`.
+///
+/// Returns the number of values consumed from `values` (including skipped unsupported ones)
+/// and the number of values written.
+pub fn write_sensor_community_body<W: Write + ?Sized>(
+    out: &mut W,
+    sensor: SensorCommunitySensor,
+    values: &[SensorValuePoint],
+) -> Result<(usize, u32), fmt::Error> {
+    out.write_str(r#"{"sensordatavalues":["#)?;
+
+    let mut consumed: usize = 0;
+    let mut written: u32 = 0;
+
+    for value in values.iter().copied() {
+        if written as usize == SENSOR_COMMUNITY_MAX_VALUES_PER_REQUEST {
+            break;
+        }
+        consumed += 1;
+        if !sensor.supports_value(value.value) {
+            continue;
+        }
+        write_sensor_community_value(out, value.value, written == 0)?;
+        written += 1;
+    }
+    out.write_str("]}")?;
+    Ok((consumed, written))
+}
+
+fn write_sensor_community_value<W: Write + ?Sized>(
+    out: &mut W,
+    value: SensorValue,
+    first_value: bool,
+) -> fmt::Result {
+    if let SensorValue::Location { lat, lon } = value {
+        // the GPS "sensor" expects one entry per coordinate
+        write_sensor_community_entry(out, lat, "lat", first_value)?;
+        return write_sensor_community_entry(out, lon, "lon", false);
+    }
+
+    let Some((value, value_type)) = value_with_type(value) else {
+        return Ok(());
+    };
+    write_sensor_community_entry(out, value, value_type, first_value)
+}
+
+fn write_sensor_community_entry<W: Write + ?Sized>(
+    out: &mut W,
+    value: f32,
+    value_type: &str,
+    first_value: bool,
+) -> fmt::Result {
+    if !first_value {
+        out.write_char(',')?;
+    }
+    out.write_str(r#"{"value":"#)?;
+    write_json_f32(out, value)?;
+    out.write_str(r#","value_type":"#)?;
+    write_json_str(out, value_type)?;
+    out.write_char('}')
+}
+
+/// Writes `values` in the InfluxDB line protocol, one line per value.
+///
+/// Each line carries the `(key, value)` pairs of `tags` after its measurement name, escaped as needed.
+/// Tags with an empty value are left out, as InfluxDB rejects them.
+/// Returns the number of values written, unknown values are skipped.
+pub fn write_influxdb_body<W: Write + ?Sized>(
+    out: &mut W,
+    values: &[SensorValuePoint],
+    tags: &[(&str, &str)],
+) -> Result<u32, fmt::Error> {
+    let mut exported_count: u32 = 0;
+    for value in values.iter().copied() {
+        if write_influxdb_line(out, value, tags, exported_count == 0)? {
+            exported_count += 1;
+        }
+    }
+    Ok(exported_count)
+}
+
+/// Returns whether a line was written, `false` for unknown values.
+fn write_influxdb_line<W: Write + ?Sized>(
+    out: &mut W,
+    value: SensorValuePoint,
+    tags: &[(&str, &str)],
+    first_value: bool,
+) -> Result<bool, fmt::Error> {
+    let measurement = match value.value {
+        SensorValue::Temperature(_) => "temperature",
+        SensorValue::Pressure(_) => "pressure",
+        SensorValue::Altitude(_) => "altitude",
+        SensorValue::AirQuality(_) => "dust_density",
+        SensorValue::Location { .. } => "location",
+        SensorValue::Humidity(_) => "humidity",
+        SensorValue::Co2(_) => "co2_ppm",
+        SensorValue::Voc(_) => "voc",
+        SensorValue::Unknown { .. } => return Ok(false),
+    };
+
+    if !first_value {
+        out.write_char('\n')?;
+    }
+    out.write_str(measurement)?;
+    for &(key, tag_value) in tags.iter().filter(|(_, tag_value)| !tag_value.is_empty()) {
+        out.write_char(',')?;
+        write_influxdb_tag_escaped(out, key)?;
+        out.write_char('=')?;
+        write_influxdb_tag_escaped(out, tag_value)?;
+    }
+    match value.value {
+        SensorValue::Temperature(v)
+        | SensorValue::Pressure(v)
+        | SensorValue::Altitude(v)
+        | SensorValue::AirQuality(v)
+        | SensorValue::Humidity(v)
+        | SensorValue::Co2(v)
+        | SensorValue::Voc(v) => write!(out, " value={v}")?,
+        SensorValue::Location { lat, lon } => write!(out, " lat={lat},lon={lon}")?,
+        SensorValue::Unknown { .. } => (),
+    }
+    Ok(true)
+}
+
+/// Writes a tag key or value, with a backslash before commas, equals signs and spaces.
+fn write_influxdb_tag_escaped<W: Write + ?Sized>(out: &mut W, tag: &str) -> fmt::Result {
+    for c in tag.chars() {
+        if matches!(c, ',' | '=' | ' ') {
+            out.write_char('\\')?;
+        }
+        out.write_char(c)?;
+    }
+    Ok(())
+}
+
+/// Writes `values` as a JSON array of `{"type", "value", "time_offset"}` objects.
+///
+/// Values of unknown types are written as `{"type": "unknown", "id", "raw", "time_offset"}` objects, with their raw
+/// bytes in hexadecimal, if these bytes were kept when decoding (see [`SensorValue::raw_bytes`]) and skipped otherwise.
+///
+/// Returns the number of values written.
+pub fn write_webhook_body<W: Write + ?Sized>(
+    out: &mut W,
+    values: &[SensorValuePoint],
+) -> Result<u32, fmt::Error> {
+    out.write_char('[')?;
+
+    let mut exported_count: u32 = 0;
+    for value in values.iter().copied() {
+        if let SensorValue::Location { lat, lon } = value.value {
+            write_webhook_location(out, lat, lon, value.time_offset, exported_count == 0)?;
+        } else if let Some((raw_value, value_type)) = value_with_type(value.value) {
+            write_webhook_value(
+                out,
+                value_type,
+                raw_value,
+                value.time_offset,
+                exported_count == 0,
+            )?;
+        } else if let (SensorValue::Unknown { id, .. }, Some(raw)) =
+            (value.value, value.value.raw_bytes())
+        {
+            write_webhook_unknown(out, id, raw, value.time_offset, exported_count == 0)?;
+        } else {
+            continue;
+        }
+        exported_count += 1;
+    }
+    out.write_char(']')?;
+    Ok(exported_count)
+}
+
+fn write_webhook_value<W: Write + ?Sized>(
+    out: &mut W,
+    value_type: &str,
+    value: f32,
+    time_offset: i64,
+    first_value: bool,
+) -> fmt::Result {
+    if !first_value {
+        out.write_char(',')?;
+    }
+    out.write_str(r#"{"type":"#)?;
+    write_json_str(out, value_type)?;
+    out.write_str(r#","value":"#)?;
+    write_json_f32(out, value)?;
+    write!(out, r#","time_offset":{time_offset}}}"#)
+}
+
+/// Same as `write_webhook_value()`, with a `{"lat", "lon"}` object as value.
+fn write_webhook_location<W: Write + ?Sized>(
+    out: &mut W,
+    lat: f32,
+    lon: f32,
+    time_offset: i64,
+    first_value: bool,
+) -> fmt::Result {
+    if !first_value {
+        out.write_char(',')?;
+    }
+    out.write_str(r#"{"type":"location","value":{"lat":"#)?;
+    write_json_f32(out, lat)?;
+    out.write_str(r#","lon":"#)?;
+    write_json_f32(out, lon)?;
+    write!(out, r#"}},"time_offset":{time_offset}}}"#)
+}
+
+/// Same as `write_webhook_value()`, with the ID of the value type and its raw bytes instead of the value.
+fn write_webhook_unknown<W: Write + ?Sized>(
+    out: &mut W,
+    id: u32,
+    raw: &[u8],
+    time_offset: i64,
+    first_value: bool,
+) -> fmt::Result {
+    if !first_value {
+        out.write_char(',')?;
+    }
+    write!(out, r#"{{"type":"unknown","id":{id},"raw":""#)?;
+    for byte in raw {
+        write!(out, "{byte:02x}")?;
+    }
+    write!(out, r#"","time_offset":{time_offset}}}"#)
+}
+
+/// Destination of exported values.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Exporter {
+    SensorCommunity = 0,
+    InfluxDb = 1,
+    Webhook = 2,
+}
+
+impl Exporter {
+    pub const ALL: [Self; 3] = [Self::SensorCommunity, Self::InfluxDb, Self::Webhook];
+
+    /// Name used in [`ExportRoutes::parse`].
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::SensorCommunity => "sensor_community",
+            Self::InfluxDb => "influxdb",
+            Self::Webhook => "webhook",
+        }
+    }
+
+    const fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// Names of the sensor value types, by ID, the same as in the request bodies.
+///
+/// Also lists the types kept by [`History`](crate::history::History), in the same order.
+pub const VALUE_TYPE_NAMES: [(u32, &str); 8] = [
+    (0, "temperature"),
+    (1, "pressure"),
+    (2, "altitude"),
+    (3, "dust_density"),
+    (5, "location"),
+    (6, "humidity"),
+    (7, "co2_ppm"),
+    (8, "voc"),
+];
+
+/// Exporters receiving each type of sensor value.
+///
+/// By default every type is sent to every exporter, values of unknown types are never restricted.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ExportRoutes {
+    /// Bitset of exporters, indexed by sensor value ID
+    exporters: [u8; 9],
+}
+
+/// Why [`ExportRoutes::parse`] failed, with the offending name.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExportRoutesError<'a> {
+    UnknownValueType(&'a str),
+    UnknownExporter(&'a str),
+    /// A route without the `:` separating the value type from its exporters
+    MissingSeparator(&'a str),
+}
+
+impl ExportRoutes {
+    pub const ALL: Self = Self {
+        exporters: [Exporter::SensorCommunity.bit()
+            | Exporter::InfluxDb.bit()
+            | Exporter::Webhook.bit(); 9],
+    };
+
+    /// Parses routes of the form `<type>:<exporter>+<exporter>,<type>:...`, e.g. `dust_density:sensor_community`.
+    ///
+    /// Types are named like in the request bodies, types that are not listed are sent to every exporter.
+    /// An empty exporter list (`voc:`) exports values of this type nowhere.
+    pub fn parse(routes: &str) -> Result<Self, ExportRoutesError<'_>> {
+        let mut parsed = Self::ALL;
+        for route in routes.split(',').map(str::trim).filter(|r| !r.is_empty()) {
+            let (type_name, exporters) = route
+                .split_once(':')
+                .ok_or(ExportRoutesError::MissingSeparator(route))?;
+            let type_name = type_name.trim();
+            let &(id, _) = VALUE_TYPE_NAMES
+                .iter()
+                .find(|&&(_, name)| name == type_name)
+                .ok_or(ExportRoutesError::UnknownValueType(type_name))?;
+
+            let mut bits = 0;
+            for name in exporters
+                .split('+')
+                .map(str::trim)
+                .filter(|n| !n.is_empty())
+            {
+                let exporter = Exporter::ALL
+                    .into_iter()
+                    .find(|exporter| exporter.name() == name)
+                    .ok_or(ExportRoutesError::UnknownExporter(name))?;
+                bits |= exporter.bit();
+            }
+            parsed.exporters[id as usize] = bits;
+        }
+        Ok(parsed)
+    }
+
+    /// Whether values of the type of `value` are sent to `exporter`.
+    pub fn accepts(&self, exporter: Exporter, value: SensorValue) -> bool {
+        !matches!(self.exporters.get(value.id() as usize), Some(bits) if bits & exporter.bit() == 0)
+    }
+
+    /// Whether `exporter` receives every type of value, so that values don't need to be filtered.
+    pub fn accepts_all(&self, exporter: Exporter) -> bool {
+        self.exporters.iter().all(|bits| bits & exporter.bit() != 0)
+    }
+}
+
+impl Default for ExportRoutes {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+/// Longest time an exporter is held off after being rate limited, in milliseconds, whatever the server asks for.
+pub const MAX_EXPORT_BACKOFF_MS: u64 = 60 * 60 * 1000;
+
+/// Exporters holding off after being rate limited, each until its own deadline.
+///
+/// Times are in milliseconds since an arbitrary instant, such as the boot.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct ExportBackoff {
+    /// Time until which each exporter is skipped, indexed by [`Exporter`]
+    until_ms: [Option<u64>; Exporter::ALL.len()],
+}
+
+impl ExportBackoff {
+    pub const fn new() -> Self {
+        Self {
+            until_ms: [None; Exporter::ALL.len()],
+        }
+    }
+
+    /// Skips `exporter` for `delay_ms` from `now_ms`, at most [`MAX_EXPORT_BACKOFF_MS`].
+    pub fn hold(&mut self, exporter: Exporter, now_ms: u64, delay_ms: u64) {
+        let delay_ms = delay_ms.min(MAX_EXPORT_BACKOFF_MS);
+        self.until_ms[exporter as usize] = Some(now_ms.saturating_add(delay_ms));
+    }
+
+    /// Returns how long `exporter` is still skipped for, `None` if it may export at `now_ms`.
+    pub fn remaining_ms(&mut self, exporter: Exporter, now_ms: u64) -> Option<u64> {
+        let until_ms = self.until_ms[exporter as usize]?;
+        if now_ms >= until_ms {
+            self.until_ms[exporter as usize] = None;
+            return None;
+        }
+        Some(until_ms - now_ms)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use protocol::app::v1::RawValue;
+
+    fn point(value: SensorValue, time_offset: i64) -> SensorValuePoint {
+        SensorValuePoint { value, time_offset }
+    }
+
+    fn sample_values() -> [SensorValuePoint; 5] {
+        [
+            point(SensorValue::Temperature(22.5), 10),
+            point(SensorValue::AirQuality(0.25), 11),
+            point(SensorValue::Pressure(101325.0), 12),
+            point(
+                SensorValue::Location {
+                    lat: 44.8412,
+                    lon: -0.5701,
+                },
+                13,
+            ),
+            point(
+                SensorValue::Unknown {
+                    id: 999,
+                    value_len: 2,
+                    raw: RawValue::EMPTY,
+                },
+                14,
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_sensor_community_body() {
+        let values = sample_values();
+        let mut body = String::new();
+
+        let res = write_sensor_community_body(
+            &mut body,
+            SensorCommunitySensor::TemperaturePressure,
+            &values,
+        );
+
+        assert_eq!(res, Ok((5, 2)));
+        assert_eq!(
+            body,
+            r#"{"sensordatavalues":[{"value":22.5,"value_type":"temperature"},{"value":101325,"value_type":"pressure"}]}"#
+        );
+    }
+
+    #[test]
+    fn test_sensor_community_body_gps() {
+        let values = sample_values();
+        let mut body = String::new();
+
+        let res = write_sensor_community_body(&mut body, SensorCommunitySensor::Gps, &values);
+
+        assert_eq!(res, Ok((5, 1)));
+        assert_eq!(
+            body,
+            r#"{"sensordatavalues":[{"value":44.8412,"value_type":"lat"},{"value":-0.5701,"value_type":"lon"}]}"#
+        );
+    }
+
+    #[test]
+    fn test_sensor_community_body_chunked() {
+        let values = [point(SensorValue::Humidity(40.0), 0); 10];
+        let mut body = String::new();
+
+        let res = write_sensor_community_body(&mut body, SensorCommunitySensor::Humidity, &values);
+
+        assert_eq!(res, Ok((8, 8)));
+        assert_eq!(body.matches(r#""value_type":"humidity""#).count(), 8);
+    }
+
+    #[test]
+    fn test_sensor_community_body_empty() {
+        let mut body = String::new();
+
+        let res = write_sensor_community_body(&mut body, SensorCommunitySensor::Co2, &[]);
+
+        assert_eq!(res, Ok((0, 0)));
+        assert_eq!(body, r#"{"sensordatavalues":[]}"#);
+    }
+
+    #[test]
+    fn test_influxdb_body() {
+        let values = sample_values();
+        let mut body = String::new();
+
+        let res = write_influxdb_body(&mut body, &values[..4], &[]);
+
+        assert_eq!(res, Ok(4));
+        assert_eq!(
+            body,
+            "temperature value=22.5\ndust_density value=0.25\npressure value=101325\nlocation lat=44.8412,lon=-0.5701"
+        );
+    }
+
+    #[test]
+    fn test_influxdb_body_tags() {
+        let values = sample_values();
+        let mut body = String::new();
+
+        let tags = [("gateway_id", "gw1"), ("location", "roof, north side")];
+        let res = write_influxdb_body(&mut body, &values[..2], &tags);
+
+        assert_eq!(res, Ok(2));
+        assert_eq!(
+            body,
+            concat!(
+                r"temperature,gateway_id=gw1,location=roof\,\ north\ side value=22.5",
+                "\n",
+                r"dust_density,gateway_id=gw1,location=roof\,\ north\ side value=0.25",
+            )
+        );
+
+        body.clear();
+        let res = write_influxdb_body(&mut body, &values[..1], &[("a=b", "c=d")]);
+        assert_eq!(res, Ok(1));
+        assert_eq!(body, r"temperature,a\=b=c\=d value=22.5");
+
+        body.clear();
+        let res = write_influxdb_body(
+            &mut body,
+            &values[..1],
+            &[("gateway_id", ""), ("location", "roof")],
+        );
+        assert_eq!(res, Ok(1));
+        assert_eq!(body, "temperature,location=roof value=22.5");
+    }
+
+    #[test]
+    fn test_influxdb_body_unknown() {
+        let values = sample_values();
+        let mut body = String::new();
+
+        // no empty line for the unknown values, wherever they are
+        let values = [values[4], values[0], values[4], values[1], values[4]];
+        let res = write_influxdb_body(&mut body, &values, &[]);
+
+        assert_eq!(res, Ok(2));
+        assert_eq!(body, "temperature value=22.5\ndust_density value=0.25");
+
+        body.clear();
+        assert_eq!(write_influxdb_body(&mut body, &values[..1], &[]), Ok(0));
+        assert_eq!(body, "");
+    }
+
+    #[test]
+    fn test_webhook_body() {
+        let values = sample_values();
+        let mut body = String::new();
+
+        let res = write_webhook_body(&mut body, &values);
+
+        assert_eq!(res, Ok(4));
+        assert_eq!(
+            body,
+            concat!(
+                r#"[{"type":"temperature","value":22.5,"time_offset":10},"#,
+                r#"{"type":"dust_density","value":0.25,"time_offset":11},"#,
+                r#"{"type":"pressure","value":101325,"time_offset":12},"#,
+                r#"{"type":"location","value":{"lat":44.8412,"lon":-0.5701},"time_offset":13}]"#,
+            )
+        );
+    }
+
+    #[test]
+    fn test_webhook_body_unknown_raw() {
+        let values = [
+            point(
+                SensorValue::Unknown {
+                    id: 999,
+                    value_len: 3,
+                    raw: RawValue::from_slice(&[0x01, 0xab, 0xff]).unwrap(),
+                },
+                20,
+            ),
+            point(SensorValue::Humidity(40.0), 21),
+        ];
+        let mut body = String::new();
+
+        let res = write_webhook_body(&mut body, &values);
+
+        assert_eq!(res, Ok(2));
+        assert_eq!(
+            body,
+            concat!(
+                r#"[{"type":"unknown","id":999,"raw":"01abff","time_offset":20},"#,
+                r#"{"type":"humidity","value":40,"time_offset":21}]"#,
+            )
+        );
+    }
+
+    #[test]
+    fn test_export_routes() {
+        let routes = ExportRoutes::parse(
+            "dust_density:sensor_community, temperature:influxdb+webhook,pressure:influxdb,voc:",
+        )
+        .unwrap();
+        let accepts = |exporter, value| routes.accepts(exporter, value);
+
+        assert!(accepts(
+            Exporter::SensorCommunity,
+            SensorValue::AirQuality(0.0)
+        ));
+        assert!(!accepts(Exporter::InfluxDb, SensorValue::AirQuality(0.0)));
+        assert!(!accepts(
+            Exporter::SensorCommunity,
+            SensorValue::Temperature(0.0)
+        ));
+        assert!(accepts(Exporter::Webhook, SensorValue::Temperature(0.0)));
+        assert!(!accepts(Exporter::Webhook, SensorValue::Pressure(0.0)));
+        assert!(!accepts(Exporter::InfluxDb, SensorValue::Voc(0.0)));
+        // not listed
+        assert!(accepts(Exporter::Webhook, SensorValue::Humidity(0.0)));
+        let unknown = SensorValue::Unknown {
+            id: 999,
+            value_len: 0,
+            raw: RawValue::EMPTY,
+        };
+        assert!(accepts(Exporter::InfluxDb, unknown));
+
+        assert!(!routes.accepts_all(Exporter::InfluxDb));
+        assert!(ExportRoutes::ALL.accepts_all(Exporter::InfluxDb));
+        assert_eq!(ExportRoutes::parse(""), Ok(ExportRoutes::ALL));
+    }
+
+    #[test]
+    fn test_export_routes_invalid() {
+        assert_eq!(
+            ExportRoutes::parse("dust:influxdb"),
+            Err(ExportRoutesError::UnknownValueType("dust"))
+        );
+        assert_eq!(
+            ExportRoutes::parse("voc:influxdb+mqtt"),
+            Err(ExportRoutesError::UnknownExporter("mqtt"))
+        );
+        assert_eq!(
+            ExportRoutes::parse("voc"),
+            Err(ExportRoutesError::MissingSeparator("voc"))
+        );
+    }
+
+    #[test]
+    fn test_value_type_names() {
+        let names = [
+            SensorValue::Temperature(0.0),
+            SensorValue::Pressure(0.0),
+            SensorValue::Altitude(0.0),
+            SensorValue::AirQuality(0.0),
+            SensorValue::Humidity(0.0),
+            SensorValue::Co2(0.0),
+            SensorValue::Voc(0.0),
+        ]
+        .map(|value| value_with_type(value).unwrap().1);
+
+        assert_eq!(
+            names,
+            [
+                "temperature",
+                "pressure",
+                "altitude",
+                "dust_density",
+                "humidity",
+                "co2_ppm",
+                "voc"
+            ]
+        );
+    }
+
+    /// One value of each type, a type added to [`SensorValue`] must be added here until this builds again.
+    fn one_value_of_each_type() -> [SensorValue; 9] {
+        let values = [
+            SensorValue::Temperature(1.0),
+            SensorValue::Pressure(2.0),
+            SensorValue::Altitude(3.0),
+            SensorValue::AirQuality(4.0),
+            SensorValue::Location { lat: 5.0, lon: 6.0 },
+            SensorValue::Humidity(7.0),
+            SensorValue::Co2(8.0),
+            SensorValue::Voc(9.0),
+            // without its raw bytes, see `test_webhook_body_unknown_raw()`
+            SensorValue::Unknown {
+                id: 999,
+                value_len: 2,
+                raw: RawValue::EMPTY,
+            },
+        ];
+        for value in values {
+            match value {
+                SensorValue::Temperature(_)
+                | SensorValue::Pressure(_)
+                | SensorValue::Altitude(_)
+                | SensorValue::AirQuality(_)
+                | SensorValue::Location { .. }
+                | SensorValue::Humidity(_)
+                | SensorValue::Co2(_)
+                | SensorValue::Voc(_)
+                | SensorValue::Unknown { .. } => (),
+            }
+        }
+        values
+    }
+
+    #[test]
+    fn test_every_value_type_is_exported() {
+        for value in one_value_of_each_type() {
+            let unknown = matches!(value, SensorValue::Unknown { .. });
+            let values = [point(value, 0)];
+            let mut body = String::new();
+
+            write_influxdb_body(&mut body, &values, &[]).unwrap();
+            assert_eq!(body.is_empty(), unknown, "influxdb: type {}", value.id());
+
+            body.clear();
+            let written = write_webhook_body(&mut body, &values).unwrap();
+            assert_eq!(written, u32::from(!unknown), "webhook: type {}", value.id());
+
+            // sensor.community has no sensor type for these
+            let skipped = matches!(
+                value,
+                SensorValue::Altitude(_) | SensorValue::Voc(_) | SensorValue::Unknown { .. }
+            );
+            let sensors = SensorCommunitySensor::ALL
+                .iter()
+                .filter(|sensor| sensor.supports_value(value))
+                .count();
+            assert_eq!(
+                sensors,
+                usize::from(!skipped),
+                "sensor.community: type {}",
+                value.id()
+            );
+
+            // the names of the export routes
+            let named = VALUE_TYPE_NAMES.iter().any(|&(id, _)| id == value.id());
+            assert_eq!(named, !unknown, "routes: type {}", value.id());
+        }
+    }
+
+    #[test]
+    fn test_export_backoff() {
+        let mut backoff = ExportBackoff::new();
+        assert_eq!(backoff.remaining_ms(Exporter::InfluxDb, 1000), None);
+
+        backoff.hold(Exporter::InfluxDb, 1000, 30_000);
+        assert_eq!(backoff.remaining_ms(Exporter::InfluxDb, 1000), Some(30_000));
+        assert_eq!(
+            backoff.remaining_ms(Exporter::InfluxDb, 21_000),
+            Some(10_000)
+        );
+        // other exporters are not held off
+        assert_eq!(backoff.remaining_ms(Exporter::Webhook, 21_000), None);
+        assert_eq!(backoff.remaining_ms(Exporter::InfluxDb, 31_000), None);
+
+        backoff.hold(Exporter::Webhook, 0, u64::MAX);
+        assert_eq!(
+            backoff.remaining_ms(Exporter::Webhook, 0),
+            Some(MAX_EXPORT_BACKOFF_MS)
+        );
+        backoff.hold(Exporter::Webhook, u64::MAX - 1, 10);
+        assert_eq!(
+            backoff.remaining_ms(Exporter::Webhook, u64::MAX - 1),
+            Some(1)
+        );
+    }
+}
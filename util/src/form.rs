@@ -0,0 +1,370 @@
+//! Application of dashboard form submissions to a configuration, one field at a time.
+//!
+//! Each submission starts with a `csrf_token` field, fields before a valid token abort the submission.
+//! An `action` field, usually the last one, picks what happens once all fields are applied.
+//!
+//! The `parse_*` functions validate the values of individual fields, for the form and the serial console alike.
+
+use core::net::Ipv4Addr;
+
+/// Value of password fields left as they are, the dashboard never shows the current password.
+pub const UNCHANGED_PASSWORD: &str = "(_unchanged_)";
+
+/// Configuration that form fields are applied to.
+pub trait FormConfig {
+    /// A configurable variable
+    type Variable: Copy;
+
+    /// Looks up a variable by its field name.
+    fn variable(name: &[u8]) -> Option<Self::Variable>;
+
+    /// Whether `var` is a password, left as-is when submitted as [`UNCHANGED_PASSWORD`].
+    fn is_password(var: Self::Variable) -> bool;
+
+    /// Token expected as the first field of each submission.
+    fn csrf_token(&self) -> &str;
+
+    /// Assigns `value` to `var`, returns `Err(())` if the value is invalid.
+    #[allow(clippy::result_unit_err)]
+    fn set_variable(&mut self, var: Self::Variable, value: &str) -> Result<(), ()>;
+}
+
+/// What to do once all fields of a submission are applied.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum FormAction {
+    #[default]
+    Apply,
+    SaveAndReboot,
+}
+
+/// Why a field was not applied, see [`FormSubmission::apply_config_field`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FieldError {
+    /// A field came before a valid CSRF token, the rest of the submission must be dropped
+    MissingCsrfToken,
+    UnknownVariable,
+    InvalidUtf8,
+    /// The value is not valid for the variable, it keeps its previous value
+    InvalidValue,
+    InvalidAction,
+    EmptyCsrfToken,
+}
+
+enum Field<V> {
+    CsrfToken,
+    Action,
+    Variable(V),
+}
+
+/// Progress of a form submission, whose fields are applied in order.
+#[derive(Default)]
+pub struct FormSubmission {
+    valid_csrf_token: bool,
+    action: FormAction,
+}
+
+impl FormSubmission {
+    pub const fn new() -> Self {
+        Self {
+            valid_csrf_token: false,
+            action: FormAction::Apply,
+        }
+    }
+
+    /// Applies a single field to `config`.
+    ///
+    /// Unknown or invalid fields are skipped, except for [`FieldError::MissingCsrfToken`] after which the caller
+    /// should stop applying fields.
+    pub fn apply_config_field<C: FormConfig>(
+        &mut self,
+        config: &mut C,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<(), FieldError> {
+        let field = match key {
+            b"csrf_token" => Field::CsrfToken,
+            b"action" => Field::Action,
+            name => Field::Variable(C::variable(name).ok_or(FieldError::UnknownVariable)?),
+        };
+        let value = core::str::from_utf8(value).map_err(|_| FieldError::InvalidUtf8)?;
+
+        if let Field::CsrfToken = field {
+            if value.is_empty() {
+                return Err(FieldError::EmptyCsrfToken);
+            }
+            self.valid_csrf_token = config.csrf_token() == value;
+            return Ok(());
+        }
+        if !self.valid_csrf_token {
+            return Err(FieldError::MissingCsrfToken);
+        }
+
+        match field {
+            Field::CsrfToken => Ok(()),
+            Field::Action => {
+                self.action = match value {
+                    "apply" => FormAction::Apply,
+                    "save-reboot" => FormAction::SaveAndReboot,
+                    _ => return Err(FieldError::InvalidAction),
+                };
+                Ok(())
+            }
+            Field::Variable(var) if C::is_password(var) && value == UNCHANGED_PASSWORD => Ok(()),
+            Field::Variable(var) => config
+                .set_variable(var, value)
+                .map_err(|()| FieldError::InvalidValue),
+        }
+    }
+
+    /// Whether a valid CSRF token was received, the submission must be rejected otherwise.
+    pub fn has_valid_csrf_token(&self) -> bool {
+        self.valid_csrf_token
+    }
+
+    pub fn action(&self) -> FormAction {
+        self.action
+    }
+}
+
+/// Lengths of a WPA2 passphrase, in bytes.
+pub const WPA2_PASSPHRASE_LEN: core::ops::RangeInclusive<usize> = 8..=63;
+
+/// Returns `value` as a string of at most `N` bytes, such as an SSID, or `None` if it is longer.
+pub fn parse_bounded_string<const N: usize>(value: &str) -> Option<heapless::String<N>> {
+    heapless::String::try_from(value).ok()
+}
+
+/// Returns `pass` if it is a valid WPA2 passphrase: 8 to 63 printable ASCII characters.
+pub fn parse_wpa2_passphrase(pass: &str) -> Option<heapless::String<64>> {
+    if !WPA2_PASSPHRASE_LEN.contains(&pass.len())
+        || !pass.bytes().all(|b| (b' '..=b'~').contains(&b))
+    {
+        return None;
+    }
+    parse_bounded_string(pass)
+}
+
+/// Parses `value` as the address of a DNS server, which may not be unspecified, broadcast or multicast.
+pub fn parse_dns_server(value: &str) -> Option<Ipv4Addr> {
+    value
+        .parse()
+        .ok()
+        .filter(|ip: &Ipv4Addr| !ip.is_unspecified() && !ip.is_broadcast() && !ip.is_multicast())
+}
+
+/// Parses `value` as a TCP port, which may not be 0.
+pub fn parse_port(value: &str) -> Option<u16> {
+    value.parse().ok().filter(|&port| port != 0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::encoding::decode_form_url_encoded;
+
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    enum Var {
+        Ssid,
+        Password,
+        DnsServer,
+    }
+
+    #[derive(Default)]
+    struct TestConfig {
+        ssid: String,
+        password: String,
+        dns_server: Option<Ipv4Addr>,
+    }
+
+    impl FormConfig for TestConfig {
+        type Variable = Var;
+
+        fn variable(name: &[u8]) -> Option<Var> {
+            match name {
+                b"wifi_sta_ssid" => Some(Var::Ssid),
+                b"wifi_sta_password" => Some(Var::Password),
+                b"dns_server" => Some(Var::DnsServer),
+                _ => None,
+            }
+        }
+
+        fn is_password(var: Var) -> bool {
+            var == Var::Password
+        }
+
+        fn csrf_token(&self) -> &str {
+            "s3cr3t"
+        }
+
+        fn set_variable(&mut self, var: Var, value: &str) -> Result<(), ()> {
+            match var {
+                Var::Ssid => {
+                    self.ssid = parse_bounded_string::<32>(value)
+                        .ok_or(())?
+                        .as_str()
+                        .to_owned()
+                }
+                Var::Password => self.password = value.to_owned(),
+                Var::DnsServer => self.dns_server = Some(parse_dns_server(value).ok_or(())?),
+            }
+            Ok(())
+        }
+    }
+
+    /// Applies every field of an URL-encoded `body`, like the dashboard does.
+    fn submit(config: &mut TestConfig, body: &[u8]) -> (FormSubmission, Vec<(String, FieldError)>) {
+        let mut body = body.to_vec();
+        let mut submission = FormSubmission::new();
+        let mut errors = Vec::new();
+        for (key, value) in decode_form_url_encoded(&mut body) {
+            match submission.apply_config_field(config, key, value) {
+                Ok(()) => (),
+                Err(e) => {
+                    errors.push((String::from_utf8_lossy(key).into_owned(), e));
+                    if e == FieldError::MissingCsrfToken {
+                        break;
+                    }
+                }
+            }
+        }
+        (submission, errors)
+    }
+
+    #[test]
+    fn test_form_submission() {
+        let mut config = TestConfig {
+            password: "hunter2".to_owned(),
+            ..Default::default()
+        };
+
+        let (submission, errors) = submit(
+            &mut config,
+            b"csrf_token=s3cr3t&wifi_sta_ssid=My+Home%21&wifi_sta_password=%28_unchanged_%29\
+              &dns_server=9.9.9.9&action=save-reboot",
+        );
+        assert!(submission.has_valid_csrf_token());
+        assert_eq!(submission.action(), FormAction::SaveAndReboot);
+        assert_eq!(errors, []);
+        assert_eq!(config.ssid, "My Home!");
+        // the sentinel keeps the previous password
+        assert_eq!(config.password, "hunter2");
+        assert_eq!(config.dns_server, Some(Ipv4Addr::new(9, 9, 9, 9)));
+
+        let (submission, errors) = submit(
+            &mut config,
+            b"csrf_token=s3cr3t&wifi_sta_password=correct+horse&dns_server=9.9.9&unknown=1",
+        );
+        assert_eq!(submission.action(), FormAction::Apply);
+        assert_eq!(
+            errors,
+            [
+                ("dns_server".to_owned(), FieldError::InvalidValue),
+                ("unknown".to_owned(), FieldError::UnknownVariable)
+            ]
+        );
+        assert_eq!(config.password, "correct horse");
+        assert_eq!(config.dns_server, Some(Ipv4Addr::new(9, 9, 9, 9)));
+    }
+
+    #[test]
+    fn test_form_submission_csrf_token_first() {
+        let mut config = TestConfig::default();
+
+        // the token must come before any other field
+        let (submission, errors) = submit(
+            &mut config,
+            b"wifi_sta_ssid=attacker&csrf_token=s3cr3t&action=apply",
+        );
+        assert!(!submission.has_valid_csrf_token());
+        assert_eq!(
+            errors,
+            [("wifi_sta_ssid".to_owned(), FieldError::MissingCsrfToken)]
+        );
+        assert_eq!(config.ssid, "");
+
+        let (submission, errors) = submit(&mut config, b"csrf_token=guess&wifi_sta_ssid=attacker");
+        assert!(!submission.has_valid_csrf_token());
+        assert_eq!(
+            errors,
+            [("wifi_sta_ssid".to_owned(), FieldError::MissingCsrfToken)]
+        );
+        assert_eq!(config.ssid, "");
+
+        let (submission, errors) = submit(&mut config, b"csrf_token=&action=apply");
+        assert!(!submission.has_valid_csrf_token());
+        assert_eq!(
+            errors,
+            [
+                ("csrf_token".to_owned(), FieldError::EmptyCsrfToken),
+                ("action".to_owned(), FieldError::MissingCsrfToken)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_form_submission_invalid_fields() {
+        let mut config = TestConfig::default();
+
+        let (submission, errors) = submit(
+            &mut config,
+            b"csrf_token=s3cr3t&wifi_sta_ssid=%FF&action=shutdown&wifi_sta_ssid=ok",
+        );
+        assert!(submission.has_valid_csrf_token());
+        assert_eq!(submission.action(), FormAction::Apply);
+        assert_eq!(
+            errors,
+            [
+                ("wifi_sta_ssid".to_owned(), FieldError::InvalidUtf8),
+                ("action".to_owned(), FieldError::InvalidAction)
+            ]
+        );
+        assert_eq!(config.ssid, "ok");
+    }
+
+    #[test]
+    fn test_parse_bounded_string() {
+        let ssid = "a".repeat(32);
+        assert_eq!(parse_bounded_string::<32>(&ssid).as_deref(), Some(&*ssid));
+        assert_eq!(
+            parse_bounded_string::<32>(""),
+            Some(heapless::String::new())
+        );
+        assert_eq!(parse_bounded_string::<32>(&"a".repeat(33)), None);
+        // the limit is in bytes, not characters
+        assert_eq!(parse_bounded_string::<32>(&"é".repeat(17)), None);
+    }
+
+    #[test]
+    fn test_parse_wpa2_passphrase() {
+        assert_eq!(
+            parse_wpa2_passphrase("correct horse").as_deref(),
+            Some("correct horse")
+        );
+        assert!(parse_wpa2_passphrase(&"~".repeat(63)).is_some());
+        assert_eq!(parse_wpa2_passphrase("short"), None);
+        assert_eq!(parse_wpa2_passphrase(&"a".repeat(64)), None);
+        assert_eq!(parse_wpa2_passphrase("tab\tseparated"), None);
+        assert_eq!(parse_wpa2_passphrase("café au lait"), None);
+    }
+
+    #[test]
+    fn test_parse_dns_server() {
+        assert_eq!(parse_dns_server("9.9.9.9"), Some(Ipv4Addr::new(9, 9, 9, 9)));
+        assert_eq!(parse_dns_server(" 9.9.9.9"), None);
+        assert_eq!(parse_dns_server("9.9.9"), None);
+        assert_eq!(parse_dns_server("dns.quad9.net"), None);
+        assert_eq!(parse_dns_server("0.0.0.0"), None);
+        assert_eq!(parse_dns_server("255.255.255.255"), None);
+        assert_eq!(parse_dns_server("224.0.0.251"), None);
+    }
+
+    #[test]
+    fn test_parse_port() {
+        assert_eq!(parse_port("8086"), Some(8086));
+        assert_eq!(parse_port("65535"), Some(65535));
+        assert_eq!(parse_port("0"), None);
+        assert_eq!(parse_port("65536"), None);
+        assert_eq!(parse_port("-80"), None);
+        assert_eq!(parse_port(""), None);
+    }
+}
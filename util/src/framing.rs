@@ -0,0 +1,139 @@
+//! Length-delimited framing of packets over byte streams such as a serial link, which have no packet boundaries.
+//!
+//! Each frame is made of [`FRAME_START`], the payload length on a single byte, then the payload itself.
+
+/// First byte of every frame, bytes received outside of a frame until this one are skipped.
+pub const FRAME_START: u8 = 0x7e;
+/// Longest payload of a single frame.
+pub const MAX_FRAME_PAYLOAD_LEN: usize = u8::MAX as usize;
+
+/// Returns the bytes to send before a payload of `len` bytes, `None` if it is too long for a single frame.
+pub fn frame_header(len: usize) -> Option<[u8; 2]> {
+    Some([FRAME_START, u8::try_from(len).ok()?])
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum DecoderState {
+    Idle,
+    Length,
+    Payload {
+        len: usize,
+    },
+    /// Skipping the payload of a frame too long for the buffer
+    Discard {
+        remaining: usize,
+    },
+}
+
+/// Collects received bytes into the payloads of frames of at most `N` bytes.
+///
+/// Longer frames and empty ones are skipped.
+pub struct FrameDecoder<const N: usize> {
+    buf: heapless::Vec<u8, N>,
+    state: DecoderState,
+}
+
+impl<const N: usize> FrameDecoder<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: heapless::Vec::new(),
+            state: DecoderState::Idle,
+        }
+    }
+
+    /// Forgets the frame being received, to be called after an error of the underlying stream.
+    pub fn reset(&mut self) {
+        self.buf.clear();
+        self.state = DecoderState::Idle;
+    }
+
+    /// Accounts for a received byte, returns the payload of the frame it completes.
+    pub fn push(&mut self, byte: u8) -> Option<&[u8]> {
+        match self.state {
+            DecoderState::Idle => {
+                if byte == FRAME_START {
+                    self.state = DecoderState::Length;
+                }
+            }
+            DecoderState::Length => {
+                let len = usize::from(byte);
+                self.buf.clear();
+                self.state = if len == 0 {
+                    DecoderState::Idle
+                } else if len > N {
+                    DecoderState::Discard { remaining: len }
+                } else {
+                    DecoderState::Payload { len }
+                };
+            }
+            DecoderState::Payload { len } => {
+                // cannot overflow, `len` is at most `N`
+                self.buf.push(byte).ok();
+                if self.buf.len() == len {
+                    self.state = DecoderState::Idle;
+                    return Some(&self.buf);
+                }
+            }
+            DecoderState::Discard { remaining } => {
+                self.state = match remaining - 1 {
+                    0 => DecoderState::Idle,
+                    remaining => DecoderState::Discard { remaining },
+                };
+            }
+        }
+        None
+    }
+}
+
+impl<const N: usize> Default for FrameDecoder<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn feed<const N: usize>(decoder: &mut FrameDecoder<N>, input: &[u8]) -> Vec<Vec<u8>> {
+        input
+            .iter()
+            .filter_map(|&byte| Some(decoder.push(byte)?.to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn test_frame_header() {
+        assert_eq!(frame_header(0), Some([FRAME_START, 0]));
+        assert_eq!(frame_header(255), Some([FRAME_START, 255]));
+        assert_eq!(frame_header(256), None);
+    }
+
+    #[test]
+    fn test_frame_decoder() {
+        let mut decoder = FrameDecoder::<4>::new();
+
+        assert_eq!(
+            feed(&mut decoder, b"\x7e\x03abc\x7e\x01\x7e"),
+            [b"abc".to_vec(), b"\x7e".to_vec()]
+        );
+        // garbage before a frame, then a frame split across reads
+        assert_eq!(feed(&mut decoder, b"xy\x7e\x02h"), Vec::<Vec<u8>>::new());
+        assert_eq!(feed(&mut decoder, b"i"), [b"hi".to_vec()]);
+    }
+
+    #[test]
+    fn test_frame_decoder_skips_frames() {
+        let mut decoder = FrameDecoder::<4>::new();
+
+        // too long, empty, then valid
+        assert_eq!(
+            feed(&mut decoder, b"\x7e\x05\x7e\x02abc\x7e\x00\x7e\x02ok"),
+            [b"ok".to_vec()]
+        );
+
+        feed(&mut decoder, b"\x7e\x04ab");
+        decoder.reset();
+        assert_eq!(feed(&mut decoder, b"cd\x7e\x01z"), [b"z".to_vec()]);
+    }
+}
@@ -0,0 +1,108 @@
+//! Framing of gzip streams (RFC 1952) around already deflated data.
+//!
+//! Parts compressed ahead of time can be mixed with uncompressed ones, which are sent as stored deflate blocks.
+//! Every compressed part must end on a byte boundary without the final block flag, as after a zlib sync flush.
+
+/// Header of a gzip member using deflate, without modification time nor optional fields.
+pub const GZIP_HEADER: [u8; 10] = [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+
+/// Empty stored block with the final block flag, ends the deflate stream.
+pub const GZIP_LAST_BLOCK: [u8; 5] = [1, 0, 0, 0xff, 0xff];
+
+/// Maximum length of a stored block, longer data must be split.
+pub const MAX_STORED_BLOCK_LEN: usize = u16::MAX as usize;
+
+/// Returns the header of a non-final stored block of `len` bytes, the data follows it as is.
+pub fn stored_block_header(len: u16) -> [u8; 5] {
+    let [len_lo, len_hi] = len.to_le_bytes();
+    let [nlen_lo, nlen_hi] = (!len).to_le_bytes();
+    [0, len_lo, len_hi, nlen_lo, nlen_hi]
+}
+
+/// CRC-32 and length of the uncompressed data, written after the deflate stream.
+#[derive(Clone, Copy, Debug)]
+pub struct GzipTrailer {
+    crc: u32,
+    len: u32,
+}
+
+impl GzipTrailer {
+    pub const fn new() -> Self {
+        Self { crc: !0, len: 0 }
+    }
+
+    /// Accounts for `data`, in the order it is sent.
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.crc ^= u32::from(byte);
+            for _ in 0..8 {
+                // reflected polynomial of IEEE 802.3
+                self.crc = (self.crc >> 1) ^ (0xedb8_8320 & (self.crc & 1).wrapping_neg());
+            }
+        }
+        // the length is stored modulo 2^32
+        self.len = self.len.wrapping_add(data.len() as u32);
+    }
+
+    pub fn crc(&self) -> u32 {
+        !self.crc
+    }
+
+    pub fn to_bytes(&self) -> [u8; 8] {
+        let mut bytes = [0u8; 8];
+        bytes[..4].copy_from_slice(&self.crc().to_le_bytes());
+        bytes[4..].copy_from_slice(&self.len.to_le_bytes());
+        bytes
+    }
+}
+
+impl Default for GzipTrailer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        let mut trailer = GzipTrailer::new();
+        assert_eq!(trailer.crc(), 0);
+
+        trailer.update(b"1234");
+        trailer.update(b"56789");
+        assert_eq!(trailer.crc(), 0xcbf4_3926);
+        assert_eq!(trailer.to_bytes(), [0x26, 0x39, 0xf4, 0xcb, 9, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_gzip_stream() {
+        // "Hello, " deflated with a sync flush, as by `zlib.compressobj(wbits=-15)`
+        let deflated_part: &[u8] = &[
+            0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0xd7, 0x51, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff,
+        ];
+        let mut stream = GZIP_HEADER.to_vec();
+        let mut trailer = GzipTrailer::new();
+
+        stream.extend_from_slice(deflated_part);
+        trailer.update(b"Hello, ");
+        stream.extend_from_slice(&stored_block_header(5));
+        stream.extend_from_slice(b"world");
+        trailer.update(b"world");
+        stream.extend_from_slice(&GZIP_LAST_BLOCK);
+        stream.extend_from_slice(&trailer.to_bytes());
+
+        // checked against `gzip -d`
+        assert_eq!(
+            stream,
+            [
+                0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xf2, 0x48, 0xcd, 0xc9,
+                0xc9, 0xd7, 0x51, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0x00, 0x05, 0x00, 0xfa, 0xff,
+                0x77, 0x6f, 0x72, 0x6c, 0x64, 0x01, 0x00, 0x00, 0xff, 0xff, 0xc2, 0xa9, 0x9a, 0xe7,
+                0x0c, 0x00, 0x00, 0x00,
+            ]
+        );
+    }
+}
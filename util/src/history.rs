@@ -0,0 +1,165 @@
+//! Bounded history of the most recent values of each sensor value type.
+
+use crate::export::VALUE_TYPE_NAMES;
+use heapless::Deque;
+use protocol::app::v1::SensorValuePoint;
+
+/// Number of value types kept, see [`slot`].
+const KIND_COUNT: usize = VALUE_TYPE_NAMES.len();
+
+/// Keeps the last `N` points of each known [`SensorValue`](protocol::app::v1::SensorValue) type, in reception order.
+///
+/// Unknown values are skipped, as values of the same id may not even share a size.
+pub struct History<const N: usize> {
+    buffers: [Deque<SensorValuePoint, N>; KIND_COUNT],
+}
+
+impl<const N: usize> History<N> {
+    const EMPTY: Deque<SensorValuePoint, N> = Deque::new();
+
+    pub const fn new() -> Self {
+        Self {
+            buffers: [Self::EMPTY; KIND_COUNT],
+        }
+    }
+
+    /// Appends `point`, evicting the oldest point of its type if full.
+    ///
+    /// Returns `false` if `point` holds an unknown value, which is not stored.
+    pub fn push(&mut self, point: SensorValuePoint) -> bool {
+        let Some(slot) = slot(point.value.id()) else {
+            return false;
+        };
+        let buffer = &mut self.buffers[slot];
+        if buffer.is_full() {
+            buffer.pop_front();
+        }
+        // cannot fail, room was made above
+        _ = buffer.push_back(point);
+        true
+    }
+
+    /// Most recent point whose value has the type `id`, see [`SensorValue::id`](protocol::app::v1::SensorValue::id).
+    pub fn latest(&self, id: u32) -> Option<&SensorValuePoint> {
+        self.buffer(id)?.back()
+    }
+
+    /// Up to `n` most recent points whose value has the type `id`, oldest first.
+    pub fn last(&self, id: u32, n: usize) -> impl Iterator<Item = &SensorValuePoint> {
+        let buffer = self.buffer(id);
+        let len = buffer.map_or(0, Deque::len);
+        buffer
+            .into_iter()
+            .flat_map(Deque::iter)
+            .skip(len.saturating_sub(n))
+    }
+
+    /// Number of points kept for the type `id`.
+    pub fn len(&self, id: u32) -> usize {
+        self.buffer(id).map_or(0, Deque::len)
+    }
+
+    /// Whether no points of any type are kept.
+    pub fn is_empty(&self) -> bool {
+        self.buffers.iter().all(Deque::is_empty)
+    }
+
+    pub fn clear(&mut self) {
+        self.buffers.iter_mut().for_each(Deque::clear);
+    }
+
+    fn buffer(&self, id: u32) -> Option<&Deque<SensorValuePoint, N>> {
+        Some(&self.buffers[slot(id)?])
+    }
+}
+
+impl<const N: usize> Default for History<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Index of the buffer holding values of the type `id`, `None` for unknown types.
+fn slot(id: u32) -> Option<usize> {
+    VALUE_TYPE_NAMES
+        .iter()
+        .position(|&(type_id, _)| type_id == id)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use protocol::app::v1::{RawValue, SensorValue};
+
+    /// Value and time offset of `point`, which doesn't implement `Debug` outside of the protocol crate.
+    fn describe(point: Option<&SensorValuePoint>) -> Option<(String, i64)> {
+        point.map(|p| (p.value.to_string(), p.time_offset))
+    }
+
+    fn point(value: SensorValue, time_offset: i64) -> SensorValuePoint {
+        SensorValuePoint { value, time_offset }
+    }
+
+    const TEMPERATURE: u32 = SensorValue::Temperature(0.0).id();
+    const PRESSURE: u32 = SensorValue::Pressure(0.0).id();
+
+    #[test]
+    fn test_history_latest_per_type() {
+        let mut history: History<4> = History::new();
+        assert!(history.is_empty());
+        assert!(history.latest(TEMPERATURE).is_none());
+
+        assert!(history.push(point(SensorValue::Temperature(21.0), 1)));
+        assert!(history.push(point(SensorValue::Pressure(101325.0), 2)));
+        assert!(history.push(point(SensorValue::Temperature(22.0), 3)));
+
+        assert_eq!(
+            describe(history.latest(TEMPERATURE)),
+            Some(("Temperature(22)".into(), 3))
+        );
+        assert_eq!(
+            describe(history.latest(PRESSURE)),
+            Some(("Pressure(101325)".into(), 2))
+        );
+        assert_eq!(history.len(TEMPERATURE), 2);
+        assert_eq!(history.len(SensorValue::Voc(0.0).id()), 0);
+    }
+
+    #[test]
+    fn test_history_evicts_oldest() {
+        let mut history: History<3> = History::new();
+        for i in 0..5 {
+            history.push(point(SensorValue::Humidity(i as f32), i));
+        }
+
+        let id = SensorValue::Humidity(0.0).id();
+        let offsets = |n| {
+            history
+                .last(id, n)
+                .map(|p| p.time_offset)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(offsets(10), [2, 3, 4]);
+        assert_eq!(offsets(2), [3, 4]);
+        assert_eq!(offsets(0), []);
+
+        history.clear();
+        assert!(history.is_empty());
+    }
+
+    #[test]
+    fn test_history_skips_unknown() {
+        let mut history: History<2> = History::new();
+        let unknown = SensorValue::Unknown {
+            id: 42,
+            value_len: 4,
+            raw: RawValue::EMPTY,
+        };
+
+        assert!(!history.push(point(unknown, 1)));
+        assert!(history.is_empty());
+        assert!(history.latest(42).is_none());
+        assert_eq!(history.last(42, 2).count(), 0);
+        assert_eq!(history.last(4, 2).count(), 0);
+    }
+}
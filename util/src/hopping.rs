@@ -0,0 +1,135 @@
+//! Frequency hopping: both ends of a link move through the same list of channels, one acknowledged uplink at a time.
+
+use core::ops::RangeInclusive;
+
+/// Sub-channels of the EU868 band hopped through by default, in Hz.
+pub const EU868_HOP_CHANNELS_HZ: [u32; 3] = [868_100_000, 868_300_000, 868_500_000];
+/// Frequencies of the EU868 band, the only ones accepted in a list of channels, in Hz.
+pub const EU868_FREQUENCY_RANGE_HZ: RangeInclusive<u32> = 863_000_000..=870_000_000;
+/// Most channels in a single list.
+pub const MAX_HOP_CHANNELS: usize = 8;
+
+/// Frequencies hopped through, in Hz.
+pub type HopChannels = heapless::Vec<u32, MAX_HOP_CHANNELS>;
+
+/// Why a list of channels is invalid, see [`parse_hop_channels`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HopChannelsError {
+    Empty,
+    /// More than [`MAX_HOP_CHANNELS`] channels
+    TooMany,
+    /// Not a number, or outside of [`EU868_FREQUENCY_RANGE_HZ`]
+    InvalidFrequency,
+}
+
+/// The default list of channels, [`EU868_HOP_CHANNELS_HZ`].
+pub fn default_hop_channels() -> HopChannels {
+    // cannot fail, the default list is shorter than the maximum
+    HopChannels::from_slice(&EU868_HOP_CHANNELS_HZ).unwrap_or_default()
+}
+
+/// Parses a comma-separated list of frequencies in Hz, such as `868100000,868300000,868500000`.
+pub fn parse_hop_channels(s: &str) -> Result<HopChannels, HopChannelsError> {
+    let mut channels = HopChannels::new();
+    for frequency in s.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+        let frequency: u32 = frequency
+            .parse()
+            .map_err(|_| HopChannelsError::InvalidFrequency)?;
+        if !EU868_FREQUENCY_RANGE_HZ.contains(&frequency) {
+            return Err(HopChannelsError::InvalidFrequency);
+        }
+        channels
+            .push(frequency)
+            .map_err(|_| HopChannelsError::TooMany)?;
+    }
+    if channels.is_empty() {
+        return Err(HopChannelsError::Empty);
+    }
+    Ok(channels)
+}
+
+/// Round-robin through a list of channels.
+///
+/// Both ends of a link start from the beginning of the list after each handshake, and advance it after each
+/// acknowledged uplink, so that they stay on the same channel without exchanging it.
+#[derive(Clone, Debug)]
+pub struct HopSchedule {
+    channels: HopChannels,
+    next: usize,
+}
+
+impl HopSchedule {
+    pub const fn new(channels: HopChannels) -> Self {
+        Self { channels, next: 0 }
+    }
+
+    pub fn channels(&self) -> &[u32] {
+        &self.channels
+    }
+
+    /// Returns the channel to switch to after an acknowledged uplink, `None` if the list is empty.
+    pub fn advance(&mut self) -> Option<u32> {
+        let channel = *self.channels.get(self.next)?;
+        self.next = (self.next + 1) % self.channels.len();
+        Some(channel)
+    }
+
+    /// Goes back to the beginning of the list, to be called when the link goes back to its default channel.
+    pub fn reset(&mut self) {
+        self.next = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_hop_channels() {
+        assert_eq!(
+            parse_hop_channels("868100000,868300000,868500000").as_deref(),
+            Ok(EU868_HOP_CHANNELS_HZ.as_slice())
+        );
+        assert_eq!(
+            parse_hop_channels(" 867100000 , 869525000,").as_deref(),
+            Ok([867_100_000, 869_525_000].as_slice())
+        );
+        assert_eq!(
+            default_hop_channels().as_slice(),
+            EU868_HOP_CHANNELS_HZ.as_slice()
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_hop_channels() {
+        assert_eq!(parse_hop_channels(""), Err(HopChannelsError::Empty));
+        assert_eq!(parse_hop_channels(" , "), Err(HopChannelsError::Empty));
+        assert_eq!(
+            parse_hop_channels("868.1"),
+            Err(HopChannelsError::InvalidFrequency)
+        );
+        // 915 MHz is outside of the EU868 band
+        assert_eq!(
+            parse_hop_channels("868100000,915000000"),
+            Err(HopChannelsError::InvalidFrequency)
+        );
+        assert_eq!(
+            parse_hop_channels(&["868100000"; MAX_HOP_CHANNELS + 1].join(",")),
+            Err(HopChannelsError::TooMany)
+        );
+    }
+
+    #[test]
+    fn test_hop_schedule() {
+        let mut schedule = HopSchedule::new(default_hop_channels());
+        assert_eq!(schedule.advance(), Some(868_100_000));
+        assert_eq!(schedule.advance(), Some(868_300_000));
+        assert_eq!(schedule.advance(), Some(868_500_000));
+        assert_eq!(schedule.advance(), Some(868_100_000));
+
+        schedule.reset();
+        assert_eq!(schedule.advance(), Some(868_100_000));
+
+        assert_eq!(HopSchedule::new(HopChannels::new()).advance(), None);
+    }
+}
@@ -0,0 +1,532 @@
+//! Interpretation of HTTP/1.x messages, independent of the transport.
+
+use core::fmt::{self, Write};
+
+/// How the body of a response is delimited.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BodyLength {
+    /// The response has no body
+    Empty,
+    /// The body is exactly this many bytes long
+    Fixed(usize),
+    /// The body uses the chunked transfer encoding
+    Chunked,
+    /// The body ends when the server closes the connection
+    UntilClose,
+}
+
+/// The parts of a response status line.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct StatusLine {
+    /// Minor version of HTTP/1.x
+    pub minor_version: u8,
+    pub status: u16,
+}
+
+/// Parses a status line such as `HTTP/1.1 204 No Content`, without its line terminator.
+pub fn parse_status_line(line: &[u8]) -> Option<StatusLine> {
+    let rest = line.strip_prefix(b"HTTP/1.")?;
+    let (&minor, rest) = rest.split_first()?;
+    let rest = rest.strip_prefix(b" ")?;
+
+    if !minor.is_ascii_digit() || rest.len() < 3 || rest.get(3).is_some_and(|&c| c != b' ') {
+        return None;
+    }
+    let status = parse_decimal(&rest[..3])?;
+    Some(StatusLine {
+        minor_version: minor - b'0',
+        status: u16::try_from(status).ok()?,
+    })
+}
+
+/// Returns the standard reason phrase of `status`, or `Unknown` for codes not used by the gateway.
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        204 => "No Content",
+        303 => "See Other",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        413 => "Payload Too Large",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    }
+}
+
+/// Writes the `HTTP/1.0` status line of a response, with its line terminator.
+pub fn write_status_line<W: Write + ?Sized>(out: &mut W, status: u16) -> fmt::Result {
+    write!(out, "HTTP/1.0 {status:03} {}\r\n", reason_phrase(status))
+}
+
+/// The response headers that decide how it is delimited and whether its connection can be reused.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct ResponseHeaders {
+    content_length: Option<usize>,
+    chunked: bool,
+    connection_close: bool,
+    connection_keep_alive: bool,
+    retry_after_secs: Option<u32>,
+}
+
+impl ResponseHeaders {
+    /// Records a single `name: value` header line, other headers are ignored.
+    pub fn parse_header(&mut self, line: &[u8]) {
+        let Some(colon) = memchr::memchr(b':', line) else {
+            return;
+        };
+        let name = &line[..colon];
+        let value = line[colon + 1..].trim_ascii();
+
+        if name.eq_ignore_ascii_case(b"Content-Length") {
+            self.content_length = parse_decimal(value);
+        } else if name.eq_ignore_ascii_case(b"Transfer-Encoding") {
+            self.chunked = tokens(value)
+                .last()
+                .is_some_and(|coding| coding.eq_ignore_ascii_case(b"chunked"));
+        } else if name.eq_ignore_ascii_case(b"Connection") {
+            for option in tokens(value) {
+                self.connection_close |= option.eq_ignore_ascii_case(b"close");
+                self.connection_keep_alive |= option.eq_ignore_ascii_case(b"keep-alive");
+            }
+        } else if name.eq_ignore_ascii_case(b"Retry-After") {
+            self.retry_after_secs = parse_retry_after(value);
+        }
+    }
+
+    /// Returns how the body of a response with these headers is delimited.
+    pub fn body_length(&self, status: StatusLine) -> BodyLength {
+        if matches!(status.status, 100..200 | 204 | 304) {
+            BodyLength::Empty
+        } else if self.chunked {
+            BodyLength::Chunked
+        } else {
+            match self.content_length {
+                Some(0) => BodyLength::Empty,
+                Some(len) => BodyLength::Fixed(len),
+                None => BodyLength::UntilClose,
+            }
+        }
+    }
+
+    /// Whether the server keeps the connection open after the response.
+    ///
+    /// HTTP/1.1 connections are persistent unless closed explicitly, HTTP/1.0 ones must opt in with `Connection: keep-alive`.
+    pub fn keep_alive(&self, status: StatusLine) -> bool {
+        let persistent = if status.minor_version >= 1 {
+            !self.connection_close
+        } else {
+            self.connection_keep_alive && !self.connection_close
+        };
+        persistent && self.body_length(status) != BodyLength::UntilClose
+    }
+
+    /// Delay in seconds of the `Retry-After` header, `None` if it is absent or not a number of seconds.
+    pub fn retry_after_secs(&self) -> Option<u32> {
+        self.retry_after_secs
+    }
+}
+
+/// Whether `status` asks the client to slow down, `429 Too Many Requests` or `503 Service Unavailable`.
+pub fn is_rate_limited(status: u16) -> bool {
+    matches!(status, 429 | 503)
+}
+
+/// Parses the value of a `Retry-After` header given as a number of seconds.
+///
+/// The HTTP-date form is not supported, the gateway does not know the current date.
+pub fn parse_retry_after(value: &[u8]) -> Option<u32> {
+    u32::try_from(parse_decimal(value.trim_ascii())?).ok()
+}
+
+/// Returns an iterator over the `(name, value)` pairs of a header block.
+///
+/// Lines without a colon, such as the request or status line, are skipped.
+/// Values are trimmed, and those folded over several lines (obsolete line folding) include their continuation lines.
+pub fn headers(block: &[u8]) -> Headers<'_> {
+    Headers { rest: block }
+}
+
+/// The iterator type created by [`headers`].
+pub struct Headers<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Iterator for Headers<'a> {
+    type Item = (&'a [u8], &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.rest.is_empty() {
+            // a line starting with a space or tab continues the previous one
+            let mut line_end = 0usize;
+            let next_start = loop {
+                match memchr::memchr(b'\n', &self.rest[line_end..]) {
+                    Some(pos) => {
+                        line_end += pos + 1;
+                        if !matches!(self.rest.get(line_end), Some(b' ' | b'\t')) {
+                            break line_end;
+                        }
+                    }
+                    None => break self.rest.len(),
+                }
+            };
+            let line = &self.rest[..next_start];
+            self.rest = &self.rest[next_start..];
+
+            if let Some(colon) = memchr::memchr(b':', line) {
+                return Some((line[..colon].trim_ascii(), line[colon + 1..].trim_ascii()));
+            }
+        }
+        None
+    }
+}
+
+/// Parses the value of a `Content-Length` header.
+pub fn parse_content_length(value: &[u8]) -> Option<usize> {
+    parse_decimal(value.trim_ascii())
+}
+
+/// Parses the value of an `Authorization` header using the `Basic` scheme (RFC 7617).
+///
+/// The credentials are decoded into `buf`, and returned as the user ID and password.
+pub fn parse_basic_auth<'b>(value: &[u8], buf: &'b mut [u8]) -> Option<(&'b [u8], &'b [u8])> {
+    let value = value.trim_ascii();
+    let space = memchr::memchr(b' ', value)?;
+    if !value[..space].eq_ignore_ascii_case(b"Basic") {
+        return None;
+    }
+
+    let len = crate::encoding::decode_base64(value[space + 1..].trim_ascii(), buf)?;
+    let credentials = &buf[..len];
+    // the user ID cannot contain a colon, but the password can
+    let colon = memchr::memchr(b':', credentials)?;
+    Some((&credentials[..colon], &credentials[colon + 1..]))
+}
+
+/// Returns the media type of a `Content-Type` header value, without its parameters.
+pub fn media_type(value: &[u8]) -> &[u8] {
+    let end = memchr::memchr(b';', value).unwrap_or(value.len());
+    value[..end].trim_ascii()
+}
+
+/// Returns whether an `Accept-Encoding` header value allows the given content coding, by name or through `*`.
+pub fn accepts_encoding(value: &[u8], coding: &[u8]) -> bool {
+    let mut wildcard = false;
+    for token in tokens(value) {
+        let (name, params) = match memchr::memchr(b';', token) {
+            Some(semicolon) => (token[..semicolon].trim_ascii(), &token[semicolon + 1..]),
+            None => (token, &[][..]),
+        };
+        // "q=0" marks a coding as not acceptable, other weights are not needed to choose a single coding
+        let acceptable = !params.split(|&c| c == b';').any(|param| {
+            let param = param.trim_ascii();
+            param.len() > 2
+                && param[..2].eq_ignore_ascii_case(b"q=")
+                && param[2..].iter().all(|&c| c == b'0' || c == b'.')
+        });
+
+        if name.eq_ignore_ascii_case(coding) {
+            return acceptable;
+        }
+        if name == b"*" {
+            wildcard = acceptable;
+        }
+    }
+    wildcard
+}
+
+/// Parses the size line of a chunk, ignoring chunk extensions.
+pub fn parse_chunk_size(line: &[u8]) -> Option<usize> {
+    let end = memchr::memchr(b';', line).unwrap_or(line.len());
+    let digits = line[..end].trim_ascii();
+
+    if digits.is_empty() {
+        return None;
+    }
+    digits.iter().try_fold(0usize, |size, &digit| {
+        let digit = char::from(digit).to_digit(16)?;
+        size.checked_mul(16)?.checked_add(digit as usize)
+    })
+}
+
+fn parse_decimal(digits: &[u8]) -> Option<usize> {
+    if digits.is_empty() {
+        return None;
+    }
+    digits.iter().try_fold(0usize, |value, &digit| {
+        let digit = char::from(digit).to_digit(10)?;
+        value.checked_mul(10)?.checked_add(digit as usize)
+    })
+}
+
+/// Splits a comma-separated header value.
+fn tokens(value: &[u8]) -> impl Iterator<Item = &[u8]> {
+    value
+        .split(|&c| c == b',')
+        .map(<[u8]>::trim_ascii)
+        .filter(|token| !token.is_empty())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn response_headers(lines: &[&str]) -> ResponseHeaders {
+        let mut headers = ResponseHeaders::default();
+        for line in lines {
+            headers.parse_header(line.as_bytes());
+        }
+        headers
+    }
+
+    const HTTP_1_0_OK: StatusLine = StatusLine {
+        minor_version: 0,
+        status: 200,
+    };
+    const HTTP_1_1_OK: StatusLine = StatusLine {
+        minor_version: 1,
+        status: 200,
+    };
+
+    #[test]
+    fn test_parse_status_line() {
+        assert_eq!(parse_status_line(b"HTTP/1.1 200 OK"), Some(HTTP_1_1_OK));
+        assert_eq!(parse_status_line(b"HTTP/1.0 200"), Some(HTTP_1_0_OK));
+        assert_eq!(
+            parse_status_line(b"HTTP/1.1 422 Unprocessable Entity"),
+            Some(StatusLine {
+                minor_version: 1,
+                status: 422
+            })
+        );
+        assert_eq!(parse_status_line(b"HTTP/2 200 OK"), None);
+        assert_eq!(parse_status_line(b"HTTP/1.1 20 OK"), None);
+        assert_eq!(parse_status_line(b"HTTP/1.1 2000 OK"), None);
+        assert_eq!(parse_status_line(b"HTTP/1.1 +20 OK"), None);
+        assert_eq!(parse_status_line(b"SSH-2.0-OpenSSH"), None);
+    }
+
+    fn status_line(status: u16) -> String {
+        let mut out = String::new();
+        write_status_line(&mut out, status).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_parse_basic_auth() {
+        let mut buf = [0u8; 32];
+
+        assert_eq!(
+            parse_basic_auth(b"Basic YWRtaW46cGFzcw==", &mut buf),
+            Some((b"admin".as_ref(), b"pass".as_ref()))
+        );
+        // the scheme is case-insensitive
+        assert_eq!(
+            parse_basic_auth(b"basic  YWRtaW46cGFzcw== ", &mut buf),
+            Some((b"admin".as_ref(), b"pass".as_ref()))
+        );
+        assert_eq!(
+            parse_basic_auth(b"Basic YWRtaW46cDpzOnM=", &mut buf),
+            Some((b"admin".as_ref(), b"p:s:s".as_ref()))
+        );
+        assert_eq!(
+            parse_basic_auth(b"Basic OnBhc3M=", &mut buf),
+            Some((b"".as_ref(), b"pass".as_ref()))
+        );
+        assert_eq!(parse_basic_auth(b"Bearer YWRtaW46cGFzcw==", &mut buf), None);
+        assert_eq!(parse_basic_auth(b"Basic", &mut buf), None);
+        assert_eq!(parse_basic_auth(b"Basic YWRtaW4=", &mut buf), None);
+        assert_eq!(parse_basic_auth(b"Basic not*base64", &mut buf), None);
+    }
+
+    #[test]
+    fn test_media_type() {
+        assert_eq!(media_type(b"application/json"), b"application/json");
+        assert_eq!(
+            media_type(b" application/json ; charset=utf-8"),
+            b"application/json"
+        );
+        assert_eq!(media_type(b"text/html;charset=UTF-8"), b"text/html");
+        assert_eq!(media_type(b""), b"");
+    }
+
+    #[test]
+    fn test_accepts_encoding() {
+        assert!(accepts_encoding(b"gzip, deflate, br", b"gzip"));
+        assert!(accepts_encoding(b"deflate,GZIP", b"gzip"));
+        assert!(accepts_encoding(b"br;q=1.0, gzip;q=0.8", b"gzip"));
+        assert!(accepts_encoding(b"*", b"gzip"));
+        assert!(!accepts_encoding(b"", b"gzip"));
+        assert!(!accepts_encoding(b"identity", b"gzip"));
+        assert!(!accepts_encoding(b"gzip;q=0", b"gzip"));
+        assert!(!accepts_encoding(b"gzip; q=0.000, *", b"gzip"));
+        assert!(!accepts_encoding(b"*;q=0", b"gzip"));
+        assert!(!accepts_encoding(b"x-gzip", b"gzip"));
+    }
+
+    #[test]
+    fn test_write_status_line() {
+        assert_eq!(status_line(200), "HTTP/1.0 200 OK\r\n");
+        assert_eq!(status_line(404), "HTTP/1.0 404 Not Found\r\n");
+        assert_eq!(status_line(408), "HTTP/1.0 408 Request Timeout\r\n");
+        assert_eq!(status_line(413), "HTTP/1.0 413 Payload Too Large\r\n");
+        assert_eq!(status_line(599), "HTTP/1.0 599 Unknown\r\n");
+
+        // the emitted code is always the one that was asked for
+        for status in 100..600 {
+            let line = status_line(status);
+            let parsed = parse_status_line(line.trim_end().as_bytes()).unwrap();
+            assert_eq!(parsed.status, status);
+            assert!(line.ends_with(&format!(" {}\r\n", reason_phrase(status))));
+        }
+    }
+
+    #[test]
+    fn test_body_length() {
+        let fixed = response_headers(&["Content-Type: application/json", "content-length:  42 "]);
+        assert_eq!(fixed.body_length(HTTP_1_1_OK), BodyLength::Fixed(42));
+
+        let chunked = response_headers(&["Transfer-Encoding: gzip, Chunked", "Content-Length: 42"]);
+        assert_eq!(chunked.body_length(HTTP_1_1_OK), BodyLength::Chunked);
+
+        let no_content = StatusLine {
+            minor_version: 1,
+            status: 204,
+        };
+        assert_eq!(fixed.body_length(no_content), BodyLength::Empty);
+        assert_eq!(
+            response_headers(&["Content-Length: 0"]).body_length(HTTP_1_1_OK),
+            BodyLength::Empty
+        );
+        assert_eq!(
+            response_headers(&[]).body_length(HTTP_1_1_OK),
+            BodyLength::UntilClose
+        );
+        assert_eq!(
+            response_headers(&["Content-Length: lots"]).body_length(HTTP_1_1_OK),
+            BodyLength::UntilClose
+        );
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        let fixed = response_headers(&["Content-Length: 2"]);
+        assert!(fixed.keep_alive(HTTP_1_1_OK));
+        assert!(!fixed.keep_alive(HTTP_1_0_OK));
+
+        let keep_alive = response_headers(&["Content-Length: 2", "Connection: Keep-Alive"]);
+        assert!(keep_alive.keep_alive(HTTP_1_0_OK));
+
+        let close = response_headers(&["Content-Length: 2", "Connection: upgrade, close"]);
+        assert!(!close.keep_alive(HTTP_1_1_OK));
+
+        // the end of the body can't be known without closing the connection
+        assert!(!response_headers(&[]).keep_alive(HTTP_1_1_OK));
+        assert!(response_headers(&["Transfer-Encoding: chunked"]).keep_alive(HTTP_1_1_OK));
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let too_many_requests = StatusLine {
+            minor_version: 1,
+            status: 429,
+        };
+        assert!(is_rate_limited(too_many_requests.status));
+        assert!(is_rate_limited(503));
+        assert!(!is_rate_limited(500));
+
+        let headers = response_headers(&["Content-Length: 0", "Retry-After: 120"]);
+        assert_eq!(headers.retry_after_secs(), Some(120));
+        assert_eq!(headers.body_length(too_many_requests), BodyLength::Empty);
+        assert_eq!(
+            response_headers(&["retry-after:  0 "]).retry_after_secs(),
+            Some(0)
+        );
+
+        // absent, or in a form that is not supported
+        assert_eq!(
+            response_headers(&["Content-Length: 0"]).retry_after_secs(),
+            None
+        );
+        assert_eq!(
+            response_headers(&["Retry-After: Wed, 21 Oct 2015 07:28:00 GMT"]).retry_after_secs(),
+            None
+        );
+        assert_eq!(parse_retry_after(b"-1"), None);
+        assert_eq!(parse_retry_after(b"99999999999"), None);
+    }
+
+    fn content_length(block: &str) -> Option<Option<usize>> {
+        let mut content_length = None;
+        for (name, value) in headers(block.as_bytes()) {
+            if name.eq_ignore_ascii_case(b"Content-Length") {
+                content_length = Some(parse_content_length(value));
+            }
+        }
+        content_length
+    }
+
+    #[test]
+    fn test_headers() {
+        let block = "HTTP/1.1\r\nHost: 192.168.4.1\r\nAccept:*/*\r\nX-Empty:\r\n";
+        let parsed: Vec<_> = headers(block.as_bytes()).collect();
+        assert_eq!(
+            parsed,
+            [
+                (&b"Host"[..], &b"192.168.4.1"[..]),
+                (b"Accept", b"*/*"),
+                (b"X-Empty", b""),
+            ]
+        );
+        assert_eq!(headers(b"").count(), 0);
+        assert_eq!(headers(b"no colon\r\n").count(), 0);
+    }
+
+    #[test]
+    fn test_headers_content_length_case_and_spacing() {
+        for block in [
+            "Content-Length: 12\r\n",
+            "content-length: 12\r\n",
+            "CONTENT-LENGTH:12\r\n",
+            "Content-Length:  12 \r\n",
+            "Content-Length:\t12\r\n",
+            "Content-Length: 12\n",
+            "Host: example.com\r\ncOnTeNt-LeNgTh: 12",
+        ] {
+            assert_eq!(content_length(block), Some(Some(12)), "{block:?}");
+        }
+        assert_eq!(content_length("Content-Type: text/plain\r\n"), None);
+        assert_eq!(content_length("Content-Length: twelve\r\n"), Some(None));
+        assert_eq!(content_length("Content-Length: -1\r\n"), Some(None));
+    }
+
+    #[test]
+    fn test_headers_folding() {
+        let block = "Content-Length:\r\n 12\r\nX-Long: first\r\n\tsecond\r\nHost: a\r\n";
+        let parsed: Vec<_> = headers(block.as_bytes()).collect();
+        assert_eq!(
+            parsed,
+            [
+                (&b"Content-Length"[..], &b"12"[..]),
+                (b"X-Long", b"first\r\n\tsecond"),
+                (b"Host", b"a"),
+            ]
+        );
+        assert_eq!(content_length(block), Some(Some(12)));
+    }
+
+    #[test]
+    fn test_parse_chunk_size() {
+        assert_eq!(parse_chunk_size(b"0"), Some(0));
+        assert_eq!(parse_chunk_size(b"1a"), Some(26));
+        assert_eq!(parse_chunk_size(b"FF ; name=value"), Some(255));
+        assert_eq!(parse_chunk_size(b""), None);
+        assert_eq!(parse_chunk_size(b"xyz"), None);
+        assert_eq!(parse_chunk_size(b"ffffffffffffffffff"), None);
+    }
+}
@@ -0,0 +1,667 @@
+//! Minimal helpers for writing and scanning JSON without an allocator.
+
+use core::fmt::Write;
+
+/// Writes `value` as a quoted JSON string, escaping characters as required by RFC 8259.
+pub fn write_json_str<W: Write + ?Sized>(out: &mut W, value: &str) -> core::fmt::Result {
+    out.write_char('"')?;
+
+    let mut unescaped_start = 0usize;
+
+    for (i, c) in value.char_indices() {
+        let escape: Option<&str> = match c {
+            '"' => Some(r#"\""#),
+            '\\' => Some(r"\\"),
+            '\n' => Some(r"\n"),
+            '\r' => Some(r"\r"),
+            '\t' => Some(r"\t"),
+            '\u{08}' => Some(r"\b"),
+            '\u{0c}' => Some(r"\f"),
+            '\u{00}'..='\u{1f}' => None,
+            _ => continue,
+        };
+
+        // flush the run of characters that don't need escaping
+        out.write_str(&value[unescaped_start..i])?;
+        unescaped_start = i + c.len_utf8();
+
+        match escape {
+            Some(escape) => out.write_str(escape)?,
+            None => write!(out, r"\u{:04x}", c as u32)?,
+        }
+    }
+
+    out.write_str(&value[unescaped_start..])?;
+    out.write_char('"')
+}
+
+/// Writes `value` as a JSON number.
+///
+/// JSON has no representation for NaN and infinities, those are written as `null` instead.
+pub fn write_json_f32<W: Write + ?Sized>(out: &mut W, value: f32) -> core::fmt::Result {
+    if value.is_finite() {
+        write!(out, "{value}")
+    } else {
+        out.write_str("null")
+    }
+}
+
+/// Finds the string field `key` at the top level of the JSON object `json`.
+///
+/// Returns the raw contents of the string, without the quotes and with escape sequences left as is,
+/// use [`unescape_json_str`] to decode them.
+/// Returns `None` if there is no such field, if its value is not a string, or if `json` is malformed.
+pub fn find_json_str_field<'a>(json: &'a str, key: &str) -> Option<&'a str> {
+    let bytes = json.as_bytes();
+    let mut pos = skip_whitespace(bytes, 0);
+
+    if bytes.get(pos) != Some(&b'{') {
+        return None;
+    }
+    pos = skip_whitespace(bytes, pos + 1);
+    if bytes.get(pos) == Some(&b'}') {
+        return None;
+    }
+
+    loop {
+        let (field_key, after_key) = scan_string(json, pos)?;
+        pos = skip_whitespace(bytes, after_key);
+        if bytes.get(pos) != Some(&b':') {
+            return None;
+        }
+        pos = skip_whitespace(bytes, pos + 1);
+
+        if bytes.get(pos) == Some(&b'"') && json_str_eq(field_key, key) {
+            return scan_string(json, pos).map(|(value, _)| value);
+        }
+        pos = skip_whitespace(bytes, skip_value(json, pos)?);
+
+        match bytes.get(pos) {
+            Some(b',') => pos = skip_whitespace(bytes, pos + 1),
+            _ => return None,
+        }
+    }
+}
+
+/// Writes the decoded contents of a raw JSON string, as returned by [`find_json_str_field`].
+///
+/// Invalid escape sequences and unpaired surrogates are replaced by U+FFFD.
+pub fn unescape_json_str<W: Write + ?Sized>(out: &mut W, raw: &str) -> core::fmt::Result {
+    let mut chars = raw.chars();
+
+    loop {
+        // flush the run of characters that don't need unescaping
+        let rest = chars.as_str();
+        let run_len = rest.find('\\').unwrap_or(rest.len());
+        out.write_str(&rest[..run_len])?;
+        chars = rest[run_len..].chars();
+
+        if chars.next().is_none() {
+            return Ok(());
+        }
+        let unescaped = match chars.next() {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('/') => '/',
+            Some('b') => '\u{08}',
+            Some('f') => '\u{0c}',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('u') => unescape_utf16(&mut chars),
+            _ => char::REPLACEMENT_CHARACTER,
+        };
+        out.write_char(unescaped)?;
+    }
+}
+
+/// The iterator type created by [`decode_json_object`].
+pub struct DecodeJsonObject<'a> {
+    /// Starts with the key of the next member, empty after the last one
+    data: &'a mut [u8],
+}
+
+/// Returns an iterator that yields the key/value pairs of the JSON object `data`,
+/// like [`crate::encoding::decode_form_url_encoded`] does for form-url-encoded data.
+///
+/// Strings are unescaped, numbers and `true`/`false` are yielded as written, and `null` as an empty value.
+/// Returns `None` if `data` is not a JSON object whose values are all strings, numbers or literals.
+///
+/// Note: this *mutates* the buffer in-place, decoded strings are never longer than their escaped form.
+pub fn decode_json_object(data: &mut [u8]) -> Option<DecodeJsonObject<'_>> {
+    let json = core::str::from_utf8(data).ok()?;
+    let bytes = json.as_bytes();
+    let mut pos = skip_whitespace(bytes, 0);
+    if bytes.get(pos) != Some(&b'{') {
+        return None;
+    }
+    let first = skip_whitespace(bytes, pos + 1);
+    pos = first;
+
+    if bytes.get(pos) == Some(&b'}') {
+        pos += 1;
+    } else {
+        loop {
+            pos = skip_whitespace(bytes, validate_string(json, pos)?);
+            if bytes.get(pos) != Some(&b':') {
+                return None;
+            }
+            pos = skip_whitespace(bytes, pos + 1);
+            pos = skip_whitespace(bytes, validate_scalar(json, pos)?);
+
+            match bytes.get(pos) {
+                Some(b',') => pos = skip_whitespace(bytes, pos + 1),
+                Some(b'}') => {
+                    pos += 1;
+                    break;
+                }
+                _ => return None,
+            }
+        }
+    }
+    if skip_whitespace(bytes, pos) != bytes.len() {
+        return None;
+    }
+
+    let data: &mut [u8] = if bytes[first] == b'}' {
+        &mut []
+    } else {
+        &mut data[first..]
+    };
+    Some(DecodeJsonObject { data })
+}
+
+impl<'a> Iterator for DecodeJsonObject<'a> {
+    // (key, value) iterator
+    type Item = (&'a [u8], &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let data: &'a mut [u8] = core::mem::take(&mut self.data);
+        if data.is_empty() {
+            return None;
+        }
+
+        // the object was validated beforehand, and is only modified behind the current member
+        let json = core::str::from_utf8(data).ok()?;
+        let bytes = json.as_bytes();
+        let (_, key_end) = scan_string(json, 0)?;
+        let value_start = skip_whitespace(bytes, skip_whitespace(bytes, key_end) + 1);
+        let value_end = skip_value(json, value_start)?;
+        let separator = skip_whitespace(bytes, value_end);
+        let next = match bytes[separator] {
+            b',' => Some(skip_whitespace(bytes, separator + 1)),
+            _ => None,
+        };
+
+        let (member, rest) = data.split_at_mut(next.unwrap_or(data.len()));
+        let (raw_key, raw_value) = member.split_at_mut(value_start);
+        let key_len = unescape_in_place(&mut raw_key[1..key_end - 1]);
+        let key: &'a [u8] = &raw_key[1..1 + key_len];
+
+        let value_len = value_end - value_start;
+        let value: &'a [u8] = match raw_value[0] {
+            b'"' => {
+                let len = unescape_in_place(&mut raw_value[1..value_len - 1]);
+                &raw_value[1..1 + len]
+            }
+            b'n' => &[],
+            _ => &raw_value[..value_len],
+        };
+
+        self.data = if next.is_some() { rest } else { &mut [] };
+        Some((key, value))
+    }
+}
+
+/// Checks the string starting with the quote at `pos`, and returns the position right after it.
+///
+/// Unlike [`scan_string`], control characters and invalid escape sequences are rejected.
+fn validate_string(json: &str, pos: usize) -> Option<usize> {
+    let (raw, end) = scan_string(json, pos)?;
+    let bytes = raw.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => match bytes.get(i + 1)? {
+                b'"' | b'\\' | b'/' | b'b' | b'f' | b'n' | b'r' | b't' => i += 2,
+                b'u' if bytes.get(i + 2..i + 6)?.iter().all(u8::is_ascii_hexdigit) => i += 6,
+                _ => return None,
+            },
+            0x00..=0x1f => return None,
+            _ => i += 1,
+        }
+    }
+    Some(end)
+}
+
+/// Checks the string, number or literal starting at `pos`, and returns the position right after it.
+fn validate_scalar(json: &str, pos: usize) -> Option<usize> {
+    if json.as_bytes().get(pos) == Some(&b'"') {
+        return validate_string(json, pos);
+    }
+
+    let end = skip_value(json, pos)?;
+    let value = &json[pos..end];
+    let is_number = || {
+        // missing parts are replaced by valid ones
+        let digits = value.strip_prefix('-').unwrap_or(value);
+        let (mantissa, exponent) = digits.split_once(['e', 'E']).unwrap_or((digits, "0"));
+        let exponent = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+        let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, "0"));
+
+        let all_digits = |s: &str| !s.is_empty() && s.bytes().all(|c| c.is_ascii_digit());
+        all_digits(integer)
+            && (integer == "0" || !integer.starts_with('0'))
+            && all_digits(fraction)
+            && all_digits(exponent)
+    };
+    (matches!(value, "true" | "false" | "null") || is_number()).then_some(end)
+}
+
+/// Unescapes the raw contents of a string checked by [`validate_string`] in place, returns the decoded length.
+fn unescape_in_place(raw: &mut [u8]) -> usize {
+    let mut read = 0;
+    let mut write = 0;
+    while read < raw.len() {
+        if raw[read] != b'\\' {
+            raw[write] = raw[read];
+            read += 1;
+            write += 1;
+            continue;
+        }
+
+        let unescaped = match raw[read + 1] {
+            b'u' => {
+                // enough for a surrogate pair, cut at a character boundary
+                let mut sequence = [0u8; 10];
+                let len = (raw.len() - read - 2).min(sequence.len());
+                sequence[..len].copy_from_slice(&raw[read + 2..read + 2 + len]);
+                let sequence = match core::str::from_utf8(&sequence[..len]) {
+                    Ok(sequence) => sequence,
+                    Err(e) => core::str::from_utf8(&sequence[..e.valid_up_to()]).unwrap_or(""),
+                };
+
+                let mut chars = sequence.chars();
+                let c = unescape_utf16(&mut chars);
+                read += 2 + sequence.len() - chars.as_str().len();
+                c
+            }
+            c => {
+                read += 2;
+                match c {
+                    b'b' => '\u{08}',
+                    b'f' => '\u{0c}',
+                    b'n' => '\n',
+                    b'r' => '\r',
+                    b't' => '\t',
+                    // '"', '\\' and '/'
+                    _ => char::from(c),
+                }
+            }
+        };
+        // an escape sequence is always longer than the character it encodes
+        write += unescaped.encode_utf8(&mut raw[write..read]).len();
+    }
+    write
+}
+
+/// Decodes the code point of a `\uXXXX` escape sequence, and of the following one for surrogate pairs.
+fn unescape_utf16(chars: &mut core::str::Chars<'_>) -> char {
+    fn hex4(chars: &mut core::str::Chars<'_>) -> Option<u16> {
+        let digits = chars.as_str().get(..4)?;
+        if !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let value = u16::from_str_radix(digits, 16).ok()?;
+        *chars = chars.as_str()[4..].chars();
+        Some(value)
+    }
+
+    let Some(first) = hex4(chars) else {
+        return char::REPLACEMENT_CHARACTER;
+    };
+    if !(0xd800..0xdc00).contains(&first) {
+        return char::decode_utf16([first])
+            .next()
+            .and_then(Result::ok)
+            .unwrap_or(char::REPLACEMENT_CHARACTER);
+    }
+
+    // high surrogate, must be followed by an escaped low surrogate
+    let mut lookahead = chars.clone();
+    if lookahead.next() != Some('\\') || lookahead.next() != Some('u') {
+        return char::REPLACEMENT_CHARACTER;
+    }
+    match hex4(&mut lookahead) {
+        Some(second @ 0xdc00..0xe000) => {
+            *chars = lookahead;
+            char::decode_utf16([first, second])
+                .next()
+                .and_then(Result::ok)
+                .unwrap_or(char::REPLACEMENT_CHARACTER)
+        }
+        _ => char::REPLACEMENT_CHARACTER,
+    }
+}
+
+fn skip_whitespace(bytes: &[u8], mut pos: usize) -> usize {
+    while matches!(bytes.get(pos), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+        pos += 1;
+    }
+    pos
+}
+
+/// Scans the string starting with the quote at `pos`.
+///
+/// Returns its raw contents and the position right after the closing quote.
+fn scan_string(json: &str, pos: usize) -> Option<(&str, usize)> {
+    let bytes = json.as_bytes();
+    if bytes.get(pos) != Some(&b'"') {
+        return None;
+    }
+
+    let start = pos + 1;
+    let mut end = start;
+    loop {
+        match bytes.get(end)? {
+            b'"' => return Some((&json[start..end], end + 1)),
+            b'\\' => end += 2,
+            _ => end += 1,
+        }
+    }
+}
+
+/// Skips the value starting at `pos`, returns the position right after it.
+fn skip_value(json: &str, pos: usize) -> Option<usize> {
+    let bytes = json.as_bytes();
+
+    match bytes.get(pos)? {
+        b'"' => Some(scan_string(json, pos)?.1),
+        b'{' | b'[' => {
+            let mut depth = 0usize;
+            let mut pos = pos;
+            loop {
+                match bytes.get(pos)? {
+                    b'"' => {
+                        pos = scan_string(json, pos)?.1;
+                        continue;
+                    }
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(pos + 1);
+                        }
+                    }
+                    _ => {}
+                }
+                pos += 1;
+            }
+        }
+        // numbers and literals
+        _ => {
+            let len = bytes[pos..]
+                .iter()
+                .position(|b| matches!(b, b',' | b'}' | b']' | b' ' | b'\t' | b'\n' | b'\r'))
+                .unwrap_or(bytes.len() - pos);
+            (len > 0).then_some(pos + len)
+        }
+    }
+}
+
+/// Compares a raw JSON string with `expected`.
+fn json_str_eq(raw: &str, expected: &str) -> bool {
+    if !raw.contains('\\') {
+        return raw == expected;
+    }
+
+    struct Matcher<'a> {
+        rest: &'a str,
+        matches: bool,
+    }
+
+    impl Write for Matcher<'_> {
+        fn write_str(&mut self, s: &str) -> core::fmt::Result {
+            match self.rest.strip_prefix(s) {
+                Some(rest) => self.rest = rest,
+                None => self.matches = false,
+            }
+            Ok(())
+        }
+    }
+
+    let mut matcher = Matcher {
+        rest: expected,
+        matches: true,
+    };
+    _ = unescape_json_str(&mut matcher, raw);
+    matcher.matches && matcher.rest.is_empty()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn json_str(value: &str) -> String {
+        let mut out = String::new();
+        write_json_str(&mut out, value).unwrap();
+        out
+    }
+
+    fn json_f32(value: f32) -> String {
+        let mut out = String::new();
+        write_json_f32(&mut out, value).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_write_json_str_plain() {
+        assert_eq!(json_str(""), r#""""#);
+        assert_eq!(json_str("temperature"), r#""temperature""#);
+        assert_eq!(json_str("esp32-32344"), r#""esp32-32344""#);
+        assert_eq!(json_str("température"), r#""température""#);
+    }
+
+    #[test]
+    fn test_write_json_str_escapes() {
+        assert_eq!(json_str(r#"a "quoted" word"#), r#""a \"quoted\" word""#);
+        assert_eq!(json_str(r"C:\path"), r#""C:\\path""#);
+        assert_eq!(json_str("line\nbreak\r\n"), r#""line\nbreak\r\n""#);
+        assert_eq!(json_str("\ttab"), r#""\ttab""#);
+        assert_eq!(json_str("\u{08}\u{0c}"), r#""\b\f""#);
+    }
+
+    #[test]
+    fn test_write_json_str_control_chars() {
+        assert_eq!(json_str("\u{00}"), r#""\u0000""#);
+        assert_eq!(json_str("a\u{1b}b"), r#""a\u001bb""#);
+        assert_eq!(json_str("\u{7f}"), "\"\u{7f}\"");
+    }
+
+    fn unescaped(raw: &str) -> String {
+        let mut out = String::new();
+        unescape_json_str(&mut out, raw).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_find_json_str_field() {
+        let json = r#"{"code":"invalid","message":"failed to parse line protocol"}"#;
+        assert_eq!(find_json_str_field(json, "code"), Some("invalid"));
+        assert_eq!(
+            find_json_str_field(json, "message"),
+            Some("failed to parse line protocol")
+        );
+        assert_eq!(find_json_str_field(json, "error"), None);
+    }
+
+    #[test]
+    fn test_find_json_str_field_skips_values() {
+        let json = r#" {
+            "line": 3, "ok": false, "err": null,
+            "details": {"message": "nested", "list": [1, "]", {"a": []}]},
+            "tags": ["message"],
+            "message" : "top-level \"quoted\""
+        } "#;
+        assert_eq!(
+            find_json_str_field(json, "message"),
+            Some(r#"top-level \"quoted\""#)
+        );
+        assert_eq!(find_json_str_field(json, "line"), None);
+        assert_eq!(find_json_str_field(json, "details"), None);
+        assert_eq!(
+            find_json_str_field(r#"{"mess\u0061ge":"x"}"#, "message"),
+            Some("x")
+        );
+    }
+
+    #[test]
+    fn test_find_json_str_field_malformed() {
+        assert_eq!(find_json_str_field("", "message"), None);
+        assert_eq!(find_json_str_field("{}", "message"), None);
+        assert_eq!(find_json_str_field(r#"["message"]"#, "message"), None);
+        assert_eq!(
+            find_json_str_field(r#"{"message":"unterminated"#, "message"),
+            None
+        );
+        assert_eq!(
+            find_json_str_field(r#"{"a":1 "message":"x"}"#, "message"),
+            None
+        );
+        assert_eq!(
+            find_json_str_field(r#"{"a":,"message":"x"}"#, "message"),
+            None
+        );
+        assert_eq!(
+            find_json_str_field(r#"{"a":{"b":1,"message":"x"}"#, "message"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_unescape_json_str() {
+        assert_eq!(unescaped("plain"), "plain");
+        assert_eq!(unescaped(r#"a \"quoted\" word"#), r#"a "quoted" word"#);
+        assert_eq!(
+            unescaped(r"C:\\path \/ \n\r\t\b\f"),
+            "C:\\path / \n\r\t\u{08}\u{0c}"
+        );
+        assert_eq!(unescaped(r"temp\u00e9rature"), "température");
+        assert_eq!(unescaped(r"\ud83d\ude00"), "\u{1f600}");
+    }
+
+    #[test]
+    fn test_unescape_json_str_invalid() {
+        assert_eq!(unescaped(r"\x"), "\u{fffd}");
+        assert_eq!(unescaped(r"\u12"), "\u{fffd}12");
+        assert_eq!(unescaped(r"\u+123"), "\u{fffd}+123");
+        assert_eq!(unescaped(r"\ud83d"), "\u{fffd}");
+        assert_eq!(unescaped(r"\ud83dx"), "\u{fffd}x");
+        assert_eq!(unescaped(r"\ude00"), "\u{fffd}");
+        assert_eq!(unescaped("trailing\\"), "trailing\u{fffd}");
+    }
+
+    fn decoded(json: &str) -> Option<Vec<(String, String)>> {
+        let mut data = json.as_bytes().to_vec();
+        let members = decode_json_object(&mut data)?;
+        Some(
+            members
+                .map(|(key, value)| {
+                    (
+                        String::from_utf8(key.to_vec()).unwrap(),
+                        String::from_utf8(value.to_vec()).unwrap(),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    fn pairs(pairs: &[(&str, &str)]) -> Option<Vec<(String, String)>> {
+        Some(
+            pairs
+                .iter()
+                .map(|&(key, value)| (key.to_owned(), value.to_owned()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_decode_json_object() {
+        assert_eq!(
+            decoded(r#"{"csrf_token":"abc","lora_tx_power":14,"wifi_sta_ssid":null}"#),
+            pairs(&[
+                ("csrf_token", "abc"),
+                ("lora_tx_power", "14"),
+                ("wifi_sta_ssid", "")
+            ])
+        );
+        assert_eq!(
+            decoded(" {\n  \"a\" : -1.5e3 ,\t\"b\": true, \"c\":false }\n"),
+            pairs(&[("a", "-1.5e3"), ("b", "true"), ("c", "false")])
+        );
+        assert_eq!(decoded("{}"), pairs(&[]));
+        assert_eq!(decoded(" { } "), pairs(&[]));
+    }
+
+    #[test]
+    fn test_decode_json_object_escapes() {
+        assert_eq!(
+            decoded(r#"{"wifi_\u0073sid":"my \"home\" w\u00effi \ud83d\ude00","p":"a\\b\/c\n"}"#),
+            pairs(&[
+                ("wifi_ssid", "my \"home\" w\u{ef}fi \u{1f600}"),
+                ("p", "a\\b/c\n")
+            ])
+        );
+        assert_eq!(
+            decoded(r#"{"a":"\ud83d","b":"\ude00\u0041"}"#),
+            pairs(&[("a", "\u{fffd}"), ("b", "\u{fffd}A")])
+        );
+        assert_eq!(
+            decoded(r#"{"a":"\u00e9\u00e9\u00e9","b":"é"}"#),
+            pairs(&[("a", "ééé"), ("b", "é")])
+        );
+    }
+
+    #[test]
+    fn test_decode_json_object_malformed() {
+        for json in [
+            "",
+            "[]",
+            r#""a""#,
+            "{",
+            r#"{"a"}"#,
+            r#"{"a":}"#,
+            r#"{"a":1,}"#,
+            r#"{"a":1 "b":2}"#,
+            r#"{"a":1}}"#,
+            r#"{"a":1} x"#,
+            r#"{a:1}"#,
+            r#"{"a":'b'}"#,
+            r#"{"a":{"b":1}}"#,
+            r#"{"a":[1]}"#,
+            r#"{"a":yes}"#,
+            r#"{"a":01}"#,
+            r#"{"a":1.}"#,
+            r#"{"a":.5}"#,
+            r#"{"a":1e}"#,
+            r#"{"a":"\x"}"#,
+            r#"{"a":"\u12"}"#,
+            "{\"a\":\"line\nbreak\"}",
+        ] {
+            assert_eq!(decoded(json), None, "{json}");
+        }
+
+        let mut invalid_utf8 = b"{\"a\":\"\xff\"}".to_vec();
+        assert!(decode_json_object(&mut invalid_utf8).is_none());
+    }
+
+    #[test]
+    fn test_write_json_f32() {
+        assert_eq!(json_f32(22.3), "22.3");
+        assert_eq!(json_f32(-4.0), "-4");
+        assert_eq!(json_f32(0.0), "0");
+        assert_eq!(json_f32(f32::NAN), "null");
+        assert_eq!(json_f32(f32::INFINITY), "null");
+        assert_eq!(json_f32(f32::NEG_INFINITY), "null");
+    }
+}
@@ -1,3 +1,20 @@
 #![cfg_attr(not(test), no_std)]
 
+pub mod adr;
+pub mod auth;
+pub mod backoff;
+pub mod barometric;
+pub mod console;
+pub mod dns_cache;
 pub mod encoding;
+pub mod export;
+pub mod form;
+pub mod framing;
+pub mod gzip;
+pub mod history;
+pub mod hopping;
+pub mod http;
+pub mod json;
+pub mod template;
+#[cfg(feature = "uart-phy")]
+pub mod uart_phy;
@@ -0,0 +1,182 @@
+//! Minimal templating of HTML pages, with `{{name}}` placeholders substituted by HTML-escaped values.
+
+use core::fmt::{self, Write};
+
+/// A piece of a template, see [`segments`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Segment<'a> {
+    /// Text copied as is
+    Text(&'a str),
+    /// Name of a value, with surrounding whitespace trimmed
+    Placeholder(&'a str),
+}
+
+/// The iterator type created by [`segments`].
+pub struct Segments<'a> {
+    rest: &'a str,
+}
+
+/// Returns an iterator over the text and placeholders of `template`.
+///
+/// An unterminated `{{` is kept as text.
+pub fn segments(template: &str) -> Segments<'_> {
+    Segments { rest: template }
+}
+
+impl<'a> Iterator for Segments<'a> {
+    type Item = Segment<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rest.is_empty() {
+            return None;
+        }
+
+        let placeholder = self.rest.find("{{").and_then(|start| {
+            let len = self.rest[start..].find("}}")?;
+            Some((start, start + len + 2))
+        });
+        match placeholder {
+            Some((0, end)) => {
+                let name = &self.rest[2..end - 2];
+                self.rest = &self.rest[end..];
+                Some(Segment::Placeholder(name.trim()))
+            }
+            Some((start, _)) => {
+                let text = &self.rest[..start];
+                self.rest = &self.rest[start..];
+                Some(Segment::Text(text))
+            }
+            None => {
+                let text = self.rest;
+                self.rest = "";
+                Some(Segment::Text(text))
+            }
+        }
+    }
+}
+
+/// The iterator type created by [`html_escape`].
+pub struct HtmlEscape<'a> {
+    rest: &'a str,
+}
+
+/// Returns an iterator over the pieces of `value` escaped for HTML text and quoted attribute values.
+///
+/// Yields runs of unchanged text and character references for `&`, `<`, `>`, `"` and `'`.
+pub fn html_escape(value: &str) -> HtmlEscape<'_> {
+    HtmlEscape { rest: value }
+}
+
+impl<'a> Iterator for HtmlEscape<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let escaped = |c| match c {
+            b'&' => Some("&amp;"),
+            b'<' => Some("&lt;"),
+            b'>' => Some("&gt;"),
+            b'"' => Some("&quot;"),
+            b'\'' => Some("&#39;"),
+            _ => None,
+        };
+
+        let first = *self.rest.as_bytes().first()?;
+        if let Some(reference) = escaped(first) {
+            self.rest = &self.rest[1..];
+            return Some(reference);
+        }
+        let end = self
+            .rest
+            .bytes()
+            .position(|c| escaped(c).is_some())
+            .unwrap_or(self.rest.len());
+        let (text, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        Some(text)
+    }
+}
+
+/// Writes `template` with each placeholder replaced by the HTML-escaped value returned by `lookup`.
+///
+/// Placeholders without a value are left empty.
+pub fn render<'v, W: Write + ?Sized>(
+    out: &mut W,
+    template: &str,
+    mut lookup: impl FnMut(&str) -> Option<&'v str>,
+) -> fmt::Result {
+    for segment in segments(template) {
+        match segment {
+            Segment::Text(text) => out.write_str(text)?,
+            Segment::Placeholder(name) => {
+                for piece in html_escape(lookup(name).unwrap_or_default()) {
+                    out.write_str(piece)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_segments() {
+        let template = "<p>{{greeting}}, {{ name }}!</p>{{last}}";
+        assert_eq!(
+            segments(template).collect::<Vec<_>>(),
+            [
+                Segment::Text("<p>"),
+                Segment::Placeholder("greeting"),
+                Segment::Text(", "),
+                Segment::Placeholder("name"),
+                Segment::Text("!</p>"),
+                Segment::Placeholder("last"),
+            ]
+        );
+
+        assert_eq!(segments("").next(), None);
+        assert_eq!(
+            segments("a {{ b").collect::<Vec<_>>(),
+            [Segment::Text("a {{ b")]
+        );
+        assert_eq!(
+            segments("}}{{}}").collect::<Vec<_>>(),
+            [Segment::Text("}}"), Segment::Placeholder("")]
+        );
+    }
+
+    #[test]
+    fn test_html_escape() {
+        let escape = |value| html_escape(value).collect::<String>();
+
+        assert_eq!(escape(""), "");
+        assert_eq!(escape("plain text"), "plain text");
+        assert_eq!(
+            escape(r#""><script>alert('x')</script>"#),
+            "&quot;&gt;&lt;script&gt;alert(&#39;x&#39;)&lt;/script&gt;"
+        );
+        assert_eq!(escape("Tom & Jerry"), "Tom &amp; Jerry");
+        assert_eq!(escape("café"), "café");
+    }
+
+    #[test]
+    fn test_render() {
+        let mut out = String::new();
+        let res = render(
+            &mut out,
+            r#"<input name="ssid" value="{{ssid}}"><p>{{missing}}</p>"#,
+            |name| match name {
+                "ssid" => Some(r#"my "home" wifi"#),
+                _ => None,
+            },
+        );
+
+        assert_eq!(res, Ok(()));
+        assert_eq!(
+            out,
+            r#"<input name="ssid" value="my &quot;home&quot; wifi"><p></p>"#
+        );
+    }
+}
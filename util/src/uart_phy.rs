@@ -0,0 +1,117 @@
+//! Physical layer over a serial link, to exercise the link and app layers on the bench without radios.
+//!
+//! Packets are sent as frames of [`crate::framing`], since serial links have no packet boundaries.
+//! Connect the TX and RX pins of both boards crosswise, along with their grounds.
+
+use crate::framing::{frame_header, FrameDecoder, MAX_FRAME_PAYLOAD_LEN};
+use defmt::trace;
+use embedded_io_async::{Read, Write};
+use protocol::phy::PhysicalLayer;
+use thiserror::Error;
+
+/// Largest packet sent or received, the same as over LoRa.
+pub const UART_PHY_MAX_PAYLOAD_LEN: usize = 128;
+
+const _: () = assert!(UART_PHY_MAX_PAYLOAD_LEN <= MAX_FRAME_PAYLOAD_LEN);
+
+#[derive(Debug, Error)]
+pub enum UartPhyError<E: embedded_io_async::Error> {
+    #[error("UART error: {0:?}")]
+    Uart(E),
+    #[error("buffer overflow")]
+    BufferOverflow,
+}
+
+/// Sends and receives packets over `U`, usually an `esp_hal::uart::Uart` in async mode.
+pub struct UartPhy<U> {
+    uart: U,
+    decoder: FrameDecoder<UART_PHY_MAX_PAYLOAD_LEN>,
+    rx_buffer: heapless::Vec<u8, UART_PHY_MAX_PAYLOAD_LEN>,
+    tx_buffer: heapless::Vec<u8, UART_PHY_MAX_PAYLOAD_LEN>,
+    /// Bytes read from the UART, those before `chunk_start` were already decoded
+    chunk: [u8; 32],
+    chunk_start: usize,
+    chunk_len: usize,
+}
+
+impl<U: Read + Write> UartPhy<U> {
+    pub fn new(uart: U) -> Self {
+        Self {
+            uart,
+            decoder: FrameDecoder::new(),
+            rx_buffer: heapless::Vec::new(),
+            tx_buffer: heapless::Vec::new(),
+            chunk: [0; 32],
+            chunk_start: 0,
+            chunk_len: 0,
+        }
+    }
+}
+
+impl<U: Read + Write> PhysicalLayer for UartPhy<U> {
+    type Error = UartPhyError<U::Error>;
+
+    async fn read(&mut self) -> Result<(), Self::Error> {
+        self.rx_buffer.clear();
+        loop {
+            // bytes after the end of a frame are kept for the next read
+            while self.chunk_start < self.chunk_len {
+                let byte = self.chunk[self.chunk_start];
+                self.chunk_start += 1;
+                if let Some(payload) = self.decoder.push(byte) {
+                    // same capacity as the decoder
+                    self.rx_buffer.extend_from_slice(payload).ok();
+                    trace!(
+                        "phy: received packet of length {=usize}",
+                        self.rx_buffer.len()
+                    );
+                    return Ok(());
+                }
+            }
+
+            // nothing is left to decode if the read is cancelled by a timeout
+            self.chunk_start = 0;
+            self.chunk_len = 0;
+            self.chunk_len = match self.uart.read(&mut self.chunk).await {
+                Ok(len) => len,
+                Err(e) => {
+                    self.decoder.reset();
+                    return Err(UartPhyError::Uart(e));
+                }
+            };
+        }
+    }
+
+    fn rx_buffer(&self) -> &[u8] {
+        &self.rx_buffer
+    }
+
+    fn max_payload_len(&self) -> usize {
+        UART_PHY_MAX_PAYLOAD_LEN
+    }
+
+    async fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.tx_buffer
+            .extend_from_slice(data)
+            .map_err(|_| UartPhyError::BufferOverflow)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        if self.tx_buffer.is_empty() {
+            return Ok(());
+        }
+        let header = frame_header(self.tx_buffer.len()).ok_or(UartPhyError::BufferOverflow)?;
+        trace!(
+            "phy: sending packet of length {=usize}",
+            self.tx_buffer.len()
+        );
+        let res = async {
+            self.uart.write_all(&header).await?;
+            self.uart.write_all(&self.tx_buffer).await?;
+            self.uart.flush().await
+        }
+        .await;
+        self.tx_buffer.clear();
+        res.map_err(UartPhyError::Uart)
+    }
+}